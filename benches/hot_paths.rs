@@ -0,0 +1,206 @@
+// Hot paths of server: mempool diffing on every update, block JSON for
+// `/block/{id}`, event fan-out to WebSocket subscribers and route matching.
+//
+//   cargo bench --features bench --bench hot_paths -- --baseline main       # compare
+//   cargo bench --features bench --bench hot_paths -- --save-baseline main  # update
+//
+// Baseline is in `benches/baselines` (`CRITERION_HOME` set by `.cargo/config.toml`).
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use hyper::Method;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use bitcoin_rust_learning::server::bench::{mempool_diff, Routes};
+use bitcoin_rust_learning::server::bitcoind::json::{
+    ResponseBlock, ResponseBlockTransaction, ResponseRawMempool, ResponseScriptPubKey,
+    ResponseTransactionInput, ResponseTransactionOutput,
+};
+use bitcoin_rust_learning::server::events::{BlockEvent, Event, EventBus, Format, TxEvent};
+use bitcoin_rust_learning::server::json;
+use bitcoin_rust_learning::server::{HashOrder, JsonFormat, Naming};
+
+fn txid(n: usize) -> String {
+    hex::encode(Sha256::digest(&n.to_le_bytes()))
+}
+
+// Node mempool as `getrawmempool` result: transactions `from..to`
+fn raw_mempool(from: usize, to: usize) -> Vec<u8> {
+    let mempool = (from..to)
+        .map(|n| {
+            (
+                txid(n),
+                json!({ "vsize": 200, "fees": { "base": 0.00001 } }),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    serde_json::to_vec(&mempool).unwrap()
+}
+
+fn mempool(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mempool_diff");
+    for size in [10_000, 100_000].iter().copied() {
+        // 1% of transactions replaced, like between two updates under load
+        let turnover = size / 100;
+        let current = (0..size)
+            .map(|n| (txid(n), 200u32))
+            .collect::<HashMap<_, _>>();
+        let body = raw_mempool(turnover, size + turnover);
+
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &body, |b, body| {
+            b.iter_batched(
+                || serde_json::from_slice::<ResponseRawMempool>(body).unwrap(),
+                |new| mempool_diff(&current, new),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// Block with `count` transactions, one input and two outputs each
+fn response_block(count: usize) -> ResponseBlock {
+    let transactions = (0..count)
+        .map(|n| ResponseBlockTransaction {
+            txid: txid(n),
+            hash: txid(n + count),
+            size: 250,
+            vsize: None,
+            fee: None,
+            vin: vec![ResponseTransactionInput {
+                txid: Some(txid(n + 2 * count)),
+                vout: Some(0),
+                txinwitness: vec![hex::encode([0; 72]), hex::encode([0; 33])],
+            }],
+            vout: vec![
+                ResponseTransactionOutput {
+                    value: 0.5,
+                    script_pubkey: ResponseScriptPubKey { hex: vec![0; 22] },
+                },
+                ResponseTransactionOutput {
+                    value: 0.1,
+                    script_pubkey: ResponseScriptPubKey { hex: vec![0; 25] },
+                },
+            ],
+        })
+        .collect();
+    ResponseBlock {
+        hash: txid(usize::MAX),
+        height: 650_000,
+        version: 0x2000_0000,
+        previousblockhash: Some(txid(usize::MAX - 1)),
+        merkleroot: txid(usize::MAX - 2),
+        time: 1_600_000_000,
+        bits: "170f4f5f".to_owned(),
+        nonce: 0,
+        size: count as u32 * 250,
+        weight: Some(count as u32 * 1000),
+        transactions,
+    }
+}
+
+fn block_json(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block_json");
+    let formats = [
+        ("display", JsonFormat::default()),
+        (
+            "internal_camel",
+            JsonFormat {
+                naming: Naming::Camel,
+                hash_order: HashOrder::Internal,
+                ..JsonFormat::default()
+            },
+        ),
+    ];
+    for (name, format) in formats.iter() {
+        group.bench_function(*name, |b| {
+            b.iter_batched(
+                || response_block(3_000),
+                |block| {
+                    let block = json::Block::new(block, 625_000_000);
+                    if format.is_default() {
+                        serde_json::to_string(&block).unwrap()
+                    } else {
+                        let value = serde_json::to_value(&block).unwrap();
+                        format.apply(value).to_string()
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// One event received and encoded by every subscriber, as WebSocket tasks do
+fn events_fan_out(c: &mut Criterion) {
+    let mut group = c.benchmark_group("events_fan_out");
+    for subscribers in [1, 100, 1_000].iter().copied() {
+        let bus = EventBus::new(HashMap::new());
+        let mut receivers = (0..subscribers)
+            .map(|_| bus.subscribe(false))
+            .collect::<Vec<_>>();
+
+        group.throughput(Throughput::Elements(subscribers as u64));
+        group.bench_function(BenchmarkId::from_parameter(subscribers), |b| {
+            let mut n = 0;
+            b.iter(|| {
+                n += 1;
+                let event = if n % 100 == 0 {
+                    Event::Block(BlockEvent::Added, n as u32, txid(n))
+                } else {
+                    Event::Tx(TxEvent::Added, txid(n))
+                };
+                bus.publish(event);
+                for receiver in receivers.iter_mut() {
+                    let message = receiver.try_recv().unwrap();
+                    if message.is_visible(None) {
+                        criterion::black_box(message.encode(Format::Json, &JsonFormat::default()));
+                    }
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn routes(c: &mut Criterion) {
+    let routes = Routes::new();
+    let hash = txid(0);
+    let paths = [
+        ("block_tip", Method::GET, "/v1/block/tip".to_owned()),
+        ("block_hash", Method::GET, format!("/v1/block/{}", hash)),
+        (
+            "outpoint_spend",
+            Method::GET,
+            format!("/v1/outpoint/{}:1/spend", hash),
+        ),
+        ("admin_peers", Method::DELETE, "/v1/admin/peers".to_owned()),
+        ("legacy_alias", Method::GET, "/mempool".to_owned()),
+        ("not_found", Method::GET, "/v1/blocks/tip/txs".to_owned()),
+    ];
+
+    let mut group = c.benchmark_group("routes");
+    for (name, method, path) in paths.iter() {
+        group.bench_function(*name, |b| b.iter(|| routes.find(method, path)));
+    }
+    group.finish();
+}
+
+// Short measurement, suite runs in about a minute
+fn config() -> Criterion {
+    Criterion::default()
+        .warm_up_time(Duration::from_secs(1))
+        .measurement_time(Duration::from_secs(3))
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = mempool, block_json, events_fan_out, routes
+}
+criterion_main!(benches);
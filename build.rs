@@ -1,25 +1,18 @@
-use std::fs;
+use std::process::Command;
 
 fn main() {
-    generate_cli_name_version();
+    embed_git_commit();
 }
 
-fn generate_cli_name_version() {
-    let cargo_toml = include_str!("./Cargo.toml");
-    let cargo: toml::Value = toml::from_str(cargo_toml).expect("Failed to parse Cargo.toml");
-    let package = cargo
-        .get("package")
-        .expect("Cargo.toml should have `package` section");
-
-    let name = package
-        .get("name")
-        .and_then(|x| x.as_str())
-        .expect("Field get String field `name` from `package` section");
-    fs::write("./src/cli.yaml.name", name).expect("Failed to create CLI file with name");
-
-    let version = package
-        .get("version")
-        .and_then(|x| x.as_str())
-        .expect("Field get String field `version` from `package` section");
-    fs::write("./src/cli.yaml.version", version).expect("Failed to create CLI file with version");
+// Commit available as `env!("GIT_COMMIT")`, "unknown" if built not from git
+fn embed_git_commit() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
 }
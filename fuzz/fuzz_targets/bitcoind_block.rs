@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use bitcoin_rust_learning::server::fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::bitcoind_block(data);
+});
@@ -1,19 +1,65 @@
-use tokio_tungstenite::tungstenite::error::Error as TungsteniteError;
-use url::ParseError as UrlParseError;
+use super::sdk::SdkError;
+use crate::exit::{CliError, ErrorClass};
 
 quick_error! {
     #[derive(Debug)]
     pub enum AppError {
-        InvalidUrl(err: UrlParseError) {
-            display("Invalid URL ({})", err)
-        }
-        TungsteniteError(err: TungsteniteError) {
-            display("WebSocket error: {}", err)
-        }
-        InvalidResponse(status: u16) {
-            display("Invalid response statuc: {}", status)
+        Sdk(err: SdkError) {
+            display("{}", err)
         }
     }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    // Server is `node` for client: rejected request or invalid data
+    pub fn to_cli_error(&self) -> CliError {
+        let url_hint = "Check --url, ws:// and wss:// are supported";
+        match self {
+            AppError::Sdk(SdkError::InvalidUrl(_))
+            | AppError::Sdk(SdkError::InvalidUrlScheme(_)) => {
+                CliError::new(ErrorClass::Config, "invalid_url", self).with_hint(url_hint)
+            }
+            AppError::Sdk(SdkError::InvalidToken) => {
+                CliError::new(ErrorClass::Config, "invalid_token", self)
+            }
+            AppError::Sdk(SdkError::Reqwest(_)) | AppError::Sdk(SdkError::Tungstenite(_)) => {
+                CliError::new(ErrorClass::Network, "server_unreachable", self)
+                    .with_hint("Check that server is running, --reconnect retries on drops")
+            }
+            AppError::Sdk(SdkError::Status(..))
+            | AppError::Sdk(SdkError::Json(_))
+            | AppError::Sdk(SdkError::Subscribe(_))
+            | AppError::Sdk(SdkError::Resume(_)) => {
+                CliError::new(ErrorClass::Node, "server_error", self)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_error_classes() {
+        let error = AppError::Sdk(SdkError::InvalidUrlScheme("http".to_owned())).to_cli_error();
+        assert_eq!(
+            (error.class, error.code),
+            (ErrorClass::Config, "invalid_url")
+        );
+        assert!(error.hint.is_some());
+
+        let error = AppError::Sdk(SdkError::InvalidToken).to_cli_error();
+        assert_eq!(error.class, ErrorClass::Config);
+
+        // Rejected request is error of server, not of client
+        let error = AppError::Sdk(SdkError::Status(429, "Too Many Requests".to_owned()));
+        let error = error.to_cli_error();
+        assert_eq!(
+            (error.class, error.code),
+            (ErrorClass::Node, "server_error")
+        );
+    }
+}
@@ -1,17 +1,48 @@
-use clap::ArgMatches;
+use std::str::FromStr;
+use std::time::Duration;
+
 use futures::stream::StreamExt as _;
-use log::{error, info};
-use tokio_tungstenite::connect_async;
-use url::Url;
+use log::{error, info, warn};
 
 use self::error::{AppError, AppResult};
+use self::sdk::{EventSchemas, SdkError};
+use crate::cli::ClientArgs;
+use crate::exit::ErrorFormat;
 use crate::logger;
 use crate::signals;
 
 mod error;
+pub mod sdk;
+
+// Delay before reconnect, doubled after every failed attempt
+const RECONNECT_DELAY_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+// Received events on stdout, `text` logs events as they sent by server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Text,
+    Json,
+    Pretty,
+    Csv,
+}
+
+impl FromStr for Output {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            "pretty" => Ok(Output::Pretty),
+            "csv" => Ok(Output::Csv),
+            _ => Err(()),
+        }
+    }
+}
 
 // Run WS client for transactions monitoring
-pub fn main(args: &ArgMatches) -> i32 {
+pub fn main(args: &ClientArgs, format: ErrorFormat) -> i32 {
     logger::init();
 
     // Create runtime and run app
@@ -24,38 +55,112 @@ pub fn main(args: &ArgMatches) -> i32 {
         .block_on(run(args));
 
     if let Some(error) = app_result.err() {
-        error!("{}", error);
-        return 1;
+        return error.to_cli_error().report(format);
     }
 
     0
 }
 
-#[allow(clippy::needless_lifetimes)]
-async fn run<'a>(args: &ArgMatches<'a>) -> AppResult<()> {
+async fn run(args: &ClientArgs) -> AppResult<()> {
     // Subscribe on shutdown signals
     let mut shutdown = signals::subscribe();
 
-    let url = Url::parse(args.value_of("url").unwrap()).map_err(AppError::InvalidUrl)?;
-    let (ws_stream, resp) = connect_async(url)
-        .await
-        .map_err(AppError::TungsteniteError)?;
-    if resp.status().as_u16() != 101 {
-        return Err(AppError::InvalidResponse(resp.status().as_u16()));
+    let url = &args.url;
+    let topics = Some(args.topic.iter().map(String::as_str).collect::<Vec<_>>())
+        .filter(|topics| !topics.is_empty());
+    let reconnect = args.reconnect;
+    let output = args.output;
+    let schemas = EventSchemas::new();
+    if output == Output::Csv {
+        println!("event,topic,fields");
     }
 
-    let (_, read) = ws_stream.split();
-    let read_fut = read.for_each(|message| async {
-        match message.unwrap().into_text() {
-            Ok(text) => info!("{}", text),
-            Err(err) => error!("{}", AppError::TungsteniteError(err)),
+    // Last received block, missed block events replayed by server after
+    // reconnect
+    let mut last: Option<sdk::ResumeBlock> = None;
+    let mut delay = RECONNECT_DELAY_MIN;
+    loop {
+        let subscribe =
+            sdk::subscribe_url_from(url.clone(), None, topics.as_deref(), last.as_ref());
+        match subscribe.await {
+            Ok(mut events) => {
+                delay = RECONNECT_DELAY_MIN;
+                let read_fut = async {
+                    while let Some(event) = events.next().await {
+                        match event {
+                            Ok(text) => {
+                                print_event(output, &schemas, &text);
+                                if let Some(block) = sdk::resume_block(&text) {
+                                    last = Some(block);
+                                }
+                            }
+                            Err(err) => error!("{}", err),
+                        }
+                    }
+                };
+
+                tokio::select! {
+                    _ = shutdown.recv() => return Ok(()),
+                    _ = read_fut => {},
+                };
+                if !reconnect {
+                    return Ok(());
+                }
+                warn!("Connection closed, reconnect in {:?}", delay);
+            }
+            // Block is not tracked by server anymore, connect without resume
+            Err(err @ SdkError::Resume(_)) if reconnect => {
+                warn!("{}, missed block events are not received", err);
+                last = None;
+                continue;
+            }
+            Err(err) if reconnect => warn!("{}, reconnect in {:?}", err, delay),
+            Err(err) => return Err(AppError::Sdk(err)),
+        }
+
+        tokio::select! {
+            _ = shutdown.recv() => return Ok(()),
+            _ = tokio::time::delay_for(delay) => {},
         };
-    });
+        delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+    }
+}
+
+// Unknown events (newer server or `format` other than text) only logged
+fn print_event(output: Output, schemas: &EventSchemas, text: &str) {
+    if output == Output::Text {
+        info!("{}", text);
+        return;
+    }
 
-    tokio::select! {
-        _ = shutdown.recv() => {},
-        _ = read_fut => {},
+    let event = match schemas.parse(text) {
+        Some(event) => event,
+        None => {
+            warn!("Unknown event: {}", text);
+            return;
+        }
     };
+    match output {
+        Output::Json => println!("{}", serde_json::to_string(&event).unwrap()),
+        Output::Pretty => println!("{}", serde_json::to_string_pretty(&event).unwrap()),
+        Output::Csv => {
+            let fields = event
+                .fields
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!("{},{},{}", event.event, event.topic, csv_escape(&fields));
+        }
+        Output::Text => unreachable!(),
+    }
+}
 
-    Ok(())
+// Quoted as RFC 4180 if value has separator, quote or line break
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
 }
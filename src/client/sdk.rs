@@ -0,0 +1,826 @@
+// Typed client for server API, so Rust consumers do not need own HTTP/WS code
+//
+//   let client = Client::new("http://localhost:8000/")?;
+//   let tip = client.block(BlockId::Tip).await?;
+//   let mut events = client.subscribe(false, Some(&["blocks"])).await?;
+//   while let Some(event) = events.next().await { ... }
+
+use std::fmt;
+
+use futures::future;
+use futures::sink::Sink;
+use futures::sink::SinkExt as _;
+use futures::stream::{BoxStream, Stream, StreamExt as _};
+use reqwest::{header, Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest as _;
+use tokio_tungstenite::tungstenite::error::Error as TungsteniteError;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use url::{ParseError as UrlParseError, Url};
+
+pub use crate::server::json::WsResumeBlock as ResumeBlock;
+pub use crate::server::json::{
+    AddressTransactions, Ban, BanEntry, BitcoindEndpoint, BitcoindSwitch, Blacklist,
+    BlacklistEntry, Block, BlockFilter, BlockPage, BlockSources, BlocksKeep, Chaos,
+    ConfirmationTimes, DifficultyAdjustment, FeeEstimate, FeeEstimateMode, Halving, JobStatus,
+    Maintenance, MempoolFeerates, MempoolHistogram, MempoolTransaction, NodeEntry, Nodes,
+    OutpointInfo, OutpointSpend, PackageSubmit, PackageSubmitRequest, PeerEntry, Peers,
+    ProjectedBlock, ReactionEntry, Reactions, Reorg, Reorgs, Root, RpcError, Stats, TimelockEntry,
+    Timelocks, Transaction, TransactionInfo, TxBroadcast, TxTestRequest, TxTestResult,
+    UpdatesPause, Usage, Version, Watch,
+};
+use crate::server::json::{ErrorResponse, WatchEntry, WsReply, WsResume, WsSubscribe};
+pub use crate::server::{AuditRecord, EventSchemas, ParsedEvent};
+
+const API_VERSION: &str = "1";
+const HEADER_ACCEPT_VERSION: &str = "accept-version";
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum SdkError {
+        InvalidUrl(err: UrlParseError) {
+            display("Invalid URL ({})", err)
+        }
+        InvalidUrlScheme(scheme: String) {
+            display(r#"URL scheme "{}" is not supported"#, scheme)
+        }
+        Reqwest(err: reqwest::Error) {
+            display("{}", err)
+        }
+        Status(status: u16, msg: String) {
+            display("Server error (status: {}): {}", status, msg)
+        }
+        Json(err: serde_json::Error) {
+            display("Invalid JSON ({})", err)
+        }
+        Tungstenite(err: TungsteniteError) {
+            display("WebSocket error: {}", err)
+        }
+        InvalidToken {
+            display("Token is not valid header value")
+        }
+        Subscribe(msg: String) {
+            display("Subscribe failed: {}", msg)
+        }
+        Resume(msg: String) {
+            display("Resume failed: {}", msg)
+        }
+    }
+}
+
+pub type SdkResult<T> = Result<T, SdkError>;
+
+// Events in text form, as they sent by server
+pub type EventStream = BoxStream<'static, SdkResult<String>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockId {
+    Tip,
+    Height(u32),
+    Hash(String),
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockId::Tip => write!(f, "tip"),
+            BlockId::Height(height) => write!(f, "{}", height),
+            BlockId::Hash(hash) => write!(f, "{}", hash),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    client: reqwest::Client,
+    url: Url,
+    // Admin token or tenant API key, sent as `Authorization: Bearer`
+    token: Option<String>,
+}
+
+impl Client {
+    // Base URL of server, for example `http://localhost:8000/`
+    pub fn new(url: &str) -> SdkResult<Self> {
+        let mut url = Url::parse(url).map_err(SdkError::InvalidUrl)?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(SdkError::InvalidUrlScheme(url.scheme().to_owned()));
+        }
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+
+        Ok(Client {
+            client: reqwest::Client::new(),
+            url,
+            token: None,
+        })
+    }
+
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_owned());
+        self
+    }
+
+    pub async fn root(&self) -> SdkResult<Root> {
+        self.get_json("").await
+    }
+
+    pub async fn version(&self) -> SdkResult<Version> {
+        self.get_json("version").await
+    }
+
+    pub async fn mempool(&self) -> SdkResult<Vec<Transaction>> {
+        self.get_json("v1/mempool").await
+    }
+
+    pub async fn mempool_feerates(&self) -> SdkResult<MempoolFeerates> {
+        self.get_json("v1/mempool/feerates").await
+    }
+
+    pub async fn mempool_histogram(&self) -> SdkResult<MempoolHistogram> {
+        self.get_json("v1/mempool/histogram").await
+    }
+
+    pub async fn projected_block(&self) -> SdkResult<ProjectedBlock> {
+        self.get_json("v1/mempool/projected-block").await
+    }
+
+    pub async fn mempool_transaction(&self, txid: &str) -> SdkResult<MempoolTransaction> {
+        self.get_json(&format!("v1/mempool/tx/{}", txid)).await
+    }
+
+    pub async fn confirmation_times(&self) -> SdkResult<ConfirmationTimes> {
+        self.get_json("v1/fees/confirmation-times").await
+    }
+
+    // Fee rate for confirmation within `target` blocks, cached until new block
+    pub async fn fee_estimate(&self, target: u16, mode: FeeEstimateMode) -> SdkResult<FeeEstimate> {
+        let path = format!("v1/fee-estimate?target={}&mode={}", target, mode.as_str());
+        self.get_json(&path).await
+    }
+
+    pub async fn block(&self, id: BlockId) -> SdkResult<Block> {
+        self.get_json(&format!("v1/block/{}", id)).await
+    }
+
+    // Transactions of large block by pages, `page` from 1
+    pub async fn block_page(&self, id: BlockId, page: usize, limit: usize) -> SdkResult<BlockPage> {
+        let path = format!("v1/block/{}?page={}&limit={}", id, page, limit);
+        self.get_json(&path).await
+    }
+
+    pub async fn block_hex(&self, id: BlockId) -> SdkResult<String> {
+        let path = format!("v1/block/{}/hex", id);
+        let body = self.send(self.request(Method::GET, &path)?).await?;
+        Ok(body.trim().to_owned())
+    }
+
+    // Basic compact block filter (BIP158)
+    pub async fn block_filter(&self, id: BlockId) -> SdkResult<BlockFilter> {
+        self.get_json(&format!("v1/block/{}/filter", id)).await
+    }
+
+    // Broadcast raw transaction in hex, rejected transaction is status error
+    // with JSON of RPC error code and message
+    pub async fn broadcast_tx(&self, tx: &str) -> SdkResult<TxBroadcast> {
+        let req = self
+            .request(Method::POST, "v1/tx")?
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(tx.to_owned());
+        let body = self.send(req).await?;
+        serde_json::from_str(&body).map_err(SdkError::Json)
+    }
+
+    // Test raw transactions (several as package) without broadcast
+    pub async fn test_txs(&self, request: &TxTestRequest) -> SdkResult<Vec<TxTestResult>> {
+        self.send_json(Method::POST, "v1/tx/test", request).await
+    }
+
+    // Broadcast parent with children as package, parents first
+    pub async fn submit_package(&self, txs: &[String]) -> SdkResult<PackageSubmit> {
+        let request = PackageSubmitRequest { txs: txs.to_vec() };
+        self.send_json(Method::POST, "v1/txs/package", &request)
+            .await
+    }
+
+    pub async fn transaction(&self, txid: &str) -> SdkResult<TransactionInfo> {
+        self.get_json(&format!("v1/tx/{}", txid)).await
+    }
+
+    // Unspent output, `include_mempool` to treat mempool spends as spent
+    pub async fn outpoint(
+        &self,
+        txid: &str,
+        vout: u32,
+        include_mempool: bool,
+    ) -> SdkResult<OutpointInfo> {
+        let path = format!(
+            "v1/outpoint/{}:{}?include_mempool={}",
+            txid, vout, include_mempool
+        );
+        self.get_json(&path).await
+    }
+
+    // Spending transaction of output in tracked blocks or mempool
+    pub async fn outpoint_spend(&self, txid: &str, vout: u32) -> SdkResult<OutpointSpend> {
+        self.get_json(&format!("v1/outpoint/{}:{}/spend", txid, vout))
+            .await
+    }
+
+    // Transactions with outputs to address in tracked blocks
+    pub async fn address_transactions(&self, address: &str) -> SdkResult<AddressTransactions> {
+        self.get_json(&format!("v1/address/{}/transactions", address))
+            .await
+    }
+
+    pub async fn stats(&self) -> SdkResult<Stats> {
+        self.get_json("v1/stats").await
+    }
+
+    // Prometheus text format
+    pub async fn metrics(&self) -> SdkResult<String> {
+        self.send(self.request(Method::GET, "metrics")?).await
+    }
+
+    pub async fn block_sources(&self) -> SdkResult<BlockSources> {
+        self.get_json("v1/metrics/block-sources").await
+    }
+
+    pub async fn reorgs(&self) -> SdkResult<Reorgs> {
+        self.get_json("v1/reorgs").await
+    }
+
+    pub async fn nodes(&self) -> SdkResult<Nodes> {
+        self.get_json("v1/nodes").await
+    }
+
+    pub async fn halving(&self) -> SdkResult<Halving> {
+        self.get_json("v1/network/halving").await
+    }
+
+    pub async fn difficulty_adjustment(&self) -> SdkResult<DifficultyAdjustment> {
+        self.get_json("v1/network/difficulty-adjustment").await
+    }
+
+    // Peers list only with admin token
+    pub async fn peers(&self, detailed: bool) -> SdkResult<Peers> {
+        if detailed {
+            self.get_json("v1/node/peers?detailed=true").await
+        } else {
+            self.get_json("v1/node/peers").await
+        }
+    }
+
+    // JSON Schemas of API, see `GET /v1/schemas`
+    pub async fn schemas(&self) -> SdkResult<serde_json::Value> {
+        self.get_json("v1/schemas").await
+    }
+
+    pub async fn events_proto(&self) -> SdkResult<String> {
+        self.send(self.request(Method::GET, "v1/schemas/events.proto")?)
+            .await
+    }
+
+    // Admin token required
+    pub async fn blacklist(&self) -> SdkResult<Blacklist> {
+        self.get_json("v1/admin/blacklist").await
+    }
+
+    pub async fn add_to_blacklist(&self, entry: &BlacklistEntry) -> SdkResult<Blacklist> {
+        self.send_json(Method::POST, "v1/admin/blacklist", entry)
+            .await
+    }
+
+    pub async fn remove_from_blacklist(&self, entry: &BlacklistEntry) -> SdkResult<Blacklist> {
+        self.send_json(Method::DELETE, "v1/admin/blacklist", entry)
+            .await
+    }
+
+    pub async fn audit(&self, limit: Option<usize>) -> SdkResult<Vec<AuditRecord>> {
+        match limit {
+            Some(limit) => {
+                self.get_json(&format!("v1/admin/audit?limit={}", limit))
+                    .await
+            }
+            None => self.get_json("v1/admin/audit").await,
+        }
+    }
+
+    // Switch server to another bitcoind node
+    pub async fn switch_bitcoind(&self, url: &str) -> SdkResult<BitcoindSwitch> {
+        let endpoint = BitcoindEndpoint {
+            url: url.to_owned(),
+        };
+        self.send_json(Method::PUT, "v1/admin/bitcoind", &endpoint)
+            .await
+    }
+
+    pub async fn maintenance(&self) -> SdkResult<Maintenance> {
+        self.get_json("v1/admin/maintenance").await
+    }
+
+    pub async fn set_maintenance(&self, maintenance: &Maintenance) -> SdkResult<Maintenance> {
+        self.send_json(Method::PUT, "v1/admin/maintenance", maintenance)
+            .await
+    }
+
+    // Stop fetching from bitcoind (reindex, planned maintenance), API keeps
+    // serving cached data
+    pub async fn pause_updates(&self) -> SdkResult<UpdatesPause> {
+        let body = self
+            .send(self.request(Method::POST, "v1/admin/pause")?)
+            .await?;
+        serde_json::from_str(&body).map_err(SdkError::Json)
+    }
+
+    pub async fn resume_updates(&self) -> SdkResult<UpdatesPause> {
+        let body = self
+            .send(self.request(Method::POST, "v1/admin/resume")?)
+            .await?;
+        serde_json::from_str(&body).map_err(SdkError::Json)
+    }
+
+    pub async fn blocks_keep(&self) -> SdkResult<BlocksKeep> {
+        self.get_json("v1/admin/blocks-keep").await
+    }
+
+    // Change number of tracked blocks, response after missed blocks fetched
+    pub async fn set_blocks_keep(&self, blocks: usize) -> SdkResult<BlocksKeep> {
+        let keep = BlocksKeep { blocks, tracked: 0 };
+        self.send_json(Method::PUT, "v1/admin/blocks-keep", &keep)
+            .await
+    }
+
+    pub async fn chaos(&self) -> SdkResult<Chaos> {
+        self.get_json("v1/admin/chaos").await
+    }
+
+    // Fails with 501 if server is built without `chaos` feature
+    pub async fn set_chaos(&self, chaos: &Chaos) -> SdkResult<Chaos> {
+        self.send_json(Method::PUT, "v1/admin/chaos", chaos).await
+    }
+
+    pub async fn jobs(&self) -> SdkResult<Vec<JobStatus>> {
+        self.get_json("v1/admin/jobs").await
+    }
+
+    pub async fn bans(&self) -> SdkResult<Vec<Ban>> {
+        self.get_json("v1/admin/bans").await
+    }
+
+    pub async fn ban(&self, entry: &BanEntry) -> SdkResult<Vec<Ban>> {
+        self.send_json(Method::POST, "v1/admin/bans", entry).await
+    }
+
+    pub async fn unban(&self, entry: &BanEntry) -> SdkResult<Vec<Ban>> {
+        self.send_json(Method::DELETE, "v1/admin/bans", entry).await
+    }
+
+    pub async fn add_node(&self, entry: &NodeEntry) -> SdkResult<NodeEntry> {
+        self.send_json(Method::POST, "v1/admin/nodes", entry).await
+    }
+
+    pub async fn remove_node(&self, entry: &NodeEntry) -> SdkResult<NodeEntry> {
+        self.send_json(Method::DELETE, "v1/admin/nodes", entry)
+            .await
+    }
+
+    pub async fn disconnect_peer(&self, entry: &PeerEntry) -> SdkResult<PeerEntry> {
+        self.send_json(Method::DELETE, "v1/admin/peers", entry)
+            .await
+    }
+
+    // Tenant API key required
+    pub async fn watch(&self) -> SdkResult<Watch> {
+        self.get_json("v1/watch").await
+    }
+
+    pub async fn usage(&self) -> SdkResult<Usage> {
+        self.get_json("v1/usage").await
+    }
+
+    pub async fn add_watch(&self, script: &[u8]) -> SdkResult<Watch> {
+        let entry = WatchEntry {
+            script: script.to_vec(),
+        };
+        self.send_json(Method::POST, "v1/watch", &entry).await
+    }
+
+    pub async fn remove_watch(&self, script: &[u8]) -> SdkResult<Watch> {
+        let entry = WatchEntry {
+            script: script.to_vec(),
+        };
+        self.send_json(Method::DELETE, "v1/watch", &entry).await
+    }
+
+    pub async fn timelocks(&self) -> SdkResult<Timelocks> {
+        self.get_json("v1/watch/timelocks").await
+    }
+
+    pub async fn add_timelock(&self, entry: &TimelockEntry) -> SdkResult<Timelocks> {
+        self.send_json(Method::POST, "v1/watch/timelocks", entry)
+            .await
+    }
+
+    pub async fn remove_timelock(&self, txid: &str, vout: u32) -> SdkResult<Timelocks> {
+        let entry = TimelockEntry {
+            txid: txid.to_owned(),
+            vout,
+            locktime: None,
+            sequence: None,
+        };
+        self.send_json(Method::DELETE, "v1/watch/timelocks", &entry)
+            .await
+    }
+
+    pub async fn reactions(&self) -> SdkResult<Reactions> {
+        self.get_json("v1/watch/reactions").await
+    }
+
+    // Pre-signed `tx` broadcast by server when output is spent
+    pub async fn add_reaction(&self, entry: &ReactionEntry) -> SdkResult<Reactions> {
+        self.send_json(Method::POST, "v1/watch/reactions", entry)
+            .await
+    }
+
+    pub async fn remove_reaction(&self, txid: &str, vout: u32) -> SdkResult<Reactions> {
+        let entry = ReactionEntry {
+            txid: txid.to_owned(),
+            vout,
+            tx: String::new(),
+            dry_run: false,
+        };
+        self.send_json(Method::DELETE, "v1/watch/reactions", &entry)
+            .await
+    }
+
+    // Events from `/v1/ws`, with token tenant also receive own watch events.
+    // Only events of `topics` sent if set (`blocks`, `mempool`, `plugins`,
+    // `alerts`)
+    pub async fn subscribe(&self, raw: bool, topics: Option<&[&str]>) -> SdkResult<EventStream> {
+        let mut url = self.url.join("v1/ws").map_err(SdkError::InvalidUrl)?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme).unwrap();
+        if raw {
+            url.set_query(Some("raw=true"));
+        }
+
+        subscribe_url(url, self.token.as_deref(), topics).await
+    }
+
+    // Same as `subscribe`, with missed block events after `last` block
+    pub async fn subscribe_from(
+        &self,
+        raw: bool,
+        topics: Option<&[&str]>,
+        last: &ResumeBlock,
+    ) -> SdkResult<EventStream> {
+        let mut url = self.url.join("v1/ws").map_err(SdkError::InvalidUrl)?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme).unwrap();
+        if raw {
+            url.set_query(Some("raw=true"));
+        }
+
+        subscribe_url_from(url, self.token.as_deref(), topics, Some(last)).await
+    }
+
+    fn request(&self, method: Method, path: &str) -> SdkResult<RequestBuilder> {
+        let url = self.url.join(path).map_err(SdkError::InvalidUrl)?;
+        let mut req = self
+            .client
+            .request(method, url)
+            .header(HEADER_ACCEPT_VERSION, API_VERSION);
+        if let Some(token) = &self.token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        Ok(req)
+    }
+
+    // Response body, non-success status converted to error
+    async fn send(&self, req: RequestBuilder) -> SdkResult<String> {
+        let res = req.send().await.map_err(SdkError::Reqwest)?;
+        let status = res.status();
+        let body = res.text().await.map_err(SdkError::Reqwest)?;
+        if status != StatusCode::OK {
+            // Handler errors are `ErrorResponse`, other bodies kept as is
+            let msg = serde_json::from_str::<ErrorResponse>(&body)
+                .map(|err| err.message)
+                .unwrap_or(body);
+            return Err(SdkError::Status(status.as_u16(), msg));
+        }
+        Ok(body)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> SdkResult<T> {
+        let body = self.send(self.request(Method::GET, path)?).await?;
+        serde_json::from_str(&body).map_err(SdkError::Json)
+    }
+
+    async fn send_json<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        data: &B,
+    ) -> SdkResult<T> {
+        let data = serde_json::to_vec(data).map_err(SdkError::Json)?;
+        let req = self
+            .request(method, path)?
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(data);
+        let body = self.send(req).await?;
+        serde_json::from_str(&body).map_err(SdkError::Json)
+    }
+}
+
+// Subscribe with full WebSocket URL, for custom query parameters
+pub async fn subscribe_url(
+    url: Url,
+    token: Option<&str>,
+    topics: Option<&[&str]>,
+) -> SdkResult<EventStream> {
+    subscribe_url_from(url, token, topics, None).await
+}
+
+// Subscribe after reconnect, `Added block` events after `last` block are
+// received again before new events, some of them can be duplicated
+pub async fn subscribe_url_from(
+    url: Url,
+    token: Option<&str>,
+    topics: Option<&[&str]>,
+    last: Option<&ResumeBlock>,
+) -> SdkResult<EventStream> {
+    let mut req = url.into_client_request().map_err(SdkError::Tungstenite)?;
+    if let Some(token) = token {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| SdkError::InvalidToken)?;
+        req.headers_mut().insert(header::AUTHORIZATION, value);
+    }
+
+    let (mut ws_stream, resp) = connect_async(req).await.map_err(SdkError::Tungstenite)?;
+    if resp.status() != StatusCode::SWITCHING_PROTOCOLS {
+        let status = resp.status().as_u16();
+        return Err(SdkError::Status(
+            status,
+            "WebSocket upgrade failed".to_owned(),
+        ));
+    }
+
+    if let Some(topics) = topics {
+        let command = WsSubscribe {
+            subscribe: topics.iter().map(|topic| (*topic).to_owned()).collect(),
+            large_value_min: None,
+        };
+        if let WsReply::Error { error } = send_command(&mut ws_stream, &command).await? {
+            return Err(SdkError::Subscribe(error));
+        }
+    }
+
+    if let Some(last) = last {
+        let command = WsResume {
+            resume: last.clone(),
+        };
+        if let WsReply::Error { error } = send_command(&mut ws_stream, &command).await? {
+            return Err(SdkError::Resume(error));
+        }
+    }
+
+    // Only text events returned, control frames handled by tungstenite
+    let stream = ws_stream.filter_map(|message| {
+        future::ready(match message {
+            Ok(Message::Text(text)) => Some(Ok(text)),
+            Ok(_) => None,
+            Err(err) => Some(Err(SdkError::Tungstenite(err))),
+        })
+    });
+    Ok(stream.boxed())
+}
+
+// Command sent to WebSocket and answer on it, events before answer sent
+// without filter, so skipped
+async fn send_command<S, C>(ws_stream: &mut S, command: &C) -> SdkResult<WsReply>
+where
+    S: Stream<Item = Result<Message, TungsteniteError>>
+        + Sink<Message, Error = TungsteniteError>
+        + Unpin,
+    C: Serialize,
+{
+    let command = serde_json::to_string(command).map_err(SdkError::Json)?;
+    ws_stream
+        .send(Message::text(command))
+        .await
+        .map_err(SdkError::Tungstenite)?;
+
+    loop {
+        let message = match ws_stream.next().await {
+            Some(message) => message.map_err(SdkError::Tungstenite)?,
+            None => return Err(SdkError::Subscribe("connection closed".to_owned())),
+        };
+        if let Message::Text(text) = message {
+            if let Ok(reply) = serde_json::from_str::<WsReply>(&text) {
+                return Ok(reply);
+            }
+        }
+    }
+}
+
+// Block to resume from after event, text (`Added block: hash`) or JSON
+// (`format=json`) form. Removed block in JSON form resumes from its height,
+// removed block in text form has no height, so skipped
+pub fn resume_block(event: &str) -> Option<ResumeBlock> {
+    if let Some(hash) = event.strip_prefix("Added block: ") {
+        return Some(ResumeBlock {
+            hash: hash.to_owned(),
+            height: None,
+        });
+    }
+
+    let event = serde_json::from_str::<serde_json::Value>(event).ok()?;
+    match event.get("type")?.as_str()? {
+        "block_added" | "block_removed" => Some(ResumeBlock {
+            hash: event.get("hash")?.as_str()?.to_owned(),
+            height: Some(event.get("height")?.as_u64()? as u32),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+
+    // Path with query, `Accept-Version` and `Authorization` of every request
+    type Requests = Arc<Mutex<Vec<(String, String, Option<String>)>>>;
+
+    fn respond(path: &str) -> Response<Body> {
+        let (status, body) = match path {
+            "/version" => {
+                let version = Version {
+                    version: "0.1.0".to_owned(),
+                    git_commit: "abcdef".to_owned(),
+                    api_versions: vec!["1".to_owned()],
+                    events_schema_version: 1,
+                };
+                (200, serde_json::to_string(&version).unwrap())
+            }
+            "/api/v1/block/tip/hex" => (200, "00ff\n".to_owned()),
+            "/api/v1/stats" => {
+                let error = ErrorResponse {
+                    error: "Service Unavailable".to_owned(),
+                    message: "Maintenance".to_owned(),
+                };
+                (503, serde_json::to_string(&error).unwrap())
+            }
+            _ => (502, "Bad Gateway".to_owned()),
+        };
+        let mut res = Response::new(Body::from(body));
+        *res.status_mut() = StatusCode::from_u16(status).unwrap();
+        res
+    }
+
+    fn serve() -> (String, Requests) {
+        let requests = Requests::default();
+        let make_svc = {
+            let requests = requests.clone();
+            make_service_fn(move |_| {
+                let requests = requests.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let header = |name| {
+                            let value = req.headers().get(name);
+                            value.map(|value| value.to_str().unwrap().to_owned())
+                        };
+                        let path = req.uri().path_and_query().unwrap().to_string();
+                        let version = header(HEADER_ACCEPT_VERSION).unwrap_or_default();
+                        let auth = header(header::AUTHORIZATION.as_str());
+                        requests.lock().unwrap().push((path, version, auth));
+                        future::ok::<_, Infallible>(respond(req.uri().path()))
+                    }))
+                }
+            })
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server = Server::bind(&addr).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, requests)
+    }
+
+    #[test]
+    fn client_url() {
+        let client = Client::new("http://localhost:8000").unwrap();
+        assert_eq!(client.url.as_str(), "http://localhost:8000/");
+        let client = Client::new("https://example.com/api").unwrap();
+        assert_eq!(client.url.as_str(), "https://example.com/api/");
+
+        assert!(matches!(
+            Client::new("ws://localhost:8000"),
+            Err(SdkError::InvalidUrlScheme(scheme)) if scheme == "ws"
+        ));
+        assert!(matches!(
+            Client::new("localhost"),
+            Err(SdkError::InvalidUrl(_))
+        ));
+
+        assert_eq!(BlockId::Tip.to_string(), "tip");
+        assert_eq!(BlockId::Height(5).to_string(), "5");
+        assert_eq!(BlockId::Hash("00ab".to_owned()).to_string(), "00ab");
+    }
+
+    #[tokio::test]
+    async fn requests() {
+        let (url, requests) = serve();
+        let client = Client::new(&url).unwrap();
+        assert_eq!(client.version().await.unwrap().git_commit, "abcdef");
+
+        // Relative paths joined to base path, token sent with every request
+        let client = Client::new(&format!("{}/api", url))
+            .unwrap()
+            .with_token("secret");
+        assert_eq!(client.block_hex(BlockId::Tip).await.unwrap(), "00ff");
+        match client.stats().await {
+            Err(SdkError::Status(503, msg)) => assert_eq!(msg, "Maintenance"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        match client.fee_estimate(6, FeeEstimateMode::Economical).await {
+            Err(SdkError::Status(502, msg)) => assert_eq!(msg, "Bad Gateway"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let bearer = Some("Bearer secret".to_owned());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                ("/version".to_owned(), "1".to_owned(), None),
+                (
+                    "/api/v1/block/tip/hex".to_owned(),
+                    "1".to_owned(),
+                    bearer.clone()
+                ),
+                ("/api/v1/stats".to_owned(), "1".to_owned(), bearer.clone()),
+                (
+                    "/api/v1/fee-estimate?target=6&mode=economical".to_owned(),
+                    "1".to_owned(),
+                    bearer
+                ),
+            ]
+        );
+    }
+
+    // WebSocket server which sends event before answer on subscribe, answer
+    // is error for unknown topic
+    async fn serve_ws() -> Url {
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/v1/ws", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                let command = match ws.next().await {
+                    Some(Ok(Message::Text(text))) => text,
+                    _ => continue,
+                };
+                let command = serde_json::from_str::<WsSubscribe>(&command).unwrap();
+                let reply = match command.subscribe.iter().find(|topic| *topic == "txs") {
+                    Some(topic) => WsReply::Error {
+                        error: format!("Unknown topic: {}", topic),
+                    },
+                    None => WsReply::Subscribed {
+                        subscribed: command.subscribe,
+                    },
+                };
+                let reply = serde_json::to_string(&reply).unwrap();
+                ws.send(Message::text("Added tx: a")).await.unwrap();
+                ws.send(Message::text(reply)).await.unwrap();
+                ws.send(Message::text("Added block: b")).await.unwrap();
+            }
+        });
+        Url::parse(&url).unwrap()
+    }
+
+    #[tokio::test]
+    async fn subscribe_topics() {
+        let url = serve_ws().await;
+
+        // Events before answer are not filtered, so skipped
+        let mut events = subscribe_url(url.clone(), None, Some(&["blocks"]))
+            .await
+            .unwrap();
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event, "Added block: b");
+
+        match subscribe_url(url, None, Some(&["blocks", "txs"])).await {
+            Err(SdkError::Subscribe(msg)) => assert_eq!(msg, "Unknown topic: txs"),
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+    }
+}
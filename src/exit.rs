@@ -0,0 +1,197 @@
+// Errors of subcommands reported on exit: logged as text by default, or one
+// JSON object on stderr with `--error-format json`. Exit code depends on error
+// class in both formats, so scripts can decide to retry or fix config.
+
+use std::fmt;
+use std::str::FromStr;
+
+use log::error;
+use serde_json::json;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorClass {
+    // Invalid arguments, files or credentials, fixed by user
+    Config,
+    // Connection to bitcoind, server or other service failed, can be retried
+    Network,
+    // bitcoind (or server for client) responded with error or invalid data
+    Node,
+    Internal,
+}
+
+impl ErrorClass {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorClass::Internal => 1,
+            ErrorClass::Config => 2,
+            ErrorClass::Network => 3,
+            ErrorClass::Node => 4,
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorClass::Config => "config",
+            ErrorClass::Network => "network",
+            ErrorClass::Node => "node",
+            ErrorClass::Internal => "internal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ErrorFormat {
+    // Format from raw arguments, for errors of arguments parsing
+    pub fn from_raw_args(args: &[String]) -> Self {
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let value = match arg.strip_prefix("--error-format") {
+                Some("") => iter.next().map(|value| value.as_str()),
+                Some(value) => value.strip_prefix('='),
+                None => None,
+            };
+            if let Some(format) = value.and_then(|value| value.parse().ok()) {
+                return format;
+            }
+        }
+        ErrorFormat::Text
+    }
+}
+
+// Stable `code` for scripts, `message` for humans and optional `hint` how to
+// fix it
+#[derive(Debug)]
+pub struct CliError {
+    pub class: ErrorClass,
+    pub code: &'static str,
+    pub message: String,
+    pub hint: Option<&'static str>,
+}
+
+impl CliError {
+    pub fn new<T: ToString>(class: ErrorClass, code: &'static str, message: T) -> Self {
+        CliError {
+            class,
+            code,
+            message: message.to_string(),
+            hint: None,
+        }
+    }
+
+    pub fn with_hint(mut self, hint: &'static str) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "class": self.class.to_string(),
+            "code": self.code,
+            "message": self.message,
+            "hint": self.hint,
+        })
+    }
+
+    // Print error in requested format and return exit code
+    pub fn report(&self, format: ErrorFormat) -> i32 {
+        match format {
+            ErrorFormat::Text => error!("{}", self.message),
+            ErrorFormat::Json => eprintln!("{}", self.to_json()),
+        }
+        self.class.exit_code()
+    }
+}
+
+// Arguments not matched by `clap`, help and version printed as is
+pub fn clap_error(err: clap::Error, format: ErrorFormat) -> i32 {
+    if !err.use_stderr() {
+        err.exit();
+    }
+
+    let text = err.to_string();
+    match format {
+        ErrorFormat::Text => eprint!("{}", text),
+        ErrorFormat::Json => {
+            // First line without `error: ` prefix, usage is for humans
+            let message = text.lines().next().unwrap_or_default();
+            let message = message.strip_prefix("error: ").unwrap_or(message);
+            let error = CliError::new(ErrorClass::Config, "invalid_arguments", message)
+                .with_hint("Run with --help for usage");
+            eprintln!("{}", error.to_json());
+        }
+    }
+    ErrorClass::Config.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(ErrorClass::Internal.exit_code(), 1);
+        assert_eq!(ErrorClass::Config.exit_code(), 2);
+        assert_eq!(ErrorClass::Network.exit_code(), 3);
+        assert_eq!(ErrorClass::Node.exit_code(), 4);
+    }
+
+    #[test]
+    fn error_format_from_raw_args() {
+        let format = ErrorFormat::from_raw_args(&args(&["server", "--error-format", "json"]));
+        assert_eq!(format, ErrorFormat::Json);
+        let format = ErrorFormat::from_raw_args(&args(&["--error-format=json", "server"]));
+        assert_eq!(format, ErrorFormat::Json);
+        let format = ErrorFormat::from_raw_args(&args(&["--error-format=text"]));
+        assert_eq!(format, ErrorFormat::Text);
+
+        // Text if value is missing or invalid
+        for raw in &[
+            &["server"][..],
+            &["server", "--error-format"],
+            &["--error-format", "xml"],
+            &["--error-formats=json"],
+        ] {
+            assert_eq!(ErrorFormat::from_raw_args(&args(raw)), ErrorFormat::Text);
+        }
+    }
+
+    #[test]
+    fn cli_error_json() {
+        let error = CliError::new(ErrorClass::Network, "unreachable", "connection refused");
+        assert_eq!(
+            error.to_json(),
+            json!({
+                "class": "network",
+                "code": "unreachable",
+                "message": "connection refused",
+                "hint": null,
+            })
+        );
+        let error = error.with_hint("Retry later");
+        assert_eq!(error.to_json()["hint"], "Retry later");
+    }
+}
@@ -0,0 +1,374 @@
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+use futures::stream::StreamExt as _;
+use log::info;
+use lru::LruCache;
+
+use super::error::{AppError, AppResult};
+use crate::cli::BackfillArgs;
+use crate::server::bitcoind::json::ResponseBlock;
+use crate::server::bitcoind::Bitcoind;
+use crate::server::pipeline::Pipeline;
+use crate::server::storage::{
+    self, Storage, StoredBlock, META_SCRIPTS_NEXT, TREE_BLOCKS, TREE_META, TREE_SCRIPTS,
+    TREE_SCRIPT_IDS, TREE_SCRIPT_TXS,
+};
+use crate::signals::{self, ShutdownReceiver};
+
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(30);
+// Recently used script ids kept in memory, other looked up in storage
+const SCRIPTS_CACHE_SIZE: usize = 100_000;
+
+// Fetch blocks in range `[from; to]` to storage, progress saved after every block,
+// so interrupted backfill continue from last saved block on next run with same
+// start, end of range can be different (by default it is current tip)
+pub async fn run(args: &BackfillArgs) -> AppResult<()> {
+    // Subscribe on shutdown signals
+    let shutdown = signals::subscribe();
+
+    // Create and validate bitcoind
+    let bitcoind = Bitcoind::new(args.bitcoind.as_str()).map_err(AppError::Bitcoind)?;
+    bitcoind.validate().await.map_err(AppError::Bitcoind)?;
+
+    // Open storage
+    let storage = storage::open(&args.storage).map_err(AppError::Storage)?;
+
+    let analyze_jobs = args
+        .analyze_jobs
+        .unwrap_or_else(Pipeline::default_analyze_jobs);
+    let pipeline = Pipeline::new(args.jobs, analyze_jobs);
+    let (from, to) = (args.from, args.to);
+    backfill(&bitcoind, storage.as_ref(), &pipeline, from, to, shutdown).await?;
+
+    storage.flush().map_err(AppError::Storage)
+}
+
+async fn backfill(
+    bitcoind: &Bitcoind,
+    storage: &dyn Storage,
+    pipeline: &Pipeline,
+    from: u32,
+    to: Option<u32>,
+    mut shutdown: ShutdownReceiver,
+) -> AppResult<()> {
+    // Range, by default up to current tip
+    let to = match to {
+        Some(to) => to,
+        None => {
+            let info = bitcoind.getblockchaininfo().await;
+            info.map_err(AppError::Bitcoind)?.blocks
+        }
+    };
+    if from > to {
+        return Err(AppError::InvalidRange(from, to));
+    }
+
+    // Continue from checkpoint if we already worked from this height
+    let checkpoint_key = format!("backfill:{}", from);
+    let checkpoint = storage::get_json::<u32>(storage, TREE_META, checkpoint_key.as_bytes());
+    let start = match checkpoint.map_err(AppError::Storage)? {
+        Some(height) if height >= to => {
+            info!("Backfill {}..{} already finished", from, to);
+            return Ok(());
+        }
+        Some(height) => height + 1,
+        None => from,
+    };
+
+    info!("Backfill {}..{} from {}", from, to, start);
+    let mut indexer = Indexer::new(storage, checkpoint_key, start)?;
+
+    // Blocks fetched and analyzed concurrently, but committed sequentially
+    let mut blocks = pipeline.blocks(bitcoind, start..=to, analyze_block);
+    loop {
+        let block = tokio::select! {
+            block = blocks.next() => block,
+            _ = shutdown.recv() => break,
+        };
+
+        match block {
+            Some(block) => indexer.commit_block(block?, to)?,
+            None => break,
+        }
+    }
+
+    info!(
+        "Backfill scripts: {}, new: {}, cache hits: {}, misses: {}",
+        indexer.scripts_next, indexer.scripts_new, indexer.scripts_hits, indexer.scripts_misses
+    );
+
+    Ok(())
+}
+
+// Block data extracted out of runtime thread, before commit
+struct AnalyzedBlock {
+    block: StoredBlock,
+    // Output scripts with transaction position in block
+    scripts: Vec<(u32, Vec<u8>)>,
+}
+
+fn analyze_block(block: ResponseBlock) -> AnalyzedBlock {
+    let mut scripts = vec![];
+    for (pos, tx) in block.transactions.iter().enumerate() {
+        for output in tx.vout.iter() {
+            scripts.push((pos as u32, output.script_pubkey.hex.clone()));
+        }
+    }
+
+    AnalyzedBlock {
+        block: StoredBlock::from(&block),
+        scripts,
+    }
+}
+
+// Save blocks and index transactions by output scripts, scripts are interned,
+// so index keep only 4-byte id instead of full script for every entry. Ids are
+// never released, table is in storage with cache of recently used scripts
+struct Indexer<'a> {
+    storage: &'a dyn Storage,
+    checkpoint_key: String,
+    scripts: LruCache<Vec<u8>, u32>,
+    scripts_next: u32,
+    scripts_new: u32,
+    scripts_hits: u64,
+    scripts_misses: u64,
+    last_log: Instant,
+    last_log_height: u32,
+}
+
+impl<'a> Indexer<'a> {
+    fn new(storage: &'a dyn Storage, checkpoint_key: String, start: u32) -> AppResult<Self> {
+        let next = storage::get_json::<u32>(storage, TREE_META, META_SCRIPTS_NEXT.as_bytes());
+        let scripts_next = match next.map_err(AppError::Storage)? {
+            Some(next) => next,
+            None => Self::index_script_ids(storage)?,
+        };
+
+        let cache_size = NonZeroUsize::new(SCRIPTS_CACHE_SIZE).unwrap();
+        Ok(Indexer {
+            storage,
+            checkpoint_key,
+            scripts: LruCache::new(cache_size),
+            scripts_next,
+            scripts_new: 0,
+            scripts_hits: 0,
+            scripts_misses: 0,
+            last_log: Instant::now(),
+            last_log_height: start,
+        })
+    }
+
+    fn commit_block(&mut self, analyzed: AnalyzedBlock, to: u32) -> AppResult<()> {
+        let block = analyzed.block;
+        let height = block.height.to_be_bytes();
+        for (pos, script) in analyzed.scripts.iter() {
+            let id = self.intern_script(script)?.to_be_bytes();
+            let key = [&id[..], &height[..], &pos.to_be_bytes()[..]].concat();
+            let txid = &block.transactions[*pos as usize];
+            self.insert(TREE_SCRIPT_TXS, &key, txid.as_bytes())?;
+        }
+
+        storage::insert_json(self.storage, TREE_BLOCKS, &height, &block)
+            .map_err(AppError::Storage)?;
+        storage::insert_json(
+            self.storage,
+            TREE_META,
+            self.checkpoint_key.as_bytes(),
+            &block.height,
+        )
+        .map_err(AppError::Storage)?;
+
+        let elapsed = self.last_log.elapsed();
+        if elapsed > PROGRESS_LOG_INTERVAL || block.height == to {
+            let elapsed = elapsed.as_secs_f64();
+            let rate = f64::from(block.height + 1 - self.last_log_height) / elapsed;
+            info!(
+                "Backfill progress: {} / {} ({:.2} blocks/s)",
+                block.height, to, rate
+            );
+            self.last_log = Instant::now();
+            self.last_log_height = block.height + 1;
+        }
+
+        Ok(())
+    }
+
+    // Ids by script for index without them (before `TREE_SCRIPT_IDS`), ids
+    // are sequential. Return next id
+    fn index_script_ids(storage: &dyn Storage) -> AppResult<u32> {
+        let mut next = 0u32;
+        for (key, script) in storage.scan(TREE_SCRIPTS).map_err(AppError::Storage)? {
+            if key != next.to_be_bytes() {
+                return Err(AppError::CorruptedIndex);
+            }
+            storage
+                .insert(TREE_SCRIPT_IDS, &script, &key)
+                .map_err(AppError::Storage)?;
+            next += 1;
+        }
+        storage::insert_json(storage, TREE_META, META_SCRIPTS_NEXT.as_bytes(), &next)
+            .map_err(AppError::Storage)?;
+        Ok(next)
+    }
+
+    fn intern_script(&mut self, script: &[u8]) -> AppResult<u32> {
+        if let Some(id) = self.scripts.get(script) {
+            self.scripts_hits += 1;
+            return Ok(*id);
+        }
+
+        self.scripts_misses += 1;
+        let stored = self.storage.get(TREE_SCRIPT_IDS, script);
+        let id = match stored.map_err(AppError::Storage)? {
+            Some(id) if id.len() == 4 => u32::from_be_bytes([id[0], id[1], id[2], id[3]]),
+            Some(_) => return Err(AppError::CorruptedIndex),
+            None => {
+                // Next id saved first, so id is not reused if we fail before
+                // script is saved
+                let id = self.scripts_next;
+                self.scripts_next += 1;
+                self.scripts_new += 1;
+                let next = &self.scripts_next;
+                storage::insert_json(self.storage, TREE_META, META_SCRIPTS_NEXT.as_bytes(), next)
+                    .map_err(AppError::Storage)?;
+                self.insert(TREE_SCRIPTS, &id.to_be_bytes(), script)?;
+                self.insert(TREE_SCRIPT_IDS, script, &id.to_be_bytes())?;
+                id
+            }
+        };
+        self.scripts.put(script.to_vec(), id);
+        Ok(id)
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> AppResult<()> {
+        self.storage
+            .insert(tree, key, value)
+            .map_err(AppError::Storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::server::bitcoind::mock::MockBitcoind;
+    use crate::server::bitcoind::RestFormat;
+    use crate::server::storage::StorageResult;
+
+    // Memory storage which remembers heights of every stored block
+    #[derive(Debug)]
+    struct RecordingStorage {
+        storage: Box<dyn Storage>,
+        heights: Mutex<Vec<u32>>,
+    }
+
+    impl Storage for RecordingStorage {
+        fn get(&self, tree: &str, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+            self.storage.get(tree, key)
+        }
+
+        fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> StorageResult<()> {
+            if tree == TREE_BLOCKS {
+                let mut height = [0; 4];
+                height.copy_from_slice(key);
+                self.heights
+                    .lock()
+                    .unwrap()
+                    .push(u32::from_be_bytes(height));
+            }
+            self.storage.insert(tree, key, value)
+        }
+
+        fn remove(&self, tree: &str, key: &[u8]) -> StorageResult<()> {
+            self.storage.remove(tree, key)
+        }
+
+        fn scan(&self, tree: &str) -> StorageResult<Vec<(Vec<u8>, Vec<u8>)>> {
+            self.storage.scan(tree)
+        }
+
+        fn flush(&self) -> StorageResult<()> {
+            self.storage.flush()
+        }
+    }
+
+    #[test]
+    fn script_ids() {
+        let storage = storage::open("memory").unwrap();
+        let storage = storage.as_ref();
+        storage
+            .insert(TREE_SCRIPTS, &0u32.to_be_bytes(), b"a")
+            .unwrap();
+        storage
+            .insert(TREE_SCRIPTS, &1u32.to_be_bytes(), b"b")
+            .unwrap();
+
+        // Ids by script added to index without them
+        let mut indexer = Indexer::new(storage, "key".to_owned(), 0).unwrap();
+        assert_eq!(indexer.intern_script(b"b").unwrap(), 1);
+        assert_eq!(indexer.intern_script(b"b").unwrap(), 1);
+        assert_eq!(indexer.intern_script(b"c").unwrap(), 2);
+        assert_eq!((indexer.scripts_hits, indexer.scripts_misses), (1, 2));
+        assert_eq!(indexer.scripts_new, 1);
+
+        // Not cached scripts found in storage
+        let mut indexer = Indexer::new(storage, "key".to_owned(), 0).unwrap();
+        assert_eq!(indexer.intern_script(b"c").unwrap(), 2);
+        assert_eq!(indexer.intern_script(b"a").unwrap(), 0);
+        assert_eq!(indexer.intern_script(b"d").unwrap(), 3);
+        let scripts = storage.scan(TREE_SCRIPTS).unwrap();
+        assert_eq!(scripts.last().unwrap().1, b"d");
+
+        let storage = storage::open("memory").unwrap();
+        storage
+            .insert(TREE_SCRIPTS, &1u32.to_be_bytes(), b"b")
+            .unwrap();
+        match Indexer::new(storage.as_ref(), "key".to_owned(), 0) {
+            Err(AppError::CorruptedIndex) => {}
+            _ => panic!("gap in script ids not found"),
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupt_and_resume() {
+        let node = MockBitcoind::start(40);
+        let bitcoind = Bitcoind::new(&node.url).unwrap();
+        let bitcoind = bitcoind.with_rest_formats(&[RestFormat::Json]);
+        let storage = RecordingStorage {
+            storage: storage::open("memory").unwrap(),
+            heights: Mutex::new(vec![]),
+        };
+        let pipeline = Pipeline::new(2, 2);
+
+        // Shutdown in the middle of range up to tip
+        let shutdown = ShutdownReceiver::new();
+        let mut notifier = shutdown.clone();
+        node.with(|node| node.on_fetch(10, move |_| notifier.set()));
+        backfill(&bitcoind, &storage, &pipeline, 5, None, shutdown)
+            .await
+            .unwrap();
+        let interrupted = storage.heights.lock().unwrap().len();
+        assert!((1..35).contains(&interrupted), "{}", interrupted);
+
+        // Tip changed, so range is different, but start is same
+        node.with(|node| {
+            for _ in 0..5 {
+                node.mine();
+            }
+        });
+        let shutdown = ShutdownReceiver::new();
+        backfill(&bitcoind, &storage, &pipeline, 5, None, shutdown)
+            .await
+            .unwrap();
+        let heights = storage.heights.lock().unwrap().clone();
+        assert_eq!(heights, (5..45).collect::<Vec<_>>());
+
+        let shutdown = ShutdownReceiver::new();
+        backfill(&bitcoind, &storage, &pipeline, 5, Some(30), shutdown)
+            .await
+            .unwrap();
+        assert_eq!(storage.heights.lock().unwrap().len(), 40);
+    }
+}
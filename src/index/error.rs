@@ -0,0 +1,80 @@
+use crate::exit::{CliError, ErrorClass};
+use crate::server::bitcoind::BitcoindError;
+use crate::server::pipeline::PipelineError;
+use crate::server::storage::StorageError;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum AppError {
+        Bitcoind(err: BitcoindError) {
+            display("bitcoind: {}", err)
+        }
+        Storage(err: StorageError) {
+            display("storage: {}", err)
+        }
+        InvalidRange(from: u32, to: u32) {
+            display("Invalid range: from ({}) is greater than to ({})", from, to)
+        }
+        Pipeline(err: PipelineError) {
+            from()
+            display("{}", err)
+        }
+        CorruptedIndex {
+            display("Stored scripts index is corrupted")
+        }
+    }
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn to_cli_error(&self) -> CliError {
+        match self {
+            AppError::Bitcoind(err) => err.to_cli_error("bitcoind: "),
+            AppError::Pipeline(PipelineError::Bitcoind(err)) => err.to_cli_error("bitcoind: "),
+            AppError::Pipeline(PipelineError::BlockNotFound(_)) => {
+                CliError::new(ErrorClass::Node, "block_not_found", self)
+            }
+            AppError::Pipeline(PipelineError::Task(_)) => {
+                CliError::new(ErrorClass::Internal, "task", self)
+            }
+            AppError::Storage(_) => CliError::new(ErrorClass::Internal, "storage", self)
+                .with_hint("Check --storage path permissions and free space"),
+            AppError::InvalidRange(..) => {
+                CliError::new(ErrorClass::Config, "invalid_argument", self)
+                    .with_hint("Run with --help for expected values")
+            }
+            AppError::CorruptedIndex => {
+                CliError::new(ErrorClass::Internal, "corrupted_index", self)
+                    .with_hint("Run backfill again with empty --storage")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_error_classes() {
+        let error = AppError::InvalidRange(10, 5).to_cli_error();
+        assert_eq!(
+            (error.class, error.code),
+            (ErrorClass::Config, "invalid_argument")
+        );
+
+        let error = AppError::CorruptedIndex.to_cli_error();
+        assert_eq!(error.class, ErrorClass::Internal);
+        assert!(error.hint.is_some());
+
+        let error = AppError::Pipeline(PipelineError::BlockNotFound(5)).to_cli_error();
+        assert_eq!(
+            (error.class, error.code),
+            (ErrorClass::Node, "block_not_found")
+        );
+
+        let error = AppError::Bitcoind(BitcoindError::ResultNotFound).to_cli_error();
+        assert_eq!(error.message, "bitcoind: Requested object not found");
+    }
+}
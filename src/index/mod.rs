@@ -0,0 +1,33 @@
+use self::error::AppResult;
+use crate::cli::IndexCommand;
+use crate::exit::ErrorFormat;
+use crate::logger;
+
+mod backfill;
+mod error;
+
+// Initialize logging and execute indexer subcommand
+pub fn main(command: &IndexCommand, format: ErrorFormat) -> i32 {
+    logger::init();
+
+    // Create runtime and run app
+    let app_result = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("error on building runtime")
+        .block_on(run(command));
+
+    if let Some(error) = app_result.err() {
+        return error.to_cli_error().report(format);
+    }
+
+    0
+}
+
+async fn run(command: &IndexCommand) -> AppResult<()> {
+    match command {
+        IndexCommand::Backfill(args) => backfill::run(args).await,
+    }
+}
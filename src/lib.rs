@@ -0,0 +1,15 @@
+// `json!` with all API responses in `server::schemas`
+#![recursion_limit = "256"]
+
+#[macro_use]
+extern crate quick_error;
+
+pub mod cli;
+pub mod client;
+pub mod index;
+pub mod server;
+
+pub mod exit;
+pub mod logger;
+pub mod signals;
+pub mod units;
@@ -1,24 +1,32 @@
-#[macro_use]
-extern crate quick_error;
+use clap::Parser as _;
 
-mod client;
-mod server;
-
-mod logger;
-mod signals;
+use bitcoin_rust_learning::cli::{Cli, Command};
+use bitcoin_rust_learning::exit::{self, ErrorFormat};
+use bitcoin_rust_learning::{cli, client, index, server};
 
 // Parse CLI arguments and run specified subcommand
 fn main() {
-    let cli_yaml = clap::load_yaml!("./cli.yaml");
-    let args = clap::App::from_yaml(cli_yaml)
-        .name(include_str!("./cli.yaml.name").trim())
-        .version(include_str!("./cli.yaml.version").trim())
-        .get_matches();
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            let raw = std::env::args().collect::<Vec<_>>();
+            std::process::exit(exit::clap_error(err, ErrorFormat::from_raw_args(&raw)));
+        }
+    };
 
-    let code = match args.subcommand() {
-        ("client", Some(args)) => client::main(args),
-        ("server", Some(args)) => server::main(args),
-        _ => 1, // not possible, but we need to cover this arm
+    let format = cli.error_format;
+    let code = match cli.command {
+        Command::Client(args) => client::main(&args, format),
+        Command::Index(command) => index::main(&command, format),
+        Command::Server(args) => server::main(&args, format),
+        Command::Completions { shell } => {
+            print!("{}", cli::completions(shell));
+            0
+        }
+        Command::Man => {
+            print!("{}", cli::man_page());
+            0
+        }
     };
 
     std::process::exit(code);
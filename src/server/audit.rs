@@ -0,0 +1,125 @@
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::storage::{self, Storage, StorageError, StorageResult, TREE_AUDIT};
+
+// Who did what and with which result, stored once and never modified
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuditRecord {
+    pub seq: u64,
+    // Unix time in milliseconds
+    pub time: u64,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub result: String,
+}
+
+// Append-only log, records keyed by big-endian sequence number
+#[derive(Debug)]
+pub struct AuditLog {
+    next_seq: Mutex<u64>,
+}
+
+impl AuditLog {
+    pub fn load(storage: &dyn Storage) -> StorageResult<Self> {
+        let next_seq = match storage.scan(TREE_AUDIT)?.last() {
+            Some((key, _)) => Self::parse_key(key)? + 1,
+            None => 0,
+        };
+
+        Ok(AuditLog {
+            next_seq: Mutex::new(next_seq),
+        })
+    }
+
+    pub fn append(
+        &self,
+        storage: &dyn Storage,
+        actor: &str,
+        action: &str,
+        target: &str,
+        result: &str,
+    ) -> StorageResult<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        // Lock held during write, so records are stored in sequence order
+        let mut next_seq = self.next_seq.lock().unwrap();
+        let record = AuditRecord {
+            seq: *next_seq,
+            time,
+            actor: actor.to_owned(),
+            action: action.to_owned(),
+            target: target.to_owned(),
+            result: result.to_owned(),
+        };
+        storage::insert_json(storage, TREE_AUDIT, &record.seq.to_be_bytes(), &record)?;
+        *next_seq += 1;
+        Ok(())
+    }
+
+    // Latest `limit` records, oldest first
+    pub fn recent(&self, storage: &dyn Storage, limit: usize) -> StorageResult<Vec<AuditRecord>> {
+        let items = storage.scan(TREE_AUDIT)?;
+        let skip = items.len().saturating_sub(limit);
+        items
+            .into_iter()
+            .skip(skip)
+            .map(|(_, value)| serde_json::from_slice(&value).map_err(StorageError::Serde))
+            .collect()
+    }
+
+    fn parse_key(key: &[u8]) -> StorageResult<u64> {
+        let mut bytes = [0; 8];
+        if key.len() != bytes.len() {
+            return Err(StorageError::InvalidKey(hex::encode(key)));
+        }
+        bytes.copy_from_slice(key);
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_and_resume() {
+        let storage = storage::open("memory").unwrap();
+        let storage = storage.as_ref();
+        let log = AuditLog::load(storage).unwrap();
+        for seq in 0..300 {
+            let target = seq.to_string();
+            log.append(storage, "admin", "blacklist.add", &target, "ok")
+                .unwrap();
+        }
+
+        // Sequence continued after restart, order kept past byte boundary
+        let log = AuditLog::load(storage).unwrap();
+        log.append(storage, "admin", "blacklist.remove", "1", "error")
+            .unwrap();
+        let records = log.recent(storage, 3).unwrap();
+        let records = records
+            .iter()
+            .map(|record| (record.seq, record.action.as_str(), record.target.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            vec![
+                (298, "blacklist.add", "298"),
+                (299, "blacklist.add", "299"),
+                (300, "blacklist.remove", "1"),
+            ]
+        );
+        assert_eq!(log.recent(storage, 1000).unwrap().len(), 301);
+
+        storage.insert(TREE_AUDIT, b"bad", b"{}").unwrap();
+        assert!(AuditLog::load(storage).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+// Entry points for benchmarks in `benches/`, route matching and mempool
+// diffing are private otherwise
+
+use std::collections::HashMap;
+
+use hyper::Method;
+
+use super::bitcoind::json::ResponseRawMempool;
+use super::router::{RouteMatch, Router};
+use super::state::diff_mempool;
+
+pub struct Routes {
+    router: Router,
+}
+
+impl Routes {
+    // Same routes as API with admin token
+    pub fn new() -> Self {
+        Routes {
+            router: Router::new(true),
+        }
+    }
+
+    // `true` if route found for method and path
+    pub fn find(&self, method: &Method, path: &str) -> bool {
+        matches!(self.router.find(method, path), RouteMatch::Found(_))
+    }
+}
+
+impl Default for Routes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Number of removed and added transactions, as update loop computes them
+pub fn mempool_diff<T>(current: &HashMap<String, T>, new: ResponseRawMempool) -> (usize, usize) {
+    let (removed, added) = diff_mempool(current, new);
+    (removed.len(), added.len())
+}
@@ -0,0 +1,150 @@
+// Fault injection for requests to bitcoind (`--features chaos`): random
+// latency, dropped responses, `500` and malformed JSON, with probabilities set
+// by `PUT /v1/admin/chaos`. Process wide, so replaced bitcoind client (see
+// `State::switch_bitcoind`) keeps injection. All probabilities are 0 on start.
+
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use super::BitcoindResult;
+use crate::server::json;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ChaosError {
+        Disabled {
+            display("Chaos injection is not enabled, rebuild with `--features chaos`")
+        }
+        InvalidProbability(name: &'static str) {
+            display("Probability of {} should be from 0 to 1", name)
+        }
+        InvalidTotal {
+            display("Sum of drop, error and malformed probabilities should not exceed 1")
+        }
+    }
+}
+
+pub type ChaosResult<T> = Result<T, ChaosError>;
+
+pub static CHAOS: Chaos = Chaos::new();
+
+#[derive(Debug)]
+pub struct Chaos {
+    config: Mutex<json::Chaos>,
+    // Xorshift state, seeded on first use
+    #[cfg_attr(not(feature = "chaos"), allow(dead_code))]
+    rng: Mutex<u64>,
+}
+
+impl Chaos {
+    const fn new() -> Self {
+        Chaos {
+            config: Mutex::new(json::Chaos {
+                latency: 0.0,
+                latency_ms: 0,
+                drop: 0.0,
+                error: 0.0,
+                malformed: 0.0,
+                injected: json::ChaosInjected {
+                    latency: 0,
+                    drop: 0,
+                    error: 0,
+                    malformed: 0,
+                },
+            }),
+            rng: Mutex::new(0),
+        }
+    }
+
+    pub fn get(&self) -> json::Chaos {
+        self.config.lock().unwrap().clone()
+    }
+
+    // Replace probabilities, counters of injected faults are kept
+    pub fn set(&self, config: json::Chaos) -> ChaosResult<json::Chaos> {
+        if !cfg!(feature = "chaos") {
+            return Err(ChaosError::Disabled);
+        }
+
+        let probabilities = [
+            ("latency", config.latency),
+            ("drop", config.drop),
+            ("error", config.error),
+            ("malformed", config.malformed),
+        ];
+        for (name, value) in probabilities.iter() {
+            if !(0.0..=1.0).contains(value) {
+                return Err(ChaosError::InvalidProbability(name));
+            }
+        }
+        if config.drop + config.error + config.malformed > 1.0 {
+            return Err(ChaosError::InvalidTotal);
+        }
+
+        let mut current = self.config.lock().unwrap();
+        let injected = current.injected.clone();
+        *current = json::Chaos { injected, ..config };
+        Ok(current.clone())
+    }
+
+    #[cfg(not(feature = "chaos"))]
+    pub async fn inject(&self) -> Option<BitcoindResult<(u16, Bytes)>> {
+        None
+    }
+
+    // Called before request, fake response with status and body should be
+    // used instead of response of bitcoind if returned
+    #[cfg(feature = "chaos")]
+    pub async fn inject(&self) -> Option<BitcoindResult<(u16, Bytes)>> {
+        use super::BitcoindError;
+
+        let (delay, fault) = {
+            let mut config = self.config.lock().unwrap();
+            let delay = if self.roll() < config.latency {
+                config.injected.latency += 1;
+                Some(std::time::Duration::from_millis(config.latency_ms))
+            } else {
+                None
+            };
+
+            let roll = self.roll();
+            let fault = if roll < config.drop {
+                config.injected.drop += 1;
+                Some(Err(BitcoindError::ChaosDropped))
+            } else if roll < config.drop + config.error {
+                config.injected.error += 1;
+                let body = Bytes::from_static(b"Internal Server Error (chaos)");
+                Some(Ok((500, body)))
+            } else if roll < config.drop + config.error + config.malformed {
+                config.injected.malformed += 1;
+                Some(Ok((200, Bytes::from_static(b"{\"result\":"))))
+            } else {
+                None
+            };
+            (delay, fault)
+        };
+
+        if let Some(delay) = delay {
+            tokio::time::delay_for(delay).await;
+        }
+        fault
+    }
+
+    // Uniform in [0, 1)
+    #[cfg(feature = "chaos")]
+    fn roll(&self) -> f64 {
+        let mut rng = self.rng.lock().unwrap();
+        if *rng == 0 {
+            let seed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64;
+            *rng = seed | 1;
+        }
+        *rng ^= *rng << 13;
+        *rng ^= *rng >> 7;
+        *rng ^= *rng << 17;
+        (*rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
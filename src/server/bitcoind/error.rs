@@ -1,8 +1,11 @@
+use std::path::PathBuf;
+
 use reqwest::Error as ReqwestError;
 use serde_json::Error as SerdeError;
 use url::ParseError as UrlParseError;
 
 use super::json::ResponseError;
+use crate::exit::{CliError, ErrorClass};
 
 quick_error! {
     #[derive(Debug)]
@@ -19,6 +22,9 @@ quick_error! {
         ResponseParse(err: SerdeError) {
             display("Invalid JSON response ({})", err)
         }
+        RawParse(reason: &'static str) {
+            display("Invalid raw response ({})", reason)
+        }
         NonceMismatch {
             display("Nonce mismatch")
         }
@@ -34,6 +40,27 @@ quick_error! {
         ResultMismatch {
             display("Result object not match to requested")
         }
+        InvalidHeader(hash: String, reason: &'static str) {
+            display("Invalid block header {}: {}", hash, reason)
+        }
+        MerkleMismatch(hash: String, reason: &'static str) {
+            display("Block {} transactions do not match header: invalid {}", hash, reason)
+        }
+        Cookie(path: PathBuf, err: std::io::Error) {
+            display("Failed to read cookie file {}: {}", path.display(), err)
+        }
+        InvalidCookie(path: PathBuf) {
+            display("Invalid cookie file {}, expected user:password", path.display())
+        }
+        Unauthorized {
+            display("Bitcoind RPC authorization failed, check credentials")
+        }
+        ChaosDropped {
+            display("Response dropped by chaos injection")
+        }
+        SpentOutputsUnavailable(hash: String) {
+            display("Spent outputs of block {} not available, bitcoind 30.0+ or -txindex required", hash)
+        }
         ClientMismatch {
             display("Chain, height or best block hash did not match between clients")
         }
@@ -41,3 +68,79 @@ quick_error! {
 }
 
 pub type BitcoindResult<T> = Result<T, BitcoindError>;
+
+impl BitcoindError {
+    // `prefix` is added to message, as in display of wrapping errors
+    pub fn to_cli_error(&self, prefix: &str) -> CliError {
+        let message = format!("{}{}", prefix, self);
+        match self {
+            BitcoindError::InvalidUrl(_) | BitcoindError::InvalidUrlScheme(_) => {
+                CliError::new(ErrorClass::Config, "bitcoind_url", message)
+                    .with_hint("Check --bitcoind URL, http:// and https:// are supported")
+            }
+            BitcoindError::Cookie(..) | BitcoindError::InvalidCookie(_) => {
+                CliError::new(ErrorClass::Config, "bitcoind_cookie", message)
+                    .with_hint("Check --bitcoind-cookie path, file is written by bitcoind on start")
+            }
+            BitcoindError::Unauthorized => {
+                CliError::new(ErrorClass::Config, "bitcoind_unauthorized", message)
+                    .with_hint("Check credentials in --bitcoind URL or --bitcoind-cookie")
+            }
+            BitcoindError::Reqwest(_) | BitcoindError::ChaosDropped => {
+                CliError::new(ErrorClass::Network, "bitcoind_unreachable", message)
+                    .with_hint("Check that bitcoind is running and --bitcoind URL is reachable")
+            }
+            BitcoindError::SpentOutputsUnavailable(_) => {
+                CliError::new(ErrorClass::Node, "bitcoind_unsupported", message).with_hint(
+                    "Upgrade bitcoind or remove bin and hex from --bitcoind-rest-formats",
+                )
+            }
+            _ => CliError::new(ErrorClass::Node, "bitcoind_error", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::mock::MockBitcoind;
+    use super::super::{Bitcoind, RetryPolicy};
+    use super::*;
+
+    #[tokio::test]
+    async fn cli_error_classes() {
+        // Dropped connection can be retried by caller
+        let node = MockBitcoind::start(5);
+        let retry = RetryPolicy {
+            retries: 0,
+            delay: Duration::from_millis(1),
+        };
+        let bitcoind = Bitcoind::new(&node.url).unwrap().with_retry(retry);
+        node.with(|node| node.drop_requests(1));
+        let error = bitcoind.getblockchaininfo().await.unwrap_err();
+        let error = error.to_cli_error("");
+        assert_eq!(
+            (error.class, error.code),
+            (ErrorClass::Network, "bitcoind_unreachable")
+        );
+
+        let error = Bitcoind::new("ftp://127.0.0.1/").unwrap_err();
+        let error = error.to_cli_error("");
+        assert_eq!(
+            (error.class, error.code),
+            (ErrorClass::Config, "bitcoind_url")
+        );
+        assert!(error.hint.is_some());
+
+        let error = BitcoindError::Unauthorized.to_cli_error("");
+        assert_eq!(error.class, ErrorClass::Config);
+
+        let error = BitcoindError::ResultNotFound.to_cli_error("bitcoind: ");
+        assert_eq!(
+            (error.class, error.code),
+            (ErrorClass::Node, "bitcoind_error")
+        );
+        assert_eq!(error.message, "bitcoind: Requested object not found");
+    }
+}
@@ -0,0 +1,228 @@
+// Local block header verification, so misbehaving node can not feed us blocks
+// which were never mined
+
+use sha2::{Digest, Sha256};
+
+use super::json::ResponseBlock;
+use super::{BitcoindError, BitcoindResult};
+
+const HEADER_SIZE: usize = 80;
+
+// Double SHA256, result in internal byte order
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&Sha256::digest(&Sha256::digest(data)));
+    hash
+}
+
+// Hash in display (reversed) order to internal order
+pub fn decode_hash(hash: &str) -> Option<[u8; 32]> {
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(hash, &mut bytes).ok()?;
+    bytes.reverse();
+    Some(bytes)
+}
+
+// Check that block hash is hash of header fields and satisfy own target
+pub fn verify_header(block: &ResponseBlock) -> BitcoindResult<()> {
+    let invalid = |reason| Err(BitcoindError::InvalidHeader(block.hash.clone(), reason));
+
+    let header = match serialize_header(block) {
+        Some(header) => header,
+        None => return invalid("malformed header fields"),
+    };
+    let mut hash = sha256d(&header);
+    hash.reverse();
+    if hex::encode(hash) != block.hash {
+        return invalid("hash does not match header");
+    }
+
+    let bits = match u32::from_str_radix(&block.bits, 16) {
+        Ok(bits) => bits,
+        Err(_) => return invalid("malformed bits"),
+    };
+    let target = match decode_target(bits) {
+        Some(target) => target,
+        None => return invalid("invalid target"),
+    };
+    // Both in big-endian, so can be compared as byte arrays
+    if hash > target {
+        return invalid("hash above target");
+    }
+
+    Ok(())
+}
+
+// Hash and previous block hash of every header from `rest/headers` binary
+// response, both in display order
+pub fn decode_headers(data: &[u8]) -> BitcoindResult<Vec<(String, String)>> {
+    if !data.len().is_multiple_of(HEADER_SIZE) {
+        return Err(BitcoindError::RawParse("unexpected end"));
+    }
+
+    let headers = data
+        .chunks_exact(HEADER_SIZE)
+        .map(|header| {
+            let mut hash = sha256d(header);
+            hash.reverse();
+            let mut prevhash = header[4..36].to_vec();
+            prevhash.reverse();
+            (hex::encode(hash), hex::encode(prevhash))
+        })
+        .collect();
+    Ok(headers)
+}
+
+pub fn serialize_header(block: &ResponseBlock) -> Option<[u8; HEADER_SIZE]> {
+    let prevhash = match &block.previousblockhash {
+        Some(prevhash) => decode_hash(prevhash)?,
+        None => [0; 32],
+    };
+    let merkleroot = decode_hash(&block.merkleroot)?;
+    let bits = u32::from_str_radix(&block.bits, 16).ok()?;
+
+    let mut header = [0; HEADER_SIZE];
+    header[0..4].copy_from_slice(&block.version.to_le_bytes());
+    header[4..36].copy_from_slice(&prevhash);
+    header[36..68].copy_from_slice(&merkleroot);
+    header[68..72].copy_from_slice(&block.time.to_le_bytes());
+    header[72..76].copy_from_slice(&bits.to_le_bytes());
+    header[76..80].copy_from_slice(&block.nonce.to_le_bytes());
+    Some(header)
+}
+
+// Compact `bits` to 256-bit big-endian target, `None` for negative, zero or
+// overflowed target (same rules as `arith_uint256::SetCompact` in bitcoind)
+fn decode_target(bits: u32) -> Option<[u8; 32]> {
+    let exponent = (bits >> 24) as usize;
+    let mut mantissa = bits & 0x007f_ffff;
+    if mantissa == 0 || bits & 0x0080_0000 != 0 {
+        return None;
+    }
+    if exponent > 34 || (mantissa > 0xff && exponent > 33) || (mantissa > 0xffff && exponent > 32) {
+        return None;
+    }
+
+    let mut target = [0; 32];
+    if exponent <= 3 {
+        mantissa >>= 8 * (3 - exponent);
+        target[29..32].copy_from_slice(&mantissa.to_be_bytes()[1..4]);
+    } else {
+        for (i, byte) in mantissa.to_be_bytes()[1..4].iter().enumerate() {
+            // Bytes before start are zero because of overflow check above
+            if let Some(pos) = (32 + i).checked_sub(exponent) {
+                target[pos] = *byte;
+            }
+        }
+    }
+
+    if target == [0; 32] {
+        None
+    } else {
+        Some(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mainnet block 1
+    fn block() -> ResponseBlock {
+        ResponseBlock {
+            hash: "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048".to_owned(),
+            height: 1,
+            version: 1,
+            previousblockhash: Some(
+                "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f".to_owned(),
+            ),
+            merkleroot: "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098"
+                .to_owned(),
+            time: 1_231_469_665,
+            bits: "1d00ffff".to_owned(),
+            nonce: 2_573_394_689,
+            size: 215,
+            weight: None,
+            transactions: vec![],
+        }
+    }
+
+    // Set hash of changed header fields, so only target is checked
+    fn rehash(block: &mut ResponseBlock) {
+        let mut hash = sha256d(&serialize_header(block).unwrap());
+        hash.reverse();
+        block.hash = hex::encode(hash);
+    }
+
+    fn reason(block: &ResponseBlock) -> Option<&'static str> {
+        match verify_header(block) {
+            Ok(()) => None,
+            Err(BitcoindError::InvalidHeader(hash, reason)) => {
+                assert_eq!(hash, block.hash);
+                Some(reason)
+            }
+            Err(error) => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn mainnet_header() {
+        assert_eq!(reason(&block()), None);
+
+        let header = serialize_header(&block()).unwrap();
+        let hashes = decode_headers(&[header, header].concat()).unwrap();
+        let expected = (block().hash, block().previousblockhash.unwrap());
+        assert_eq!(hashes, vec![expected.clone(), expected]);
+        assert!(decode_headers(&header[1..]).is_err());
+    }
+
+    #[test]
+    fn changed_header() {
+        let mut block = block();
+        block.nonce += 1;
+        assert_eq!(reason(&block), Some("hash does not match header"));
+
+        let mut block = self::block();
+        block.merkleroot.replace_range(0..2, "0f");
+        assert_eq!(reason(&block), Some("hash does not match header"));
+
+        let mut block = self::block();
+        block.hash.replace_range(62..64, "49");
+        assert_eq!(reason(&block), Some("hash does not match header"));
+    }
+
+    #[test]
+    fn hash_above_target() {
+        let mut block = block();
+        block.bits = "1c00ffff".to_owned();
+        rehash(&mut block);
+        assert_eq!(reason(&block), Some("hash above target"));
+    }
+
+    #[test]
+    fn malformed_bits() {
+        let mut block = block();
+        block.bits = "1d00fffg".to_owned();
+        assert_eq!(reason(&block), Some("malformed header fields"));
+
+        // Negative, zero and overflowed targets
+        for bits in &["1d80ffff", "1d000000", "2301ffff"] {
+            let mut block = self::block();
+            block.bits = (*bits).to_owned();
+            rehash(&mut block);
+            assert_eq!(reason(&block), Some("invalid target"), "{}", bits);
+        }
+    }
+
+    #[test]
+    fn compact_target() {
+        let mut expected = [0; 32];
+        expected[4..6].copy_from_slice(&[0xff, 0xff]);
+        assert_eq!(decode_target(0x1d00_ffff), Some(expected));
+
+        let mut expected = [0; 32];
+        expected[31] = 0x12;
+        assert_eq!(decode_target(0x0112_3456), Some(expected));
+        assert_eq!(decode_target(0x0100_3456), None);
+    }
+}
@@ -21,6 +21,7 @@ pub struct Response<T> {
 pub struct ResponseError {
     pub code: i32,
     pub message: String,
+    #[allow(dead_code)]
     pub data: Option<serde_json::Value>,
 }
 
@@ -42,19 +43,147 @@ pub struct ResponseBlockchainInfo {
 }
 
 #[derive(Debug, Deserialize)]
+pub struct ResponseNetworkInfo {
+    // Version as number, `260000` for 26.0.0
+    pub version: u32,
+    pub subversion: String,
+    // Seconds, median time of peers minus node time
+    #[serde(default)]
+    pub timeoffset: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResponseBlockHeader {
+    pub hash: String,
+    pub height: u32,
+    pub time: u32,
+    // Median time past of block
+    pub mediantime: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ResponseBlock {
     pub hash: String,
     pub height: u32,
+    pub version: i32,
     pub previousblockhash: Option<String>,
+    pub merkleroot: String,
+    pub time: u32,
+    pub bits: String,
+    pub nonce: u32,
     pub size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
     #[serde(rename = "tx")]
     pub transactions: Vec<ResponseBlockTransaction>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ResponseBlockTransaction {
+    pub txid: String,
     pub hash: String,
     pub size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsize: Option<u32>,
+    // BTC, in blocks only with undo data (bitcoind 23+, spent outputs for raw
+    // blocks), not set for coinbase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<f64>,
+    pub vin: Vec<ResponseTransactionInput>,
+    pub vout: Vec<ResponseTransactionOutput>,
+}
+
+// Verbose `getrawtransaction` or `rest/tx`, block hash is not set for mempool
+// transaction
+#[derive(Debug, Deserialize)]
+pub struct ResponseRawTransaction {
+    #[serde(flatten)]
+    pub transaction: ResponseBlockTransaction,
+    #[serde(default)]
+    pub blockhash: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseTransactionInput {
+    // Spent output, not set for coinbase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vout: Option<u32>,
+    // Witness used for witness commitment verification in coinbase
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub txinwitness: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseTransactionOutput {
+    pub value: f64,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pubkey: ResponseScriptPubKey,
+}
+
+// Output spent by transaction input, from undo data of block
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseSpentOutput {
+    pub value: f64,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pubkey: ResponseScriptPubKey,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseScriptPubKey {
+    #[serde(with = "hex")]
+    pub hex: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResponsePeerInfo {
+    pub id: u64,
+    pub addr: String,
+    // Not available before bitcoind 22.0
+    #[serde(default = "unknown")]
+    pub network: String,
+    pub subver: String,
+    pub inbound: bool,
+    #[serde(default)]
+    pub connection_type: Option<String>,
+    // Seconds, not set before first pong
+    #[serde(default)]
+    pub pingtime: Option<f64>,
+    #[serde(default)]
+    pub synced_blocks: Option<i64>,
+    pub conntime: u64,
+}
+
+fn unknown() -> String {
+    "unknown".to_owned()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResponseBanned {
+    // IP address or subnet
+    pub address: String,
+    pub ban_created: u64,
+    pub banned_until: u64,
+}
+
+// Result of `gettxout` for unspent output
+#[derive(Debug, Deserialize)]
+pub struct ResponseTxOut {
+    // Zero for mempool transaction output
+    pub confirmations: u32,
+    pub value: f64,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pubkey: ResponseScriptPubKey,
+    pub coinbase: bool,
+}
+
+// Result of `validateaddress`, script is set only for valid address
+#[derive(Debug, Deserialize)]
+pub struct ResponseValidateAddress {
+    pub isvalid: bool,
+    #[serde(default, rename = "scriptPubKey", with = "hex")]
+    pub script_pubkey: Vec<u8>,
 }
 
 pub type ResponseRawMempool = HashMap<String, ResponseRawMempoolTransaction>;
@@ -63,4 +192,91 @@ pub type ResponseRawMempool = HashMap<String, ResponseRawMempoolTransaction>;
 pub struct ResponseRawMempoolTransaction {
     #[serde(rename = "vsize")]
     pub size: u32,
+    // Not available in old bitcoind versions
+    #[serde(default)]
+    pub fees: Option<ResponseMempoolFees>,
+    // Replaced by `fees` in new bitcoind versions
+    #[serde(default)]
+    pub fee: Option<f64>,
+    // Unix time of mempool entry
+    #[serde(default)]
+    pub time: u64,
+    // Unconfirmed ancestors and descendants with transaction itself
+    #[serde(default = "ResponseRawMempoolTransaction::default_count")]
+    pub ancestorcount: u32,
+    #[serde(default = "ResponseRawMempoolTransaction::default_count")]
+    pub descendantcount: u32,
+}
+
+impl ResponseRawMempoolTransaction {
+    fn default_count() -> u32 {
+        1
+    }
+
+    // Fee in satoshis, 0 if not reported
+    pub fn fee_sat(&self) -> u64 {
+        let fee = match (&self.fees, self.fee) {
+            (Some(fees), _) => fees.base,
+            (None, Some(fee)) => fee,
+            (None, None) => 0.0,
+        };
+        (fee * 1e8).round() as u64
+    }
+}
+
+// Result of `testmempoolaccept` for one transaction
+#[derive(Debug, Deserialize)]
+pub struct ResponseMempoolAccept {
+    pub txid: String,
+    // Not available in old bitcoind versions
+    #[serde(default)]
+    pub wtxid: Option<String>,
+    // Not set if package validation failed before this transaction
+    #[serde(default)]
+    pub allowed: Option<bool>,
+    #[serde(default)]
+    pub vsize: Option<u32>,
+    #[serde(default)]
+    pub fees: Option<ResponseMempoolFees>,
+    #[serde(default, rename = "reject-reason")]
+    pub reject_reason: Option<String>,
+    #[serde(default, rename = "package-error")]
+    pub package_error: Option<String>,
+}
+
+// Result of `estimatesmartfee`, `feerate` not set without enough data
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponseSmartFee {
+    // BTC/kvB
+    pub feerate: Option<f64>,
+    #[serde(default)]
+    pub errors: Vec<String>,
+    pub blocks: u32,
+}
+
+// Result of `submitpackage`
+#[derive(Debug, Deserialize)]
+pub struct ResponseSubmitPackage {
+    // Not available before bitcoind 28.0
+    #[serde(default)]
+    pub package_msg: Option<String>,
+    // Results by wtxid
+    #[serde(rename = "tx-results")]
+    pub tx_results: HashMap<String, ResponseSubmitPackageTx>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResponseSubmitPackageTx {
+    pub txid: String,
+    #[serde(default)]
+    pub vsize: Option<u32>,
+    #[serde(default)]
+    pub fees: Option<ResponseMempoolFees>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResponseMempoolFees {
+    pub base: f64,
 }
@@ -0,0 +1,198 @@
+// Merkle root of block transactions, verified against header, so corrupted or
+// incomplete transactions list is rejected before any processing
+
+use super::header::{decode_hash, sha256d};
+use super::json::ResponseBlock;
+use super::{BitcoindError, BitcoindResult};
+
+// OP_RETURN, push 36 bytes, commitment header 0xaa21a9ed (BIP141)
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+const WITNESS_COMMITMENT_SIZE: usize = 38;
+
+pub fn verify_merkle_root(block: &ResponseBlock) -> BitcoindResult<()> {
+    let mismatch = |reason| Err(BitcoindError::MerkleMismatch(block.hash.clone(), reason));
+
+    let txids = block.transactions.iter().map(|tx| decode_hash(&tx.txid));
+    let root = match txids.collect::<Option<Vec<_>>>() {
+        Some(txids) => compute_root(txids),
+        None => return mismatch("malformed txid"),
+    };
+    if root.is_none() || root != decode_hash(&block.merkleroot) {
+        return mismatch("merkle root");
+    }
+
+    if !verify_witness_commitment(block) {
+        return mismatch("witness commitment");
+    }
+
+    Ok(())
+}
+
+// Commitment is required only if some transaction have witness, but if it
+// exists it always should be valid (same as in bitcoind)
+fn verify_witness_commitment(block: &ResponseBlock) -> bool {
+    let coinbase = match block.transactions.first() {
+        Some(coinbase) => coinbase,
+        None => return false,
+    };
+
+    // Last output with commitment is used
+    let commitment = coinbase.vout.iter().rev().find_map(|output| {
+        let script = &output.script_pubkey.hex;
+        if script.len() >= WITNESS_COMMITMENT_SIZE && script.starts_with(&WITNESS_COMMITMENT_PREFIX)
+        {
+            Some(&script[WITNESS_COMMITMENT_PREFIX.len()..WITNESS_COMMITMENT_SIZE])
+        } else {
+            None
+        }
+    });
+    let commitment = match commitment {
+        Some(commitment) => commitment,
+        None => return block.transactions.iter().all(|tx| tx.hash == tx.txid),
+    };
+
+    // Witness reserved value from coinbase input witness
+    let reserved = coinbase
+        .vin
+        .first()
+        .and_then(|input| match input.txinwitness.as_slice() {
+            [item] => hex::decode(item).ok(),
+            _ => None,
+        });
+    let reserved = match reserved {
+        Some(reserved) if reserved.len() == 32 => reserved,
+        _ => return false,
+    };
+
+    // Coinbase wtxid is zero by definition
+    let mut wtxids = vec![[0; 32]];
+    for tx in block.transactions.iter().skip(1) {
+        match decode_hash(&tx.hash) {
+            Some(wtxid) => wtxids.push(wtxid),
+            None => return false,
+        }
+    }
+    let root = match compute_root(wtxids) {
+        Some(root) => root,
+        None => return false,
+    };
+
+    let mut data = root.to_vec();
+    data.extend_from_slice(&reserved);
+    sha256d(&data)[..] == commitment[..]
+}
+
+// `None` for empty list or mutated tree (duplicated hashes pair, CVE-2012-2459)
+pub fn compute_root(mut hashes: Vec<[u8; 32]>) -> Option<[u8; 32]> {
+    if hashes.is_empty() {
+        return None;
+    }
+
+    let mut data = [0; 64];
+    while hashes.len() > 1 {
+        if hashes
+            .chunks(2)
+            .any(|pair| pair.len() == 2 && pair[0] == pair[1])
+        {
+            return None;
+        }
+        if hashes.len() % 2 == 1 {
+            hashes.push(*hashes.last().unwrap());
+        }
+
+        hashes = hashes
+            .chunks(2)
+            .map(|pair| {
+                data[..32].copy_from_slice(&pair[0]);
+                data[32..].copy_from_slice(&pair[1]);
+                sha256d(&data)
+            })
+            .collect();
+    }
+    Some(hashes[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::{txid, MockChain};
+    use super::*;
+
+    fn reason(block: &ResponseBlock) -> Option<&'static str> {
+        match verify_merkle_root(block) {
+            Ok(()) => None,
+            Err(BitcoindError::MerkleMismatch(hash, reason)) => {
+                assert_eq!(hash, block.hash);
+                Some(reason)
+            }
+            Err(error) => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn transactions_list() {
+        let mut chain = MockChain::default();
+        let txids = vec![txid("a"), txid("b"), txid("c")];
+        let block = chain.mine_with(&txids).json();
+        assert_eq!(reason(&block), None);
+
+        let mut changed = chain.tip().json();
+        changed.transactions.swap(1, 2);
+        assert_eq!(reason(&changed), Some("merkle root"));
+
+        let mut changed = chain.tip().json();
+        changed.transactions.pop();
+        assert_eq!(reason(&changed), Some("merkle root"));
+
+        let mut changed = chain.tip().json();
+        changed.transactions.clear();
+        assert_eq!(reason(&changed), Some("merkle root"));
+
+        let mut changed = chain.tip().json();
+        changed.transactions[1].txid = "zz".to_owned();
+        assert_eq!(reason(&changed), Some("malformed txid"));
+    }
+
+    #[test]
+    fn witness_commitment() {
+        let mut chain = MockChain::default();
+        chain.mine_with(&[]);
+        let block = chain.mine_raw("a").json();
+        assert_eq!(reason(&block), None);
+
+        // Witness changed, txid is same
+        let mut changed = chain.tip().json();
+        changed.transactions[1].hash = txid("b");
+        assert_eq!(reason(&changed), Some("witness commitment"));
+
+        let mut changed = chain.tip().json();
+        changed.transactions[0].vin[0].txinwitness = vec![hex::encode([1; 32])];
+        assert_eq!(reason(&changed), Some("witness commitment"));
+
+        // Witness without commitment
+        let mut changed = chain.tip().json();
+        changed.transactions[0].vout.pop();
+        assert_eq!(reason(&changed), Some("witness commitment"));
+    }
+
+    #[test]
+    fn root() {
+        let hash = |n: u8| [n; 32];
+        let pair = |a: [u8; 32], b: [u8; 32]| sha256d(&[a, b].concat());
+
+        assert_eq!(compute_root(vec![]), None);
+        assert_eq!(compute_root(vec![hash(1)]), Some(hash(1)));
+        assert_eq!(
+            compute_root(vec![hash(1), hash(2)]),
+            Some(pair(hash(1), hash(2)))
+        );
+
+        // Last hash of odd level is duplicated
+        let expected = pair(pair(hash(1), hash(2)), pair(hash(3), hash(3)));
+        assert_eq!(
+            compute_root(vec![hash(1), hash(2), hash(3)]),
+            Some(expected)
+        );
+        // but explicit duplicate is mutated tree with same root
+        assert_eq!(compute_root(vec![hash(1), hash(2), hash(3), hash(3)]), None);
+    }
+}
@@ -0,0 +1,811 @@
+// In-process bitcoind for tests: scripted chain with valid headers and merkle
+// roots, served over JSON-RPC and REST endpoints used by server. Disconnected
+// blocks stay available by hash, like stale blocks in bitcoind.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::header::{decode_hash, serialize_header, sha256d, verify_header};
+use super::json::{
+    ResponseBlock, ResponseBlockTransaction, ResponseScriptPubKey, ResponseTransactionInput,
+    ResponseTransactionOutput,
+};
+use super::merkle::compute_root;
+use super::{basic_auth, wtxid};
+
+const BLOCK_TIME_START: u32 = 1_600_000_000;
+const BLOCK_INTERVAL: u32 = 600;
+// Easiest target, every second nonce is valid
+const BITS: &str = "207fffff";
+
+type FetchHook = Box<dyn FnOnce(&mut MockChain) + Send>;
+
+#[derive(Debug, Clone)]
+pub struct MockBlock {
+    pub hash: String,
+    pub height: u32,
+    // Transactions except coinbase
+    pub txids: Vec<String>,
+    body: Vec<u8>,
+    // Network serialization, only for blocks from `mine_raw`
+    raw: Option<Vec<u8>>,
+    // Spent outputs by transaction as `rest/spenttxouts`, only for blocks
+    // from `mine_raw`
+    spent: Option<Vec<u8>>,
+}
+
+impl MockBlock {
+    // Block as served in JSON format
+    pub fn json(&self) -> ResponseBlock {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+#[derive(Default)]
+pub struct MockChain {
+    chain: Vec<MockBlock>,
+    blocks: HashMap<String, MockBlock>,
+    // Sorted, so mined blocks do not depend on hash map order
+    // Virtual size, fee and entry time
+    mempool: BTreeMap<String, (u32, u64, u32)>,
+    // Ancestor and descendant counts, 1 (transaction alone) if not set
+    packages: HashMap<String, (u32, u32)>,
+    // Remaining `404 Not Found` responses by block hash
+    missing: HashMap<String, usize>,
+    // Blocks served truncated in binary and hex formats
+    corrupted: HashSet<String>,
+    // Blocks served with `500 Internal Server Error` in any format
+    failed: HashSet<String>,
+    // Remaining requests closed without response
+    drops: usize,
+    // Username and password required by RPC, any credentials accepted if not set
+    auth: Option<(String, String)>,
+    // RPC requests refused with `401 Unauthorized`
+    unauthorized: usize,
+    // Delay before every response, as busy node
+    stall: Option<Duration>,
+    fetches: usize,
+    fetch_hook: Option<(usize, FetchHook)>,
+    // Makes blocks of different branches at same height different
+    branch: u32,
+    // Times of blocks mined later, by height
+    times: HashMap<u32, u32>,
+    // `timeoffset` of `getnetworkinfo`
+    timeoffset: i64,
+    // BTC/kvB of `estimatesmartfee`, for any target
+    feerate: Option<f64>,
+    // Scripted responses (`result` or `error`) of RPC methods, used instead
+    // of modeled ones
+    responses: HashMap<String, serde_json::Value>,
+    // Method and params of calls with scripted responses
+    calls: Vec<(String, Vec<serde_json::Value>)>,
+}
+
+impl MockChain {
+    pub fn tip(&self) -> &MockBlock {
+        self.chain.last().unwrap()
+    }
+
+    // Active chain, from genesis
+    pub fn chain(&self) -> &[MockBlock] {
+        &self.chain
+    }
+
+    pub fn mempool(&self) -> Vec<String> {
+        self.mempool.keys().cloned().collect()
+    }
+
+    // 10 sat/vB
+    pub fn add_mempool(&mut self, txid: &str) {
+        self.add_mempool_with_fee(txid, 100, 1000);
+    }
+
+    // Entry time is time of tip, so confirmation in next block takes 600s
+    pub fn add_mempool_with_fee(&mut self, txid: &str, vsize: u32, fee: u64) {
+        let height = self.chain.len().saturating_sub(1) as u32;
+        let time = BLOCK_TIME_START + height * BLOCK_INTERVAL;
+        self.mempool.insert(txid.to_owned(), (vsize, fee, time));
+    }
+
+    pub fn set_entry_time(&mut self, txid: &str, time: u32) {
+        if let Some(entry) = self.mempool.get_mut(txid) {
+            entry.2 = time;
+        }
+    }
+
+    pub fn set_timeoffset(&mut self, offset: i64) {
+        self.timeoffset = offset;
+    }
+
+    // Time of block at `height` when it is mined, every 600s by default
+    pub fn set_block_time(&mut self, height: u32, time: u32) {
+        self.times.insert(height, time);
+    }
+
+    pub fn block_time(&self, height: u32) -> u32 {
+        self.times
+            .get(&height)
+            .copied()
+            .unwrap_or(BLOCK_TIME_START + height * BLOCK_INTERVAL)
+    }
+
+    pub fn set_feerate(&mut self, feerate: Option<f64>) {
+        self.feerate = feerate;
+    }
+
+    pub fn set_response(&mut self, method: &str, response: serde_json::Value) {
+        self.responses.insert(method.to_owned(), response);
+    }
+
+    pub fn calls(&self) -> &[(String, Vec<serde_json::Value>)] {
+        &self.calls
+    }
+
+    pub fn set_package(&mut self, txid: &str, ancestors: u32, descendants: u32) {
+        self.packages
+            .insert(txid.to_owned(), (ancestors, descendants));
+    }
+
+    pub fn remove_mempool(&mut self, txid: &str) {
+        self.mempool.remove(txid);
+    }
+
+    // Block with all mempool transactions
+    pub fn mine(&mut self) -> MockBlock {
+        let txids = self.mempool();
+        self.mine_with(&txids)
+    }
+
+    // Block with given transactions, which are removed from mempool
+    pub fn mine_with(&mut self, txids: &[String]) -> MockBlock {
+        let height = self.chain.len() as u32;
+        let prevhash = self.chain.last().map(|block| block.hash.clone());
+
+        let coinbase = format!("coinbase {} {}", height, self.branch);
+        let mut transactions = vec![Self::transaction(&txid(&coinbase), None)];
+        for id in txids {
+            let mut tx = Self::transaction(id, Some(&funding(id)));
+            // Fee known from undo data, as in bitcoind 23+
+            if let Some((vsize, fee, _)) = self.mempool.remove(id) {
+                tx.vsize = Some(vsize);
+                tx.fee = Some(fee as f64 / 1e8);
+            }
+            transactions.push(tx);
+        }
+        let root = transactions
+            .iter()
+            .map(|tx| decode_hash(&tx.txid).unwrap())
+            .collect();
+        let mut merkleroot = compute_root(root).unwrap();
+        merkleroot.reverse();
+        let weight = transactions
+            .iter()
+            .map(|tx| tx.vsize.unwrap_or(tx.size) * 4)
+            .sum::<u32>()
+            + 320;
+
+        let mut block = ResponseBlock {
+            hash: String::new(),
+            height,
+            version: 1,
+            previousblockhash: prevhash,
+            merkleroot: hex::encode(merkleroot),
+            time: self.block_time(height),
+            bits: BITS.to_owned(),
+            nonce: 0,
+            size: 1000,
+            weight: Some(weight),
+            transactions,
+        };
+        loop {
+            let mut hash = sha256d(&serialize_header(&block).unwrap());
+            hash.reverse();
+            block.hash = hex::encode(hash);
+            if verify_header(&block).is_ok() {
+                break;
+            }
+            block.nonce += 1;
+        }
+
+        let block = MockBlock {
+            hash: block.hash.clone(),
+            height,
+            txids: txids.to_vec(),
+            body: serde_json::to_vec(&block).unwrap(),
+            raw: None,
+            spent: None,
+        };
+        self.blocks.insert(block.hash.clone(), block.clone());
+        self.chain.push(block.clone());
+        block
+    }
+
+    // Block with transactions in network serialization, served in all REST
+    // formats: segwit coinbase with BIP34 height and segwit transaction which
+    // spends output of `funding(name)`
+    pub fn mine_raw(&mut self, name: &str) -> MockBlock {
+        let height = self.chain.len() as u32;
+        let prevhash = self.chain.last().map(|block| block.hash.clone());
+
+        let funding = decode_hash(&txid(&funding(name))).unwrap();
+        let spend = RawTx {
+            inputs: vec![(funding, 0, vec![], vec![vec![0x30; 72], vec![0x02; 33]])],
+            outputs: vec![(100_000_000, p2wpkh(name))],
+        };
+        let (spend_txid, spend_wtxid) = spend.hashes();
+
+        // Witness commitment with zero reserved value, coinbase wtxid is zero
+        let root = compute_root(vec![[0; 32], spend_wtxid]).unwrap();
+        let mut data = root.to_vec();
+        data.extend_from_slice(&[0; 32]);
+        let mut commitment = vec![0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+        commitment.extend_from_slice(&sha256d(&data));
+
+        let mut script = script_number(height);
+        script.extend_from_slice(&[0x04]);
+        script.extend_from_slice(&self.branch.to_le_bytes());
+        let coinbase = RawTx {
+            inputs: vec![([0; 32], u32::MAX, script, vec![vec![0; 32]])],
+            outputs: vec![
+                (5_000_000_000, p2wpkh(&format!("coinbase {}", height))),
+                (0, commitment),
+            ],
+        };
+        let (coinbase_txid, _) = coinbase.hashes();
+        let txs = [coinbase, spend];
+
+        let mut merkleroot = compute_root(vec![coinbase_txid, spend_txid]).unwrap();
+        merkleroot.reverse();
+        let mut transactions = txs.iter().map(RawTx::json).collect::<Vec<_>>();
+        transactions[1].fee = Some(0.00001);
+        let weight = 81 * 4 + txs.iter().map(RawTx::weight).sum::<usize>();
+        let mut block = ResponseBlock {
+            hash: String::new(),
+            height,
+            version: 0x2000_0000,
+            previousblockhash: prevhash,
+            merkleroot: hex::encode(merkleroot),
+            time: self.block_time(height),
+            bits: BITS.to_owned(),
+            nonce: 0,
+            size: 0,
+            weight: Some(weight as u32),
+            transactions,
+        };
+        let header = loop {
+            let header = serialize_header(&block).unwrap();
+            let mut hash = sha256d(&header);
+            hash.reverse();
+            block.hash = hex::encode(hash);
+            if verify_header(&block).is_ok() {
+                break header;
+            }
+            block.nonce += 1;
+        };
+
+        let mut raw = header.to_vec();
+        raw.push(txs.len() as u8);
+        for tx in txs.iter() {
+            raw.extend_from_slice(&tx.serialize(true));
+        }
+        block.size = raw.len() as u32;
+        let spent = json!([
+            [],
+            [{ "value": 1.00001, "scriptPubKey": { "hex": hex::encode(funding_script(name)) } }],
+        ]);
+
+        let block = MockBlock {
+            hash: block.hash.clone(),
+            height,
+            txids: vec![],
+            body: serde_json::to_vec(&block).unwrap(),
+            raw: Some(raw),
+            spent: Some(spent.to_string().into_bytes()),
+        };
+        self.blocks.insert(block.hash.clone(), block.clone());
+        self.chain.push(block.clone());
+        block
+    }
+
+    fn transaction(txid: &str, spent: Option<&str>) -> ResponseBlockTransaction {
+        let input = ResponseTransactionInput {
+            txid: spent.map(self::txid),
+            vout: spent.map(|_| 0),
+            txinwitness: vec![],
+        };
+        let output = ResponseTransactionOutput {
+            value: 1.0,
+            script_pubkey: ResponseScriptPubKey {
+                hex: hex::decode(format!("0014{}", &txid[..40])).unwrap(),
+            },
+        };
+        ResponseBlockTransaction {
+            txid: txid.to_owned(),
+            hash: txid.to_owned(),
+            size: 200,
+            vsize: None,
+            fee: None,
+            vin: vec![input],
+            vout: vec![output],
+        }
+    }
+
+    // Disconnect blocks from tip, their transactions are back in mempool
+    pub fn disconnect(&mut self, count: usize) {
+        for _ in 0..count {
+            let block = self.chain.pop().unwrap();
+            for id in block.txids.iter() {
+                self.add_mempool(id);
+            }
+        }
+        self.branch += 1;
+    }
+
+    // Replace `depth` blocks with `depth + 1` empty blocks
+    pub fn reorg(&mut self, depth: usize) {
+        self.disconnect(depth);
+        for _ in 0..=depth {
+            self.mine_with(&[]);
+        }
+    }
+
+    // Block known by `getblockhash`, but not found on next `count` fetches
+    pub fn hide(&mut self, hash: &str, count: usize) {
+        self.missing.insert(hash.to_owned(), count);
+    }
+
+    // Raw block which can not be decoded, JSON is not changed
+    pub fn corrupt(&mut self, hash: &str) {
+        self.corrupted.insert(hash.to_owned());
+    }
+
+    // Block fetches fail with server error, as on node disk error
+    pub fn fail(&mut self, hash: &str) {
+        self.failed.insert(hash.to_owned());
+    }
+
+    // Change chain after `count` more block fetches, before response
+    pub fn on_fetch<F>(&mut self, count: usize, hook: F)
+    where
+        F: FnOnce(&mut MockChain) + Send + 'static,
+    {
+        self.fetch_hook = Some((self.fetches + count, Box::new(hook)));
+    }
+
+    // Next requests fail on connection level, as on node restart
+    pub fn drop_requests(&mut self, count: usize) {
+        self.drops = count;
+    }
+
+    pub fn set_auth(&mut self, username: &str, password: &str) {
+        self.auth = Some((username.to_owned(), password.to_owned()));
+    }
+
+    pub fn unauthorized(&self) -> usize {
+        self.unauthorized
+    }
+
+    pub fn stall(&mut self, delay: Option<Duration>) {
+        self.stall = delay;
+    }
+
+    fn fetch(&mut self, hash: &str, format: &str) -> Option<Vec<u8>> {
+        self.fetches += 1;
+        if self
+            .fetch_hook
+            .as_ref()
+            .is_some_and(|(at, _)| *at < self.fetches)
+        {
+            let (_, hook) = self.fetch_hook.take().unwrap();
+            hook(self);
+        }
+
+        if let Some(count) = self.missing.get_mut(hash) {
+            if *count > 0 {
+                *count -= 1;
+                return None;
+            }
+        }
+        let block = self.blocks.get(hash)?;
+        let mut raw = block.raw.clone();
+        if self.corrupted.contains(hash) {
+            raw = raw.map(|raw| raw[..raw.len() - 1].to_vec());
+        }
+        match format {
+            "json" => Some(block.body.clone()),
+            "bin" => raw,
+            _ => raw.map(|raw| format!("{}\n", hex::encode(raw)).into_bytes()),
+        }
+    }
+
+    // Serialized headers of active chain from `hash`, as `rest/headers`
+    fn headers(&self, count: usize, hash: &str) -> Option<Vec<u8>> {
+        let start = self.chain.iter().position(|block| block.hash == hash)?;
+        let headers = self.chain[start..]
+            .iter()
+            .take(count)
+            .flat_map(|block| {
+                let block: ResponseBlock = serde_json::from_slice(&block.body).unwrap();
+                serialize_header(&block).unwrap().to_vec()
+            })
+            .collect();
+        Some(headers)
+    }
+
+    fn blockchaininfo(&self) -> serde_json::Value {
+        json!({
+            "chain": "regtest",
+            "blocks": self.tip().height,
+            "bestblockhash": self.tip().hash,
+        })
+    }
+
+    fn rpc(&mut self, request: &serde_json::Value) -> serde_json::Value {
+        let mut response = self.rpc_result(
+            request["method"].as_str().unwrap(),
+            request["params"]
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+        );
+        response["id"] = request["id"].clone();
+        response
+    }
+
+    fn rpc_result(&mut self, method: &str, params: &[serde_json::Value]) -> serde_json::Value {
+        if let Some(response) = self.responses.get(method) {
+            self.calls.push((method.to_owned(), params.to_vec()));
+            return response.clone();
+        }
+
+        match method {
+            "getblockchaininfo" => json!({ "result": self.blockchaininfo() }),
+            "getnetworkinfo" => json!({
+                "result": {
+                    "version": 260000,
+                    "subversion": "/Satoshi:26.0.0/",
+                    "timeoffset": self.timeoffset,
+                }
+            }),
+            "getblockhash" => {
+                let height = params[0].as_u64().unwrap() as usize;
+                match self.chain.get(height) {
+                    Some(block) => json!({ "result": block.hash }),
+                    None => json!({
+                        "error": { "code": -8, "message": "Block height out of range" }
+                    }),
+                }
+            }
+            "getrawmempool" => {
+                let mempool = self
+                    .mempool
+                    .iter()
+                    .map(|(txid, (vsize, fee, time))| {
+                        let fees = json!({ "base": *fee as f64 / 1e8 });
+                        let (ancestors, descendants) =
+                            self.packages.get(txid).copied().unwrap_or((1, 1));
+                        let tx = json!({
+                            "vsize": vsize,
+                            "fees": fees,
+                            "time": time,
+                            "ancestorcount": ancestors,
+                            "descendantcount": descendants,
+                        });
+                        (txid.clone(), tx)
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+                json!({ "result": mempool })
+            }
+            "getrawtransaction" => {
+                let txid = params[0].as_str().unwrap();
+                // Without `-txindex`, confirmed transaction only with block hash
+                let block = params
+                    .get(2)
+                    .and_then(|hash| self.blocks.get(hash.as_str().unwrap()))
+                    .filter(|block| block.txids.iter().any(|id| id == txid));
+                if let Some(block) = block {
+                    let mut tx = json!(Self::transaction(txid, Some(&funding(txid))));
+                    tx["blockhash"] = json!(block.hash);
+                    json!({ "result": tx })
+                } else if self.mempool.contains_key(txid) {
+                    json!({ "result": Self::transaction(txid, Some(&funding(txid))) })
+                } else {
+                    json!({
+                        "error": { "code": -5, "message": "No such mempool or blockchain transaction" }
+                    })
+                }
+            }
+            "estimatesmartfee" => match self.feerate {
+                Some(feerate) => json!({ "result": { "feerate": feerate, "blocks": params[0] } }),
+                None => json!({
+                    "result": { "errors": ["Insufficient data or no feerate found"], "blocks": 0 }
+                }),
+            },
+            // Any hex accepted to mempool, txid is hash of whole data
+            "sendrawtransaction" => match hex::decode(params[0].as_str().unwrap()) {
+                Ok(raw) if !raw.is_empty() => {
+                    let txid = wtxid(&raw);
+                    self.add_mempool(&txid);
+                    json!({ "result": txid })
+                }
+                _ => json!({ "error": { "code": -22, "message": "TX decode failed" } }),
+            },
+            _ => json!({ "error": { "code": -32601, "message": "Method not found" } }),
+        }
+    }
+}
+
+// Spent outpoint (internal byte order), script and witness
+type RawInput = ([u8; 32], u32, Vec<u8>, Vec<Vec<u8>>);
+
+// Transaction in network serialization, outputs with value and script
+struct RawTx {
+    inputs: Vec<RawInput>,
+    outputs: Vec<(u64, Vec<u8>)>,
+}
+
+impl RawTx {
+    fn serialize(&self, witness: bool) -> Vec<u8> {
+        let mut data = 2u32.to_le_bytes().to_vec();
+        if witness {
+            data.extend_from_slice(&[0, 1]);
+        }
+        data.push(self.inputs.len() as u8);
+        for (txid, vout, script, _) in self.inputs.iter() {
+            data.extend_from_slice(txid);
+            data.extend_from_slice(&vout.to_le_bytes());
+            data.push(script.len() as u8);
+            data.extend_from_slice(script);
+            data.extend_from_slice(&u32::MAX.to_le_bytes());
+        }
+        data.push(self.outputs.len() as u8);
+        for (value, script) in self.outputs.iter() {
+            data.extend_from_slice(&value.to_le_bytes());
+            data.push(script.len() as u8);
+            data.extend_from_slice(script);
+        }
+        if witness {
+            for (_, _, _, items) in self.inputs.iter() {
+                data.push(items.len() as u8);
+                for item in items {
+                    data.push(item.len() as u8);
+                    data.extend_from_slice(item);
+                }
+            }
+        }
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data
+    }
+
+    // Txid and wtxid, internal byte order
+    fn hashes(&self) -> ([u8; 32], [u8; 32]) {
+        (
+            sha256d(&self.serialize(false)),
+            sha256d(&self.serialize(true)),
+        )
+    }
+
+    fn weight(&self) -> usize {
+        self.serialize(false).len() * 3 + self.serialize(true).len()
+    }
+
+    // Same as in bitcoind REST JSON, except fee from undo data
+    fn json(&self) -> ResponseBlockTransaction {
+        let (mut txid, mut wtxid) = self.hashes();
+        txid.reverse();
+        wtxid.reverse();
+        let vin = self
+            .inputs
+            .iter()
+            .map(|(txid, vout, _, witness)| {
+                let coinbase = *vout == u32::MAX;
+                let mut txid = *txid;
+                txid.reverse();
+                ResponseTransactionInput {
+                    txid: Some(hex::encode(txid)).filter(|_| !coinbase),
+                    vout: Some(*vout).filter(|_| !coinbase),
+                    txinwitness: witness.iter().map(hex::encode).collect(),
+                }
+            })
+            .collect();
+        let vout = self
+            .outputs
+            .iter()
+            .map(|(value, script)| ResponseTransactionOutput {
+                value: *value as f64 / 1e8,
+                script_pubkey: ResponseScriptPubKey {
+                    hex: script.clone(),
+                },
+            })
+            .collect();
+        ResponseBlockTransaction {
+            txid: hex::encode(txid),
+            hash: hex::encode(wtxid),
+            size: self.serialize(true).len() as u32,
+            vsize: Some(self.weight().div_ceil(4) as u32),
+            fee: None,
+            vin,
+            vout,
+        }
+    }
+}
+
+// Pay to witness key hash with key hash from name
+fn p2wpkh(name: &str) -> Vec<u8> {
+    let mut script = vec![0x00, 0x14];
+    script.extend_from_slice(&Sha256::digest(name.as_bytes())[..20]);
+    script
+}
+
+// Script of output spent by `mine_raw(name)` block
+pub fn funding_script(name: &str) -> Vec<u8> {
+    p2wpkh(&funding(name))
+}
+
+// Minimal push of height, as in coinbase of bitcoind (BIP34)
+fn script_number(height: u32) -> Vec<u8> {
+    match height {
+        1..=16 => vec![0x50 + height as u8],
+        _ => {
+            let mut bytes = height.to_le_bytes().to_vec();
+            while bytes.len() > 1 && bytes[bytes.len() - 1] == 0 && bytes[bytes.len() - 2] < 0x80 {
+                bytes.pop();
+            }
+            let mut script = vec![bytes.len() as u8];
+            script.extend_from_slice(&bytes);
+            script
+        }
+    }
+}
+
+// Txid for test transaction name
+pub fn txid(name: &str) -> String {
+    hex::encode(Sha256::digest(name.as_bytes()))
+}
+
+// Name of transaction with output spent by transaction, output index is zero
+pub fn funding(txid: &str) -> String {
+    format!("funding {}", txid)
+}
+
+#[derive(Clone)]
+pub struct MockBitcoind {
+    pub url: String,
+    chain: Arc<Mutex<MockChain>>,
+}
+
+impl MockBitcoind {
+    // Start server with chain of `blocks` blocks on random port
+    pub fn start(blocks: usize) -> Self {
+        let mut chain = MockChain::default();
+        for _ in 0..blocks {
+            chain.mine_with(&[]);
+        }
+        let chain = Arc::new(Mutex::new(chain));
+
+        let make_svc = {
+            let chain = chain.clone();
+            make_service_fn(move |_| {
+                let chain = chain.clone();
+                async move { Ok::<_, Infallible>(service_fn(move |req| handle(chain.clone(), req))) }
+            })
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server = Server::bind(&addr).serve(make_svc);
+        let url = format!("http://user:pass@{}/", server.local_addr());
+        tokio::spawn(server);
+
+        MockBitcoind { url, chain }
+    }
+
+    pub fn with<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut MockChain) -> T,
+    {
+        f(&mut self.chain.lock().unwrap())
+    }
+}
+
+async fn handle(
+    chain: Arc<Mutex<MockChain>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, &'static str> {
+    {
+        let mut chain = chain.lock().unwrap();
+        if chain.drops > 0 {
+            chain.drops -= 1;
+            // Connection closed by hyper on service error
+            return Err("request dropped");
+        }
+    }
+    let stall = chain.lock().unwrap().stall;
+    if let Some(delay) = stall {
+        tokio::time::delay_for(delay).await;
+    }
+
+    let path = req.uri().path().to_owned();
+    if req.method() == Method::POST {
+        let refused = {
+            let mut chain = chain.lock().unwrap();
+            let auth = req.headers().get(hyper::header::AUTHORIZATION);
+            let refused = chain.auth.as_ref().is_some_and(|(username, password)| {
+                auth.map(|auth| auth.as_bytes()) != Some(&basic_auth(username, password)[..])
+            });
+            chain.unauthorized += usize::from(refused);
+            refused
+        };
+        if refused {
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Body::empty())
+                .unwrap());
+        }
+
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let mut chain = chain.lock().unwrap();
+        let response = match request.as_array() {
+            // Reversed, so client has to match responses by id
+            Some(batch) => batch
+                .iter()
+                .rev()
+                .map(|request| chain.rpc(request))
+                .collect(),
+            None => chain.rpc(&request),
+        };
+        return Ok(Response::new(Body::from(response.to_string())));
+    }
+
+    let mut chain = chain.lock().unwrap();
+    let body = if path == "/rest/chaininfo.json" {
+        Some(chain.blockchaininfo().to_string().into_bytes())
+    } else if let Some((hash, format)) = path
+        .strip_prefix("/rest/block/")
+        .and_then(|name| name.split_once('.'))
+    {
+        if chain.failed.contains(hash) {
+            return Ok(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("block read failed"))
+                .unwrap());
+        }
+        chain.fetch(hash, format)
+    } else if let Some((count, hash)) = path
+        .strip_prefix("/rest/headers/")
+        .and_then(|name| name.strip_suffix(".bin"))
+        .and_then(|name| name.split_once('/'))
+    {
+        chain.headers(count.parse().unwrap(), hash)
+    } else if let Some(hash) = path
+        .strip_prefix("/rest/spenttxouts/")
+        .and_then(|name| name.strip_suffix(".json"))
+    {
+        chain.blocks.get(hash).and_then(|block| block.spent.clone())
+    } else if let Some(txid) = path
+        .strip_prefix("/rest/tx/")
+        .and_then(|name| name.strip_suffix(".json"))
+    {
+        // Mempool only, as bitcoind without `-txindex`
+        chain.mempool.get(txid).map(|_| {
+            let tx = MockChain::transaction(txid, Some(&funding(txid)));
+            serde_json::to_vec(&tx).unwrap()
+        })
+    } else {
+        None
+    };
+    Ok(match body {
+        Some(body) => Response::new(Body::from(body)),
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    })
+}
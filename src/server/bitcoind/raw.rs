@@ -0,0 +1,255 @@
+// Blocks in network serialization, for binary and hex REST formats which are
+// smaller and faster to produce for bitcoind than JSON. Decoded with consensus
+// decoding of `bitcoin` crate, then converted to same structure as JSON block.
+// Height is not part of serialization, so it is taken from coinbase (BIP34)
+// and blocks before BIP34 (version 1) should be fetched as JSON. Fees require
+// undo data, so they are set only with spent outputs from `rest/spenttxouts`
+// (bitcoind 30+).
+
+use std::convert::TryFrom;
+
+use bitcoin::block::Version;
+use bitcoin::consensus::deserialize;
+use bitcoin::hashes::Hash;
+use bitcoin::opcodes::{Class, ClassifyContext};
+use bitcoin::script::Instruction;
+use bitcoin::{Block, BlockHash, Transaction};
+
+use super::json::{
+    ResponseBlock, ResponseBlockTransaction, ResponseScriptPubKey, ResponseSpentOutput,
+    ResponseTransactionInput, ResponseTransactionOutput,
+};
+
+pub type RawResult<T> = Result<T, &'static str>;
+
+// Whole data should be one block, without anything after it
+pub fn decode(data: &[u8]) -> RawResult<Block> {
+    deserialize(data).map_err(|_| "invalid block serialization")
+}
+
+// Height from first push of coinbase script (BIP34)
+pub fn block_height(block: &Block) -> RawResult<u32> {
+    let height = match block.bip34_block_height() {
+        Ok(height) => height,
+        // Heights up to 16 are pushed with `OP_1..OP_16` by bitcoind, which
+        // is not accepted by `bip34_block_height`
+        Err(_) if block.header.version >= Version::TWO => {
+            let coinbase = block.coinbase().ok_or("no height in coinbase")?;
+            let input = coinbase.input.first().ok_or("no height in coinbase")?;
+            match input.script_sig.instructions().next() {
+                Some(Ok(Instruction::Op(opcode))) => match opcode.classify(ClassifyContext::Legacy)
+                {
+                    Class::PushNum(value) if value > 0 => value as u64,
+                    _ => return Err("no height in coinbase"),
+                },
+                _ => return Err("no height in coinbase"),
+            }
+        }
+        Err(_) => return Err("no height in coinbase"),
+    };
+    u32::try_from(height).map_err(|_| "no height in coinbase")
+}
+
+pub fn to_response(block: &Block) -> RawResult<ResponseBlock> {
+    let height = block_height(block)?;
+    let header = &block.header;
+    // Genesis block has zero hash as previous
+    let previousblockhash = Some(header.prev_blockhash)
+        .filter(|hash| *hash != BlockHash::all_zeros())
+        .map(|hash| hash.to_string());
+
+    Ok(ResponseBlock {
+        hash: block.block_hash().to_string(),
+        height,
+        version: header.version.to_consensus(),
+        previousblockhash,
+        merkleroot: header.merkle_root.to_string(),
+        time: header.time,
+        bits: format!("{:08x}", header.bits.to_consensus()),
+        nonce: header.nonce,
+        size: block.total_size() as u32,
+        weight: Some(block.weight().to_wu() as u32),
+        transactions: block.txdata.iter().map(to_response_transaction).collect(),
+    })
+}
+
+fn to_response_transaction(tx: &Transaction) -> ResponseBlockTransaction {
+    let vin = tx
+        .input
+        .iter()
+        .map(|input| {
+            let outpoint = input.previous_output;
+            let coinbase = outpoint.is_null();
+            ResponseTransactionInput {
+                txid: Some(outpoint.txid.to_string()).filter(|_| !coinbase),
+                vout: Some(outpoint.vout).filter(|_| !coinbase),
+                txinwitness: input.witness.iter().map(hex::encode).collect(),
+            }
+        })
+        .collect();
+    let vout = tx
+        .output
+        .iter()
+        .map(|output| ResponseTransactionOutput {
+            value: output.value.to_sat() as f64 / 1e8,
+            script_pubkey: ResponseScriptPubKey {
+                hex: output.script_pubkey.to_bytes(),
+            },
+        })
+        .collect();
+
+    ResponseBlockTransaction {
+        txid: tx.compute_txid().to_string(),
+        hash: tx.compute_wtxid().to_string(),
+        size: tx.total_size() as u32,
+        vsize: Some(tx.vsize() as u32),
+        fee: None,
+        vin,
+        vout,
+    }
+}
+
+// Fees of non-coinbase transactions from outputs spent by every transaction,
+// coinbase entry can be omitted. Sums in satoshis, so fees are same as in JSON
+pub fn set_fees(block: &mut ResponseBlock, spent: &[Vec<ResponseSpentOutput>]) -> RawResult<()> {
+    let skip = match block.transactions.len().checked_sub(spent.len()) {
+        Some(0) if !spent.is_empty() => 1,
+        Some(1) => 0,
+        _ => return Err("spent outputs mismatch"),
+    };
+    let sats = |value: f64| (value * 1e8).round() as i64;
+    for (tx, outputs) in block.transactions[1..].iter_mut().zip(&spent[skip..]) {
+        if tx.vin.len() != outputs.len() {
+            return Err("spent outputs mismatch");
+        }
+        let input = outputs.iter().map(|output| sats(output.value)).sum::<i64>();
+        let output = tx.vout.iter().map(|output| sats(output.value)).sum::<i64>();
+        tx.fee = Some((input - output) as f64 / 1e8);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::block::Header;
+    use bitcoin::consensus::serialize;
+    use bitcoin::constants::genesis_block;
+    use bitcoin::{
+        absolute, transaction, Amount, CompactTarget, Network, OutPoint, ScriptBuf, Sequence, TxIn,
+        TxMerkleNode, TxOut, Witness,
+    };
+
+    use super::*;
+
+    // Block with coinbase script starting with `script` and one spend
+    fn block(version: i32, script: Vec<u8>) -> Block {
+        let tx = |previous_output, script_sig, witness| Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                script_sig,
+                sequence: Sequence::MAX,
+                witness,
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(5_000),
+                script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+            }],
+        };
+        let coinbase = tx(
+            OutPoint::null(),
+            ScriptBuf::from_bytes(script),
+            Witness::new(),
+        );
+        let outpoint = OutPoint::new(coinbase.compute_txid(), 0);
+        let witness = Witness::from_slice(&[vec![0x30; 72], vec![0x02; 33]]);
+        let spend = tx(outpoint, ScriptBuf::new(), witness);
+        Block {
+            header: Header {
+                version: Version::from_consensus(version),
+                prev_blockhash: BlockHash::from_byte_array([1; 32]),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_600_000_000,
+                bits: CompactTarget::from_consensus(0x207f_ffff),
+                nonce: 7,
+            },
+            txdata: vec![coinbase, spend],
+        }
+    }
+
+    #[test]
+    fn coinbase_height() {
+        let heights = vec![
+            (vec![0x55], Ok(5)),
+            (vec![0x60, 0xaa], Ok(16)),
+            (vec![0x01, 0x11], Ok(17)),
+            (vec![0x03, 0x20, 0xa1, 0x07], Ok(500_000)),
+            // Negative script number
+            (vec![0x01, 0x81], Err("no height in coinbase")),
+            (vec![0x00], Ok(0)),
+            (vec![], Err("no height in coinbase")),
+        ];
+        for (script, height) in heights {
+            let block = block(2, script.clone());
+            assert_eq!(block_height(&block), height, "{:?}", script);
+        }
+
+        // Height push is not required before version 2 (BIP34)
+        assert!(block_height(&block(1, vec![0x55])).is_err());
+        assert!(block_height(&genesis_block(Network::Bitcoin)).is_err());
+    }
+
+    #[test]
+    fn decode_block() {
+        let block = block(0x2000_0000, vec![0x03, 0x20, 0xa1, 0x07]);
+        let mut data = serialize(&block);
+        let decoded = to_response(&decode(&data).unwrap()).unwrap();
+
+        assert_eq!(decoded.hash, block.block_hash().to_string());
+        assert_eq!(decoded.height, 500_000);
+        assert_eq!(decoded.version, 0x2000_0000);
+        assert_eq!(decoded.previousblockhash, Some("01".repeat(32)));
+        assert_eq!((decoded.time, decoded.nonce), (1_600_000_000, 7));
+        assert_eq!(decoded.bits, "207fffff");
+        assert_eq!(decoded.size as usize, data.len());
+        assert_eq!(decoded.weight, Some(block.weight().to_wu() as u32));
+
+        let (coinbase, spend) = (&decoded.transactions[0], &decoded.transactions[1]);
+        assert_eq!(
+            (coinbase.vin[0].txid.as_ref(), coinbase.vin[0].vout),
+            (None, None)
+        );
+        assert_eq!(spend.vin[0].txid.as_ref(), Some(&coinbase.txid));
+        assert_eq!(spend.vin[0].txinwitness.len(), 2);
+        assert_ne!(spend.txid, spend.hash);
+        assert!(spend.vsize.unwrap() < spend.size);
+        assert_eq!(spend.vout[0].value, 0.00005);
+        assert_eq!(spend.vout[0].script_pubkey.hex, vec![0x51]);
+
+        // Nothing allowed after block
+        data.push(0);
+        assert!(decode(&data).is_err());
+        assert!(decode(&data[..80]).is_err());
+    }
+
+    #[test]
+    fn spent_outputs_fees() {
+        let block = block(2, vec![0x55]);
+        let mut decoded = to_response(&block).unwrap();
+        let spent = |value| ResponseSpentOutput {
+            value,
+            script_pubkey: ResponseScriptPubKey { hex: vec![] },
+        };
+
+        // Coinbase entry is optional
+        set_fees(&mut decoded, &[vec![spent(0.0001)]]).unwrap();
+        assert_eq!(decoded.transactions[1].fee, Some(0.00005));
+        set_fees(&mut decoded, &[vec![], vec![spent(0.00006)]]).unwrap();
+        assert_eq!(decoded.transactions[1].fee, Some(0.00001));
+        assert_eq!(decoded.transactions[0].fee, None);
+
+        assert!(set_fees(&mut decoded, &[]).is_err());
+        assert!(set_fees(&mut decoded, &[vec![spent(0.1), spent(0.1)]]).is_err());
+    }
+}
@@ -3,22 +3,90 @@
 // See issue in bitcoin repo: https://github.com/bitcoin/bitcoin/issues/15925
 
 use std::fmt;
+use std::str::FromStr;
 use std::time::Duration;
 
+use bitcoin::Block;
+use bytes::Bytes;
+use log::{debug, warn};
 use reqwest::{header, redirect, Client, ClientBuilder, RequestBuilder};
 use url::Url;
 
-use super::{json::*, BitcoindError, BitcoindResult};
+use super::chaos::CHAOS;
+use super::header::{decode_headers, verify_header};
+use super::merkle::verify_merkle_root;
+use super::raw;
+use super::retry::RetryPolicy;
+use super::{json::*, node_name, BitcoindError, BitcoindResult};
+use crate::server::metrics::METRICS;
+
+// Smallest and fastest for bitcoind first, JSON has height of blocks before
+// BIP34
+pub const DEFAULT_FORMATS: [RestFormat; 3] = [RestFormat::Bin, RestFormat::Hex, RestFormat::Json];
+
+// Body formats of REST endpoints
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestFormat {
+    Bin,
+    Hex,
+    Json,
+}
+
+impl FromStr for RestFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bin" => Ok(RestFormat::Bin),
+            "hex" => Ok(RestFormat::Hex),
+            "json" => Ok(RestFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for RestFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestFormat::Bin => write!(f, "bin"),
+            RestFormat::Hex => write!(f, "hex"),
+            RestFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+// Block as received, should be parsed with `parse_block`. Binary and hex are
+// decoded on receive, so body which can not be decoded is replaced by next
+// format, hashing and verification are left for `parse_block`. Raw block comes
+// with `rest/spenttxouts` body if bitcoind has it, fees are computed from it
+#[derive(Debug)]
+pub enum BlockBody {
+    Json(Bytes),
+    Raw(Box<Block>, Option<Bytes>),
+}
+
+impl BlockBody {
+    // Block in network serialization, with height in coinbase
+    pub fn decode_raw(data: &[u8], spent: Option<Bytes>) -> BitcoindResult<Self> {
+        let block = raw::decode(data).map_err(BitcoindError::RawParse)?;
+        raw::block_height(&block).map_err(BitcoindError::RawParse)?;
+        Ok(BlockBody::Raw(Box::new(block), spent))
+    }
+}
 
 pub struct RESTClient {
     client: Client,
     url: Url,
+    retry: RetryPolicy,
+    // Formats of block bodies in preference order, never empty
+    formats: Vec<RestFormat>,
 }
 
 impl fmt::Debug for RESTClient {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("RESTClient")
             .field("url", &self.url)
+            .field("formats", &self.formats)
             .finish()
     }
 }
@@ -41,23 +109,70 @@ impl RESTClient {
         Ok(RESTClient {
             client: client.build().map_err(BitcoindError::Reqwest)?,
             url,
+            retry: RetryPolicy::default(),
+            formats: DEFAULT_FORMATS.to_vec(),
         })
     }
 
+    // Empty list keeps current formats
+    pub fn set_formats(&mut self, formats: &[RestFormat]) {
+        if !formats.is_empty() {
+            self.formats = formats.to_vec();
+        }
+    }
+
+    pub fn set_retry(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    pub fn formats(&self) -> &[RestFormat] {
+        &self.formats
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    // Same client with new connection pool
+    pub fn recycle(&self) -> BitcoindResult<Self> {
+        let mut client = Self::new(self.url.clone())?;
+        client.retry = self.retry;
+        client.formats = self.formats.clone();
+        Ok(client)
+    }
+
     fn request(&self, path: &str) -> RequestBuilder {
         let mut url = self.url.clone();
         url.set_path(path);
         self.client.get(url)
     }
 
-    pub async fn getblockchaininfo(&self) -> BitcoindResult<ResponseBlockchainInfo> {
-        let timeout = Duration::from_millis(200);
+    // Status code and body, response can be faked by chaos injection
+    async fn get(&self, path: &str, timeout: Option<Duration>) -> BitcoindResult<(u16, Bytes)> {
+        if let Some(response) = CHAOS.inject().await {
+            return response;
+        }
 
-        let res_fut = self.request("rest/chaininfo.json").timeout(timeout).send();
-        let res = res_fut.await.map_err(BitcoindError::Reqwest)?;
-        let status_code = res.status().as_u16();
+        self.retry
+            .run(|| async {
+                let mut req = self.request(path);
+                if let Some(timeout) = timeout {
+                    req = req.timeout(timeout);
+                }
+                let res = req.send().await.map_err(BitcoindError::Reqwest)?;
+                let status_code = res.status().as_u16();
 
-        let body = res.bytes().await.map_err(BitcoindError::Reqwest)?;
+                // Should be serde_json::from_reader
+                let body = res.bytes().await.map_err(BitcoindError::Reqwest)?;
+                METRICS.record_bitcoind(node_name(&self.url), "rest", 0, body.len());
+                Ok((status_code, body))
+            })
+            .await
+    }
+
+    pub async fn getblockchaininfo(&self) -> BitcoindResult<ResponseBlockchainInfo> {
+        let timeout = Duration::from_millis(200);
+        let (status_code, body) = self.get("rest/chaininfo.json", Some(timeout)).await?;
 
         match status_code {
             200 => serde_json::from_slice(&body).map_err(BitcoindError::ResponseParse),
@@ -69,28 +184,198 @@ impl RESTClient {
     }
 
     pub async fn getblock(&self, hash: &str) -> BitcoindResult<Option<ResponseBlock>> {
-        let res_fut = self.request(&format!("rest/block/{}.json", hash)).send();
-        let res = res_fut.await.map_err(BitcoindError::Reqwest)?;
+        match self.getblock_body(hash).await? {
+            Some(body) => parse_block(hash, &body).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Block only in given format, preferred formats are ignored
+    pub async fn getblock_as(
+        &self,
+        hash: &str,
+        format: RestFormat,
+    ) -> BitcoindResult<Option<ResponseBlock>> {
+        match self.getblock_body_as(hash, format).await? {
+            Some(body) => parse_block(hash, &body).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Outputs spent by every block transaction, from undo data. `None` if
+    // block is not found or bitcoind has no endpoint (before 30.0)
+    pub async fn getspenttxouts(
+        &self,
+        hash: &str,
+    ) -> BitcoindResult<Option<Vec<Vec<ResponseSpentOutput>>>> {
+        let path = format!("rest/spenttxouts/{}", hash);
+        match self.get_body(&path, RestFormat::Json).await? {
+            Some(body) => serde_json::from_slice(&body)
+                .map(Some)
+                .map_err(BitcoindError::ResponseParse),
+            None => Ok(None),
+        }
+    }
 
-        let status_code = res.status().as_u16();
+    pub async fn getblockhex(&self, hash: &str) -> BitcoindResult<Option<String>> {
+        let path = format!("rest/block/{}", hash);
+        let body = self.get_body(&path, RestFormat::Hex).await?;
+        Ok(body.map(|body| String::from_utf8_lossy(&body).trim().to_owned()))
+    }
+
+    // Fetch block without parsing, so parsing can be moved out of runtime
+    // thread. Formats tried in preference order, next format is used if block
+    // is not found, response is not valid or has no height (before BIP34)
+    pub async fn getblock_body(&self, hash: &str) -> BitcoindResult<Option<BlockBody>> {
+        let (last, formats) = self.formats.split_last().unwrap();
+        for format in formats {
+            match self.getblock_body_as(hash, *format).await {
+                Ok(Some(body)) => return Ok(Some(body)),
+                // Not retried, so retries of transport errors are not multiplied
+                Err(BitcoindError::Reqwest(err)) => return Err(BitcoindError::Reqwest(err)),
+                Ok(None) => debug!("Block {} not found in {} format", hash, format),
+                Err(err) => warn!("Block {} in {} format failed: {}", hash, format, err),
+            }
+        }
+        self.getblock_body_as(hash, *last).await
+    }
+
+    async fn getblock_body_as(
+        &self,
+        hash: &str,
+        format: RestFormat,
+    ) -> BitcoindResult<Option<BlockBody>> {
+        let path = format!("rest/block/{}", hash);
+        let body = match self.get_body(&path, format).await? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        let data = match format {
+            RestFormat::Json => return Ok(Some(BlockBody::Json(body))),
+            RestFormat::Bin => body.to_vec(),
+            RestFormat::Hex => {
+                let hex = String::from_utf8_lossy(&body);
+                hex::decode(hex.trim()).map_err(|_| BitcoindError::RawParse("invalid hex"))?
+            }
+        };
+        let path = format!("rest/spenttxouts/{}", hash);
+        let spent = self.get_body(&path, RestFormat::Json).await?;
+        BlockBody::decode_raw(&data, spent).map(Some)
+    }
+
+    // Body of `{path}.{format}`, `None` if not found
+    async fn get_body(&self, path: &str, format: RestFormat) -> BitcoindResult<Option<Bytes>> {
+        let path = format!("{}.{}", path, format);
+        let (status_code, body) = self.get(&path, None).await?;
         if status_code == 404 {
             return Ok(None);
         }
 
-        // Should be serde_json::from_reader
-        let body_fut = res.bytes();
-        let body = body_fut.await.map_err(BitcoindError::Reqwest)?;
         if status_code != 200 {
             let msg = String::from_utf8_lossy(&body).trim().to_owned();
             return Err(BitcoindError::ResultRest(status_code, msg));
         }
 
-        let parsed = serde_json::from_slice(&body);
-        let block: ResponseBlock = parsed.map_err(BitcoindError::ResponseParse)?;
-        if block.hash != hash {
-            return Err(BitcoindError::ResultMismatch);
+        Ok(Some(body))
+    }
+
+    // Hash and previous hash of up to `count` headers of active chain, from
+    // `hash` forward. `None` if block is not found
+    pub async fn getheaders(
+        &self,
+        count: usize,
+        hash: &str,
+    ) -> BitcoindResult<Option<Vec<(String, String)>>> {
+        let path = format!("rest/headers/{}/{}", count, hash);
+        match self.get_body(&path, RestFormat::Bin).await? {
+            Some(body) => decode_headers(&body).map(Some),
+            None => Ok(None),
         }
+    }
+
+    // Mempool transaction or confirmed transaction if bitcoind has `-txindex`,
+    // always in JSON, because only it has block hash of transaction
+    pub async fn gettransaction(
+        &self,
+        txid: &str,
+    ) -> BitcoindResult<Option<ResponseRawTransaction>> {
+        let path = format!("rest/tx/{}", txid);
+        match self.get_body(&path, RestFormat::Json).await? {
+            Some(body) => serde_json::from_slice(&body)
+                .map(Some)
+                .map_err(BitcoindError::ResponseParse),
+            None => Ok(None),
+        }
+    }
+}
+
+// Parse block and check that this is requested block with valid header and
+// transactions
+pub fn parse_block(hash: &str, body: &BlockBody) -> BitcoindResult<ResponseBlock> {
+    let block = match body {
+        BlockBody::Json(body) => {
+            serde_json::from_slice(body).map_err(BitcoindError::ResponseParse)?
+        }
+        BlockBody::Raw(block, spent) => {
+            let mut block = raw::to_response(block).map_err(BitcoindError::RawParse)?;
+            if let Some(spent) = spent {
+                let spent: Vec<Vec<ResponseSpentOutput>> =
+                    serde_json::from_slice(spent).map_err(BitcoindError::ResponseParse)?;
+                raw::set_fees(&mut block, &spent).map_err(BitcoindError::RawParse)?;
+            }
+            block
+        }
+    };
+    if block.hash != hash {
+        return Err(BitcoindError::ResultMismatch);
+    }
+    verify_header(&block)?;
+    verify_merkle_root(&block)?;
+
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockBitcoind;
+    use super::*;
+
+    fn client(node: &MockBitcoind, formats: &[RestFormat]) -> RESTClient {
+        let mut client = RESTClient::new(Url::parse(&node.url).unwrap()).unwrap();
+        client.set_formats(formats);
+        client
+    }
+
+    #[tokio::test]
+    async fn formats_fallback() {
+        let node = MockBitcoind::start(5);
+        let raw = node.with(|node| node.mine_raw("raw"));
+        let json = client(&node, &[RestFormat::Json]);
+        let expected = json.getblock(&raw.hash).await.unwrap().unwrap();
+        let expected = serde_json::to_value(expected).unwrap();
+
+        // Binary first by default
+        let default = client(&node, &[]);
+        assert_eq!(default.formats(), &DEFAULT_FORMATS);
+        let body = default.getblock_body(&raw.hash).await.unwrap().unwrap();
+        assert!(matches!(body, BlockBody::Raw(..)));
+
+        // Not decoded binary and hex replaced by JSON
+        node.with(|node| node.corrupt(&raw.hash));
+        let body = default.getblock_body(&raw.hash).await.unwrap().unwrap();
+        assert!(matches!(body, BlockBody::Json(_)));
+        let block = parse_block(&raw.hash, &body).unwrap();
+        assert_eq!(serde_json::to_value(block).unwrap(), expected);
+
+        // Error of last format returned
+        let bin = client(&node, &[RestFormat::Hex, RestFormat::Bin]);
+        let error = bin.getblock_body(&raw.hash).await.unwrap_err();
+        assert!(matches!(error, BitcoindError::RawParse(_)), "{}", error);
 
-        Ok(Some(block))
+        // Block without binary form is not found in it
+        let hash = node.with(|node| node.chain()[3].hash.clone());
+        let body = default.getblock_body(&hash).await.unwrap().unwrap();
+        assert!(matches!(body, BlockBody::Json(_)));
+        assert!(bin.getblock_body(&hash).await.unwrap().is_none());
     }
 }
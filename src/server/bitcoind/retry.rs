@@ -0,0 +1,79 @@
+// Retries of requests failed before response from bitcoind (node restarted,
+// connection refused or reset, timeout), so short outages pause callers
+// instead of failing them. Delays grow exponentially with random jitter, so
+// several clients do not hit restarted node at same moments.
+
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+use super::{BitcoindError, BitcoindResult};
+
+// Upper bound of delay between attempts
+const DELAY_MAX: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    // Attempts after first, 0 disables retries
+    pub retries: u32,
+    // Delay before first retry, doubled for every next
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Run request until success, not transient error or retries exhausted
+    pub async fn run<T, F, Fut>(&self, mut request: F) -> BitcoindResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = BitcoindResult<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match request().await {
+                Err(err) if attempt < self.retries && is_transient(&err) => {
+                    let delay = self.backoff(attempt);
+                    attempt += 1;
+                    warn!(
+                        "Bitcoind request failed ({}), retry {}/{} in {:?}",
+                        err, attempt, self.retries, delay
+                    );
+                    tokio::time::delay_for(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Random delay from half to full of exponential delay
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .delay
+            .checked_mul(1 << attempt.min(16))
+            .map_or(DELAY_MAX, |delay| delay.min(DELAY_MAX));
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        let jitter = f64::from(nanos) / 1e9;
+        delay.mul_f64(0.5 + jitter / 2.0)
+    }
+}
+
+// Request was not answered, redirects are disabled and status is not checked
+// by reqwest, so other errors are transport errors
+fn is_transient(err: &BitcoindError) -> bool {
+    match err {
+        BitcoindError::Reqwest(err) => !err.is_builder(),
+        _ => false,
+    }
+}
@@ -1,18 +1,41 @@
+use std::collections::HashMap;
 use std::fmt;
-use std::sync::Arc;
-use std::time::Duration;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use reqwest::{header, redirect, Client, ClientBuilder};
+use reqwest::{header, redirect, Client, ClientBuilder, StatusCode};
 use tokio::sync::Mutex;
 use url::Url;
 
+use super::chaos::CHAOS;
 use super::error::{BitcoindError, BitcoindResult};
-use super::json::{Request, Response, ResponseBlockchainInfo, ResponseRawMempool};
+use super::json::{
+    Request, Response, ResponseBanned, ResponseBlockHeader, ResponseBlockTransaction,
+    ResponseBlockchainInfo, ResponseMempoolAccept, ResponseNetworkInfo, ResponsePeerInfo,
+    ResponseRawMempool, ResponseRawTransaction, ResponseSmartFee, ResponseSubmitPackage,
+    ResponseTxOut, ResponseValidateAddress,
+};
+use super::retry::RetryPolicy;
+use super::{basic_auth, node_name};
+use crate::server::metrics::METRICS;
 
 pub struct RPCClient {
     client: Client,
     url: Url,
     req_id: Arc<Mutex<u64>>,
+    // Credentials from cookie file instead of URL
+    cookie: Option<PathBuf>,
+    auth: StdMutex<RPCAuth>,
+    retry: RetryPolicy,
+}
+
+// Authorization header with modification time of cookie file it was read from
+#[derive(Debug)]
+struct RPCAuth {
+    header: Option<header::HeaderValue>,
+    mtime: Option<SystemTime>,
 }
 
 impl fmt::Debug for RPCClient {
@@ -25,53 +48,135 @@ impl fmt::Debug for RPCClient {
 }
 
 impl RPCClient {
-    // Construct new RPCClient for specified URL
-    pub fn new(url: Url, auth: Vec<u8>) -> BitcoindResult<Self> {
-        let mut headers = header::HeaderMap::with_capacity(2);
-        headers.insert(
-            header::AUTHORIZATION,
-            header::HeaderValue::from_bytes(&auth)
-                .expect("Not possible build auth from provided username/password"),
-        );
-        headers.insert(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("applicaiton/json"),
-        );
-
-        let client = ClientBuilder::new()
-            .connect_timeout(Duration::from_millis(100))
-            .timeout(Duration::from_secs(30))
-            .default_headers(headers)
-            .no_gzip()
-            .redirect(redirect::Policy::none());
+    // Construct new RPCClient for specified URL, credentials are read from
+    // cookie file on first request if it is set
+    pub fn new(url: Url, auth: Vec<u8>, cookie: Option<PathBuf>) -> BitcoindResult<Self> {
+        let auth = RPCAuth {
+            header: match cookie {
+                Some(_) => None,
+                None => Some(
+                    header::HeaderValue::from_bytes(&auth)
+                        .expect("Not possible build auth from provided username/password"),
+                ),
+            },
+            mtime: None,
+        };
 
         Ok(RPCClient {
-            client: client.build().map_err(BitcoindError::Reqwest)?,
+            client: build_client()?,
             url,
             req_id: Arc::new(Mutex::new(0)),
+            cookie,
+            auth: StdMutex::new(auth),
+            retry: RetryPolicy::default(),
         })
     }
 
+    pub fn set_retry(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    // Same client with new connection pool, credentials and request ids kept
+    pub fn recycle(&self) -> BitcoindResult<Self> {
+        let auth = self.auth.lock().unwrap();
+        Ok(RPCClient {
+            client: build_client()?,
+            url: self.url.clone(),
+            req_id: self.req_id.clone(),
+            cookie: self.cookie.clone(),
+            auth: StdMutex::new(RPCAuth {
+                header: auth.header.clone(),
+                mtime: auth.mtime,
+            }),
+            retry: self.retry,
+        })
+    }
+
+    pub fn cookie(&self) -> Option<&Path> {
+        self.cookie.as_deref()
+    }
+
+    // Cookie is read again if file was modified (bitcoind restarted) or if
+    // `reload` is set after `401 Unauthorized`
+    fn auth_header(&self, reload: bool) -> BitcoindResult<header::HeaderValue> {
+        let mut auth = self.auth.lock().unwrap();
+        if let Some(path) = &self.cookie {
+            let mtime = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+            if reload || auth.header.is_none() || mtime != auth.mtime {
+                let content = fs::read_to_string(path)
+                    .map_err(|err| BitcoindError::Cookie(path.clone(), err))?;
+                let mut parts = content.trim().splitn(2, ':');
+                let (username, password) = match (parts.next(), parts.next()) {
+                    (Some(username), Some(password)) => (username, password),
+                    _ => return Err(BitcoindError::InvalidCookie(path.clone())),
+                };
+                let header = header::HeaderValue::from_bytes(&basic_auth(username, password))
+                    .map_err(|_| BitcoindError::InvalidCookie(path.clone()))?;
+                auth.header = Some(header);
+                auth.mtime = mtime;
+            }
+        }
+        Ok(auth.header.clone().unwrap())
+    }
+
     async fn get_next_req_id(&self) -> u64 {
         let mut req_id = self.req_id.lock().await;
         *req_id = req_id.wrapping_add(1);
         *req_id
     }
 
+    // `Response` for single call or list of them for batch, duration recorded
+    // by `method` for failed requests too
     async fn request<T: serde::de::DeserializeOwned>(
         &self,
+        method: &str,
         body: Vec<u8>,
-    ) -> BitcoindResult<Response<T>> {
-        let res_fut = self.client.post(self.url.clone()).body(body).send();
-        let res = res_fut.await.map_err(BitcoindError::Reqwest)?;
+    ) -> BitcoindResult<T> {
+        let started = Instant::now();
+        let body = self.send(body).await;
+        METRICS.observe_rpc(method, started.elapsed());
+        serde_json::from_slice(&body?).map_err(BitcoindError::ResponseParse)
+    }
+
+    async fn send(&self, body: Vec<u8>) -> BitcoindResult<bytes::Bytes> {
+        // Status is ignored as for real response
+        if let Some(response) = CHAOS.inject().await {
+            return response.map(|(_, body)| body);
+        }
+
+        self.retry.run(|| self.send_once(&body)).await
+    }
+
+    async fn send_once(&self, body: &[u8]) -> BitcoindResult<bytes::Bytes> {
+        let auth = self.auth_header(false)?;
+        let req = self.client.post(self.url.clone());
+        let res_fut = req
+            .header(header::AUTHORIZATION, auth)
+            .body(body.to_vec())
+            .send();
+        let mut res = res_fut.await.map_err(BitcoindError::Reqwest)?;
+        // New cookie can have same mtime on file systems with coarse timestamps
+        if res.status() == StatusCode::UNAUTHORIZED && self.cookie.is_some() {
+            let auth = self.auth_header(true)?;
+            let req = self.client.post(self.url.clone());
+            let res_fut = req
+                .header(header::AUTHORIZATION, auth)
+                .body(body.to_vec())
+                .send();
+            res = res_fut.await.map_err(BitcoindError::Reqwest)?;
+        }
+        if res.status() == StatusCode::UNAUTHORIZED {
+            return Err(BitcoindError::Unauthorized);
+        }
 
         // We ignore status, because expect error information in the body
         // let status = res.status();
 
         // Should be serde_json::from_reader
         let body_fut = res.bytes();
-        let body = body_fut.await.map_err(BitcoindError::Reqwest)?;
-        serde_json::from_slice(&body).map_err(BitcoindError::ResponseParse)
+        let data = body_fut.await.map_err(BitcoindError::Reqwest)?;
+        METRICS.record_bitcoind(node_name(&self.url), "rpc", body.len(), data.len());
+        Ok(data)
     }
 
     async fn call<T: serde::de::DeserializeOwned>(
@@ -88,10 +193,14 @@ impl RPCClient {
         })
         .expect("Invalid data for building JSON");
 
-        let data = self.request::<T>(body).await?;
+        let data = self.request::<Response<T>>(method, body).await?;
         if data.id != req_id {
             return Err(BitcoindError::NonceMismatch);
         }
+        Self::result(data)
+    }
+
+    fn result<T>(data: Response<T>) -> BitcoindResult<T> {
         if let Some(error) = data.error {
             return Err(BitcoindError::ResultRPC(error));
         }
@@ -101,28 +210,259 @@ impl RPCClient {
         }
     }
 
+    // Several calls in one request, results in order of calls. Error of one
+    // call do not fail others, responses matched by id because bitcoind can
+    // reorder them.
+    pub async fn call_batch<T: serde::de::DeserializeOwned>(
+        &self,
+        calls: &[(&str, Vec<serde_json::Value>)],
+    ) -> BitcoindResult<Vec<BitcoindResult<T>>> {
+        if calls.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut requests = Vec::with_capacity(calls.len());
+        for (method, params) in calls.iter() {
+            requests.push(Request {
+                method,
+                params: Some(&params[..]),
+                id: self.get_next_req_id().await,
+            });
+        }
+        let body = serde_json::to_vec(&requests).expect("Invalid data for building JSON");
+
+        let data = self.request::<Vec<Response<T>>>(calls[0].0, body).await?;
+        let mut responses = data
+            .into_iter()
+            .map(|response| (response.id, response))
+            .collect::<HashMap<_, _>>();
+        Ok(requests
+            .iter()
+            .map(|request| match responses.remove(&request.id) {
+                Some(response) => Self::result(response),
+                None => Err(BitcoindError::NonceMismatch),
+            })
+            .collect())
+    }
+
+    // For methods without result, `null` returned on success
+    async fn call_void(
+        &self,
+        method: &str,
+        params: Option<&[serde_json::Value]>,
+    ) -> BitcoindResult<()> {
+        match self.call::<serde_json::Value>(method, params).await {
+            Ok(_) | Err(BitcoindError::ResultNotFound) => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
     pub async fn getblockchaininfo(&self) -> BitcoindResult<ResponseBlockchainInfo> {
         self.call("getblockchaininfo", None).await
     }
 
+    pub async fn getnetworkinfo(&self) -> BitcoindResult<ResponseNetworkInfo> {
+        self.call("getnetworkinfo", None).await
+    }
+
+    pub async fn getpeerinfo(&self) -> BitcoindResult<Vec<ResponsePeerInfo>> {
+        self.call("getpeerinfo", None).await
+    }
+
+    pub async fn listbanned(&self) -> BitcoindResult<Vec<ResponseBanned>> {
+        self.call("listbanned", None).await
+    }
+
+    // Ban for `bantime` seconds or until `bantime` Unix time if `absolute`
+    pub async fn setban(
+        &self,
+        subnet: &str,
+        add: bool,
+        bantime: Option<u64>,
+        absolute: bool,
+    ) -> BitcoindResult<()> {
+        let command = if add { "add" } else { "remove" };
+        let mut params = vec![subnet.into(), command.into()];
+        if add {
+            params.push(bantime.unwrap_or(0).into());
+            params.push(absolute.into());
+        }
+        self.call_void("setban", Some(&params)).await
+    }
+
+    // Command is `add`, `remove` or `onetry`
+    pub async fn addnode(&self, node: &str, command: &str) -> BitcoindResult<()> {
+        let params = [node.into(), command.into()];
+        self.call_void("addnode", Some(&params)).await
+    }
+
+    // Disconnect by address or by node id, address should be empty for id
+    pub async fn disconnectnode(&self, address: &str, nodeid: Option<u64>) -> BitcoindResult<()> {
+        let mut params = vec![address.into()];
+        if let Some(nodeid) = nodeid {
+            params.push(nodeid.into());
+        }
+        self.call_void("disconnectnode", Some(&params)).await
+    }
+
+    // Mine blocks to output descriptor, regtest only
+    pub async fn generatetodescriptor(
+        &self,
+        blocks: u32,
+        descriptor: &str,
+    ) -> BitcoindResult<Vec<String>> {
+        let params = [blocks.into(), descriptor.into()];
+        self.call("generatetodescriptor", Some(&params)).await
+    }
+
+    pub async fn invalidateblock(&self, hash: &str) -> BitcoindResult<()> {
+        let params = [hash.into()];
+        self.call_void("invalidateblock", Some(&params)).await
+    }
+
     pub async fn getblockhash(&self, height: u32) -> BitcoindResult<Option<String>> {
         let params = [height.into()];
-        match self.call::<String>("getblockhash", Some(&params)).await {
+        Self::block_hash(self.call("getblockhash", Some(&params)).await)
+    }
+
+    // Hashes of several blocks in one request
+    pub async fn getblockhashes(&self, heights: &[u32]) -> BitcoindResult<Vec<Option<String>>> {
+        let calls = heights
+            .iter()
+            .map(|height| ("getblockhash", vec![(*height).into()]))
+            .collect::<Vec<_>>();
+        let results = self.call_batch(&calls).await?;
+        results.into_iter().map(Self::block_hash).collect()
+    }
+
+    fn block_hash(result: BitcoindResult<String>) -> BitcoindResult<Option<String>> {
+        match result {
             Ok(hash) => Ok(Some(hash)),
-            Err(BitcoindError::ResultRPC(error)) => {
-                // Block height out of range
-                if error.code == -8 {
-                    Ok(None)
-                } else {
-                    Err(BitcoindError::ResultRPC(error))
-                }
-            }
+            // Block height out of range
+            Err(BitcoindError::ResultRPC(error)) if error.code == -8 => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    // Mempool transactions in one request, `None` for transactions which
+    // already left mempool. Confirmed transactions only with `-txindex`
+    pub async fn getrawtransactions(
+        &self,
+        txids: &[String],
+    ) -> BitcoindResult<Vec<Option<ResponseBlockTransaction>>> {
+        let calls = txids
+            .iter()
+            .map(|txid| ("getrawtransaction", vec![txid.as_str().into(), true.into()]))
+            .collect::<Vec<_>>();
+        let results = self.call_batch(&calls).await?;
+        results
+            .into_iter()
+            .map(|result| match result {
+                Ok(tx) => Ok(Some(tx)),
+                // No such mempool or blockchain transaction
+                Err(BitcoindError::ResultRPC(error)) if error.code == -5 => Ok(None),
+                Err(error) => Err(error),
+            })
+            .collect()
+    }
+
+    // Confirmed transaction without `-txindex` only if block hash is given
+    pub async fn getrawtransaction(
+        &self,
+        txid: &str,
+        blockhash: Option<&str>,
+    ) -> BitcoindResult<Option<ResponseRawTransaction>> {
+        let mut params = vec![txid.into(), true.into()];
+        if let Some(blockhash) = blockhash {
+            params.push(blockhash.into());
+        }
+        match self.call("getrawtransaction", Some(&params)).await {
+            Ok(tx) => Ok(Some(tx)),
+            // No such mempool or blockchain transaction
+            Err(BitcoindError::ResultRPC(error)) if error.code == -5 => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    // Output not found or spent if `None`
+    pub async fn gettxout(
+        &self,
+        txid: &str,
+        vout: u32,
+        include_mempool: bool,
+    ) -> BitcoindResult<Option<ResponseTxOut>> {
+        let params = [txid.into(), vout.into(), include_mempool.into()];
+        match self.call("gettxout", Some(&params)).await {
+            Ok(txout) => Ok(Some(txout)),
+            // Result is `null`
+            Err(BitcoindError::ResultNotFound) => Ok(None),
             Err(error) => Err(error),
         }
     }
 
+    pub async fn validateaddress(&self, address: &str) -> BitcoindResult<ResponseValidateAddress> {
+        let params = [address.into()];
+        self.call("validateaddress", Some(&params)).await
+    }
+
+    pub async fn getblockheader(&self, hash: &str) -> BitcoindResult<ResponseBlockHeader> {
+        let params = [hash.into()];
+        self.call("getblockheader", Some(&params)).await
+    }
+
     pub async fn getrawmempool(&self) -> BitcoindResult<ResponseRawMempool> {
         let params = [true.into()];
         self.call("getrawmempool", Some(&params)).await
     }
+
+    // Several transactions tested as package (bitcoind 22+)
+    pub async fn testmempoolaccept(
+        &self,
+        rawtxs: &[String],
+        maxfeerate: Option<f64>,
+    ) -> BitcoindResult<Vec<ResponseMempoolAccept>> {
+        let mut params = vec![rawtxs.into()];
+        if let Some(maxfeerate) = maxfeerate {
+            params.push(maxfeerate.into());
+        }
+        self.call("testmempoolaccept", Some(&params)).await
+    }
+
+    pub async fn submitpackage(&self, rawtxs: &[String]) -> BitcoindResult<ResponseSubmitPackage> {
+        let params = [rawtxs.into()];
+        self.call("submitpackage", Some(&params)).await
+    }
+
+    // `mode` is `conservative` or `economical`
+    pub async fn estimatesmartfee(
+        &self,
+        target: u16,
+        mode: &str,
+    ) -> BitcoindResult<ResponseSmartFee> {
+        let params = [target.into(), mode.into()];
+        self.call("estimatesmartfee", Some(&params)).await
+    }
+
+    // Returns txid of accepted transaction
+    pub async fn sendrawtransaction(&self, rawtx: &str) -> BitcoindResult<String> {
+        let params = [rawtx.into()];
+        self.call("sendrawtransaction", Some(&params)).await
+    }
+}
+
+fn build_client() -> BitcoindResult<Client> {
+    let mut headers = header::HeaderMap::with_capacity(1);
+    headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("applicaiton/json"),
+    );
+
+    ClientBuilder::new()
+        .connect_timeout(Duration::from_millis(100))
+        .timeout(Duration::from_secs(30))
+        .default_headers(headers)
+        .no_gzip()
+        .redirect(redirect::Policy::none())
+        .build()
+        .map_err(BitcoindError::Reqwest)
 }
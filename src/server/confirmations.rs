@@ -0,0 +1,76 @@
+// Time from mempool entry to confirmation of transactions, grouped by fee rate
+// band, so fee estimates can be compared with what actually happened. Entry
+// time is reported by bitcoind (`time` of `getrawmempool`), confirmation time
+// is block header time, so catching up after downtime does not skew samples.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::json;
+
+// Lower bounds of fee rate bands in sat/vB, last band unbounded
+const BANDS: [f64; 8] = [0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+// Latest confirmations kept in every band for medians
+const SAMPLES_MAX: usize = 1000;
+
+#[derive(Debug)]
+pub struct ConfirmationTimes {
+    // Seconds, by band
+    bands: Mutex<Vec<VecDeque<u64>>>,
+}
+
+impl Default for ConfirmationTimes {
+    fn default() -> Self {
+        ConfirmationTimes {
+            bands: Mutex::new(vec![VecDeque::new(); BANDS.len()]),
+        }
+    }
+}
+
+impl ConfirmationTimes {
+    // Transaction seen in mempool since `entry_time` confirmed in block with
+    // `block_time`, block time can be earlier than entry time
+    pub fn record(&self, vsize: u32, fee: u64, entry_time: u64, block_time: u64) {
+        let rate = fee as f64 / f64::from(vsize.max(1));
+        let band = BANDS.iter().rposition(|min| rate >= *min).unwrap_or(0);
+
+        let mut bands = self.bands.lock().unwrap();
+        let samples = &mut bands[band];
+        if samples.len() == SAMPLES_MAX {
+            samples.pop_front();
+        }
+        samples.push_back(block_time.saturating_sub(entry_time));
+    }
+
+    pub fn report(&self) -> json::ConfirmationTimes {
+        let bands = self.bands.lock().unwrap();
+        let bands = bands
+            .iter()
+            .enumerate()
+            .map(|(i, samples)| {
+                let mut samples = samples.iter().copied().collect::<Vec<_>>();
+                samples.sort_unstable();
+                json::ConfirmationTimesBand {
+                    min: BANDS[i],
+                    max: BANDS.get(i + 1).copied(),
+                    samples: samples.len(),
+                    median: median(&samples),
+                }
+            })
+            .collect();
+        json::ConfirmationTimes {
+            bands,
+            skew_correction: None,
+        }
+    }
+}
+
+// Sorted samples, mean of two middle samples for even count
+fn median(samples: &[u64]) -> Option<u64> {
+    let len = samples.len();
+    match len {
+        0 => None,
+        _ if len % 2 == 1 => Some(samples[len / 2]),
+        _ => Some((samples[len / 2 - 1] + samples[len / 2]) / 2),
+    }
+}
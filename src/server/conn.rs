@@ -0,0 +1,349 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future;
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use log::{error, info, warn};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::time::{delay_for, Delay, Instant};
+use tokio_rustls::server::TlsStream;
+
+use crate::signals::ShutdownReceiver;
+
+const ACCEPT_QUEUE_SIZE: usize = 128;
+
+// Limits for client connections, so slow or stuck clients can not exhaust
+// single-threaded runtime
+#[derive(Debug, Clone, Copy)]
+pub struct ConnLimits {
+    // Time for receiving headers of first request (including TLS handshake)
+    pub header_timeout: Duration,
+    // Time for receiving request body
+    pub read_timeout: Duration,
+    // Time for which write can be blocked by client
+    pub write_timeout: Duration,
+    // Connection without reads and writes closed after this time
+    pub idle_timeout: Duration,
+    // Time for handling request until response is ready, `None` disables
+    pub request_timeout: Option<Duration>,
+    pub max_body_size: usize,
+    pub max_connections: usize,
+}
+
+impl Default for ConnLimits {
+    fn default() -> Self {
+        ConnLimits {
+            header_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(120),
+            request_timeout: Some(Duration::from_secs(30)),
+            max_body_size: 64 * 1024,
+            max_connections: 1024,
+        }
+    }
+}
+
+// Client connection with timeouts on IO
+#[derive(Debug)]
+pub struct Conn<T> {
+    io: T,
+    remote: SocketAddr,
+    limits: ConnLimits,
+    headers_received: Arc<AtomicBool>,
+    header_deadline: Delay,
+    idle_deadline: Delay,
+    write_deadline: Option<Delay>,
+    _counter: ConnCounter,
+}
+
+impl<T> Conn<T> {
+    fn new(io: T, remote: SocketAddr, limits: ConnLimits, counter: ConnCounter) -> Self {
+        Conn {
+            io,
+            remote,
+            limits,
+            headers_received: Arc::new(AtomicBool::new(false)),
+            header_deadline: delay_for(limits.header_timeout),
+            idle_deadline: delay_for(limits.idle_timeout),
+            write_deadline: None,
+            _counter: counter,
+        }
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote
+    }
+
+    // Flag set by service on first request, header timeout not checked after that
+    pub fn headers_received(&self) -> Arc<AtomicBool> {
+        self.headers_received.clone()
+    }
+
+    fn on_activity(&mut self) {
+        self.idle_deadline
+            .reset(Instant::now() + self.limits.idle_timeout);
+    }
+
+    fn poll_read_timeouts(&mut self, cx: &mut Context) -> Poll<io::Error> {
+        if !self.headers_received.load(Ordering::Relaxed)
+            && Pin::new(&mut self.header_deadline).poll(cx).is_ready()
+        {
+            return Poll::Ready(io::Error::new(io::ErrorKind::TimedOut, "header timeout"));
+        }
+        if Pin::new(&mut self.idle_deadline).poll(cx).is_ready() {
+            return Poll::Ready(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"));
+        }
+        Poll::Pending
+    }
+
+    fn poll_write_timeout(&mut self, cx: &mut Context) -> Poll<io::Error> {
+        let write_timeout = self.limits.write_timeout;
+        let deadline = self
+            .write_deadline
+            .get_or_insert_with(|| delay_for(write_timeout));
+        match Pin::new(deadline).poll(cx) {
+            Poll::Ready(()) => {
+                Poll::Ready(io::Error::new(io::ErrorKind::TimedOut, "write timeout"))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Conn<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.io).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                this.on_activity();
+                Poll::Ready(result)
+            }
+            Poll::Pending => this.poll_read_timeouts(cx).map(Err),
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Conn<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.io).poll_write(cx, buf) {
+            Poll::Ready(result) => {
+                this.write_deadline = None;
+                this.on_activity();
+                Poll::Ready(result)
+            }
+            Poll::Pending => this.poll_write_timeout(cx).map(Err),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.io).poll_flush(cx) {
+            Poll::Ready(result) => {
+                this.write_deadline = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => this.poll_write_timeout(cx).map(Err),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+// Access to connection under TLS
+pub trait AsConn {
+    fn as_conn(&self) -> &Conn<AddrStream>;
+}
+
+impl AsConn for Conn<AddrStream> {
+    fn as_conn(&self) -> &Conn<AddrStream> {
+        self
+    }
+}
+
+impl AsConn for TlsStream<Conn<AddrStream>> {
+    fn as_conn(&self) -> &Conn<AddrStream> {
+        self.get_ref().0
+    }
+}
+
+// Number of open connections, decremented on drop
+#[derive(Debug)]
+struct ConnCounter(Arc<AtomicUsize>);
+
+impl ConnCounter {
+    fn acquire(count: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        if count.fetch_add(1, Ordering::SeqCst) >= max {
+            count.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(ConnCounter(count.clone()))
+    }
+}
+
+impl Drop for ConnCounter {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Accept connections and run `handshake` (e.g. TLS) for every connection in
+// separate task, so slow client do not block other connections
+pub fn accept<F, Fut, C>(
+    mut incoming: AddrIncoming,
+    limits: ConnLimits,
+    mut shutdown: ShutdownReceiver,
+    handshake: F,
+) -> mpsc::Receiver<io::Result<C>>
+where
+    F: Fn(Conn<AddrStream>) -> Fut + Send + 'static,
+    Fut: Future<Output = io::Result<C>> + Send + 'static,
+    C: Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(ACCEPT_QUEUE_SIZE);
+    let count = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(async move {
+        loop {
+            let accept = future::poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx));
+            let stream = tokio::select! {
+                stream = accept => stream,
+                _ = shutdown.recv() => break,
+            };
+            let stream = match stream {
+                Some(Ok(stream)) => stream,
+                Some(Err(err)) => {
+                    error!("accept error: {}", err);
+                    continue;
+                }
+                None => break,
+            };
+
+            let remote = stream.remote_addr();
+            let counter = match ConnCounter::acquire(&count, limits.max_connections) {
+                Some(counter) => counter,
+                None => {
+                    warn!("Connections limit reached, drop connection from {}", remote);
+                    continue;
+                }
+            };
+
+            let conn = Conn::new(stream, remote, limits, counter);
+            let handshake = handshake(conn);
+            let mut tx = tx.clone();
+            tokio::spawn(async move {
+                match handshake.await {
+                    Ok(conn) => {
+                        let _ = tx.send(Ok(conn)).await;
+                    }
+                    Err(err) => info!("Handshake with {} failed: {}", remote, err),
+                }
+            });
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // IO which never becomes ready
+    struct Stuck;
+
+    impl AsyncRead for Stuck {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context,
+            _: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    impl AsyncWrite for Stuck {
+        fn poll_write(self: Pin<&mut Self>, _: &mut Context, _: &[u8]) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Pending
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn conn(limits: ConnLimits) -> (Conn<Stuck>, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = ConnCounter::acquire(&count, 1).unwrap();
+        let remote = "127.0.0.1:8000".parse().unwrap();
+        (Conn::new(Stuck, remote, limits, counter), count)
+    }
+
+    #[test]
+    fn counter() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let first = ConnCounter::acquire(&count, 2).unwrap();
+        let second = ConnCounter::acquire(&count, 2).unwrap();
+        assert!(ConnCounter::acquire(&count, 2).is_none());
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        drop(first);
+        let third = ConnCounter::acquire(&count, 2).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        drop((second, third));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn timeouts() {
+        let limits = ConnLimits {
+            header_timeout: Duration::from_millis(20),
+            write_timeout: Duration::from_millis(20),
+            idle_timeout: Duration::from_millis(60),
+            ..ConnLimits::default()
+        };
+        let mut buf = [0; 8];
+
+        let (mut stream, count) = conn(limits);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "header timeout");
+        drop(stream);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        let (mut stream, _) = conn(limits);
+        stream.headers_received().store(true, Ordering::Relaxed);
+        let started = Instant::now();
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.to_string(), "idle timeout");
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        let (mut stream, _) = conn(limits);
+        let err = stream.write(b"data").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "write timeout");
+        let err = stream.flush().await.unwrap_err();
+        assert_eq!(err.to_string(), "write timeout");
+    }
+}
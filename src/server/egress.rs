@@ -0,0 +1,97 @@
+// Budgets of bytes sent to API clients (responses and WebSocket events) per
+// UTC day or month, for servers on metered links. After budget is spent data
+// routes answer `503` and WebSocket connections are closed until next
+// window, admin routes and metrics are still served. Usage is kept in memory,
+// so it starts from zero after restart.
+
+use std::sync::Mutex;
+
+use super::json;
+use super::quotas::{unix_time, Period};
+use crate::units;
+
+#[derive(Debug, Clone)]
+pub struct EgressBudget {
+    pub period: Period,
+    // Bytes
+    pub limit: u64,
+}
+
+// Parse budget in form `period=size`, size with units (`10GiB`)
+pub fn parse_egress_budget(value: &str) -> Option<EgressBudget> {
+    let mut parts = value.splitn(2, '=');
+    let period = parts.next()?.parse::<Period>().ok()?;
+    let limit = units::parse_size(parts.next()?, "B").ok()?;
+    Some(EgressBudget { period, limit })
+}
+
+#[derive(Debug)]
+pub struct EgressBudgets {
+    // With window and bytes sent in it
+    budgets: Mutex<Vec<(EgressBudget, String, u64)>>,
+}
+
+impl EgressBudgets {
+    pub fn new(budgets: Vec<EgressBudget>) -> Self {
+        let now = unix_time();
+        let budgets = budgets
+            .into_iter()
+            .map(|budget| {
+                let window = budget.period.window(now);
+                (budget, window, 0)
+            })
+            .collect();
+        EgressBudgets {
+            budgets: Mutex::new(budgets),
+        }
+    }
+
+    pub fn record(&self, bytes: usize) {
+        let now = unix_time();
+        let mut budgets = self.budgets.lock().unwrap();
+        for (budget, window, used) in budgets.iter_mut() {
+            Self::roll(budget, window, used, now);
+            *used += bytes as u64;
+        }
+    }
+
+    // First spent budget with seconds until its reset
+    pub fn exceeded(&self) -> Option<(Period, u64)> {
+        let now = unix_time();
+        let mut budgets = self.budgets.lock().unwrap();
+        budgets.iter_mut().find_map(|(budget, window, used)| {
+            Self::roll(budget, window, used, now);
+            if *used >= budget.limit {
+                Some((budget.period, budget.period.reset_after(now)))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn report(&self) -> Vec<json::EgressBudget> {
+        let now = unix_time();
+        let mut budgets = self.budgets.lock().unwrap();
+        budgets
+            .iter_mut()
+            .map(|(budget, window, used)| {
+                Self::roll(budget, window, used, now);
+                json::EgressBudget {
+                    period: budget.period.to_string(),
+                    window: window.clone(),
+                    limit: budget.limit,
+                    used: *used,
+                }
+            })
+            .collect()
+    }
+
+    // Usage starts from zero in new window
+    fn roll(budget: &EgressBudget, window: &mut String, used: &mut u64, now: i64) {
+        let current = budget.period.window(now);
+        if *window != current {
+            *window = current;
+            *used = 0;
+        }
+    }
+}
@@ -1,9 +1,16 @@
-use std::io::Error as IOError;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
 use hyper::error::Error as HyperError;
+use hyper::StatusCode;
 
 use super::bitcoind::BitcoindError;
+use super::crosscheck::CrossCheckError;
+use super::pipeline::PipelineError;
+use super::plugins::PluginError;
+use super::rules::RulesError;
+use super::storage::StorageError;
+use crate::exit::{CliError, ErrorClass};
 
 quick_error! {
     #[derive(Debug)]
@@ -11,22 +18,168 @@ quick_error! {
         Bitcoind(err: BitcoindError) {
             display("bitcoind: {}", err)
         }
-        ListenHostPortParse(err: IOError) {
-            display("Listen host:port parse error: {}", err)
-        }
-        ListenHostPortNotFound {
-            display(r#"Nothing to listen, please check "--listen" argument"#)
+        InvalidArgument(name: &'static str, value: String) {
+            display(r#"Invalid value "{}" for "--{}""#, value, name)
         }
         HyperBind(addr: SocketAddr, err: HyperError) {
             display("Address ({}) bind error: {}", addr, err)
         }
+        Tls(path: PathBuf, err: String) {
+            display("TLS config error ({}): {}", path.display(), err)
+        }
+        Storage(err: StorageError) {
+            display("storage: {}", err)
+        }
+        Plugin(err: PluginError) {
+            display("{}", err)
+        }
+        Rules(err: RulesError) {
+            display("{}", err)
+        }
+        BitcoindSwitch(reason: String) {
+            display("bitcoind switch rejected: new node is {}", reason)
+        }
+        CrossCheck(err: CrossCheckError) {
+            display("{}", err)
+        }
+        Pipeline(err: PipelineError) {
+            display("{}", err)
+        }
+        // Chain is not loaded yet by first update
+        NotReady {
+            display("Blocks are not loaded yet")
+        }
         NotEnoughBlocks {
             display("Not enough blocks for app")
         }
         InvalidBlockchain {
             display("Invalid blockchain")
         }
+        InvalidBlockTime(hash: String, reason: &'static str) {
+            display("Invalid block {} time: {}", hash, reason)
+        }
+        UpdateLoop(err: String) {
+            display("update loop failed: {}", err)
+        }
+        SoakViolation(reason: String) {
+            display("soak: {}", reason)
+        }
     }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
+
+// Errors of API handlers, sent as `json::ErrorResponse` with status
+quick_error! {
+    #[derive(Debug)]
+    pub enum ApiError {
+        // Malformed path parameter, query or body
+        BadRequest(msg: String) {
+            display("{}", msg)
+        }
+        Unauthorized {
+            display("Unauthorized")
+        }
+        NotFound(msg: String) {
+            display("{}", msg)
+        }
+        // Request to bitcoind failed, object not found on node is 404
+        Bitcoind(err: BitcoindError) {
+            from()
+            display("bitcoind: {}", err)
+        }
+        Internal(msg: String) {
+            display("{}", msg)
+        }
+        // Statuses specific for few routes (conflict, payload too large, ...)
+        Status(status: StatusCode, msg: String) {
+            display("{}", msg)
+        }
+    }
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Bitcoind(BitcoindError::ResultNotFound) => StatusCode::NOT_FOUND,
+            ApiError::Bitcoind(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Status(status, _) => *status,
+        }
+    }
+}
+
+impl AppError {
+    pub fn to_cli_error(&self) -> CliError {
+        let config = |code| CliError::new(ErrorClass::Config, code, self);
+        match self {
+            AppError::Bitcoind(err) => err.to_cli_error("bitcoind: "),
+            AppError::InvalidArgument(..) => {
+                config("invalid_argument").with_hint("Run with --help for expected values")
+            }
+            AppError::HyperBind(..) => CliError::new(ErrorClass::Network, "bind", self)
+                .with_hint("Check that address is not used by another process"),
+            AppError::Tls(..) => config("tls").with_hint("Check --tls-cert and --tls-key files"),
+            AppError::Storage(_) => CliError::new(ErrorClass::Internal, "storage", self)
+                .with_hint("Check --storage path permissions and free space"),
+            AppError::Plugin(_) => config("plugin"),
+            AppError::Rules(_) => config("rules"),
+            AppError::BitcoindSwitch(_) => CliError::new(ErrorClass::Node, "bitcoind_switch", self),
+            AppError::CrossCheck(CrossCheckError::Bitcoind(err)) => err.to_cli_error(""),
+            AppError::CrossCheck(CrossCheckError::InvalidUrl(_)) => config("cross_check_url"),
+            AppError::CrossCheck(CrossCheckError::Reqwest(_)) => {
+                CliError::new(ErrorClass::Network, "cross_check_unreachable", self)
+            }
+            AppError::CrossCheck(CrossCheckError::InvalidResponse(_)) => {
+                CliError::new(ErrorClass::Node, "cross_check_response", self)
+            }
+            AppError::Pipeline(PipelineError::Bitcoind(err)) => err.to_cli_error("bitcoind: "),
+            AppError::Pipeline(PipelineError::BlockNotFound(_)) => {
+                CliError::new(ErrorClass::Node, "block_not_found", self)
+            }
+            AppError::Pipeline(PipelineError::Task(_)) => {
+                CliError::new(ErrorClass::Internal, "task", self)
+            }
+            AppError::NotReady => CliError::new(ErrorClass::Node, "not_ready", self),
+            AppError::NotEnoughBlocks => CliError::new(ErrorClass::Node, "not_enough_blocks", self)
+                .with_hint("Wait until bitcoind has more blocks than --blocks-keep"),
+            AppError::InvalidBlockchain | AppError::InvalidBlockTime(..) => {
+                CliError::new(ErrorClass::Node, "invalid_chain", self)
+            }
+            AppError::UpdateLoop(_) => CliError::new(ErrorClass::Internal, "update_loop", self),
+            AppError::SoakViolation(_) => CliError::new(ErrorClass::Internal, "soak", self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_error_classes() {
+        let error = AppError::InvalidArgument("blocks-keep", "0".to_owned()).to_cli_error();
+        assert_eq!(
+            (error.class, error.code),
+            (ErrorClass::Config, "invalid_argument")
+        );
+        assert_eq!(error.class.exit_code(), 2);
+
+        // Message of wrapped bitcoind error is prefixed as in display
+        let error = AppError::Bitcoind(BitcoindError::ResultNotFound).to_cli_error();
+        assert_eq!(
+            (error.class, error.class.exit_code()),
+            (ErrorClass::Node, 4)
+        );
+        assert_eq!(
+            error.to_json()["message"],
+            "bitcoind: Requested object not found"
+        );
+
+        let error = AppError::NotReady.to_cli_error();
+        assert_eq!((error.class, error.code), (ErrorClass::Node, "not_ready"));
+    }
+}
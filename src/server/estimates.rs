@@ -0,0 +1,48 @@
+// Cache of `estimatesmartfee` results. Estimates of bitcoind change only with
+// new blocks, so results are kept until our tip is changed and clients
+// polling fee estimate do not reach bitcoind on every request.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::bitcoind::json::ResponseSmartFee;
+use super::json::FeeEstimateMode;
+
+#[derive(Debug, Default)]
+pub struct FeeEstimates {
+    cache: Mutex<FeeEstimatesCache>,
+}
+
+#[derive(Debug, Default)]
+struct FeeEstimatesCache {
+    tip: String,
+    // Results by target and mode
+    estimates: HashMap<(u16, FeeEstimateMode), ResponseSmartFee>,
+}
+
+impl FeeEstimates {
+    // Cached result if tip is not changed
+    pub fn get(&self, tip: &str, target: u16, mode: FeeEstimateMode) -> Option<ResponseSmartFee> {
+        let cache = self.cache.lock().unwrap();
+        if cache.tip != tip {
+            return None;
+        }
+        cache.estimates.get(&(target, mode)).cloned()
+    }
+
+    // Results for previous tip dropped
+    pub fn insert(
+        &self,
+        tip: &str,
+        target: u16,
+        mode: FeeEstimateMode,
+        estimate: ResponseSmartFee,
+    ) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.tip != tip {
+            cache.tip = tip.to_owned();
+            cache.estimates.clear();
+        }
+        cache.estimates.insert((target, mode), estimate);
+    }
+}
@@ -0,0 +1,763 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use super::format::JsonFormat;
+use super::hooks::{EventHook, HookRunner, HookStats};
+use super::json;
+use super::proto;
+use crate::signals::ShutdownReceiver;
+use crate::units;
+
+const EVENTS_CHANNEL_CAPACITY: usize = 10_000;
+const FLUSH_INTERVAL_MIN: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Blocks,
+    Mempool,
+    Plugins,
+    Alerts,
+    Large,
+}
+
+impl FromStr for Topic {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blocks" => Ok(Topic::Blocks),
+            "mempool" => Ok(Topic::Mempool),
+            "plugins" => Ok(Topic::Plugins),
+            "alerts" => Ok(Topic::Alerts),
+            "large" => Ok(Topic::Large),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Topic::Blocks => "blocks",
+            Topic::Mempool => "mempool",
+            Topic::Plugins => "plugins",
+            Topic::Alerts => "alerts",
+            Topic::Large => "large",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Encoding of events for subscriber, negotiated per WebSocket connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Protobuf,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "protobuf" => Ok(Format::Protobuf),
+            _ => Err(()),
+        }
+    }
+}
+
+// Block time used as chain-derived time of events: time of tip or median
+// time past of tip (never decrease along one chain)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChainTimeSource {
+    Tip,
+    Median,
+}
+
+impl FromStr for ChainTimeSource {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tip" => Ok(ChainTimeSource::Tip),
+            "mtp" => Ok(ChainTimeSource::Median),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ChainTimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainTimeSource::Tip => write!(f, "tip"),
+            ChainTimeSource::Median => write!(f, "mtp"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxEvent {
+    Added,
+    Confirmed,
+    Removed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockEvent {
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Tx(TxEvent, String),
+    // Block added to or removed (reorg) from our chain: height and hash
+    Block(BlockEvent, u32, String),
+    CaughtUp(String),
+    // Blocks removed from our chain and node chain followed again
+    Reorg(json::Reorg),
+    // Confirmed transaction with output to script watched by tenant
+    Watch(String, String),
+    // Timelock of output watched by tenant became spendable: tenant, txid, vout
+    Timelock(String, String, u32),
+    // Event with custom topic emitted by WASM plugin
+    Plugin(String, String),
+    // Mempool transaction matched by alert rule
+    Alert(String, String),
+    // Transaction moving more than `--large-tx` BTC
+    Large(json::LargeTx),
+    // Empty or near-full blocks in a row, reached `--block-streak`
+    Streak(json::BlockStreak),
+    // Tip disagree with cross-check source: source, local hash, remote hash
+    Divergence(String, String, String),
+    // Node from `--bitcoind` list on another chain than primary node
+    ChainSplit(json::ChainSplit),
+    // Periodic projection of next block, sent without txids
+    ProjectedBlock(Arc<json::ProjectedBlock>),
+    // Maintenance mode of API enabled or disabled, with message for clients
+    Maintenance(bool, String),
+    // Sent only to WebSocket client which missed events, with skipped count
+    Lagged(u64),
+}
+
+impl Event {
+    pub fn topic(&self) -> Topic {
+        match self {
+            Event::Tx(..) => Topic::Mempool,
+            Event::Block(..) => Topic::Blocks,
+            Event::CaughtUp(_) => Topic::Blocks,
+            Event::Reorg(_) => Topic::Blocks,
+            Event::Watch(..) => Topic::Blocks,
+            Event::Timelock(..) => Topic::Blocks,
+            Event::Plugin(..) => Topic::Plugins,
+            Event::Alert(..) => Topic::Alerts,
+            Event::Large(_) => Topic::Large,
+            Event::Streak(_) => Topic::Blocks,
+            Event::Divergence(..) => Topic::Alerts,
+            Event::ChainSplit(_) => Topic::Alerts,
+            Event::ProjectedBlock(_) => Topic::Mempool,
+            Event::Maintenance(..) => Topic::Alerts,
+            Event::Lagged(_) => Topic::Alerts,
+        }
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        match self {
+            Event::Watch(tenant, _) => Some(tenant),
+            Event::Timelock(tenant, ..) => Some(tenant),
+            _ => None,
+        }
+    }
+
+    // Events with same key replace each other in coalescing window
+    fn key(&self) -> String {
+        match self {
+            Event::Tx(_, hash) => hash.clone(),
+            Event::Block(_, _, hash) => hash.clone(),
+            Event::CaughtUp(hash) => hash.clone(),
+            Event::Reorg(reorg) => format!("reorg:{}", reorg.old_tip.hash),
+            Event::Watch(tenant, txid) => format!("{}:{}", tenant, txid),
+            Event::Timelock(tenant, txid, vout) => format!("{}:{}:{}", tenant, txid, vout),
+            Event::Plugin(topic, data) => format!("{}:{}", topic, data),
+            Event::Alert(rule, txid) => format!("{}:{}", rule, txid),
+            Event::Large(tx) => format!("large:{}", tx.txid),
+            Event::Streak(streak) => format!("streak:{}", streak.start_height),
+            Event::Divergence(source, _, remote) => format!("{}:{}", source, remote),
+            Event::ChainSplit(split) => format!("split:{}:{}", split.node, split.node_hash),
+            // Latest projection replaces previous
+            Event::ProjectedBlock(_) => "projected".to_owned(),
+            Event::Maintenance(..) => "maintenance".to_owned(),
+            Event::Lagged(_) => "lagged".to_owned(),
+        }
+    }
+
+    // Object with `type` field, for clients with `format=json`
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Event::Tx(event, txid) => {
+                let kind = match event {
+                    TxEvent::Added => "tx_added",
+                    TxEvent::Confirmed => "tx_confirmed",
+                    TxEvent::Removed => "tx_removed",
+                };
+                serde_json::json!({ "type": kind, "txid": txid })
+            }
+            Event::Block(event, height, hash) => {
+                let kind = match event {
+                    BlockEvent::Added => "block_added",
+                    BlockEvent::Removed => "block_removed",
+                };
+                serde_json::json!({ "type": kind, "height": height, "hash": hash })
+            }
+            Event::CaughtUp(hash) => serde_json::json!({ "type": "caught_up", "hash": hash }),
+            Event::Reorg(reorg) => serde_json::json!({
+                "type": "reorg",
+                "depth": reorg.depth,
+                "old_tip": reorg.old_tip,
+                "new_tip": reorg.new_tip,
+            }),
+            Event::Watch(_, txid) => serde_json::json!({ "type": "watch", "txid": txid }),
+            Event::Timelock(_, txid, vout) => {
+                serde_json::json!({ "type": "timelock", "txid": txid, "vout": vout })
+            }
+            Event::Plugin(topic, data) => {
+                serde_json::json!({ "type": "plugin", "topic": topic, "data": data })
+            }
+            Event::Alert(rule, txid) => {
+                serde_json::json!({ "type": "alert", "rule": rule, "txid": txid })
+            }
+            Event::Large(tx) => serde_json::json!({
+                "type": "large",
+                "txid": tx.txid,
+                "value": tx.value,
+                "height": tx.height,
+            }),
+            Event::Streak(streak) => serde_json::json!({
+                "type": "streak",
+                "kind": streak.kind,
+                "length": streak.length,
+                "start_height": streak.start_height,
+                "end_height": streak.end_height,
+            }),
+            Event::Divergence(source, local, remote) => serde_json::json!({
+                "type": "divergence",
+                "source": source,
+                "local": local,
+                "remote": remote,
+            }),
+            Event::ChainSplit(split) => serde_json::json!({
+                "type": "chain_split",
+                "node": split.node,
+                "height": split.height,
+                "primary_hash": split.primary_hash,
+                "node_hash": split.node_hash,
+            }),
+            Event::ProjectedBlock(block) => serde_json::json!({
+                "type": "projected_block",
+                "height": block.height,
+                "transactions": block.transactions,
+                "weight": block.weight,
+                "fees": block.fees,
+            }),
+            Event::Maintenance(enabled, message) => serde_json::json!({
+                "type": "maintenance",
+                "enabled": enabled,
+                "message": message,
+            }),
+            Event::Lagged(skipped) => serde_json::json!({ "type": "lagged", "skipped": skipped }),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Tx(event, hash) => write!(f, "{:?} tx: {}", event, hash),
+            Event::Block(event, _, hash) => write!(f, "{:?} block: {}", event, hash),
+            Event::CaughtUp(hash) => write!(f, "CaughtUp block: {}", hash),
+            Event::Reorg(reorg) => write!(
+                f,
+                "Reorg depth {}: old tip {}, new tip {}",
+                reorg.depth, reorg.old_tip.hash, reorg.new_tip.hash
+            ),
+            Event::Watch(_, txid) => write!(f, "Watch tx: {}", txid),
+            Event::Timelock(_, txid, vout) => write!(f, "Timelock expired: {}:{}", txid, vout),
+            Event::Plugin(topic, data) => write!(f, "Plugin {}: {}", topic, data),
+            Event::Alert(rule, txid) => write!(f, "Alert {} tx: {}", rule, txid),
+            Event::Large(tx) => write!(f, "Large tx {}: {} sat", tx.txid, tx.value),
+            Event::Streak(streak) => write!(
+                f,
+                "Streak {}: {} blocks, {}-{}",
+                streak.kind, streak.length, streak.start_height, streak.end_height
+            ),
+            Event::Divergence(source, local, remote) => write!(
+                f,
+                "Divergence {}: local {}, remote {}",
+                source, local, remote
+            ),
+            Event::ChainSplit(split) => write!(
+                f,
+                "ChainSplit {} at {}: primary {}, node {}",
+                split.node, split.height, split.primary_hash, split.node_hash
+            ),
+            Event::ProjectedBlock(block) => write!(
+                f,
+                "ProjectedBlock {}: {} txs, weight {}, fees {} sat",
+                block.height, block.transactions, block.weight, block.fees
+            ),
+            Event::Maintenance(true, message) => write!(f, "Maintenance on: {}", message),
+            Event::Maintenance(false, _) => write!(f, "Maintenance off"),
+            Event::Lagged(skipped) => write!(f, "Lagged: {} events skipped", skipped),
+        }
+    }
+}
+
+// Message for subscribers, messages with tenant delivered only to that tenant.
+// Sequence is strictly increasing across all topics of channel, starts from 1
+// and has no gaps except filtered by subscriber (topics, tenant). Messages
+// created for one connection (lagged, resume replay) have sequence 0.
+#[derive(Debug, Clone)]
+pub struct BusMessage {
+    pub tenant: Option<String>,
+    pub event: Event,
+    pub seq: u64,
+    pub time: EventTime,
+}
+
+// Server time when event was published (unix milliseconds) and chain time at
+// that moment (seconds), `None` until first block tracked. Coalesced event
+// has time of last merged event, so only sequence defines order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventTime {
+    pub received: u64,
+    pub chain: Option<u32>,
+}
+
+impl EventTime {
+    fn now(chain: Option<u32>) -> Self {
+        let received = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as u64)
+            .unwrap_or(0);
+        EventTime { received, chain }
+    }
+}
+
+impl BusMessage {
+    // Message outside of bus sequence, for one connection only
+    pub fn local(event: Event) -> Self {
+        BusMessage {
+            tenant: None,
+            event,
+            seq: 0,
+            time: EventTime::now(None),
+        }
+    }
+
+    pub fn is_visible(&self, tenant: Option<&str>) -> bool {
+        self.tenant.is_none() || self.tenant.as_deref() == tenant
+    }
+
+    // JSON output options applied to JSON events, as to API responses
+    pub fn encode(&self, format: Format, json_format: &JsonFormat) -> Message {
+        match format {
+            Format::Text => Message::text(self.event.to_string()),
+            Format::Json => {
+                let mut value = self.event.to_json();
+                value["seq"] = self.seq.into();
+                value["received"] = self.time.received.into();
+                value["chain_time"] = self.time.chain.into();
+                let value = if json_format.is_default() {
+                    value
+                } else {
+                    json_format.apply(value)
+                };
+                Message::text(value.to_string())
+            }
+            Format::Protobuf => Message::binary(proto::encode_event(self)),
+        }
+    }
+}
+
+// Parse coalescing window in form `topic=duration` (milliseconds without unit)
+pub fn parse_coalesce_window(value: &str) -> Option<(Topic, Duration)> {
+    let mut parts = value.splitn(2, '=');
+    let topic = parts.next()?.parse::<Topic>().ok()?;
+    let window = units::parse_duration(parts.next()?, "ms").ok()?;
+    Some((topic, window))
+}
+
+// Events published to two channels:
+//   raw: every event immediately
+//   coalesced: events with same key inside topic window merged, events which
+//     cancel each other (tx added and removed) are not sent at all
+// Every channel has own sequence
+#[derive(Debug)]
+pub struct EventBus {
+    raw: SequencedSender,
+    coalesced: SequencedSender,
+    windows: HashMap<Topic, Duration>,
+    pending: Mutex<HashMap<Topic, PendingTopic>>,
+    chain_time_source: ChainTimeSource,
+    // Time and median time past of our tip
+    tip_time: Mutex<Option<(u32, u32)>>,
+    // Hooks receive every event as is, like raw channel
+    hooks: broadcast::Sender<Event>,
+    hook_runners: Vec<HookRunner>,
+}
+
+// Sequence assigned under lock while message sent, so order of messages in
+// channel is order of sequence numbers even with concurrent publishers
+#[derive(Debug)]
+struct SequencedSender {
+    sender: broadcast::Sender<BusMessage>,
+    seq: Mutex<u64>,
+}
+
+impl SequencedSender {
+    fn new() -> Self {
+        SequencedSender {
+            sender: broadcast::channel(EVENTS_CHANNEL_CAPACITY).0,
+            seq: Mutex::new(0),
+        }
+    }
+
+    fn receiver_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<BusMessage> {
+        self.sender.subscribe()
+    }
+
+    fn send(&self, event: Event, time: EventTime) {
+        let mut seq = self.seq.lock().unwrap();
+        *seq += 1;
+        let message = BusMessage {
+            tenant: event.tenant().map(|tenant| tenant.to_owned()),
+            event,
+            seq: *seq,
+            time,
+        };
+        let _ = self.sender.send(message);
+    }
+}
+
+#[derive(Debug, Default)]
+struct PendingTopic {
+    keys: VecDeque<(Instant, String)>,
+    events: HashMap<String, PendingEvent>,
+}
+
+#[derive(Debug)]
+struct PendingEvent {
+    first: Event,
+    last: Event,
+    time: EventTime,
+}
+
+impl PendingEvent {
+    // Result event after window, `None` if nothing changed for consumer
+    fn merged(self) -> Option<(Event, EventTime)> {
+        match (&self.first, &self.last) {
+            (Event::Tx(TxEvent::Added, _), Event::Tx(TxEvent::Removed, _)) => None,
+            (Event::Tx(TxEvent::Removed, _), Event::Tx(TxEvent::Added, _)) => None,
+            (Event::Block(BlockEvent::Added, ..), Event::Block(BlockEvent::Removed, ..)) => None,
+            _ => Some((self.last, self.time)),
+        }
+    }
+}
+
+impl EventBus {
+    pub fn new(windows: HashMap<Topic, Duration>) -> Self {
+        let windows = windows
+            .into_iter()
+            .filter(|(_, window)| *window > Duration::from_millis(0))
+            .collect();
+
+        EventBus {
+            raw: SequencedSender::new(),
+            coalesced: SequencedSender::new(),
+            windows,
+            pending: Mutex::new(HashMap::new()),
+            chain_time_source: ChainTimeSource::Median,
+            tip_time: Mutex::new(None),
+            hooks: broadcast::channel(EVENTS_CHANNEL_CAPACITY).0,
+            hook_runners: vec![],
+        }
+    }
+
+    pub fn set_chain_time_source(&mut self, source: ChainTimeSource) {
+        self.chain_time_source = source;
+    }
+
+    // Called on every change of our tip, `None` when no blocks tracked
+    pub fn set_tip_time(&self, time: Option<(u32, u32)>) {
+        *self.tip_time.lock().unwrap() = time;
+    }
+
+    fn chain_time(&self) -> Option<u32> {
+        let tip_time = *self.tip_time.lock().unwrap();
+        tip_time.map(|(time, median_time)| match self.chain_time_source {
+            ChainTimeSource::Tip => time,
+            ChainTimeSource::Median => median_time,
+        })
+    }
+
+    pub fn add_hook(&mut self, hook: Box<dyn EventHook>) {
+        self.hook_runners.push(HookRunner::new(hook));
+    }
+
+    // Start receiving events in registered hooks
+    pub fn spawn_hooks(&self, shutdown: ShutdownReceiver) {
+        for runner in self.hook_runners.iter() {
+            runner.spawn(self.hooks.subscribe(), shutdown.clone());
+        }
+    }
+
+    pub fn hooks_stats(&self) -> Vec<HookStats> {
+        self.hook_runners
+            .iter()
+            .map(|runner| runner.stats())
+            .collect()
+    }
+
+    pub fn has_receivers(&self) -> bool {
+        self.raw.receiver_count() > 0
+            || self.coalesced.receiver_count() > 0
+            || self.hooks.receiver_count() > 0
+    }
+
+    pub fn publish(&self, event: Event) {
+        if !self.has_receivers() {
+            return;
+        }
+
+        if self.hooks.receiver_count() > 0 {
+            let _ = self.hooks.send(event.clone());
+        }
+
+        let time = EventTime::now(self.chain_time());
+        if self.raw.receiver_count() > 0 {
+            self.raw.send(event.clone(), time);
+        }
+
+        if self.coalesced.receiver_count() == 0 {
+            return;
+        }
+
+        let topic = event.topic();
+        if !self.windows.contains_key(&topic) {
+            self.coalesced.send(event, time);
+            return;
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        let pending = pending.entry(topic).or_default();
+        let key = event.key();
+        match pending.events.get_mut(&key) {
+            Some(item) => {
+                item.last = event;
+                item.time = time;
+            }
+            None => {
+                pending.keys.push_back((Instant::now(), key.clone()));
+                pending.events.insert(
+                    key,
+                    PendingEvent {
+                        first: event.clone(),
+                        last: event,
+                        time,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn subscribe(&self, raw: bool) -> broadcast::Receiver<BusMessage> {
+        if raw {
+            self.raw.subscribe()
+        } else {
+            self.coalesced.subscribe()
+        }
+    }
+
+    // Send coalesced events for which window elapsed
+    fn flush(&self) {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap();
+        for (topic, pending) in pending.iter_mut() {
+            let window = self.windows[topic];
+            while let Some((ts, _)) = pending.keys.front() {
+                if now.duration_since(*ts) < window {
+                    break;
+                }
+
+                let (_, key) = pending.keys.pop_front().unwrap();
+                let event = pending.events.remove(&key).unwrap();
+                if let Some((event, time)) = event.merged() {
+                    self.coalesced.send(event, time);
+                }
+            }
+        }
+    }
+
+    pub async fn run_flush_loop(&self, mut shutdown: ShutdownReceiver) {
+        let interval = match self.windows.values().min() {
+            Some(window) => std::cmp::max(*window / 4, FLUSH_INTERVAL_MIN),
+            None => return,
+        };
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::delay_for(interval) => self.flush(),
+                _ = shutdown.recv() => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recv_all(rx: &mut broadcast::Receiver<BusMessage>) -> Vec<(u64, String)> {
+        let mut messages = vec![];
+        while let Ok(message) = rx.try_recv() {
+            messages.push((message.seq, message.event.to_string()));
+        }
+        messages
+    }
+
+    #[test]
+    fn coalesce_window() {
+        assert_eq!(
+            parse_coalesce_window("mempool=500"),
+            Some((Topic::Mempool, Duration::from_millis(500)))
+        );
+        assert_eq!(
+            parse_coalesce_window("blocks=2s"),
+            Some((Topic::Blocks, Duration::from_secs(2)))
+        );
+        for value in &["mempool", "txs=500", "mempool=fast", "=500"] {
+            assert_eq!(parse_coalesce_window(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn coalesce_events() {
+        let window = Duration::from_millis(20);
+        let bus = EventBus::new(vec![(Topic::Mempool, window)].into_iter().collect());
+        let mut raw = bus.subscribe(true);
+        let mut coalesced = bus.subscribe(false);
+
+        bus.publish(Event::Tx(TxEvent::Added, "a".to_owned()));
+        bus.publish(Event::Tx(TxEvent::Removed, "a".to_owned()));
+        bus.publish(Event::Tx(TxEvent::Added, "b".to_owned()));
+        bus.publish(Event::Tx(TxEvent::Confirmed, "b".to_owned()));
+        bus.publish(Event::Block(BlockEvent::Added, 1, "c".to_owned()));
+        assert_eq!(recv_all(&mut raw).len(), 5);
+
+        // Topic without window sent immediately, others after window
+        assert_eq!(
+            recv_all(&mut coalesced),
+            vec![(1, "Added block: c".to_owned())]
+        );
+        bus.flush();
+        assert_eq!(recv_all(&mut coalesced), vec![]);
+
+        // Cancelled events not sent, last event sent for others
+        std::thread::sleep(window);
+        bus.flush();
+        assert_eq!(
+            recv_all(&mut coalesced),
+            vec![(2, "Confirmed tx: b".to_owned())]
+        );
+    }
+
+    #[test]
+    fn json_events() {
+        assert_eq!("json".parse::<Format>(), Ok(Format::Json));
+        assert_eq!("xml".parse::<Format>(), Err(()));
+
+        let block = Event::Block(BlockEvent::Removed, 7, "ab".to_owned());
+        assert_eq!(block.topic(), Topic::Blocks);
+        assert_eq!(
+            block.to_json(),
+            serde_json::json!({ "type": "block_removed", "height": 7, "hash": "ab" })
+        );
+        let tx = Event::Tx(TxEvent::Confirmed, "cd".to_owned());
+        assert_eq!(
+            tx.to_json(),
+            serde_json::json!({ "type": "tx_confirmed", "txid": "cd" })
+        );
+        let lagged = Event::Lagged(3);
+        assert_eq!(lagged.to_string(), "Lagged: 3 events skipped");
+        assert_eq!(
+            lagged.to_json(),
+            serde_json::json!({ "type": "lagged", "skipped": 3 })
+        );
+
+        // Bus fields added to event object
+        let mut msg = BusMessage::local(block);
+        msg.time.chain = Some(100);
+        let text = match msg.encode(Format::Json, &JsonFormat::default()) {
+            Message::Text(text) => text,
+            other => panic!("{:?}", other),
+        };
+        let value = serde_json::from_str::<serde_json::Value>(&text).unwrap();
+        assert_eq!(value["type"], "block_removed");
+        assert_eq!(
+            (value["seq"].as_u64(), value["chain_time"].as_u64()),
+            (Some(0), Some(100))
+        );
+        assert!(value["received"].as_u64().unwrap() > 0);
+        assert_eq!(
+            msg.encode(Format::Text, &JsonFormat::default()),
+            Message::text("Removed block: ab")
+        );
+    }
+
+    #[test]
+    fn coalesce_blocks() {
+        let window = Duration::from_millis(20);
+        let bus = EventBus::new(vec![(Topic::Blocks, window)].into_iter().collect());
+        let mut coalesced = bus.subscribe(false);
+
+        // Block added and removed by reorg in window is not sent
+        bus.publish(Event::Block(BlockEvent::Added, 1, "a".to_owned()));
+        bus.publish(Event::Block(BlockEvent::Removed, 1, "a".to_owned()));
+        bus.publish(Event::Block(BlockEvent::Added, 1, "b".to_owned()));
+        std::thread::sleep(window);
+        bus.flush();
+        assert_eq!(
+            recv_all(&mut coalesced),
+            vec![(1, "Added block: b".to_owned())]
+        );
+    }
+
+    #[test]
+    fn tenant_visibility() {
+        let bus = EventBus::new(HashMap::new());
+        let mut rx = bus.subscribe(true);
+        bus.publish(Event::Watch("a".to_owned(), "tx".to_owned()));
+        bus.publish(Event::Tx(TxEvent::Added, "tx".to_owned()));
+
+        let watch = rx.try_recv().unwrap();
+        assert_eq!(watch.tenant.as_deref(), Some("a"));
+        assert!(watch.is_visible(Some("a")));
+        assert!(!watch.is_visible(Some("b")));
+        assert!(!watch.is_visible(None));
+        let tx = rx.try_recv().unwrap();
+        assert!(tx.is_visible(Some("b")) && tx.is_visible(None));
+    }
+}
@@ -0,0 +1,226 @@
+// Compact block filters (BIP158), basic type only: output scripts of block
+// and scripts of spent outputs, so light clients can test their scripts
+// without downloading block. Filters are computed from binary block and undo
+// data, and cached by block hash, so reorgs do not invalidate them.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::convert::TryInto;
+use std::sync::Mutex;
+
+use super::bitcoind::json::ResponseBlock;
+use super::json;
+
+// Golomb-Rice parameters of basic filter
+const BASIC_FILTER_P: u8 = 19;
+const BASIC_FILTER_M: u64 = 784_931;
+const OP_RETURN: u8 = 0x6a;
+// Filters kept in cache, about one week of blocks
+const FILTERS_MAX: usize = 1_000;
+
+#[derive(Debug, Default)]
+struct Cache {
+    filters: HashMap<String, json::BlockFilter>,
+    // Block hashes in insertion order, oldest dropped first
+    order: VecDeque<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct BlockFilters {
+    cache: Mutex<Cache>,
+}
+
+impl BlockFilters {
+    pub fn get(&self, hash: &str) -> Option<json::BlockFilter> {
+        self.cache.lock().unwrap().filters.get(hash).cloned()
+    }
+
+    pub fn insert(&self, filter: json::BlockFilter) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.filters.contains_key(&filter.hash) {
+            return;
+        }
+        if cache.order.len() == FILTERS_MAX {
+            let hash = cache.order.pop_front().unwrap();
+            cache.filters.remove(&hash);
+        }
+        cache.order.push_back(filter.hash.clone());
+        cache.filters.insert(filter.hash.clone(), filter);
+    }
+}
+
+// Basic filter of block, `spent` are scripts of outputs spent by every
+// transaction (empty for coinbase)
+pub fn basic_filter(block: &ResponseBlock, spent: &[Vec<Vec<u8>>]) -> json::BlockFilter {
+    let outputs = block
+        .transactions
+        .iter()
+        .flat_map(|tx| tx.vout.iter())
+        .map(|output| output.script_pubkey.hex.as_slice())
+        .filter(|script| script.first() != Some(&OP_RETURN));
+    let inputs = spent.iter().flatten().map(|script| script.as_slice());
+    let (elements, filter) = build_filter(&block.hash, outputs.chain(inputs));
+
+    json::BlockFilter {
+        hash: block.hash.clone(),
+        height: block.height,
+        elements,
+        filter: hex::encode(filter),
+    }
+}
+
+// Number of unique elements and serialized filter: element count as
+// CompactSize and Golomb-Rice coded set
+pub fn build_filter<'a, I>(hash: &str, scripts: I) -> (usize, Vec<u8>)
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    // Key is first 16 bytes of block hash in internal byte order
+    let mut key = hex::decode(hash).expect("block hash in hex");
+    key.reverse();
+    let k0 = u64::from_le_bytes(key[0..8].try_into().unwrap());
+    let k1 = u64::from_le_bytes(key[8..16].try_into().unwrap());
+
+    let elements = scripts
+        .filter(|script| !script.is_empty())
+        .collect::<BTreeSet<_>>();
+    let range = elements.len() as u64 * BASIC_FILTER_M;
+    let mut values = elements
+        .iter()
+        .map(|script| hash_to_range(siphash24(k0, k1, script), range))
+        .collect::<Vec<_>>();
+    values.sort_unstable();
+
+    let mut filter = compact_size(elements.len() as u64);
+    let mut writer = BitWriter::new(&mut filter);
+    let mut last = 0;
+    for value in values {
+        writer.write_golomb_rice(value - last, BASIC_FILTER_P);
+        last = value;
+    }
+    writer.flush();
+    (elements.len(), filter)
+}
+
+// Uniform mapping of 64-bit hash to `[0, range)` without division
+fn hash_to_range(hash: u64, range: u64) -> u64 {
+    ((u128::from(hash) * u128::from(range)) >> 64) as u64
+}
+
+fn compact_size(value: u64) -> Vec<u8> {
+    match value {
+        0..=0xfc => vec![value as u8],
+        0xfd..=0xffff => {
+            let mut data = vec![0xfd];
+            data.extend_from_slice(&(value as u16).to_le_bytes());
+            data
+        }
+        0x1_0000..=0xffff_ffff => {
+            let mut data = vec![0xfe];
+            data.extend_from_slice(&(value as u32).to_le_bytes());
+            data
+        }
+        _ => {
+            let mut data = vec![0xff];
+            data.extend_from_slice(&value.to_le_bytes());
+            data
+        }
+    }
+}
+
+// Bits appended from most significant, last byte padded with zeros
+struct BitWriter<'a> {
+    data: &'a mut Vec<u8>,
+    byte: u8,
+    bits: u8,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(data: &'a mut Vec<u8>) -> Self {
+        BitWriter {
+            data,
+            byte: 0,
+            bits: 0,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        self.byte = (self.byte << 1) | bit as u8;
+        self.bits += 1;
+        if self.bits == 8 {
+            self.data.push(self.byte);
+            self.byte = 0;
+            self.bits = 0;
+        }
+    }
+
+    // Quotient in unary (ones terminated by zero), remainder in `p` bits
+    fn write_golomb_rice(&mut self, value: u64, p: u8) {
+        for _ in 0..(value >> p) {
+            self.write_bit(true);
+        }
+        self.write_bit(false);
+        for shift in (0..p).rev() {
+            self.write_bit((value >> shift) & 1 == 1);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.bits > 0 {
+            self.data.push(self.byte << (8 - self.bits));
+            self.byte = 0;
+            self.bits = 0;
+        }
+    }
+}
+
+// SipHash-2-4, as `CSipHasher` of bitcoind
+fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap());
+        v[3] ^= m;
+        sip_round(&mut v);
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+
+    // Last block with remaining bytes and length in most significant byte
+    let mut last = [0; 8];
+    let rest = chunks.remainder();
+    last[..rest.len()].copy_from_slice(rest);
+    let m = u64::from_le_bytes(last) | ((data.len() as u64) << 56);
+    v[3] ^= m;
+    sip_round(&mut v);
+    sip_round(&mut v);
+    v[0] ^= m;
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13);
+    v[1] ^= v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16);
+    v[3] ^= v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21);
+    v[3] ^= v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17);
+    v[1] ^= v[2];
+    v[2] = v[2].rotate_left(32);
+}
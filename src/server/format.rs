@@ -0,0 +1,385 @@
+// JSON output options for different ecosystems: field naming, hash byte
+// order and amounts, applied to every JSON response and event in one place.
+// Formatted output is deterministic: object keys are sorted (`Map` is
+// `BTreeMap` without `preserve_order` feature of serde_json) and floats are
+// printed with shortest round-trip representation, same on every platform
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{Map, Number, Value};
+
+// Fields with transaction or block hashes (string or array of strings)
+const HASH_FIELDS: &[&str] = &["hash", "prevhash", "txid", "txids"];
+// Fields with amounts in satoshis (integers)
+const AMOUNT_FIELDS: &[&str] = &["value", "fee", "subsidy", "next_subsidy"];
+// Suffix of BTC field added next to satoshis field
+const AMOUNT_BTC_SUFFIX: &str = "_btc";
+const SATS_PER_BTC: u64 = 100_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Naming {
+    Snake,
+    Camel,
+}
+
+impl FromStr for Naming {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "snake" => Ok(Naming::Snake),
+            "camel" => Ok(Naming::Camel),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Naming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Naming::Snake => write!(f, "snake"),
+            Naming::Camel => write!(f, "camel"),
+        }
+    }
+}
+
+// Display order is used by bitcoind RPC and explorers, internal order is
+// byte order of serialized data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashOrder {
+    Display,
+    Internal,
+}
+
+impl FromStr for HashOrder {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "display" => Ok(HashOrder::Display),
+            "internal" => Ok(HashOrder::Internal),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for HashOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashOrder::Display => write!(f, "display"),
+            HashOrder::Internal => write!(f, "internal"),
+        }
+    }
+}
+
+// Integer satoshis are exact, decimal BTC strings (`"0.00010000"`) are for
+// consumers which would parse BTC as float otherwise
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmountFormat {
+    Sats,
+    Btc,
+    // Satoshis with BTC string in `{field}_btc`
+    Both,
+}
+
+impl FromStr for AmountFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sats" => Ok(AmountFormat::Sats),
+            "btc" => Ok(AmountFormat::Btc),
+            "both" => Ok(AmountFormat::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for AmountFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmountFormat::Sats => write!(f, "sats"),
+            AmountFormat::Btc => write!(f, "btc"),
+            AmountFormat::Both => write!(f, "both"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JsonFormat {
+    pub naming: Naming,
+    pub hash_order: HashOrder,
+    pub amounts: AmountFormat,
+    // Every response and event formatted, even with default options above, so
+    // payloads can be hashed and compared byte by byte
+    pub canonical: bool,
+}
+
+impl Default for JsonFormat {
+    fn default() -> Self {
+        JsonFormat {
+            naming: Naming::Snake,
+            hash_order: HashOrder::Display,
+            amounts: AmountFormat::Sats,
+            canonical: false,
+        }
+    }
+}
+
+impl JsonFormat {
+    // Override with `naming=`, `hash_order=`, `amounts=` and `canonical=` from
+    // query, invalid parameter returned as error
+    pub fn with_query(mut self, query: Option<&str>) -> Result<Self, String> {
+        for kv in query.unwrap_or_default().split('&') {
+            if let Some(value) = kv.strip_prefix("naming=") {
+                self.naming = value.parse().map_err(|_| kv.to_owned())?;
+            }
+            if let Some(value) = kv.strip_prefix("hash_order=") {
+                self.hash_order = value.parse().map_err(|_| kv.to_owned())?;
+            }
+            if let Some(value) = kv.strip_prefix("amounts=") {
+                self.amounts = value.parse().map_err(|_| kv.to_owned())?;
+            }
+            if let Some(value) = kv.strip_prefix("canonical=") {
+                self.canonical = value.parse().map_err(|_| kv.to_owned())?;
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn apply(&self, value: Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let mut result = Map::with_capacity(map.len());
+                for (key, value) in map {
+                    if HASH_FIELDS.contains(&key.as_str()) {
+                        result.insert(self.apply_name(key), self.apply_hash(value));
+                    } else if AMOUNT_FIELDS.contains(&key.as_str()) && value.is_u64() {
+                        self.apply_amount(&mut result, key, value);
+                    } else {
+                        result.insert(self.apply_name(key), self.apply(value));
+                    }
+                }
+                Value::Object(result)
+            }
+            Value::Array(items) => Value::Array(items.into_iter().map(|v| self.apply(v)).collect()),
+            // Negative zero printed as `-0.0`, same value as zero
+            Value::Number(n) if n.as_f64() == Some(0.0) && n.is_f64() => {
+                Value::Number(Number::from_f64(0.0).unwrap())
+            }
+            value => value,
+        }
+    }
+
+    // Hash from request (e.g. path parameter) to display order
+    pub fn parse_hash(&self, hash: &str) -> String {
+        match self.hash_order {
+            HashOrder::Display => hash.to_owned(),
+            HashOrder::Internal => reverse_hash(hash),
+        }
+    }
+
+    fn apply_name(&self, key: String) -> String {
+        match self.naming {
+            Naming::Snake => key,
+            Naming::Camel => to_camel_case(&key),
+        }
+    }
+
+    fn apply_amount(&self, map: &mut Map<String, Value>, key: String, value: Value) {
+        let btc = || Value::String(format_btc(value.as_u64().unwrap()));
+        match self.amounts {
+            AmountFormat::Sats => {
+                map.insert(self.apply_name(key), value);
+            }
+            AmountFormat::Btc => {
+                map.insert(self.apply_name(key), btc());
+            }
+            AmountFormat::Both => {
+                let btc_key = format!("{}{}", key, AMOUNT_BTC_SUFFIX);
+                map.insert(self.apply_name(btc_key), btc());
+                map.insert(self.apply_name(key), value);
+            }
+        }
+    }
+
+    fn apply_hash(&self, value: Value) -> Value {
+        if self.hash_order == HashOrder::Display {
+            return value;
+        }
+
+        match value {
+            Value::String(hash) => Value::String(reverse_hash(&hash)),
+            Value::Array(items) => {
+                Value::Array(items.into_iter().map(|v| self.apply_hash(v)).collect())
+            }
+            value => value,
+        }
+    }
+}
+
+fn to_camel_case(key: &str) -> String {
+    let mut result = String::with_capacity(key.len());
+    let mut upper = false;
+    for ch in key.chars() {
+        if ch == '_' && !result.is_empty() {
+            upper = true;
+        } else if upper {
+            result.extend(ch.to_uppercase());
+            upper = false;
+        } else {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+// Satoshis as decimal BTC with all 8 digits, no float rounding
+fn format_btc(sats: u64) -> String {
+    format!("{}.{:08}", sats / SATS_PER_BTC, sats % SATS_PER_BTC)
+}
+
+// Hex with reversed bytes, invalid hex returned as is
+fn reverse_hash(hash: &str) -> String {
+    match hex::decode(hash) {
+        Ok(mut bytes) => {
+            bytes.reverse();
+            hex::encode(bytes)
+        }
+        Err(_) => hash.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn naming_and_hash_order() {
+        let format = JsonFormat {
+            naming: Naming::Camel,
+            hash_order: HashOrder::Internal,
+            ..JsonFormat::default()
+        };
+        let value = json!({
+            "block_hash_count": 1,
+            "_private": true,
+            "hash": "0001ff",
+            "txids": ["abcd", "not hex"],
+            "inputs": [{"prev_vout": 0, "txid": "0102"}],
+        });
+        assert_eq!(
+            format.apply(value.clone()),
+            json!({
+                "blockHashCount": 1,
+                "_private": true,
+                "hash": "ff0100",
+                "txids": ["cdab", "not hex"],
+                "inputs": [{"prevVout": 0, "txid": "0201"}],
+            })
+        );
+        assert_eq!(JsonFormat::default().apply(value.clone()), value);
+
+        assert_eq!(format.parse_hash("0001ff"), "ff0100");
+        assert_eq!(JsonFormat::default().parse_hash("0001ff"), "0001ff");
+    }
+
+    #[test]
+    fn query_options() {
+        let format = JsonFormat::default();
+        assert!(format.is_default());
+        assert_eq!(format.with_query(None), Ok(format));
+
+        let query = Some("limit=10&naming=camel&hash_order=internal");
+        let format = format.with_query(query).unwrap();
+        assert_eq!(format.naming, Naming::Camel);
+        assert_eq!(format.hash_order, HashOrder::Internal);
+        assert!(!format.is_default());
+
+        // deployment options overridden per request
+        let format = format.with_query(Some("naming=snake")).unwrap();
+        assert_eq!(format.naming, Naming::Snake);
+        assert_eq!(format.hash_order, HashOrder::Internal);
+
+        let invalid = JsonFormat::default().with_query(Some("naming=kebab"));
+        assert_eq!(invalid, Err("naming=kebab".to_owned()));
+
+        for naming in &[Naming::Snake, Naming::Camel] {
+            assert_eq!(naming.to_string().parse(), Ok(*naming));
+        }
+        for order in &[HashOrder::Display, HashOrder::Internal] {
+            assert_eq!(order.to_string().parse(), Ok(*order));
+        }
+    }
+
+    #[test]
+    fn canonical_key_order() {
+        let format = JsonFormat {
+            naming: Naming::Camel,
+            canonical: true,
+            ..JsonFormat::default()
+        };
+        let value = json!({
+            "zeta": {"b_y": 1, "ba": 2, "a": {"z": [{"y": 1, "x": -0.0}], "b": null}},
+            "alpha": [{"size": 1, "fee": 2}],
+        });
+        // Keys sorted after renaming, on every level
+        assert_eq!(
+            serde_json::to_string(&format.apply(value)).unwrap(),
+            concat!(
+                r#"{"alpha":[{"fee":2,"size":1}],"#,
+                r#""zeta":{"a":{"b":null,"z":[{"x":0.0,"y":1}]},"bY":1,"ba":2}}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn amounts() {
+        let amounts = vec![
+            (100_000_000, "1.00000000"),
+            (1, "0.00000001"),
+            (99_999_999, "0.99999999"),
+            (2_100_000_000_000_000, "21000000.00000000"),
+            (u64::MAX, "184467440737.09551615"),
+        ];
+        for (sats, btc) in &amounts {
+            assert_eq!(format_btc(*sats), *btc);
+        }
+
+        let value = json!({"fee": 1, "value": 100_000_000, "vsize": 1, "next_subsidy": -1});
+        let format = |amounts| JsonFormat {
+            amounts,
+            ..JsonFormat::default()
+        };
+        assert_eq!(format(AmountFormat::Sats).apply(value.clone()), value);
+        assert_eq!(
+            format(AmountFormat::Btc).apply(value.clone()),
+            json!({"fee": "0.00000001", "value": "1.00000000", "vsize": 1, "next_subsidy": -1})
+        );
+        assert_eq!(
+            format(AmountFormat::Both).apply(value),
+            json!({
+                "fee": 1,
+                "fee_btc": "0.00000001",
+                "value": 100_000_000,
+                "value_btc": "1.00000000",
+                "vsize": 1,
+                "next_subsidy": -1,
+            })
+        );
+
+        for amounts in &[AmountFormat::Sats, AmountFormat::Btc, AmountFormat::Both] {
+            assert_eq!(amounts.to_string().parse(), Ok(*amounts));
+        }
+        assert_eq!("sat".parse::<AmountFormat>(), Err(()));
+        let invalid = JsonFormat::default().with_query(Some("amounts=msat"));
+        assert_eq!(invalid, Err("amounts=msat".to_owned()));
+    }
+}
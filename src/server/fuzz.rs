@@ -0,0 +1,84 @@
+// Entry points for fuzz targets in `fuzz/`, parsers of untrusted input
+// (request path and query, WebSocket messages, bitcoind responses) are
+// private otherwise. Results are ignored, only panics are interesting.
+
+use bytes::Bytes;
+use hyper::Method;
+
+use super::api::{block_id, parse_ws_subscribe};
+use super::bitcoind::json::{Response, ResponseBlock, ResponseRawMempool};
+use super::bitcoind::{parse_block, BlockBody};
+use super::format::{HashOrder, JsonFormat};
+use super::json;
+use super::router::{negotiate_version, RouteMatch, Router};
+
+const METHODS: [Method; 4] = [Method::GET, Method::POST, Method::PUT, Method::DELETE];
+
+thread_local! {
+    // Routes compiled once, not on every input
+    static ROUTER: Router = Router::new(true);
+}
+
+// Request target (`/v1/block/{id}?hash_order=internal`) as API handles it,
+// input also used as `Accept-Version` value
+pub fn route(input: &str) {
+    let _ = negotiate_version(Some(input));
+
+    let (path, query) = match input.find('?') {
+        Some(pos) => (&input[..pos], Some(&input[pos + 1..])),
+        None => (input, None),
+    };
+    let format = JsonFormat::default().with_query(query);
+    let format = format.unwrap_or_default();
+    ROUTER.with(|router| {
+        for method in METHODS.iter() {
+            if let RouteMatch::Found(found) = router.find(method, path) {
+                for (_, param) in found.params.iter() {
+                    let _ = block_id(param, &format);
+                    let internal = JsonFormat {
+                        hash_order: HashOrder::Internal,
+                        ..format
+                    };
+                    let _ = block_id(param, &internal);
+                }
+            }
+        }
+    });
+}
+
+// Text message of WebSocket client
+pub fn ws_subscribe(text: &str) {
+    let _ = parse_ws_subscribe(text);
+}
+
+// Body of REST `/rest/block/{hash}.json`, hash taken from body itself so
+// header and merkle root verification are reached
+pub fn bitcoind_block(body: &[u8]) {
+    let block = match serde_json::from_slice::<ResponseBlock>(body) {
+        Ok(block) => block,
+        Err(_) => return,
+    };
+    let hash = block.hash.clone();
+    let _ = parse_block(&hash, &BlockBody::Json(Bytes::copy_from_slice(body)));
+
+    let value = serde_json::to_value(json::Block::new(block, 0)).unwrap();
+    let format = JsonFormat {
+        hash_order: HashOrder::Internal,
+        ..JsonFormat::default()
+    };
+    let _ = format.apply(value);
+}
+
+// Body of REST `/rest/block/{hash}.bin`, hash of header is not checked, so
+// decoding is reached without mining
+pub fn bitcoind_raw_block(data: &[u8]) {
+    if let Ok(body) = BlockBody::decode_raw(data, None) {
+        let _ = parse_block("", &body);
+    }
+}
+
+// JSON-RPC response of `getrawmempool`, single and batch
+pub fn bitcoind_mempool(body: &[u8]) {
+    let _ = serde_json::from_slice::<Response<ResponseRawMempool>>(body);
+    let _ = serde_json::from_slice::<Vec<Response<ResponseRawMempool>>>(body);
+}
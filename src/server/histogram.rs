@@ -0,0 +1,121 @@
+// Mempool transactions grouped by fee rate band with their total virtual size,
+// as in mempool charts of block explorers. Updated on every insert and remove
+// of mempool transaction, so request does not walk whole mempool.
+
+use super::json;
+
+// Lower bounds of fee rate bands in sat/vB, last band unbounded
+const BANDS: [f64; 25] = [
+    0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 12.0, 15.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0,
+    80.0, 100.0, 125.0, 150.0, 200.0, 300.0, 500.0, 1000.0,
+];
+// Weight units in one virtual byte
+const WITNESS_SCALE_FACTOR: u64 = 4;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Band {
+    transactions: usize,
+    vsize: u64,
+    // Satoshis
+    fee: u64,
+}
+
+#[derive(Debug)]
+pub struct FeeHistogram {
+    bands: Vec<Band>,
+}
+
+impl Default for FeeHistogram {
+    fn default() -> Self {
+        FeeHistogram {
+            bands: vec![Band::default(); BANDS.len()],
+        }
+    }
+}
+
+impl FeeHistogram {
+    pub fn insert(&mut self, vsize: u32, fee: u64) {
+        let band = &mut self.bands[band(vsize, fee)];
+        band.transactions += 1;
+        band.vsize += u64::from(vsize);
+        band.fee += fee;
+    }
+
+    // Values should be same as on insert
+    pub fn remove(&mut self, vsize: u32, fee: u64) {
+        let band = &mut self.bands[band(vsize, fee)];
+        band.transactions -= 1;
+        band.vsize -= u64::from(vsize);
+        band.fee -= fee;
+    }
+
+    pub fn report(&self) -> json::MempoolHistogram {
+        let bands = self
+            .bands
+            .iter()
+            .enumerate()
+            .map(|(i, band)| json::MempoolHistogramBand {
+                min: BANDS[i],
+                max: BANDS.get(i + 1).copied(),
+                transactions: band.transactions,
+                vsize: band.vsize,
+                weight: band.vsize * WITNESS_SCALE_FACTOR,
+                fee: band.fee,
+            })
+            .collect::<Vec<_>>();
+
+        json::MempoolHistogram {
+            transactions: bands.iter().map(|band| band.transactions).sum(),
+            vsize: bands.iter().map(|band| band.vsize).sum(),
+            weight: bands.iter().map(|band| band.weight).sum(),
+            fee: bands.iter().map(|band| band.fee).sum(),
+            bands,
+        }
+    }
+}
+
+fn band(vsize: u32, fee: u64) -> usize {
+    let rate = fee as f64 / f64::from(vsize.max(1));
+    BANDS.iter().rposition(|min| rate >= *min).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn band_bounds() {
+        // Lower bound is inclusive, last band has no upper bound
+        assert_eq!(band(100, 99), 0);
+        assert_eq!(band(100, 100), 1);
+        assert_eq!(band(100, 1_999), 10);
+        assert_eq!(band(100, 2_000), 11);
+        assert_eq!(band(100, 100_000), BANDS.len() - 1);
+        assert_eq!(band(100, 10_000_000), BANDS.len() - 1);
+        assert_eq!(band(0, 0), 0);
+    }
+
+    #[test]
+    fn insert_remove() {
+        let mut histogram = FeeHistogram::default();
+        histogram.insert(100, 150);
+        histogram.insert(200, 10_000);
+        histogram.insert(100, 50);
+        histogram.remove(100, 150);
+
+        let report = histogram.report();
+        assert_eq!(report.bands.len(), BANDS.len());
+        assert_eq!(
+            (report.transactions, report.vsize, report.weight, report.fee),
+            (2, 300, 1_200, 10_050)
+        );
+        let last = report.bands.last().unwrap();
+        assert_eq!((last.min, last.max), (1000.0, None));
+        let band = &report.bands[14];
+        assert_eq!(
+            (band.min, band.max, band.transactions),
+            (50.0, Some(60.0), 1)
+        );
+        assert_eq!(report.bands[1].transactions, 0);
+    }
+}
@@ -0,0 +1,170 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use futures::future::{BoxFuture, FutureExt as _};
+use log::{error, warn};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use super::events::Event;
+use crate::signals::ShutdownReceiver;
+
+pub type HookResult = Result<(), Box<dyn StdError + Send + Sync>>;
+
+// Custom side effect for events, registered by embedders with `Server::add_hook`
+pub trait EventHook: Send + Sync {
+    fn name(&self) -> &str;
+    fn on_event<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, HookResult>;
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HookStats {
+    pub name: String,
+    pub calls: u64,
+    pub errors: u64,
+    pub panics: u64,
+    // Events missed because hook was too slow
+    pub lagged: u64,
+    pub time_total_us: u64,
+    pub time_max_us: u64,
+}
+
+// Hook with own stats, every hook receive events in separate task, so failed
+// or slow hook do not affect other hooks and events delivery to clients
+pub struct HookRunner {
+    hook: Arc<dyn EventHook>,
+    stats: Arc<Mutex<HookStats>>,
+}
+
+impl fmt::Debug for HookRunner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HookRunner")
+            .field("hook", &self.hook.name())
+            .finish()
+    }
+}
+
+impl HookRunner {
+    pub fn new(hook: Box<dyn EventHook>) -> Self {
+        let stats = HookStats {
+            name: hook.name().to_owned(),
+            ..Default::default()
+        };
+
+        HookRunner {
+            hook: hook.into(),
+            stats: Arc::new(Mutex::new(stats)),
+        }
+    }
+
+    pub fn stats(&self) -> HookStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    pub fn spawn(&self, mut rx: broadcast::Receiver<Event>, mut shutdown: ShutdownReceiver) {
+        let hook = self.hook.clone();
+        let stats = self.stats.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = rx.recv() => event,
+                    _ = shutdown.recv() => break,
+                };
+                let event = match event {
+                    Ok(event) => event,
+                    Err(broadcast::RecvError::Lagged(skipped)) => {
+                        warn!("Hook {} skipped {} events", hook.name(), skipped);
+                        stats.lock().unwrap().lagged += skipped;
+                        continue;
+                    }
+                    Err(broadcast::RecvError::Closed) => break,
+                };
+
+                let ts = Instant::now();
+                let result = AssertUnwindSafe(hook.on_event(&event)).catch_unwind().await;
+                let elapsed = ts.elapsed().as_micros() as u64;
+
+                let mut stats = stats.lock().unwrap();
+                stats.calls += 1;
+                stats.time_total_us += elapsed;
+                stats.time_max_us = std::cmp::max(stats.time_max_us, elapsed);
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => {
+                        stats.errors += 1;
+                        error!("Hook {} error on event ({}): {}", hook.name(), event, err);
+                    }
+                    Err(_) => {
+                        stats.panics += 1;
+                        error!("Hook {} panicked on event ({})", hook.name(), event);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::events::{BlockEvent, TxEvent};
+    use super::*;
+
+    struct TestHook {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl EventHook for TestHook {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn on_event<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, HookResult> {
+            async move {
+                match event {
+                    Event::Tx(TxEvent::Removed, _) => Err("removed".into()),
+                    Event::Block(..) => panic!("block"),
+                    _ => {
+                        self.events.lock().unwrap().push(event.to_string());
+                        Ok(())
+                    }
+                }
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_hook_keeps_running() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let hook = TestHook {
+            events: events.clone(),
+        };
+        let runner = HookRunner::new(Box::new(hook));
+        let (tx, rx) = broadcast::channel(16);
+        let mut shutdown = ShutdownReceiver::new();
+        runner.spawn(rx, shutdown.clone());
+
+        tx.send(Event::Tx(TxEvent::Removed, "a".to_owned()))
+            .unwrap();
+        tx.send(Event::Block(BlockEvent::Added, 1, "b".to_owned()))
+            .unwrap();
+        tx.send(Event::Tx(TxEvent::Added, "c".to_owned())).unwrap();
+        while runner.stats().calls < 3 {
+            tokio::time::delay_for(Duration::from_millis(1)).await;
+        }
+        shutdown.set();
+
+        let stats = runner.stats();
+        assert_eq!(
+            (stats.name.as_str(), stats.errors, stats.panics),
+            ("test", 1, 1)
+        );
+        assert_eq!(*events.lock().unwrap(), vec!["Added tx: c"]);
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Table of unique byte strings (script pubkeys), indexes keep `u32` ids instead of values.
+// Every `intern` increase references counter, `release` decrease it, unused ids reused.
+#[derive(Debug, Default)]
+pub struct Interner {
+    ids: HashMap<Arc<[u8]>, u32>,
+    entries: Vec<Option<InternerEntry>>,
+    free: Vec<u32>,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug)]
+struct InternerEntry {
+    value: Arc<[u8]>,
+    refs: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct InternerStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    // Return id for value, allocate new one if value is not known yet
+    pub fn intern(&mut self, value: &[u8]) -> u32 {
+        if let Some(id) = self.ids.get(value) {
+            self.hits += 1;
+            self.entries[*id as usize].as_mut().unwrap().refs += 1;
+            return *id;
+        }
+
+        self.misses += 1;
+        let value: Arc<[u8]> = value.into();
+        let entry = Some(InternerEntry {
+            value: value.clone(),
+            refs: 1,
+        });
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.entries[id as usize] = entry;
+                id
+            }
+            None => {
+                self.entries.push(entry);
+                (self.entries.len() - 1) as u32
+            }
+        };
+        self.bytes += value.len();
+        self.ids.insert(value, id);
+        id
+    }
+
+    // Id of known value, without references counter change
+    pub fn get(&self, value: &[u8]) -> Option<u32> {
+        self.ids.get(value).copied()
+    }
+
+    // Value of id which is in use
+    pub fn value(&self, id: u32) -> &[u8] {
+        let entry = self.entries[id as usize].as_ref();
+        &entry.expect("Value of unknown interned id").value
+    }
+
+    // Decrease references counter, value removed when nobody use it
+    pub fn release(&mut self, id: u32) {
+        let slot = &mut self.entries[id as usize];
+        let entry = slot.as_mut().expect("Release of unknown interned id");
+        entry.refs -= 1;
+        if entry.refs == 0 {
+            let entry = slot.take().unwrap();
+            self.bytes -= entry.value.len();
+            self.ids.remove(&entry.value);
+            self.free.push(id);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn stats(&self) -> InternerStats {
+        InternerStats {
+            entries: self.ids.len(),
+            bytes: self.bytes,
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_refs() {
+        let mut interner = Interner::new();
+        let id = interner.intern(b"script");
+        assert_eq!(interner.intern(b"script"), id);
+        assert_eq!(interner.value(id), b"script");
+
+        // Value kept until last reference released
+        interner.release(id);
+        assert_eq!(interner.get(b"script"), Some(id));
+        interner.release(id);
+        assert_eq!(interner.get(b"script"), None);
+        assert!(interner.is_empty());
+
+        let stats = interner.stats();
+        assert_eq!((stats.entries, stats.bytes), (0, 0));
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+    }
+
+    #[test]
+    fn reuse_free_ids() {
+        let mut interner = Interner::new();
+        let ids = [b"a", b"b", b"c"]
+            .iter()
+            .map(|value| interner.intern(*value))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![0, 1, 2]);
+
+        interner.release(1);
+        interner.release(0);
+        assert_eq!(interner.intern(b"d"), 0);
+        assert_eq!(interner.intern(b"e"), 1);
+        assert_eq!(interner.intern(b"f"), 3);
+        assert_eq!(interner.value(0), b"d");
+        assert_eq!(interner.get(b"a"), None);
+        assert_eq!(interner.stats().bytes, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "Release of unknown interned id")]
+    fn release_unused() {
+        let mut interner = Interner::new();
+        let id = interner.intern(b"a");
+        interner.release(id);
+        interner.release(id);
+    }
+}
@@ -1,27 +1,226 @@
-use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
 
-use super::bitcoind::json::ResponseBlock;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+use super::bitcoind::json::{ResponseBlock, ResponseBlockTransaction, ResponseMempoolAccept};
+use super::hooks::HookStats;
+use super::interner::InternerStats;
+use super::plugins::PluginStats;
+use super::rules::RuleStats;
+use super::timings::StageStats;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Transaction {
     pub hash: String,
     pub size: u32,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransactionInfo {
+    pub txid: String,
+    // Witness hash, same as txid for transaction without witness
+    pub hash: String,
+    pub size: u32,
+    pub inputs: Vec<TransactionInput>,
+    pub outputs: Vec<TransactionOutput>,
+    // Not set for mempool transaction
+    pub block: Option<TransactionBlock>,
+    // Zero for mempool transaction
+    pub confirmations: u32,
+}
+
+impl TransactionInfo {
+    pub fn new(tx: ResponseBlockTransaction, block: Option<TransactionBlock>, tip: u32) -> Self {
+        TransactionInfo {
+            txid: tx.txid,
+            hash: tx.hash,
+            size: tx.size,
+            inputs: tx
+                .vin
+                .into_iter()
+                .map(|input| TransactionInput {
+                    txid: input.txid,
+                    vout: input.vout,
+                })
+                .collect(),
+            outputs: tx
+                .vout
+                .into_iter()
+                .map(|output| TransactionOutput {
+                    value: (output.value * 1e8).round() as u64,
+                    script: output.script_pubkey.hex,
+                })
+                .collect(),
+            confirmations: block
+                .as_ref()
+                .map(|block| (tip + 1).saturating_sub(block.height))
+                .unwrap_or(0),
+            block,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransactionInput {
+    // Spent output, not set for coinbase
+    pub txid: Option<String>,
+    pub vout: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransactionOutput {
+    // Value in satoshis
+    pub value: u64,
+    #[serde(with = "hex")]
+    #[schemars(with = "String")]
+    pub script: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransactionBlock {
+    pub height: u32,
+    pub hash: String,
+}
+
+// Mempool transaction with package counts, `GET /v1/mempool/tx/{txid}`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MempoolTransaction {
+    pub txid: String,
+    // Virtual size
+    pub size: u32,
+    // Satoshis
+    pub fee: u64,
+    // sat/vB
+    pub fee_rate: f64,
+    // Unconfirmed ancestors and descendants with transaction itself
+    pub ancestors: u32,
+    pub descendants: u32,
+    // Unix time of node mempool entry, 0 if unknown
+    pub time: u64,
+    // Unix time when transaction was seen by server first time
+    pub first_seen: u64,
+    // Seconds since first seen
+    pub time_in_mempool: u64,
+}
+
+// Fee rates of mempool transactions in sat/vB, zeros if mempool is empty
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MempoolFeerates {
+    pub transactions: usize,
+    pub min: f64,
+    pub p10: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub max: f64,
+}
+
+// Mempool transactions by fee rate band (sat/vB), lowest band first
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MempoolHistogram {
+    pub transactions: usize,
+    pub vsize: u64,
+    pub weight: u64,
+    // Satoshis
+    pub fee: u64,
+    pub bands: Vec<MempoolHistogramBand>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MempoolHistogramBand {
+    pub min: f64,
+    // `null` for last band
+    pub max: Option<f64>,
+    pub transactions: usize,
+    pub vsize: u64,
+    pub weight: u64,
+    pub fee: u64,
+}
+
+// Next block assembled from mempool by package fee rate, `txids` in block order
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ProjectedBlock {
+    pub height: u32,
+    pub transactions: usize,
+    pub weight: u64,
+    pub vsize: u64,
+    // Satoshis
+    pub fees: u64,
+    // Lowest package fee rate in sat/vB, `null` for empty block
+    pub feerate_min: Option<f64>,
+    // Unix time of assembly
+    pub time: u64,
+    pub txids: Vec<String>,
+}
+
+// Time from mempool entry to confirmation by fee rate band
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ConfirmationTimes {
+    pub bands: Vec<ConfirmationTimesBand>,
+    // Seconds to add to medians for node clock offset, see `ClockSkew`
+    pub skew_correction: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ConfirmationTimesBand {
+    // Fee rate in sat/vB, `max` not set for last band
+    pub min: f64,
+    pub max: Option<f64>,
+    // Latest confirmations, up to 1000
+    pub samples: usize,
+    // Seconds, not set without samples
+    pub median: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FeeEstimateMode {
+    Conservative,
+    Economical,
+}
+
+impl FeeEstimateMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FeeEstimateMode::Conservative => "conservative",
+            FeeEstimateMode::Economical => "economical",
+        }
+    }
+}
+
+// Result of `estimatesmartfee` for our tip
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FeeEstimate {
+    pub target: u16,
+    pub mode: FeeEstimateMode,
+    // Tip when estimate was received
+    pub height: u32,
+    // Target for which estimate was found, can differ from requested
+    pub blocks: u32,
+    // BTC/kvB and sat/vB, not set if bitcoind has not enough data
+    pub feerate: Option<f64>,
+    pub sat_per_vbyte: Option<f64>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Block {
     pub height: u32,
     pub hash: String,
     pub size: u32,
+    // Block subsidy in satoshis, without fees
+    pub subsidy: u64,
     pub transactions: Vec<Transaction>,
 }
 
-impl From<ResponseBlock> for Block {
-    fn from(block: ResponseBlock) -> Self {
+impl Block {
+    pub fn new(block: ResponseBlock, subsidy: u64) -> Self {
         Block {
             height: block.height,
             hash: block.hash,
             size: block.size,
+            subsidy,
             transactions: block
                 .transactions
                 .into_iter()
@@ -33,3 +232,865 @@ impl From<ResponseBlock> for Block {
         }
     }
 }
+
+// Part of block transactions, `GET /v1/block/{id}?page=&limit=`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BlockPage {
+    pub height: u32,
+    pub hash: String,
+    pub size: u32,
+    pub subsidy: u64,
+    // From 1, page after last one has no transactions
+    pub page: usize,
+    pub limit: usize,
+    // Transactions in block
+    pub total: usize,
+    pub transactions: Vec<Transaction>,
+}
+
+impl BlockPage {
+    pub fn new(block: Block, page: usize, limit: usize) -> Self {
+        let total = block.transactions.len();
+        let transactions = block
+            .transactions
+            .into_iter()
+            .skip((page - 1).saturating_mul(limit))
+            .take(limit)
+            .collect();
+        BlockPage {
+            height: block.height,
+            hash: block.hash,
+            size: block.size,
+            subsidy: block.subsidy,
+            page,
+            limit,
+            total,
+            transactions,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Stats {
+    pub blocks: usize,
+    pub mempool: usize,
+    pub scripts: InternerStats,
+    pub hooks: Vec<HookStats>,
+    pub plugins: Vec<PluginStats>,
+    pub rules: Vec<RuleStats>,
+    // Block processing durations by stage
+    pub block_timings: BTreeMap<String, StageStats>,
+    pub block_streaks: BlockStreaks,
+    // Unix time of update loop pause, see `UpdatesPause`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_since: Option<u64>,
+    pub clock_skew: ClockSkew,
+    pub traffic: TrafficStats,
+    pub egress_budgets: Vec<EgressBudget>,
+}
+
+// Bytes of bodies and WebSocket messages
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct Traffic {
+    pub sent: u64,
+    pub received: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TrafficStats {
+    // By `host:port client`, client is `rpc` or `rest`
+    pub bitcoind: BTreeMap<String, Traffic>,
+    // By route path without version
+    pub routes: BTreeMap<String, Traffic>,
+    // WebSocket events by topic
+    pub topics: BTreeMap<String, Traffic>,
+}
+
+// Bytes sent to API clients in current window of `--egress-budget`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EgressBudget {
+    pub period: String,
+    // UTC date or month
+    pub window: String,
+    pub limit: u64,
+    pub used: u64,
+}
+
+// Clock offsets in seconds, not set until measured
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClockSkew {
+    // Node time offset to its peers, `timeoffset` of `getnetworkinfo`
+    pub node_offset: Option<i64>,
+    // Server time minus node time, upper bound from mempool entry times
+    pub server_offset: Option<i64>,
+    // Median of time between header time and receive of latest tip blocks
+    pub block_delay: Option<i64>,
+    // Block delay with server offset to network time subtracted
+    pub block_delay_corrected: Option<i64>,
+    pub threshold: u64,
+    // Some offset is above threshold
+    pub exceeded: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Blacklist {
+    pub txids: Vec<String>,
+    pub scripts: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BlacklistEntry {
+    Txid(String),
+    Script(
+        #[serde(with = "hex")]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
+}
+
+impl fmt::Display for BlacklistEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlacklistEntry::Txid(txid) => write!(f, "txid:{}", txid),
+            BlacklistEntry::Script(script) => write!(f, "script:{}", hex::encode(script)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Watch {
+    pub scripts: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WatchEntry {
+    #[serde(with = "hex")]
+    #[schemars(with = "String")]
+    pub script: Vec<u8>,
+}
+
+// Output with timelock of spending transaction: absolute `locktime` (CLTV)
+// and/or relative `sequence` (CSV, BIP 68), by height or median time past
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TimelockEntry {
+    pub txid: String,
+    pub vout: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locktime: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Timelock {
+    pub txid: String,
+    pub vout: u32,
+    pub locktime: Option<u32>,
+    pub sequence: Option<u32>,
+    // Confirmation height of output, relative timelock counted from it
+    pub height: Option<u32>,
+    pub spendable: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Timelocks {
+    pub timelocks: Vec<Timelock>,
+}
+
+// Pre-signed transaction (penalty or sweep) broadcast when output is spent,
+// `tx` is not required on remove. With `dry_run` reaction is only logged and
+// written to audit log.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ReactionEntry {
+    pub txid: String,
+    pub vout: u32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub tx: String,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Reaction {
+    pub txid: String,
+    pub vout: u32,
+    pub tx: String,
+    pub dry_run: bool,
+    // Spending transaction which triggered reaction
+    pub spent_by: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Reactions {
+    pub reactions: Vec<Reaction>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Ban {
+    // IP address or subnet
+    pub address: String,
+    pub ban_created: u64,
+    pub banned_until: u64,
+}
+
+// Body of `POST /v1/admin/bans` and `DELETE /v1/admin/bans`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BanEntry {
+    // IP address or subnet (`1.2.3.0/24`)
+    pub subnet: String,
+    // Seconds, bitcoind default (24 hours) if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bantime: Option<u64>,
+    // `bantime` is Unix time
+    #[serde(default)]
+    pub absolute: bool,
+}
+
+// Body of `POST /v1/admin/nodes` and `DELETE /v1/admin/nodes`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NodeEntry {
+    // `host:port`, host can be IP, domain or onion address
+    pub addr: String,
+    // Connect once without adding to added nodes
+    #[serde(default)]
+    pub onetry: bool,
+}
+
+// Body of `DELETE /v1/admin/peers`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerEntry {
+    Address(String),
+    Nodeid(u64),
+}
+
+impl fmt::Display for PeerEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PeerEntry::Address(address) => write!(f, "address:{}", address),
+            PeerEntry::Nodeid(nodeid) => write!(f, "nodeid:{}", nodeid),
+        }
+    }
+}
+
+// WebSocket message from client, replace topics of sent events (all by default)
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WsSubscribe {
+    pub subscribe: Vec<String>,
+    // BTC, `large` events below are skipped for this connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub large_value_min: Option<f64>,
+}
+
+// WebSocket message from client after reconnect, `Added block` events for
+// tracked blocks after given block are sent again
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WsResume {
+    pub resume: WsResumeBlock,
+}
+
+// Last block received by client, height is required to resume after block
+// was removed from chain while client was disconnected
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WsResumeBlock {
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+// WebSocket operations with `op` name, `{"op": "watch-height", "height": 840000}`
+// sends `height_reached` every time block at height is added to chain (again
+// after reorg) and `height_reverted` when it is removed, for connection lifetime
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum WsOp {
+    WatchHeight { height: u32 },
+}
+
+// Messages accepted by WebSocket
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum WsCommand {
+    Subscribe(WsSubscribe),
+    Resume(WsResume),
+    Op(WsOp),
+}
+
+// Transaction with inputs total above `--large-tx`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LargeTx {
+    pub txid: String,
+    // Inputs total in satoshis
+    pub value: u64,
+    // Not set for mempool transaction
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StreakKind {
+    // Coinbase only
+    Empty,
+    // Weight above 99% of limit
+    Full,
+}
+
+impl fmt::Display for StreakKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreakKind::Empty => write!(f, "empty"),
+            StreakKind::Full => write!(f, "full"),
+        }
+    }
+}
+
+// Consecutive empty or near-full blocks
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BlockStreak {
+    pub kind: StreakKind,
+    pub length: usize,
+    pub start_height: u32,
+    pub end_height: u32,
+}
+
+// Empty and near-full blocks among latest blocks
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BlockStreaks {
+    // Streak length for `streak` event
+    pub threshold: usize,
+    pub blocks: usize,
+    pub transactions_avg: usize,
+    pub empty: usize,
+    pub full: usize,
+    // Streak ending at tip
+    pub current: Option<BlockStreak>,
+    pub longest_empty: usize,
+    pub longest_full: usize,
+}
+
+// WebSocket answer on `WsCommand`, also sent for watched heights
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum WsReply {
+    Subscribed { subscribed: Vec<String> },
+    // Number of replayed block events
+    Resumed { resumed: usize },
+    WatchingHeight { watching_height: u32 },
+    HeightReached { height_reached: u32, hash: String },
+    HeightReverted { height_reverted: u32, hash: String },
+    Error { error: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BitcoindEndpoint {
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BitcoindSwitch {
+    pub chain: String,
+    pub tip: Tip,
+    // Blocks of our chain replaced because new node has different hashes
+    pub resynced: usize,
+}
+
+// Body of `POST /v1/tx/test`, several transactions tested as package
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TxTestRequest {
+    // Raw transactions in hex
+    pub txs: Vec<String>,
+    // Fee rate limit in BTC/kvB, bitcoind default if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxfeerate: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TxTestResult {
+    pub txid: String,
+    pub wtxid: Option<String>,
+    pub allowed: bool,
+    pub vsize: Option<u32>,
+    // Fee in satoshis
+    pub fee: Option<u64>,
+    pub reject_reason: Option<String>,
+    pub package_error: Option<String>,
+}
+
+impl From<ResponseMempoolAccept> for TxTestResult {
+    fn from(result: ResponseMempoolAccept) -> Self {
+        TxTestResult {
+            txid: result.txid,
+            wtxid: result.wtxid,
+            allowed: result.allowed.unwrap_or(false),
+            vsize: result.vsize,
+            fee: result.fees.map(|fees| (fees.base * 1e8).round() as u64),
+            reject_reason: result.reject_reason,
+            package_error: result.package_error,
+        }
+    }
+}
+
+// Result of `POST /v1/tx`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TxBroadcast {
+    pub txid: String,
+}
+
+// Transaction rejected by bitcoind, body of `POST /v1/tx` error
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+}
+
+// Body of `POST /v1/txs/package`, parents before children
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PackageSubmitRequest {
+    // Raw transactions in hex
+    pub txs: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PackageSubmit {
+    // `submitpackage` or `sendrawtransaction` for nodes without package relay
+    pub method: String,
+    // Package result message of bitcoind 28.0+
+    pub message: Option<String>,
+    // In same order as in request
+    pub txs: Vec<PackageTxResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PackageTxResult {
+    pub txid: Option<String>,
+    pub wtxid: String,
+    pub accepted: bool,
+    pub vsize: Option<u32>,
+    // Fee in satoshis
+    pub fee: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct OutpointInfo {
+    pub txid: String,
+    pub vout: u32,
+    // Mempool transactions outputs and spends considered by bitcoind
+    pub include_mempool: bool,
+    // Not set if output spent or not exists
+    pub utxo: Option<Utxo>,
+    // Spending mempool transaction from spent outputs index
+    pub mempool_spend: Option<Spend>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Utxo {
+    // Value in satoshis
+    pub value: u64,
+    #[serde(with = "hex")]
+    #[schemars(with = "String")]
+    pub script: Vec<u8>,
+    // Zero for mempool transaction output
+    pub confirmations: u32,
+    pub coinbase: bool,
+}
+
+// Basic compact block filter (BIP158)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BlockFilter {
+    pub hash: String,
+    pub height: u32,
+    // Unique scripts in filter
+    pub elements: usize,
+    // Serialized filter in hex, same as `filter` of `getblockfilter`
+    pub filter: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct OutpointSpend {
+    pub txid: String,
+    pub vout: u32,
+    // Not set if output not spent in tracked blocks or mempool
+    pub spend: Option<Spend>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Spend {
+    pub txid: String,
+    // Input index in spending transaction
+    pub vin: u32,
+    // Block height, not set for mempool transaction
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AddressTransactions {
+    pub address: String,
+    #[serde(with = "hex")]
+    #[schemars(with = "String")]
+    pub script: Vec<u8>,
+    // Latest blocks first
+    pub transactions: Vec<AddressTransaction>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AddressTransaction {
+    pub txid: String,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Halving {
+    // Tip height
+    pub height: u32,
+    // Halvings before tip, 0 for initial subsidy
+    pub era: u32,
+    // Subsidy in satoshis
+    pub subsidy: u64,
+    pub next_height: u32,
+    pub next_subsidy: u64,
+    pub blocks_left: u32,
+    // Average seconds between tracked blocks
+    pub block_interval: u32,
+    // Estimated Unix time of next halving
+    pub estimated_time: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DifficultyAdjustment {
+    // Tip height
+    pub height: u32,
+    // First block of current retarget window
+    pub window_start: u32,
+    // Block with new difficulty
+    pub next_height: u32,
+    pub blocks_elapsed: u32,
+    pub blocks_left: u32,
+    // Average seconds between blocks in current window
+    pub block_interval: u32,
+    // Estimated difficulty change in percent, positive for increase
+    pub estimated_change: f64,
+    // Estimated Unix time of retarget
+    pub estimated_time: u64,
+    pub retargeting: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Peers {
+    pub total: usize,
+    pub inbound: usize,
+    pub outbound: usize,
+    // Peers count by network (ipv4, ipv6, onion, i2p, cjdns)
+    pub networks: BTreeMap<String, usize>,
+    // Peers count by user agent
+    pub versions: BTreeMap<String, usize>,
+    pub ping_min_ms: Option<u64>,
+    pub ping_median_ms: Option<u64>,
+    // Only in detailed mode, requires admin token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peers: Option<Vec<Peer>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Peer {
+    pub id: u64,
+    pub addr: String,
+    pub network: String,
+    pub subver: String,
+    pub inbound: bool,
+    pub connection_type: Option<String>,
+    pub ping_ms: Option<u64>,
+    pub synced_blocks: Option<i64>,
+    // Unix time of connection
+    pub conntime: u64,
+}
+
+// Maintenance mode, body of `PUT /v1/admin/maintenance` and response
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Maintenance {
+    pub enabled: bool,
+    // Message for clients, in 503 responses and WebSocket event
+    #[serde(default = "Maintenance::default_message")]
+    pub message: String,
+    // Seconds for `Retry-After` header
+    #[serde(default = "Maintenance::default_retry_after")]
+    pub retry_after: u64,
+}
+
+impl Maintenance {
+    fn default_message() -> String {
+        "Server is under maintenance".to_owned()
+    }
+
+    fn default_retry_after() -> u64 {
+        300
+    }
+}
+
+// Update loop state, response of `POST /v1/admin/pause` and
+// `POST /v1/admin/resume`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UpdatesPause {
+    pub paused: bool,
+    // Unix time of pause, cached data is served without updates since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_since: Option<u64>,
+}
+
+// Number of tracked blocks, body of `PUT /v1/admin/blocks-keep` and response
+// (`tracked` is ignored in body)
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BlocksKeep {
+    pub blocks: usize,
+    // Blocks in chain after change, less than `blocks` only near genesis
+    #[serde(default)]
+    pub tracked: usize,
+}
+
+// Fault injection in bitcoind client (`--features chaos`), body of
+// `PUT /v1/admin/chaos` and response (`injected` is ignored in body).
+// Probabilities are from 0 to 1, faults except latency exclude each other.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Chaos {
+    #[serde(default)]
+    pub latency: f64,
+    #[serde(default)]
+    pub latency_ms: u64,
+    // Request fails as if connection was closed
+    #[serde(default)]
+    pub drop: f64,
+    // `500 Internal Server Error` with text body
+    #[serde(default)]
+    pub error: f64,
+    // Truncated JSON with status 200
+    #[serde(default)]
+    pub malformed: f64,
+    #[serde(default)]
+    pub injected: ChaosInjected,
+}
+
+// Injected faults since start
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ChaosInjected {
+    pub latency: u64,
+    pub drop: u64,
+    pub error: u64,
+    pub malformed: u64,
+}
+
+// Response for API requests in maintenance mode or above egress budget
+// (status 503), and above rate limit (status 429)
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Unavailable {
+    pub error: String,
+    pub message: String,
+    pub retry_after: u64,
+}
+
+// API usage of tenant in current day and month
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Usage {
+    pub tenant: String,
+    pub periods: Vec<UsagePeriod>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UsagePeriod {
+    pub period: String,
+    // UTC day (`2020-01-31`) or month (`2020-01`)
+    pub window: String,
+    // Seconds until counters reset
+    pub reset_after: u64,
+    pub total: u64,
+    pub routes: BTreeMap<String, u64>,
+    pub quotas: Vec<QuotaUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct QuotaUsage {
+    pub route: Option<String>,
+    pub limit: u64,
+    pub remaining: u64,
+}
+
+// Response for requests above quota (status 429)
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct QuotaExceeded {
+    pub error: String,
+    pub period: String,
+    pub route: Option<String>,
+    pub limit: u64,
+    pub reset_after: u64,
+}
+
+// Block announces by notification sources
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BlockSources {
+    pub sources: BTreeMap<String, BlockSourceStats>,
+    pub blocks: Vec<BlockAnnounce>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BlockSourceStats {
+    pub announced: u64,
+    // Number of blocks announced by this source first
+    pub first: u64,
+    // Delay after first source, for blocks announced not first
+    pub lag_avg_ms: u64,
+    pub lag_max_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BlockAnnounce {
+    pub height: u32,
+    pub hash: String,
+    pub first: String,
+    // Unix time of first announce in milliseconds
+    pub time: u64,
+    pub lags_ms: BTreeMap<String, u64>,
+}
+
+// Reorg of our chain, `depth` is number of removed blocks
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Reorg {
+    pub depth: u32,
+    pub old_tip: ReorgTip,
+    pub new_tip: ReorgTip,
+    // Unix time of reorg end in milliseconds
+    pub time: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReorgTip {
+    pub height: u32,
+    pub hash: String,
+}
+
+// Recent reorgs, newest first
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Reorgs {
+    pub reorgs: Vec<Reorg>,
+}
+
+// Periodic job of scheduler, times are unix seconds
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JobStatus {
+    pub name: String,
+    pub interval_ms: u64,
+    pub jitter_ms: u64,
+    pub running: bool,
+    pub runs: u64,
+    pub failures: u64,
+    // Ticks passed while previous run was not finished
+    pub skipped: u64,
+    pub last_run: Option<u64>,
+    pub last_duration_ms: Option<u64>,
+    // Error of last run, `null` if it succeeded
+    pub last_error: Option<String>,
+    pub next_run: Option<u64>,
+}
+
+// Tip of bitcoind node from `--bitcoind` list, first node is primary
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NodeTip {
+    pub name: String,
+    pub primary: bool,
+    pub height: Option<u32>,
+    pub hash: Option<String>,
+    // Chain differ from primary node deeper than `--chain-split-depth`
+    pub split: bool,
+    pub error: Option<String>,
+    // Unix time of last check
+    pub checked: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Nodes {
+    pub nodes: Vec<NodeTip>,
+}
+
+// Different blocks at same height on primary and another node
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ChainSplit {
+    pub node: String,
+    pub height: u32,
+    pub primary_hash: String,
+    pub node_hash: String,
+}
+
+// Error of API handler: reason phrase of status and details
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub message: String,
+}
+
+// Error for unknown path with close routes
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NotFound {
+    pub error: String,
+    pub path: String,
+    pub suggestions: Vec<RouteHint>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RouteHint {
+    pub methods: Vec<String>,
+    pub path: String,
+    pub description: String,
+    // Format of every path parameter
+    pub params: BTreeMap<String, String>,
+    // Link for routes without parameters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+}
+
+// Server description for discovery, `GET /`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Root {
+    pub name: String,
+    pub version: String,
+    pub chain: String,
+    pub tip: Tip,
+    pub features: Features,
+    pub endpoints: Vec<RouteHint>,
+    // Unix time of update loop pause, tip and other data are not updated since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_since: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Tip {
+    pub height: u32,
+    pub hash: String,
+    pub transactions: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Features {
+    pub admin: bool,
+    pub tenants: bool,
+    pub tls: bool,
+    pub hooks: usize,
+    pub plugins: usize,
+    pub rules: usize,
+    pub protobuf: bool,
+    pub chaos: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Version {
+    pub version: String,
+    pub git_commit: String,
+    pub api_versions: Vec<String>,
+    pub events_schema_version: u32,
+}
+
+// Requested API version is not supported
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UnsupportedVersion {
+    pub error: String,
+    pub requested: String,
+    pub supported: Vec<String>,
+}
@@ -0,0 +1,296 @@
+// Prometheus metrics for `/metrics`. Counters and histograms are process
+// wide and updated where things happen (update loop, bitcoind client,
+// WebSocket tasks), gauges of our chain and mempool are read from state on
+// scrape.
+//
+// Traffic is counted as bytes of bodies and WebSocket messages, headers and
+// TLS overhead are not included.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::json;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// Same prefix as default `--statsd-prefix`
+const PREFIX: &str = "bitcoin_rust_learning";
+// Upper bounds in seconds, RPC calls on local node are mostly milliseconds
+const RPC_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Debug)]
+pub struct Metrics {
+    pub blocks_added: Counter,
+    // Reorgs and resynced blocks on bitcoind switch
+    pub blocks_removed: Counter,
+    // API requests answered with 504 after `--request-timeout`
+    pub requests_timed_out: Counter,
+    pub ws_clients: Gauge,
+    // By RPC method, batch calls by method of first call
+    rpc_durations: Mutex<BTreeMap<String, Histogram>>,
+    // By node host:port and client (`rpc` or `rest`)
+    bitcoind_traffic: Mutex<BTreeMap<(String, &'static str), json::Traffic>>,
+    // By route path without version, WebSocket commands counted for `/ws`
+    route_traffic: Mutex<BTreeMap<&'static str, json::Traffic>>,
+    // WebSocket events by topic, only sent
+    topic_traffic: Mutex<BTreeMap<String, json::Traffic>>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            blocks_added: Counter(AtomicU64::new(0)),
+            blocks_removed: Counter(AtomicU64::new(0)),
+            requests_timed_out: Counter(AtomicU64::new(0)),
+            ws_clients: Gauge(AtomicI64::new(0)),
+            rpc_durations: Mutex::new(BTreeMap::new()),
+            bitcoind_traffic: Mutex::new(BTreeMap::new()),
+            route_traffic: Mutex::new(BTreeMap::new()),
+            topic_traffic: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record_bitcoind(
+        &self,
+        node: String,
+        client: &'static str,
+        sent: usize,
+        received: usize,
+    ) {
+        let mut traffic = self.bitcoind_traffic.lock().unwrap();
+        traffic
+            .entry((node, client))
+            .or_default()
+            .add(sent, received);
+    }
+
+    pub fn record_route(&self, route: &'static str, sent: usize, received: usize) {
+        let mut traffic = self.route_traffic.lock().unwrap();
+        traffic.entry(route).or_default().add(sent, received);
+    }
+
+    pub fn record_topic(&self, topic: &str, sent: usize) {
+        let mut traffic = self.topic_traffic.lock().unwrap();
+        match traffic.get_mut(topic) {
+            Some(traffic) => traffic.add(sent, 0),
+            None => {
+                let mut entry = json::Traffic::default();
+                entry.add(sent, 0);
+                traffic.insert(topic.to_owned(), entry);
+            }
+        }
+    }
+
+    pub fn traffic(&self) -> json::TrafficStats {
+        let bitcoind = self.bitcoind_traffic.lock().unwrap();
+        let routes = self.route_traffic.lock().unwrap();
+        json::TrafficStats {
+            bitcoind: bitcoind
+                .iter()
+                .map(|((node, client), traffic)| (format!("{} {}", node, client), *traffic))
+                .collect(),
+            routes: routes
+                .iter()
+                .map(|(route, traffic)| ((*route).to_owned(), *traffic))
+                .collect(),
+            topics: self.topic_traffic.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn observe_rpc(&self, method: &str, duration: Duration) {
+        let mut durations = self.rpc_durations.lock().unwrap();
+        match durations.get_mut(method) {
+            Some(histogram) => histogram.observe(duration),
+            None => {
+                let mut histogram = Histogram::default();
+                histogram.observe(duration);
+                durations.insert(method.to_owned(), histogram);
+            }
+        }
+    }
+
+    // Text exposition format, `gauges` are values of state: name, help, value
+    pub fn render(&self, gauges: &[(&str, &str, u64)]) -> String {
+        let mut out = String::new();
+        for (name, help, value) in gauges.iter() {
+            header(&mut out, name, help, "gauge");
+            writeln!(out, "{}_{} {}", PREFIX, name, value).unwrap();
+        }
+
+        let counters = [
+            (
+                "blocks_added_total",
+                "Blocks added to our chain",
+                &self.blocks_added,
+            ),
+            (
+                "blocks_removed_total",
+                "Blocks removed from our chain",
+                &self.blocks_removed,
+            ),
+            (
+                "requests_timed_out_total",
+                "API requests cancelled after request timeout",
+                &self.requests_timed_out,
+            ),
+        ];
+        for (name, help, counter) in counters.iter() {
+            header(&mut out, name, help, "counter");
+            writeln!(out, "{}_{} {}", PREFIX, name, counter.get()).unwrap();
+        }
+
+        let name = "ws_clients";
+        header(&mut out, name, "Connected WebSocket clients", "gauge");
+        writeln!(out, "{}_{} {}", PREFIX, name, self.ws_clients.get()).unwrap();
+
+        let name = "bitcoind_rpc_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "Duration of bitcoind RPC calls",
+            "histogram",
+        );
+        for (method, histogram) in self.rpc_durations.lock().unwrap().iter() {
+            histogram.render(&mut out, name, method);
+        }
+
+        let name = "bitcoind_bytes_total";
+        header(&mut out, name, "Bytes exchanged with bitcoind", "counter");
+        for ((node, client), traffic) in self.bitcoind_traffic.lock().unwrap().iter() {
+            let labels = format!("node=\"{}\",client=\"{}\"", node, client);
+            traffic.render(&mut out, name, &labels);
+        }
+
+        let name = "api_bytes_total";
+        header(
+            &mut out,
+            name,
+            "Bytes exchanged with API clients by route",
+            "counter",
+        );
+        for (route, traffic) in self.route_traffic.lock().unwrap().iter() {
+            traffic.render(&mut out, name, &format!("route=\"{}\"", route));
+        }
+
+        let name = "ws_bytes_total";
+        header(
+            &mut out,
+            name,
+            "Bytes of WebSocket events by topic",
+            "counter",
+        );
+        for (topic, traffic) in self.topic_traffic.lock().unwrap().iter() {
+            traffic.render(&mut out, name, &format!("topic=\"{}\"", topic));
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {}_{} {}", PREFIX, name, help).unwrap();
+    writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind).unwrap();
+}
+
+#[derive(Debug)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    // Incremented until returned guard dropped
+    pub fn track(&self) -> GaugeGuard<'_> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        GaugeGuard(self)
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct GaugeGuard<'a>(&'a Gauge);
+
+impl Drop for GaugeGuard<'_> {
+    fn drop(&mut self) {
+        (self.0).0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl json::Traffic {
+    fn add(&mut self, sent: usize, received: usize) {
+        self.sent += sent as u64;
+        self.received += received as u64;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (direction, value) in [("sent", self.sent), ("received", self.received)].iter() {
+            writeln!(
+                out,
+                "{}_{}{{{},direction=\"{}\"}} {}",
+                PREFIX, name, labels, direction, value
+            )
+            .unwrap();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // Not cumulative, last is `+Inf`
+    buckets: [u64; RPC_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let index = RPC_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(RPC_BUCKETS.len());
+        self.buckets[index] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, method: &str) {
+        let mut cumulative = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            cumulative += count;
+            let bound = match RPC_BUCKETS.get(index) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_owned(),
+            };
+            writeln!(
+                out,
+                "{}_{}_bucket{{method=\"{}\",le=\"{}\"}} {}",
+                PREFIX, name, method, bound, cumulative
+            )
+            .unwrap();
+        }
+        let labels = format!("{{method=\"{}\"}}", method);
+        writeln!(out, "{}_{}_sum{} {}", PREFIX, name, labels, self.sum).unwrap();
+        writeln!(out, "{}_{}_count{} {}", PREFIX, name, labels, self.count).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+// Consensus schedule derived from chain and block heights: subsidy halvings
+// and difficulty retargets
+
+use super::json;
+
+const COIN: u64 = 100_000_000;
+const SUBSIDY_INITIAL: u64 = 50 * COIN;
+const HALVING_INTERVAL: u32 = 210_000;
+// Regtest halves much more often, so halving can be tested
+const HALVING_INTERVAL_REGTEST: u32 = 150;
+// Subsidy is zero after 64 halvings, shift would overflow
+const HALVINGS_MAX: u32 = 64;
+// Target spacing, used if tracked blocks can not give cadence
+const BLOCK_INTERVAL_TARGET: u32 = 600;
+const RETARGET_INTERVAL: u32 = 2016;
+// Difficulty change limited by factor of 4 in both directions
+const RETARGET_FACTOR_MAX: f64 = 4.0;
+
+pub fn halving_interval(chain: &str) -> u32 {
+    if chain == "regtest" {
+        HALVING_INTERVAL_REGTEST
+    } else {
+        HALVING_INTERVAL
+    }
+}
+
+// Block subsidy in satoshis
+pub fn block_subsidy(height: u32, interval: u32) -> u64 {
+    let halvings = height / interval;
+    if halvings >= HALVINGS_MAX {
+        0
+    } else {
+        SUBSIDY_INITIAL >> halvings
+    }
+}
+
+// Average seconds between blocks, block times are not monotonic so result
+// can be meaningless for few blocks
+pub fn block_interval(times: &[u32]) -> u32 {
+    match (times.first(), times.last()) {
+        (Some(first), Some(last)) if last > first => (last - first) / (times.len() as u32 - 1),
+        _ => BLOCK_INTERVAL_TARGET,
+    }
+}
+
+// Current era and estimated time of next halving from tip
+pub fn halving(chain: &str, height: u32, time: u32, block_interval: u32) -> json::Halving {
+    let interval = halving_interval(chain);
+    let era = height / interval;
+    let next_height = (era + 1) * interval;
+    let blocks_left = next_height - height;
+
+    json::Halving {
+        height,
+        era,
+        subsidy: block_subsidy(height, interval),
+        next_height,
+        next_subsidy: block_subsidy(next_height, interval),
+        blocks_left,
+        block_interval,
+        estimated_time: time as u64 + blocks_left as u64 * block_interval as u64,
+    }
+}
+
+// First block of retarget window of block
+pub fn retarget_start(height: u32) -> u32 {
+    height - height % RETARGET_INTERVAL
+}
+
+// Estimated change from window start to tip, as if rest of window mined with
+// same cadence
+pub fn difficulty_adjustment(
+    chain: &str,
+    height: u32,
+    time: u32,
+    start_time: u32,
+) -> json::DifficultyAdjustment {
+    let window_start = retarget_start(height);
+    let next_height = window_start + RETARGET_INTERVAL;
+    let blocks_elapsed = height - window_start;
+    let blocks_left = next_height - height;
+
+    let elapsed = time.saturating_sub(start_time);
+    let (block_interval, estimated_change) = if blocks_elapsed == 0 || elapsed == 0 {
+        (BLOCK_INTERVAL_TARGET, 0.0)
+    } else {
+        let expected = (blocks_elapsed * BLOCK_INTERVAL_TARGET) as f64;
+        let factor =
+            (expected / elapsed as f64).clamp(1.0 / RETARGET_FACTOR_MAX, RETARGET_FACTOR_MAX);
+        (elapsed / blocks_elapsed, (factor - 1.0) * 100.0)
+    };
+
+    json::DifficultyAdjustment {
+        height,
+        window_start,
+        next_height,
+        blocks_elapsed,
+        blocks_left,
+        block_interval,
+        estimated_change,
+        estimated_time: time as u64 + blocks_left as u64 * block_interval as u64,
+        // Regtest never changes difficulty
+        retargeting: chain != "regtest",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subsidy() {
+        assert_eq!(halving_interval("main"), 210_000);
+        assert_eq!(halving_interval("regtest"), 150);
+        assert_eq!(block_subsidy(0, HALVING_INTERVAL), 50 * COIN);
+        assert_eq!(block_subsidy(209_999, HALVING_INTERVAL), 50 * COIN);
+        assert_eq!(
+            block_subsidy(840_000, HALVING_INTERVAL),
+            3 * COIN + COIN / 8
+        );
+        assert_eq!(block_subsidy(150, HALVING_INTERVAL_REGTEST), 25 * COIN);
+        // Shift by 64 would overflow
+        assert_eq!(block_subsidy(63 * HALVING_INTERVAL, HALVING_INTERVAL), 0);
+        assert_eq!(block_subsidy(64 * HALVING_INTERVAL, HALVING_INTERVAL), 0);
+        assert_eq!(block_subsidy(u32::MAX, 1), 0);
+    }
+
+    #[test]
+    fn halving_countdown() {
+        assert_eq!(block_interval(&[]), BLOCK_INTERVAL_TARGET);
+        assert_eq!(block_interval(&[100]), BLOCK_INTERVAL_TARGET);
+        assert_eq!(block_interval(&[100, 50]), BLOCK_INTERVAL_TARGET);
+        assert_eq!(block_interval(&[100, 90, 400, 700]), 200);
+
+        let main = halving("main", 839_999, 1_700_000_000, 500);
+        assert_eq!((main.era, main.subsidy), (3, 625_000_000));
+        assert_eq!(
+            (main.next_height, main.next_subsidy),
+            (840_000, 312_500_000)
+        );
+        assert_eq!(main.blocks_left, 1);
+        assert_eq!(main.estimated_time, 1_700_000_500);
+
+        // Tip at halving height is first block of new era
+        let regtest = halving("regtest", 300, 0, 600);
+        assert_eq!((regtest.era, regtest.next_height), (2, 450));
+        assert_eq!(regtest.blocks_left, 150);
+        assert_eq!(regtest.estimated_time, 90_000);
+    }
+
+    #[test]
+    fn difficulty() {
+        assert_eq!(retarget_start(0), 0);
+        assert_eq!(retarget_start(4031), 2016);
+        assert_eq!(retarget_start(4032), 4032);
+
+        // Blocks twice faster than target
+        let adjustment = difficulty_adjustment("main", 3024, 1_000_000, 1_000_000 - 1008 * 300);
+        assert_eq!(adjustment.window_start, 2016);
+        assert_eq!(adjustment.next_height, 4032);
+        assert_eq!(
+            (adjustment.blocks_elapsed, adjustment.blocks_left),
+            (1008, 1008)
+        );
+        assert_eq!(adjustment.block_interval, 300);
+        assert_eq!(adjustment.estimated_change, 100.0);
+        assert_eq!(adjustment.estimated_time, 1_000_000 + 1008 * 300);
+        assert!(adjustment.retargeting);
+
+        // Change limited by factor of 4
+        let adjustment = difficulty_adjustment("main", 2026, 600_000, 0);
+        assert_eq!(adjustment.estimated_change, -75.0);
+
+        // Nothing to estimate at window start or with same times
+        for (height, start_time) in &[(2016, 0), (2017, 1_000_000)] {
+            let adjustment = difficulty_adjustment("regtest", *height, 1_000_000, *start_time);
+            assert_eq!(adjustment.block_interval, BLOCK_INTERVAL_TARGET);
+            assert_eq!(adjustment.estimated_change, 0.0);
+            assert!(!adjustment.retargeting);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// Connectivity of our bitcoind node, peers addresses only in detailed mode so
+// aggregates can be public
+
+use std::collections::BTreeMap;
+
+use super::bitcoind::json::ResponsePeerInfo;
+use super::json;
+
+pub fn summarize(peers: Vec<ResponsePeerInfo>, detailed: bool) -> json::Peers {
+    let mut networks = BTreeMap::<String, usize>::new();
+    let mut versions = BTreeMap::<String, usize>::new();
+    let mut pings = vec![];
+    for peer in peers.iter() {
+        *networks.entry(peer.network.clone()).or_default() += 1;
+        *versions.entry(peer.subver.clone()).or_default() += 1;
+        if let Some(ping) = peer.pingtime {
+            pings.push(ping_ms(ping));
+        }
+    }
+    pings.sort_unstable();
+
+    let inbound = peers.iter().filter(|peer| peer.inbound).count();
+    json::Peers {
+        total: peers.len(),
+        inbound,
+        outbound: peers.len() - inbound,
+        networks,
+        versions,
+        ping_min_ms: pings.first().copied(),
+        ping_median_ms: pings.get(pings.len() / 2).copied(),
+        peers: if detailed {
+            Some(peers.into_iter().map(peer).collect())
+        } else {
+            None
+        },
+    }
+}
+
+fn peer(peer: ResponsePeerInfo) -> json::Peer {
+    json::Peer {
+        id: peer.id,
+        addr: peer.addr,
+        network: peer.network,
+        subver: peer.subver,
+        inbound: peer.inbound,
+        connection_type: peer.connection_type,
+        ping_ms: peer.pingtime.map(ping_ms),
+        synced_blocks: peer.synced_blocks,
+        conntime: peer.conntime,
+    }
+}
+
+// bitcoind reports ping in seconds
+fn ping_ms(ping: f64) -> u64 {
+    (ping * 1000.0).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peers() -> Vec<ResponsePeerInfo> {
+        let peers = serde_json::json!([
+            {
+                "id": 1, "addr": "10.0.0.1:8333", "network": "ipv4",
+                "subver": "/Satoshi:27.0.0/", "inbound": false,
+                "connection_type": "outbound-full-relay", "pingtime": 0.0304,
+                "synced_blocks": 850000, "conntime": 1700000000,
+            },
+            {
+                "id": 2, "addr": "abc.onion:8333", "network": "onion",
+                "subver": "/Satoshi:27.0.0/", "inbound": true,
+                "pingtime": 0.1, "conntime": 1700000100,
+            },
+            // Before bitcoind 22.0 and first pong
+            {
+                "id": 3, "addr": "10.0.0.3:8333",
+                "subver": "/Satoshi:0.21.0/", "inbound": true,
+                "conntime": 1700000200,
+            },
+            {
+                "id": 4, "addr": "[::1]:8333", "network": "ipv6",
+                "subver": "/Satoshi:26.0.0/", "inbound": false,
+                "pingtime": 0.2, "conntime": 1700000300,
+            },
+        ]);
+        serde_json::from_value(peers).unwrap()
+    }
+
+    #[test]
+    fn aggregates() {
+        let summary = summarize(peers(), false);
+        assert_eq!(
+            (summary.total, summary.inbound, summary.outbound),
+            (4, 2, 2)
+        );
+        let networks = summary.networks.into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            networks,
+            vec![
+                ("ipv4".to_owned(), 1),
+                ("ipv6".to_owned(), 1),
+                ("onion".to_owned(), 1),
+                ("unknown".to_owned(), 1)
+            ]
+        );
+        assert_eq!(summary.versions["/Satoshi:27.0.0/"], 2);
+        assert_eq!(summary.ping_min_ms, Some(30));
+        assert_eq!(summary.ping_median_ms, Some(100));
+        // Addresses only in detailed mode
+        assert!(summary.peers.is_none());
+
+        let empty = summarize(vec![], false);
+        assert_eq!(empty.total, 0);
+        assert_eq!((empty.ping_min_ms, empty.ping_median_ms), (None, None));
+    }
+
+    #[test]
+    fn detailed() {
+        let peers = summarize(peers(), true).peers.unwrap();
+        let ids = peers.iter().map(|peer| peer.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+        assert_eq!(peers[0].addr, "10.0.0.1:8333");
+        assert_eq!(peers[0].ping_ms, Some(30));
+        assert_eq!(
+            peers[0].connection_type.as_deref(),
+            Some("outbound-full-relay")
+        );
+        assert_eq!((peers[2].ping_ms, peers[2].synced_blocks), (None, None));
+    }
+}
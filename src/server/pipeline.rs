@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use futures::future::{self, FutureExt as _};
+use futures::stream::{self, BoxStream, StreamExt as _};
+use tokio::sync::mpsc;
+use tokio::task::JoinError;
+
+use super::bitcoind::json::ResponseBlock;
+use super::bitcoind::{parse_block, Bitcoind, BitcoindError, BlockBody};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum PipelineError {
+        Bitcoind(err: BitcoindError) {
+            display("bitcoind: {}", err)
+        }
+        BlockNotFound(height: u32) {
+            display("Block at height {} not found", height)
+        }
+        Task(err: JoinError) {
+            display("Analyze task failed: {}", err)
+        }
+    }
+}
+
+pub type StageResult<T> = Result<T, PipelineError>;
+
+// Blocks processing split to stages connected with channels:
+//   fetch: download block bodies, `fetch_jobs` requests in parallel
+//   decode + analyze: CPU-bound, run on blocking threads, `analyze_jobs` blocks in parallel
+//   output: analyzed blocks strictly in height order, ready for commit
+#[derive(Debug, Clone, Copy)]
+pub struct Pipeline {
+    pub fetch_jobs: usize,
+    pub analyze_jobs: usize,
+}
+
+impl Pipeline {
+    pub fn new(fetch_jobs: usize, analyze_jobs: usize) -> Self {
+        Pipeline {
+            fetch_jobs,
+            analyze_jobs,
+        }
+    }
+
+    pub fn default_analyze_jobs() -> usize {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    }
+
+    // Stream of analyzed blocks in height order, stream stops after first
+    // error, which is returned after all blocks before it
+    pub fn blocks<'a, T, A>(
+        &self,
+        bitcoind: &'a Bitcoind,
+        heights: RangeInclusive<u32>,
+        analyze: A,
+    ) -> BoxStream<'a, StageResult<T>>
+    where
+        T: Send + 'static,
+        A: Fn(ResponseBlock) -> T + Send + Sync + 'static,
+    {
+        let (fetched_tx, fetched_rx) = mpsc::channel(self.fetch_jobs);
+        let (analyzed_tx, analyzed_rx) = mpsc::channel(self.analyze_jobs);
+        let next_height = *heights.start();
+
+        // Stages driven together with output stream, stop when stream dropped
+        let fetch = fetch_stage(bitcoind, heights, self.fetch_jobs, fetched_tx);
+        let analyze = analyze_stage(fetched_rx, analyze, self.analyze_jobs, analyzed_tx);
+        let stages = future::join(fetch, analyze).map(|_| None).into_stream();
+        let analyzed = stream::select(stages, analyzed_rx.map(Some))
+            .filter_map(future::ready)
+            .boxed();
+
+        // Blocks analyzed in any order, keep them until previous blocks returned.
+        // Error kept as block result, so blocks before it are returned first.
+        let state = (analyzed, BTreeMap::new(), next_height, false);
+        stream::unfold(
+            state,
+            |(mut analyzed, mut pending, next, failed)| async move {
+                if failed {
+                    return None;
+                }
+
+                loop {
+                    match pending.remove(&next) {
+                        Some(Ok(value)) => {
+                            return Some((Ok(value), (analyzed, pending, next + 1, false)))
+                        }
+                        Some(Err(error)) => {
+                            return Some((Err(error), (analyzed, pending, next, true)))
+                        }
+                        None => {}
+                    }
+
+                    match analyzed.next().await {
+                        Some((height, result)) => {
+                            pending.insert(height, result);
+                        }
+                        // Stages stopped without block at `next`, report lowest
+                        // error if any
+                        None => {
+                            let error = pending.into_iter().find_map(|(_, result)| result.err());
+                            return error.map(|error| {
+                                let analyzed = stream::empty().boxed();
+                                (Err(error), (analyzed, BTreeMap::new(), next, true))
+                            });
+                        }
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+}
+
+// Results passed through channels with height, so error is returned in
+// height order. Fetch stops after first error, analyze drains blocks fetched
+// before it.
+async fn fetch_stage(
+    bitcoind: &Bitcoind,
+    heights: RangeInclusive<u32>,
+    jobs: usize,
+    mut tx: mpsc::Sender<(u32, StageResult<(String, BlockBody)>)>,
+) {
+    let mut bodies = stream::iter(heights)
+        .map(|height| fetch_body(bitcoind, height))
+        .buffered(jobs);
+    while let Some(item) = bodies.next().await {
+        let stop = item.1.is_err();
+        if tx.send(item).await.is_err() || stop {
+            break;
+        }
+    }
+}
+
+async fn analyze_stage<T, A>(
+    rx: mpsc::Receiver<(u32, StageResult<(String, BlockBody)>)>,
+    analyze: A,
+    jobs: usize,
+    mut tx: mpsc::Sender<(u32, StageResult<T>)>,
+) where
+    T: Send + 'static,
+    A: Fn(ResponseBlock) -> T + Send + Sync + 'static,
+{
+    let analyze = Arc::new(analyze);
+    let mut blocks = rx
+        .map(|(height, item)| {
+            let analyze = analyze.clone();
+            let result = async move {
+                let (hash, body) = item?;
+                let task = tokio::task::spawn_blocking(move || {
+                    let block = parse_block(&hash, &body).map_err(PipelineError::Bitcoind)?;
+                    if block.height != height {
+                        return Err(PipelineError::Bitcoind(BitcoindError::ResultMismatch));
+                    }
+                    Ok(analyze(block))
+                });
+                task.await.map_err(PipelineError::Task)?
+            };
+            result.map(move |result| (height, result))
+        })
+        .buffer_unordered(jobs);
+    while let Some(item) = blocks.next().await {
+        if tx.send(item).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn fetch_body(bitcoind: &Bitcoind, height: u32) -> (u32, StageResult<(String, BlockBody)>) {
+    let result = match bitcoind.getblockbodybyheight(height).await {
+        Ok(Some((hash, body))) => Ok((hash, body)),
+        Ok(None) => Err(PipelineError::BlockNotFound(height)),
+        Err(err) => Err(PipelineError::Bitcoind(err)),
+    };
+    (height, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::bitcoind::mock::MockBitcoind;
+    use super::*;
+
+    #[tokio::test]
+    async fn ordered_blocks() {
+        let node = MockBitcoind::start(20);
+        let bitcoind = Bitcoind::new(&node.url).unwrap();
+
+        // Later blocks analyzed faster, but returned after previous
+        let pipeline = Pipeline::new(4, 4);
+        let blocks = pipeline
+            .blocks(&bitcoind, 2..=19, |block| {
+                std::thread::sleep(Duration::from_millis(u64::from(20 - block.height)));
+                (block.height, block.hash)
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        let expected = node.with(|node| {
+            node.chain()[2..]
+                .iter()
+                .map(|block| (block.height, block.hash.clone()))
+                .collect::<Vec<_>>()
+        });
+        let blocks = blocks.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(blocks, expected);
+    }
+
+    #[tokio::test]
+    async fn stop_on_error() {
+        let node = MockBitcoind::start(10);
+        let bitcoind = Bitcoind::new(&node.url).unwrap();
+
+        // Blocks before missing one returned, nothing after error. Slow
+        // analyze, so fetch error is known before blocks before it analyzed.
+        let pipeline = Pipeline::new(4, 2);
+        let blocks = pipeline
+            .blocks(&bitcoind, 5..=14, |block| {
+                std::thread::sleep(Duration::from_millis(20));
+                block.height
+            })
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(blocks.len(), 6);
+        for (block, height) in blocks[..5].iter().zip(5..) {
+            assert_eq!(block.as_ref().ok(), Some(&height));
+        }
+        assert!(matches!(
+            blocks.last(),
+            Some(Err(PipelineError::BlockNotFound(10)))
+        ));
+    }
+}
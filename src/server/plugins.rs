@@ -0,0 +1,370 @@
+// WASM plugins for custom transaction/block analysis (`--features plugins`)
+//
+// Plugin is WASM module which export:
+//   memory
+//   alloc(len: i32) -> i32                  memory for input JSON
+//   classify_tx(ptr: i32, len: i32) -> i32  optional, called for new mempool transactions
+//   on_block(ptr: i32, len: i32) -> i32     optional, called for new blocks
+// Non-zero result treated as error. Events emitted with imported function
+//   env.emit(topic_ptr: i32, topic_len: i32, data_ptr: i32, data_len: i32)
+
+use std::path::PathBuf;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "plugins")]
+use log::error;
+#[cfg(feature = "plugins")]
+use wasmtime::{
+    Caller, Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedFunc,
+};
+
+#[cfg(feature = "plugins")]
+const PLUGIN_EVENTS_PER_CALL_MAX: usize = 100;
+#[cfg(feature = "plugins")]
+const PLUGIN_EVENT_SIZE_MAX: usize = 64 * 1024;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum PluginError {
+        Disabled {
+            display("Plugins support is not enabled, rebuild with `--features plugins`")
+        }
+        Load(path: PathBuf, err: String) {
+            display("Failed to load plugin {}: {}", path.display(), err)
+        }
+    }
+}
+
+pub type PluginResult<T> = Result<T, PluginError>;
+
+#[derive(Debug, Clone)]
+pub struct PluginConfig {
+    pub paths: Vec<PathBuf>,
+    // Fuel (roughly number of instructions) for one call
+    pub fuel: u64,
+    // Memory limit in bytes for every plugin
+    pub memory: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct PluginEvent {
+    pub topic: String,
+    pub data: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PluginStats {
+    pub name: String,
+    pub calls: u64,
+    pub errors: u64,
+    pub events: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Export {
+    ClassifyTx,
+    OnBlock,
+}
+
+#[derive(Debug)]
+pub struct PluginHost {
+    plugins: Vec<Plugin>,
+}
+
+impl PluginHost {
+    // `None` if no plugins specified
+    pub fn load(config: &PluginConfig) -> PluginResult<Option<Self>> {
+        if config.paths.is_empty() {
+            return Ok(None);
+        }
+
+        Self::load_plugins(config).map(|plugins| Some(PluginHost { plugins }))
+    }
+
+    #[cfg(not(feature = "plugins"))]
+    fn load_plugins(_config: &PluginConfig) -> PluginResult<Vec<Plugin>> {
+        Err(PluginError::Disabled)
+    }
+
+    #[cfg(feature = "plugins")]
+    fn load_plugins(config: &PluginConfig) -> PluginResult<Vec<Plugin>> {
+        let mut engine_config = Config::new();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config)
+            .map_err(|err| PluginError::Load(PathBuf::new(), format!("{:#}", err)))?;
+
+        config
+            .paths
+            .iter()
+            .map(|path| {
+                Plugin::load(&engine, path, config)
+                    .map_err(|err| PluginError::Load(path.clone(), format!("{:#}", err)))
+            })
+            .collect()
+    }
+
+    // Transaction JSON in form of `ResponseBlockTransaction`
+    pub fn classify_tx(&mut self, tx: &[u8]) -> Vec<PluginEvent> {
+        self.call(Export::ClassifyTx, tx)
+    }
+
+    // Block JSON in form of `ResponseBlock`
+    pub fn on_block(&mut self, block: &[u8]) -> Vec<PluginEvent> {
+        self.call(Export::OnBlock, block)
+    }
+
+    // Errors in one plugin do not affect other plugins
+    fn call(&mut self, export: Export, input: &[u8]) -> Vec<PluginEvent> {
+        let mut events = vec![];
+        for plugin in self.plugins.iter_mut() {
+            events.extend(plugin.call(export, input));
+        }
+        events
+    }
+
+    pub fn stats(&self) -> Vec<PluginStats> {
+        self.plugins.iter().map(|plugin| plugin.stats()).collect()
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+#[derive(Debug)]
+enum Plugin {}
+
+#[cfg(not(feature = "plugins"))]
+impl Plugin {
+    fn call(&mut self, _export: Export, _input: &[u8]) -> Vec<PluginEvent> {
+        match *self {}
+    }
+
+    fn stats(&self) -> PluginStats {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "plugins")]
+struct PluginState {
+    limits: StoreLimits,
+    events: Vec<PluginEvent>,
+}
+
+#[cfg(feature = "plugins")]
+struct Plugin {
+    store: Store<PluginState>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    classify_tx: Option<TypedFunc<(i32, i32), i32>>,
+    on_block: Option<TypedFunc<(i32, i32), i32>>,
+    fuel: u64,
+    stats: PluginStats,
+}
+
+#[cfg(feature = "plugins")]
+impl std::fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Plugin")
+            .field("name", &self.stats.name)
+            .finish()
+    }
+}
+
+#[cfg(feature = "plugins")]
+impl Plugin {
+    fn load(
+        engine: &Engine,
+        path: &std::path::Path,
+        config: &PluginConfig,
+    ) -> wasmtime::Result<Self> {
+        let module = Module::from_file(engine, path)?;
+
+        let mut linker = Linker::new(engine);
+        linker.func_wrap("env", "emit", Self::emit)?;
+
+        let state = PluginState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(config.memory)
+                .instances(1)
+                .build(),
+            events: vec![],
+        };
+        let mut store = Store::new(engine, state);
+        store.limiter(|state| &mut state.limits);
+        // Fuel for start function
+        store.set_fuel(config.fuel)?;
+
+        let instance = linker.instantiate(&mut store, &module)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("memory export not found"))?;
+        let alloc = instance.get_typed_func(&mut store, "alloc")?;
+        let classify_tx = instance.get_typed_func(&mut store, "classify_tx").ok();
+        let on_block = instance.get_typed_func(&mut store, "on_block").ok();
+
+        let name = path
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Ok(Plugin {
+            store,
+            memory,
+            alloc,
+            classify_tx,
+            on_block,
+            fuel: config.fuel,
+            stats: PluginStats {
+                name,
+                ..Default::default()
+            },
+        })
+    }
+
+    fn emit(
+        mut caller: Caller<'_, PluginState>,
+        topic_ptr: i32,
+        topic_len: i32,
+        data_ptr: i32,
+        data_len: i32,
+    ) -> wasmtime::Result<()> {
+        if caller.data().events.len() >= PLUGIN_EVENTS_PER_CALL_MAX {
+            return Err(wasmtime::Error::msg("too many events"));
+        }
+        if topic_len < 0 || data_len < 0 {
+            return Err(wasmtime::Error::msg("invalid event"));
+        }
+        if topic_len as usize + data_len as usize > PLUGIN_EVENT_SIZE_MAX {
+            return Err(wasmtime::Error::msg("event is too big"));
+        }
+
+        let memory = caller
+            .get_export("memory")
+            .and_then(|export| export.into_memory())
+            .ok_or_else(|| wasmtime::Error::msg("memory export not found"))?;
+        let read = |caller: &Caller<'_, PluginState>, ptr: i32, len: i32| {
+            let mut buf = vec![0; len as usize];
+            memory.read(caller, ptr as usize, &mut buf)?;
+            String::from_utf8(buf).map_err(wasmtime::Error::msg)
+        };
+        let topic = read(&caller, topic_ptr, topic_len)?;
+        let data = read(&caller, data_ptr, data_len)?;
+
+        caller.data_mut().events.push(PluginEvent { topic, data });
+        Ok(())
+    }
+
+    fn call(&mut self, export: Export, input: &[u8]) -> Vec<PluginEvent> {
+        let func = match export {
+            Export::ClassifyTx => self.classify_tx.clone(),
+            Export::OnBlock => self.on_block.clone(),
+        };
+        let func = match func {
+            Some(func) => func,
+            None => return vec![],
+        };
+
+        self.stats.calls += 1;
+        let result = self.call_func(&func, input);
+        let events = std::mem::take(&mut self.store.data_mut().events);
+        match result {
+            Ok(()) => {
+                self.stats.events += events.len() as u64;
+                events
+            }
+            Err(err) => {
+                self.stats.errors += 1;
+                error!("Plugin {} {:?} error: {:#}", self.stats.name, export, err);
+                vec![]
+            }
+        }
+    }
+
+    fn call_func(
+        &mut self,
+        func: &TypedFunc<(i32, i32), i32>,
+        input: &[u8],
+    ) -> wasmtime::Result<()> {
+        self.store.set_fuel(self.fuel)?;
+
+        let len = input.len() as i32;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory.write(&mut self.store, ptr as usize, input)?;
+        match func.call(&mut self.store, (ptr, len))? {
+            0 => Ok(()),
+            code => Err(wasmtime::Error::msg(format!("exit code {}", code))),
+        }
+    }
+
+    fn stats(&self) -> PluginStats {
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Emits `tx` event with input as data, never returns from `on_block`
+    #[cfg(feature = "plugins")]
+    const PLUGIN_WAT: &str = r#"
+        (module
+            (import "env" "emit" (func $emit (param i32 i32 i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "tx")
+            (func (export "alloc") (param i32) (result i32) i32.const 16)
+            (func (export "classify_tx") (param i32 i32) (result i32)
+                (call $emit (i32.const 0) (i32.const 2) (local.get 0) (local.get 1))
+                i32.const 0)
+            (func (export "on_block") (param i32 i32) (result i32)
+                (loop $spin (br $spin))
+                i32.const 0))
+    "#;
+
+    fn config(paths: Vec<PathBuf>) -> PluginConfig {
+        PluginConfig {
+            paths,
+            fuel: 100_000,
+            memory: 1 << 20,
+        }
+    }
+
+    #[test]
+    fn no_plugins() {
+        assert!(PluginHost::load(&config(vec![])).unwrap().is_none());
+    }
+
+    #[cfg(not(feature = "plugins"))]
+    #[test]
+    fn disabled() {
+        let result = PluginHost::load(&config(vec![PathBuf::from("plugin.wasm")]));
+        assert!(matches!(result, Err(PluginError::Disabled)));
+    }
+
+    #[cfg(feature = "plugins")]
+    #[test]
+    fn fuel_limit() {
+        let dir = std::env::temp_dir().join(format!("plugins-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("echo.wat");
+        std::fs::write(&path, PLUGIN_WAT).unwrap();
+        let mut host = PluginHost::load(&config(vec![path])).unwrap().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        // Plugin out of fuel fails only current call
+        assert!(host.on_block(b"{}").is_empty());
+        let events = host.classify_tx(br#"{"txid":"00"}"#);
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            (events[0].topic.as_str(), events[0].data.as_str()),
+            ("tx", r#"{"txid":"00"}"#)
+        );
+        let stats = &host.stats()[0];
+        assert_eq!(stats.name, "echo");
+        assert_eq!((stats.calls, stats.errors, stats.events), (2, 1, 1));
+
+        let error = PluginHost::load(&config(vec![PathBuf::from("missed.wasm")])).unwrap_err();
+        assert!(matches!(error, PluginError::Load(..)));
+    }
+}
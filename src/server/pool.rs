@@ -0,0 +1,186 @@
+// Chain split detection across bitcoind nodes from `--bitcoind` list
+//
+// Every node is compared with primary node (first in list, used for
+// tracking). Nodes see new blocks at different time, so hashes compared
+// `depth` blocks below lower tip and short races at tip are not reported.
+
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt as _};
+use log::{info, warn};
+use url::Url;
+
+use super::bitcoind::{Bitcoind, BitcoindResult};
+use super::events::Event;
+use super::json;
+use super::scheduler::{Job, JobResult, Schedule};
+use super::state::{unix_time, State};
+
+#[derive(Debug, Clone)]
+pub struct ChainSplitConfig {
+    // Nodes except primary
+    pub nodes: Vec<Url>,
+    pub interval: Duration,
+    // Blocks at tip which can differ without split
+    pub depth: u32,
+}
+
+#[derive(Debug)]
+struct PoolNode {
+    bitcoind: Bitcoind,
+    // Host and port of node, without credentials
+    name: String,
+    split: bool,
+}
+
+#[derive(Debug)]
+pub struct BitcoindPool {
+    nodes: Vec<PoolNode>,
+    interval: Duration,
+    depth: u32,
+}
+
+impl BitcoindPool {
+    pub fn new(config: &ChainSplitConfig) -> BitcoindResult<Self> {
+        let mut nodes = vec![];
+        for url in config.nodes.iter() {
+            let bitcoind = Bitcoind::new(url.as_str())?;
+            nodes.push(PoolNode {
+                name: bitcoind.name(),
+                bitcoind,
+                split: false,
+            });
+        }
+
+        Ok(BitcoindPool {
+            nodes,
+            interval: config.interval,
+            depth: config.depth,
+        })
+    }
+
+    // First check on start, so node tips are known right away
+    pub fn schedule(&self) -> Schedule {
+        Schedule::every(self.interval).immediate()
+    }
+
+    // Query tips of all nodes and compare with primary, result saved in state
+    pub async fn check(&mut self, state: &State) {
+        let checked = unix_time();
+        let primary = state.get_node_tip().await;
+        let mut tips = vec![json::NodeTip {
+            name: state.bitcoind_name(),
+            primary: true,
+            height: primary.as_ref().ok().map(|(height, _)| *height),
+            hash: primary.as_ref().ok().map(|(_, hash)| hash.clone()),
+            split: false,
+            error: primary.as_ref().err().map(|err| err.to_string()),
+            checked,
+        }];
+
+        for node in self.nodes.iter_mut() {
+            let result = match &primary {
+                Ok(primary) => Self::compare(state, node, primary, self.depth).await,
+                // Node tip is still reported, split state is kept
+                Err(_) => Self::get_tip(&node.bitcoind).await.map(|tip| (tip, None)),
+            };
+            let ((height, hash), split) = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    warn!("Chain split check with {} failed: {}", node.name, err);
+                    tips.push(json::NodeTip {
+                        name: node.name.clone(),
+                        primary: false,
+                        height: None,
+                        hash: None,
+                        split: node.split,
+                        error: Some(err.to_string()),
+                        checked,
+                    });
+                    continue;
+                }
+            };
+
+            match split {
+                Some(split) if !node.split => {
+                    warn!(
+                        "Chain split with {} at {}: primary {}, node {}",
+                        node.name, split.height, split.primary_hash, split.node_hash
+                    );
+                    state.get_events().publish(Event::ChainSplit(split));
+                    node.split = true;
+                }
+                None if node.split && primary.is_ok() => {
+                    info!("Node {} follows primary chain again", node.name);
+                    node.split = false;
+                }
+                _ => {}
+            }
+            tips.push(json::NodeTip {
+                name: node.name.clone(),
+                primary: false,
+                height: Some(height),
+                hash: Some(hash),
+                split: node.split,
+                error: None,
+                checked,
+            });
+        }
+
+        state.set_node_tips(tips);
+    }
+
+    // Node tip and split, if hashes `depth` blocks below lower tip differ
+    async fn compare(
+        state: &State,
+        node: &PoolNode,
+        primary: &(u32, String),
+        depth: u32,
+    ) -> BitcoindResult<((u32, String), Option<json::ChainSplit>)> {
+        let (primary_height, primary_tip) = primary;
+        let (tip_height, tip_hash) = Self::get_tip(&node.bitcoind).await?;
+        let height = match tip_height.min(*primary_height).checked_sub(depth) {
+            Some(height) => height,
+            None => return Ok(((tip_height, tip_hash), None)),
+        };
+
+        let primary_hash = match height {
+            height if height == *primary_height => Some(primary_tip.clone()),
+            height => state.get_block_hash_by_height(height).await?,
+        };
+        let node_hash = match height {
+            height if height == tip_height => Some(tip_hash.clone()),
+            height => node.bitcoind.getblockhash(height).await?,
+        };
+
+        let split = match (primary_hash, node_hash) {
+            (Some(primary_hash), Some(node_hash)) if primary_hash != node_hash => {
+                Some(json::ChainSplit {
+                    node: node.name.clone(),
+                    height,
+                    primary_hash,
+                    node_hash,
+                })
+            }
+            // Same hashes or chain changed between requests
+            _ => None,
+        };
+        Ok(((tip_height, tip_hash), split))
+    }
+
+    async fn get_tip(bitcoind: &Bitcoind) -> BitcoindResult<(u32, String)> {
+        let info = bitcoind.getblockchaininfo().await?;
+        Ok((info.blocks, info.bestblockhash))
+    }
+}
+
+// Failed nodes are reported in tips, check itself does not fail
+impl Job for BitcoindPool {
+    fn run<'a>(&'a mut self, state: &'a State) -> BoxFuture<'a, JobResult> {
+        async move {
+            self.check(state).await;
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,258 @@
+// Projected next block: greedy assembly of mempool transactions by ancestor
+// fee rate, as `CreateNewBlock` of bitcoind does. Transaction is added only
+// together with its unconfirmed ancestors, and once ancestors are in block,
+// score of their descendants is recomputed without them, so child paying for
+// low fee parent (CPFP) is picked by rate of whole package.
+//
+// Parents are known from spent outputs index, which is filled after new
+// mempool transactions are fetched, so until then transaction is assembled
+// as if it has no parents.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use futures::future::{BoxFuture, FutureExt as _};
+
+use super::json;
+use super::scheduler::{Job, JobResult};
+use super::state::{unix_time, State};
+
+// Block weight limit without space reserved for coinbase, as default
+// `-blockmaxweight` of bitcoind
+pub const BLOCK_WEIGHT_MAX: u64 = 4_000_000 - 4_000;
+// Weight units in one virtual byte
+const WITNESS_SCALE_FACTOR: u64 = 4;
+
+#[derive(Debug)]
+pub struct Candidate {
+    pub txid: String,
+    pub vsize: u32,
+    // Satoshis
+    pub fee: u64,
+    // Parents which are also candidates, other parents are confirmed
+    pub parents: Vec<String>,
+}
+
+// Fee and virtual size of transaction with ancestors not in block yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Score {
+    fee: u64,
+    vsize: u64,
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let a = u128::from(self.fee) * u128::from(other.vsize.max(1));
+        let b = u128::from(other.fee) * u128::from(self.vsize.max(1));
+        a.cmp(&b)
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Job of periodic projection with `ProjectedBlock` event
+pub struct Projection;
+
+impl Job for Projection {
+    fn run<'a>(&'a mut self, state: &'a State) -> BoxFuture<'a, JobResult> {
+        async move {
+            state.update_projected_block().await;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+struct Assembler<'a> {
+    candidates: &'a [Candidate],
+    parents: Vec<Vec<usize>>,
+    children: Vec<Vec<usize>>,
+    included: Vec<bool>,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(candidates: &'a [Candidate]) -> Self {
+        let index = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| (tx.txid.as_str(), i))
+            .collect::<HashMap<_, _>>();
+        let mut parents = vec![vec![]; candidates.len()];
+        let mut children = vec![vec![]; candidates.len()];
+        for (i, tx) in candidates.iter().enumerate() {
+            for parent in tx
+                .parents
+                .iter()
+                .filter_map(|txid| index.get(txid.as_str()))
+            {
+                if *parent != i && !parents[i].contains(parent) {
+                    parents[i].push(*parent);
+                    children[*parent].push(i);
+                }
+            }
+        }
+
+        Assembler {
+            candidates,
+            parents,
+            children,
+            included: vec![false; candidates.len()],
+        }
+    }
+
+    // Ancestors not in block with transaction itself
+    fn package(&self, tx: usize) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![tx];
+        while let Some(tx) = stack.pop() {
+            if !self.included[tx] && seen.insert(tx) {
+                stack.extend(self.parents[tx].iter().copied());
+            }
+        }
+        seen.into_iter().collect()
+    }
+
+    fn score(&self, package: &[usize]) -> Score {
+        let txs = package.iter().map(|tx| &self.candidates[*tx]);
+        txs.fold(Score { fee: 0, vsize: 0 }, |score, tx| Score {
+            fee: score.fee + tx.fee,
+            vsize: score.vsize + u64::from(tx.vsize),
+        })
+    }
+
+    // Not included descendants of package, their scores changed
+    fn descendants(&self, package: &[usize]) -> HashSet<usize> {
+        let mut seen = HashSet::new();
+        let mut stack = package.to_vec();
+        while let Some(tx) = stack.pop() {
+            for child in self.children[tx].iter() {
+                if !self.included[*child] && seen.insert(*child) {
+                    stack.push(*child);
+                }
+            }
+        }
+        seen
+    }
+
+    // Ancestors before descendants, so block stays valid
+    fn sorted(&self, mut package: Vec<usize>) -> Vec<usize> {
+        let mut sorted = Vec::with_capacity(package.len());
+        while !package.is_empty() {
+            let (ready, rest) = package.into_iter().partition::<Vec<_>, _>(|tx| {
+                let parents = self.parents[*tx].iter();
+                parents
+                    .filter(|parent| !self.included[**parent] && !sorted.contains(*parent))
+                    .count()
+                    == 0
+            });
+            if ready.is_empty() {
+                // Cycle can not be in valid mempool, keep as is
+                sorted.extend(rest);
+                break;
+            }
+            sorted.extend(ready);
+            package = rest;
+        }
+        sorted
+    }
+}
+
+// Transactions for block with weight up to `weight_max`, highest package fee
+// rate first
+pub fn assemble(height: u32, candidates: &[Candidate], weight_max: u64) -> json::ProjectedBlock {
+    let mut assembler = Assembler::new(candidates);
+    let mut heap = (0..candidates.len())
+        .map(|tx| (assembler.score(&assembler.package(tx)), tx))
+        .collect::<BinaryHeap<_>>();
+
+    let mut block = json::ProjectedBlock {
+        height,
+        transactions: 0,
+        weight: 0,
+        vsize: 0,
+        fees: 0,
+        feerate_min: None,
+        time: unix_time(),
+        txids: vec![],
+    };
+    // Package too large for rest of block is skipped, smaller can still fit
+    let mut skipped = vec![false; candidates.len()];
+    while let Some((score, tx)) = heap.pop() {
+        if assembler.included[tx] || skipped[tx] {
+            continue;
+        }
+        let package = assembler.package(tx);
+        // Stale entry, fresh one pushed when ancestors were included
+        if assembler.score(&package) != score {
+            continue;
+        }
+        if block.weight + score.vsize * WITNESS_SCALE_FACTOR > weight_max {
+            skipped[tx] = true;
+            continue;
+        }
+
+        for tx in assembler.sorted(package.clone()) {
+            let candidate = &candidates[tx];
+            block.txids.push(candidate.txid.clone());
+            assembler.included[tx] = true;
+        }
+        block.transactions += package.len();
+        block.vsize += score.vsize;
+        block.weight += score.vsize * WITNESS_SCALE_FACTOR;
+        block.fees += score.fee;
+        let feerate = score.fee as f64 / score.vsize.max(1) as f64;
+        block.feerate_min = Some(block.feerate_min.map_or(feerate, |min| min.min(feerate)));
+
+        for descendant in assembler.descendants(&package) {
+            let score = assembler.score(&assembler.package(descendant));
+            heap.push((score, descendant));
+        }
+    }
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_fee_rate() {
+        let candidate = |txid: &str, fee: u64, parents: &[&str]| Candidate {
+            txid: txid.to_owned(),
+            vsize: 100,
+            fee,
+            parents: parents.iter().map(|txid| (*txid).to_owned()).collect(),
+        };
+        // Child pays for parent, package rate 25.5 sat/vB; `z` spends confirmed
+        let candidates = vec![
+            candidate("y", 1_500, &[]),
+            candidate("p", 100, &[]),
+            candidate("x", 2_000, &[]),
+            candidate("c", 5_000, &["p"]),
+            candidate("z", 1_000, &["confirmed"]),
+        ];
+
+        let block = assemble(21, &candidates, 1_000_000);
+        assert_eq!(block.txids, vec!["p", "c", "x", "y", "z"]);
+        assert_eq!(
+            (block.transactions, block.weight, block.fees),
+            (5, 2_000, 9_600)
+        );
+        assert_eq!(block.feerate_min, Some(10.0));
+
+        // Package above rest of block skipped, smaller one still fits
+        let candidates = vec![
+            candidate("p", 100, &[]),
+            candidate("c", 30_000, &["p"]),
+            candidate("x", 2_000, &[]),
+            candidate("y", 1_500, &[]),
+        ];
+        let block = assemble(21, &candidates, 700);
+        assert_eq!(block.txids, vec!["x"]);
+        assert_eq!(block.weight, 400);
+    }
+}
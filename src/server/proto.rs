@@ -0,0 +1,352 @@
+// Protobuf encoding of events, messages match `proto/events.proto`
+
+use prost::{Message, Oneof};
+
+use super::events::{BlockEvent, BusMessage, Event, TxEvent};
+use super::json::StreakKind;
+
+pub const EVENTS_PROTO: &str = include_str!("../../proto/events.proto");
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoEvent {
+    #[prost(
+        oneof = "ProtoEventKind",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 18"
+    )]
+    event: Option<ProtoEventKind>,
+    #[prost(uint64, tag = "15")]
+    seq: u64,
+    #[prost(uint64, tag = "16")]
+    received: u64,
+    // Zero until first block tracked
+    #[prost(uint32, tag = "17")]
+    chain_time: u32,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+enum ProtoEventKind {
+    #[prost(message, tag = "1")]
+    Tx(ProtoTx),
+    #[prost(message, tag = "2")]
+    CaughtUp(ProtoCaughtUp),
+    #[prost(message, tag = "3")]
+    Watch(ProtoWatch),
+    #[prost(message, tag = "4")]
+    Plugin(ProtoPlugin),
+    #[prost(message, tag = "5")]
+    Alert(ProtoAlert),
+    #[prost(message, tag = "6")]
+    Divergence(ProtoDivergence),
+    #[prost(message, tag = "7")]
+    Maintenance(ProtoMaintenance),
+    #[prost(message, tag = "8")]
+    Block(ProtoBlock),
+    #[prost(message, tag = "9")]
+    Lagged(ProtoLagged),
+    #[prost(message, tag = "10")]
+    Reorg(ProtoReorg),
+    #[prost(message, tag = "11")]
+    Large(ProtoLarge),
+    #[prost(message, tag = "12")]
+    Streak(ProtoStreak),
+    #[prost(message, tag = "13")]
+    ChainSplit(ProtoChainSplit),
+    #[prost(message, tag = "14")]
+    Timelock(ProtoTimelock),
+    #[prost(message, tag = "18")]
+    ProjectedBlock(ProtoProjectedBlock),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoTx {
+    #[prost(int32, tag = "1")]
+    status: i32,
+    #[prost(string, tag = "2")]
+    txid: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoBlock {
+    #[prost(int32, tag = "1")]
+    status: i32,
+    #[prost(uint32, tag = "2")]
+    height: u32,
+    #[prost(string, tag = "3")]
+    hash: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoCaughtUp {
+    #[prost(string, tag = "1")]
+    block: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoWatch {
+    #[prost(string, tag = "1")]
+    txid: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoTimelock {
+    #[prost(string, tag = "1")]
+    txid: String,
+    #[prost(uint32, tag = "2")]
+    vout: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoPlugin {
+    #[prost(string, tag = "1")]
+    topic: String,
+    #[prost(string, tag = "2")]
+    data: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoAlert {
+    #[prost(string, tag = "1")]
+    rule: String,
+    #[prost(string, tag = "2")]
+    txid: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoDivergence {
+    #[prost(string, tag = "1")]
+    source: String,
+    #[prost(string, tag = "2")]
+    local: String,
+    #[prost(string, tag = "3")]
+    remote: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoChainSplit {
+    #[prost(string, tag = "1")]
+    node: String,
+    #[prost(uint32, tag = "2")]
+    height: u32,
+    #[prost(string, tag = "3")]
+    primary_hash: String,
+    #[prost(string, tag = "4")]
+    node_hash: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoProjectedBlock {
+    #[prost(uint32, tag = "1")]
+    height: u32,
+    #[prost(uint64, tag = "2")]
+    transactions: u64,
+    #[prost(uint64, tag = "3")]
+    weight: u64,
+    #[prost(uint64, tag = "4")]
+    fees: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoMaintenance {
+    #[prost(bool, tag = "1")]
+    enabled: bool,
+    #[prost(string, tag = "2")]
+    message: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoLagged {
+    #[prost(uint64, tag = "1")]
+    skipped: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoReorg {
+    #[prost(uint32, tag = "1")]
+    depth: u32,
+    #[prost(uint32, tag = "2")]
+    old_height: u32,
+    #[prost(string, tag = "3")]
+    old_hash: String,
+    #[prost(uint32, tag = "4")]
+    new_height: u32,
+    #[prost(string, tag = "5")]
+    new_hash: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoLarge {
+    #[prost(string, tag = "1")]
+    txid: String,
+    #[prost(uint64, tag = "2")]
+    value: u64,
+    // Zero for mempool transaction
+    #[prost(uint32, tag = "3")]
+    height: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoStreak {
+    #[prost(int32, tag = "1")]
+    kind: i32,
+    #[prost(uint32, tag = "2")]
+    length: u32,
+    #[prost(uint32, tag = "3")]
+    start_height: u32,
+    #[prost(uint32, tag = "4")]
+    end_height: u32,
+}
+
+pub fn encode_event(msg: &BusMessage) -> Vec<u8> {
+    let kind = match msg.event.clone() {
+        Event::Tx(status, txid) => {
+            let status = match status {
+                TxEvent::Added => 0,
+                TxEvent::Confirmed => 1,
+                TxEvent::Removed => 2,
+            };
+            ProtoEventKind::Tx(ProtoTx { status, txid })
+        }
+        Event::Block(status, height, hash) => {
+            let status = match status {
+                BlockEvent::Added => 0,
+                BlockEvent::Removed => 1,
+            };
+            ProtoEventKind::Block(ProtoBlock {
+                status,
+                height,
+                hash,
+            })
+        }
+        Event::CaughtUp(block) => ProtoEventKind::CaughtUp(ProtoCaughtUp { block }),
+        Event::Reorg(reorg) => ProtoEventKind::Reorg(ProtoReorg {
+            depth: reorg.depth,
+            old_height: reorg.old_tip.height,
+            old_hash: reorg.old_tip.hash,
+            new_height: reorg.new_tip.height,
+            new_hash: reorg.new_tip.hash,
+        }),
+        // Tenant is not included, event delivered only to that tenant
+        Event::Watch(_, txid) => ProtoEventKind::Watch(ProtoWatch { txid }),
+        Event::Timelock(_, txid, vout) => ProtoEventKind::Timelock(ProtoTimelock { txid, vout }),
+        Event::Plugin(topic, data) => ProtoEventKind::Plugin(ProtoPlugin { topic, data }),
+        Event::Alert(rule, txid) => ProtoEventKind::Alert(ProtoAlert { rule, txid }),
+        Event::Large(tx) => ProtoEventKind::Large(ProtoLarge {
+            txid: tx.txid,
+            value: tx.value,
+            height: tx.height.unwrap_or(0),
+        }),
+        Event::Streak(streak) => {
+            let kind = match streak.kind {
+                StreakKind::Empty => 0,
+                StreakKind::Full => 1,
+            };
+            ProtoEventKind::Streak(ProtoStreak {
+                kind,
+                length: streak.length as u32,
+                start_height: streak.start_height,
+                end_height: streak.end_height,
+            })
+        }
+        Event::Divergence(source, local, remote) => ProtoEventKind::Divergence(ProtoDivergence {
+            source,
+            local,
+            remote,
+        }),
+        Event::ChainSplit(split) => ProtoEventKind::ChainSplit(ProtoChainSplit {
+            node: split.node,
+            height: split.height,
+            primary_hash: split.primary_hash,
+            node_hash: split.node_hash,
+        }),
+        Event::ProjectedBlock(block) => ProtoEventKind::ProjectedBlock(ProtoProjectedBlock {
+            height: block.height,
+            transactions: block.transactions as u64,
+            weight: block.weight,
+            fees: block.fees,
+        }),
+        Event::Maintenance(enabled, message) => {
+            ProtoEventKind::Maintenance(ProtoMaintenance { enabled, message })
+        }
+        Event::Lagged(skipped) => ProtoEventKind::Lagged(ProtoLagged { skipped }),
+    };
+
+    let event = ProtoEvent {
+        event: Some(kind),
+        seq: msg.seq,
+        received: msg.time.received,
+        chain_time: msg.time.chain.unwrap_or(0),
+    };
+    let mut buf = Vec::with_capacity(event.encoded_len());
+    event.encode(&mut buf).unwrap();
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::events::EventTime;
+    use super::*;
+
+    fn message(event: Event, chain: Option<u32>) -> BusMessage {
+        BusMessage {
+            tenant: None,
+            event,
+            seq: 3,
+            time: EventTime {
+                received: 1_600_000_000_000,
+                chain,
+            },
+        }
+    }
+
+    #[test]
+    fn encode_decode() {
+        let msg = message(Event::Tx(TxEvent::Confirmed, "ab".to_owned()), Some(100));
+        let event = ProtoEvent::decode(&encode_event(&msg)[..]).unwrap();
+        assert_eq!(
+            event,
+            ProtoEvent {
+                event: Some(ProtoEventKind::Tx(ProtoTx {
+                    status: 1,
+                    txid: "ab".to_owned(),
+                })),
+                seq: 3,
+                received: 1_600_000_000_000,
+                chain_time: 100,
+            }
+        );
+
+        // Tenant is not encoded
+        let msg = message(Event::Watch("a".to_owned(), "cd".to_owned()), None);
+        let event = ProtoEvent::decode(&encode_event(&msg)[..]).unwrap();
+        let watch = ProtoWatch {
+            txid: "cd".to_owned(),
+        };
+        assert_eq!(event.event, Some(ProtoEventKind::Watch(watch)));
+        assert_eq!(event.chain_time, 0);
+    }
+
+    #[test]
+    fn block_events() {
+        for (status, code) in &[(BlockEvent::Added, 0), (BlockEvent::Removed, 1)] {
+            let msg = message(Event::Block(*status, 42, "ef".to_owned()), Some(100));
+            let event = ProtoEvent::decode(&encode_event(&msg)[..]).unwrap();
+            let block = ProtoBlock {
+                status: *code,
+                height: 42,
+                hash: "ef".to_owned(),
+            };
+            assert_eq!(event.event, Some(ProtoEventKind::Block(block)));
+        }
+        assert!(EVENTS_PROTO.contains("Block block = 8;"));
+    }
+
+    #[test]
+    fn wire_format() {
+        // `lagged = 9` with `skipped = 1`, then `seq = 15`, zero fields skipped
+        let mut msg = message(Event::Lagged(5), None);
+        msg.time.received = 0;
+        assert_eq!(encode_event(&msg), vec![0x4a, 0x02, 0x08, 0x05, 0x78, 0x03]);
+        assert!(EVENTS_PROTO.contains("Lagged lagged = 9;"));
+        assert!(EVENTS_PROTO.contains("uint64 seq = 15;"));
+    }
+}
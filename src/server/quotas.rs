@@ -0,0 +1,372 @@
+// Daily and monthly request quotas for tenant API keys. Usage counted per
+// route and persisted in storage, so it survives restart and can be used for
+// chargeback.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::json;
+use super::storage::{self, Storage, StorageResult, TREE_USAGE};
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    const ALL: [Period; 2] = [Period::Day, Period::Month];
+
+    // UTC date (`2020-01-31`) or month (`2020-01`) of timestamp
+    pub(super) fn window(self, now: i64) -> String {
+        let (year, month, day) = civil_from_days(now.div_euclid(SECS_PER_DAY));
+        match self {
+            Period::Day => format!("{:04}-{:02}-{:02}", year, month, day),
+            Period::Month => format!("{:04}-{:02}", year, month),
+        }
+    }
+
+    // Seconds until start of next window
+    pub(super) fn reset_after(self, now: i64) -> u64 {
+        let days = now.div_euclid(SECS_PER_DAY);
+        let next = match self {
+            Period::Day => days + 1,
+            Period::Month => {
+                let (year, month, _) = civil_from_days(days);
+                if month == 12 {
+                    days_from_civil(year + 1, 1, 1)
+                } else {
+                    days_from_civil(year, month + 1, 1)
+                }
+            }
+        };
+        (next * SECS_PER_DAY - now) as u64
+    }
+}
+
+impl FromStr for Period {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Period::Day),
+            "month" => Ok(Period::Month),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Period::Day => write!(f, "day"),
+            Period::Month => write!(f, "month"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaConfig {
+    pub period: Period,
+    pub limit: u64,
+    // Route path without version (`/block/{id}`), all routes if not set
+    pub route: Option<String>,
+}
+
+impl QuotaConfig {
+    fn applies_to(&self, route: &str) -> bool {
+        self.route.as_deref().is_none_or(|quota| quota == route)
+    }
+
+    fn used(&self, usage: &Usage) -> u64 {
+        match &self.route {
+            Some(route) => usage.routes.get(route).copied().unwrap_or(0),
+            None => usage.total,
+        }
+    }
+}
+
+// Parse quota in form `period=limit[:route]`
+pub fn parse_quota(value: &str) -> Option<QuotaConfig> {
+    let mut parts = value.splitn(2, '=');
+    let period = parts.next()?.parse::<Period>().ok()?;
+    let mut parts = parts.next()?.splitn(2, ':');
+    let limit = parts.next()?.parse::<u64>().ok()?;
+    let route = match parts.next() {
+        Some(route) if route.starts_with('/') => Some(route.to_owned()),
+        Some(_) => return None,
+        None => None,
+    };
+
+    Some(QuotaConfig {
+        period,
+        limit,
+        route,
+    })
+}
+
+#[derive(Debug)]
+pub enum QuotaResult {
+    // Minimum remaining requests of applied quotas, `None` without quotas
+    Allowed(Option<u64>),
+    Exceeded(QuotaConfig, u64),
+}
+
+// Requests in one window, stored as `<tenant>:<window>`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Usage {
+    total: u64,
+    routes: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub struct Quotas {
+    configs: Vec<QuotaConfig>,
+    // Usage of current windows by storage key
+    usage: HashMap<String, Usage>,
+}
+
+impl Quotas {
+    pub fn new(configs: Vec<QuotaConfig>) -> Self {
+        Quotas {
+            configs,
+            usage: HashMap::new(),
+        }
+    }
+
+    // Count request if every quota of route allow it, usage counted for
+    // reports even without quotas
+    pub fn consume(
+        &mut self,
+        storage: &dyn Storage,
+        tenant: &str,
+        route: &str,
+    ) -> StorageResult<QuotaResult> {
+        let now = unix_time();
+
+        let mut remaining: Option<u64> = None;
+        for quota in self.configs.iter().filter(|quota| quota.applies_to(route)) {
+            let key = Self::key(tenant, quota.period, now);
+            let used = match self.usage.get(&key) {
+                Some(usage) => quota.used(usage),
+                None => quota.used(&Self::load(storage, &key)?),
+            };
+            if used >= quota.limit {
+                let reset_after = quota.period.reset_after(now);
+                return Ok(QuotaResult::Exceeded(quota.clone(), reset_after));
+            }
+            let left = quota.limit - used - 1;
+            remaining = Some(remaining.map_or(left, |remaining| remaining.min(left)));
+        }
+
+        for period in Period::ALL.iter() {
+            let usage = self.get_usage(storage, tenant, *period, now)?;
+            usage.total += 1;
+            *usage.routes.entry(route.to_owned()).or_default() += 1;
+            let usage = usage.clone();
+            let key = Self::key(tenant, *period, now);
+            storage::insert_json(storage, TREE_USAGE, key.as_bytes(), &usage)?;
+        }
+
+        Ok(QuotaResult::Allowed(remaining))
+    }
+
+    // Usage of current day and month with remaining requests of quotas
+    pub fn report(&mut self, storage: &dyn Storage, tenant: &str) -> StorageResult<json::Usage> {
+        let now = unix_time();
+
+        let mut periods = vec![];
+        for period in Period::ALL.iter() {
+            let usage = self.get_usage(storage, tenant, *period, now)?.clone();
+            let quotas = self
+                .configs
+                .iter()
+                .filter(|quota| quota.period == *period)
+                .map(|quota| json::QuotaUsage {
+                    route: quota.route.clone(),
+                    limit: quota.limit,
+                    remaining: quota.limit.saturating_sub(quota.used(&usage)),
+                })
+                .collect();
+            periods.push(json::UsagePeriod {
+                period: period.to_string(),
+                window: period.window(now),
+                reset_after: period.reset_after(now),
+                total: usage.total,
+                routes: usage.routes,
+                quotas,
+            });
+        }
+
+        Ok(json::Usage {
+            tenant: tenant.to_owned(),
+            periods,
+        })
+    }
+
+    fn get_usage(
+        &mut self,
+        storage: &dyn Storage,
+        tenant: &str,
+        period: Period,
+        now: i64,
+    ) -> StorageResult<&mut Usage> {
+        let key = Self::key(tenant, period, now);
+        if !self.usage.contains_key(&key) {
+            // New window or tenant, usage of previous windows is not needed
+            let windows = Period::ALL
+                .iter()
+                .map(|period| period.window(now))
+                .collect::<Vec<_>>();
+            self.usage
+                .retain(|key, _| windows.iter().any(|window| key.ends_with(window.as_str())));
+
+            let usage = Self::load(storage, &key)?;
+            self.usage.insert(key.clone(), usage);
+        }
+        Ok(self.usage.get_mut(&key).unwrap())
+    }
+
+    fn load(storage: &dyn Storage, key: &str) -> StorageResult<Usage> {
+        let usage = storage::get_json(storage, TREE_USAGE, key.as_bytes())?;
+        Ok(usage.unwrap_or_default())
+    }
+
+    fn key(tenant: &str, period: Period, now: i64) -> String {
+        format!("{}:{}", tenant, period.window(now))
+    }
+}
+
+pub(super) fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+// Days since 1970-01-01 to (year, month, day), see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub(super) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Inverse of `civil_from_days`
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2020-02-29 23:59:59 UTC
+    const LEAP_DAY_END: i64 = 1_583_020_799;
+
+    #[test]
+    fn periods() {
+        assert_eq!(Period::Day.window(0), "1970-01-01");
+        assert_eq!(Period::Day.window(LEAP_DAY_END), "2020-02-29");
+        assert_eq!(Period::Month.window(LEAP_DAY_END), "2020-02");
+        assert_eq!(Period::Day.window(LEAP_DAY_END + 1), "2020-03-01");
+        assert_eq!(Period::Day.window(-1), "1969-12-31");
+
+        assert_eq!(Period::Day.reset_after(LEAP_DAY_END), 1);
+        assert_eq!(Period::Month.reset_after(LEAP_DAY_END), 1);
+        assert_eq!(Period::Day.reset_after(LEAP_DAY_END + 1), 86_400);
+        // 2020-12-01, next month in next year
+        assert_eq!(Period::Month.reset_after(1_606_780_800), 31 * 86_400);
+
+        for days in (-800_000..800_000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn quota_config() {
+        let quota = parse_quota("day=1000").unwrap();
+        assert_eq!(
+            (quota.period, quota.limit, quota.route),
+            (Period::Day, 1000, None)
+        );
+        let quota = parse_quota("month=10:/block/{id}").unwrap();
+        assert_eq!(quota.period, Period::Month);
+        assert_eq!(quota.route.as_deref(), Some("/block/{id}"));
+        assert!(quota.applies_to("/block/{id}"));
+        assert!(!quota.applies_to("/mempool"));
+
+        for value in &[
+            "",
+            "day",
+            "week=10",
+            "day=-1",
+            "day=10:block",
+            "day=x:/mempool",
+        ] {
+            assert!(parse_quota(value).is_none(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn consume_and_report() {
+        let storage = storage::open("memory").unwrap();
+        let storage = storage.as_ref();
+        let configs = vec![
+            parse_quota("day=3").unwrap(),
+            parse_quota("month=10:/mempool").unwrap(),
+        ];
+        let mut quotas = Quotas::new(configs.clone());
+        let consume =
+            |quotas: &mut Quotas, route| match quotas.consume(storage, "acme", route).unwrap() {
+                QuotaResult::Allowed(remaining) => Ok(remaining),
+                QuotaResult::Exceeded(quota, reset_after) => Err((quota.period, reset_after)),
+            };
+
+        assert_eq!(consume(&mut quotas, "/mempool"), Ok(Some(2)));
+        assert_eq!(consume(&mut quotas, "/stats"), Ok(Some(1)));
+        // Usage persisted, so counted after restart
+        let mut quotas = Quotas::new(configs);
+        assert_eq!(consume(&mut quotas, "/mempool"), Ok(Some(0)));
+        match consume(&mut quotas, "/stats") {
+            Err((Period::Day, reset_after)) => assert!(reset_after <= 86_400),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let usage = quotas.report(storage, "acme").unwrap();
+        assert_eq!(usage.tenant, "acme");
+        let day = &usage.periods[0];
+        assert_eq!((day.period.as_str(), day.total), ("day", 3));
+        assert_eq!(day.routes["/mempool"], 2);
+        assert_eq!(day.quotas[0].remaining, 0);
+        let month = &usage.periods[1];
+        assert_eq!(month.quotas[0].route.as_deref(), Some("/mempool"));
+        assert_eq!(month.quotas[0].remaining, 8);
+
+        // Usage counted without quotas, other tenants are independent
+        let mut quotas = Quotas::new(vec![]);
+        assert_eq!(consume(&mut quotas, "/stats"), Ok(None));
+        assert_eq!(quotas.report(storage, "acme").unwrap().periods[0].total, 4);
+        assert_eq!(quotas.report(storage, "other").unwrap().periods[0].total, 0);
+    }
+}
@@ -0,0 +1,147 @@
+// Per-IP token bucket for API requests, so single client can not saturate
+// bitcoind through proxy endpoints. Bucket holds one second of requests (at
+// least one request) and is refilled continuously.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Full buckets are dropped when there are more clients
+const BUCKETS_MAX: usize = 10_000;
+// Wait reported for very low rates, which do not fit into `Duration`
+const WAIT_MAX: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = burst.min(self.tokens + elapsed * rate);
+        self.updated = now;
+        self.tokens
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    // Requests per second
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> Self {
+        RateLimiter {
+            rate,
+            burst: rate.max(1.0),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Time until next request is allowed, if limited
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let (rate, burst) = (self.rate, self.burst);
+        let mut buckets = self.buckets.lock().unwrap();
+        // Full bucket is same as new one
+        if buckets.len() >= BUCKETS_MAX && !buckets.contains_key(&ip) {
+            buckets.retain(|_, bucket| bucket.refill(now, rate, burst) < burst);
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let tokens = bucket.refill(now, rate, burst);
+        if tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = Duration::try_from_secs_f64((1.0 - tokens) / rate);
+            Err(wait.map_or(WAIT_MAX, |wait| wait.min(WAIT_MAX)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn ip(index: u32) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::from(index))
+    }
+
+    #[test]
+    fn burst_and_refill() {
+        let limiter = RateLimiter::new(4.0);
+        let now = Instant::now();
+        for _ in 0..4 {
+            assert_eq!(limiter.check_at(ip(1), now), Ok(()));
+        }
+        assert_eq!(
+            limiter.check_at(ip(1), now),
+            Err(Duration::from_millis(250))
+        );
+        // Buckets are independent
+        assert_eq!(limiter.check_at(ip(2), now), Ok(()));
+
+        // One token in quarter of second, bucket is not filled above burst
+        let later = now + Duration::from_millis(250);
+        assert_eq!(limiter.check_at(ip(1), later), Ok(()));
+        assert!(limiter.check_at(ip(1), later).is_err());
+        let later = now + Duration::from_secs(10);
+        for _ in 0..4 {
+            assert_eq!(limiter.check_at(ip(1), later), Ok(()));
+        }
+        assert!(limiter.check_at(ip(1), later).is_err());
+    }
+
+    #[test]
+    fn wait() {
+        // Partially refilled token
+        let limiter = RateLimiter::new(0.5);
+        let now = Instant::now();
+        assert_eq!(limiter.check_at(ip(1), now), Ok(()));
+        let later = now + Duration::from_millis(500);
+        assert_eq!(
+            limiter.check_at(ip(1), later),
+            Err(Duration::from_millis(1500))
+        );
+
+        // Not overflowed on tiny rate
+        let limiter = RateLimiter::new(1e-300);
+        assert_eq!(limiter.check_at(ip(1), now), Ok(()));
+        assert_eq!(limiter.check_at(ip(1), now), Err(WAIT_MAX));
+    }
+
+    #[test]
+    fn eviction() {
+        let limiter = RateLimiter::new(1.0);
+        let now = Instant::now();
+        for index in 0..BUCKETS_MAX as u32 {
+            assert_eq!(limiter.check_at(ip(index), now), Ok(()));
+        }
+
+        // Not refilled buckets are kept
+        let new = ip(BUCKETS_MAX as u32);
+        assert_eq!(limiter.check_at(new, now), Ok(()));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), BUCKETS_MAX + 1);
+
+        // Full buckets dropped, new one is not limited
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.check_at(ip(BUCKETS_MAX as u32 + 1), later), Ok(()));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+        assert!(limiter.check_at(ip(0), later).is_ok());
+    }
+}
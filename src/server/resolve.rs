@@ -0,0 +1,71 @@
+// Periodic DNS resolution of bitcoind host. Pooled connections stay on
+// address they were opened to, so after failover which moves host name to
+// another IP requests keep going to old node until it fails. When resolved
+// addresses change, clients are recreated with new connection pools.
+
+use std::net::IpAddr;
+
+use futures::future::{BoxFuture, FutureExt as _};
+use log::warn;
+
+use super::scheduler::{Job, JobResult};
+use super::state::State;
+
+#[derive(Debug, Default)]
+pub struct Resolver {
+    // Host with its sorted addresses from previous resolution
+    resolved: Option<(String, Vec<IpAddr>)>,
+}
+
+impl Job for Resolver {
+    fn run<'a>(&'a mut self, state: &'a State) -> BoxFuture<'a, JobResult> {
+        async move {
+            // Host changes on switch to another node, IP addresses are skipped
+            let (host, port) = match state.bitcoind_host() {
+                Some(host) => host,
+                None => return Ok(()),
+            };
+            let addrs = match resolve(&host, port).await {
+                Some(addrs) => addrs,
+                None => return Err(format!("failed to resolve bitcoind host {}", host)),
+            };
+
+            if let Some((prev_host, prev_addrs)) = &self.resolved {
+                if *prev_host == host && *prev_addrs != addrs {
+                    warn!(
+                        "Bitcoind host {} resolved to {} instead of {}, recycle connections",
+                        host,
+                        format_addrs(&addrs),
+                        format_addrs(prev_addrs)
+                    );
+                    if let Err(err) = state.recycle_bitcoind() {
+                        warn!("Failed to recycle bitcoind connections: {}", err);
+                    }
+                }
+            }
+            self.resolved = Some((host, addrs));
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+async fn resolve(host: &str, port: u16) -> Option<Vec<IpAddr>> {
+    let addrs = tokio::net::lookup_host((host, port)).await.ok()?;
+    let mut addrs = addrs.map(|addr| addr.ip()).collect::<Vec<_>>();
+    addrs.sort();
+    addrs.dedup();
+    if addrs.is_empty() {
+        None
+    } else {
+        Some(addrs)
+    }
+}
+
+fn format_addrs(addrs: &[IpAddr]) -> String {
+    addrs
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,981 @@
+use hyper::Method;
+use regex::Regex;
+
+use super::json::{NotFound, RouteHint};
+
+// Supported API versions, latest last, routes served with `/v<version>` prefix
+pub const API_VERSIONS: &[&str] = &["1"];
+// Routes of this version also served without prefix, as deprecated aliases
+const LEGACY_VERSION: &str = "1";
+// Date after which unprefixed routes can be removed (`Sunset` header)
+pub const LEGACY_SUNSET: &str = "Sat, 01 May 2027 00:00:00 GMT";
+
+// Block hash, height or `tip`
+const BLOCK_ID_PATTERN: &str = r"([0-9a-f]{64}|\d+|tip)";
+const BLOCK_ID_FORMAT: &str = "block hash (64 lowercase hex chars), height or `tip`";
+
+// Transaction id with output index
+const OUTPOINT_PATTERN: &str = r"([0-9a-f]{64}:\d+)";
+const OUTPOINT_FORMAT: &str = "`txid:vout`, txid as 64 lowercase hex chars";
+
+const TXID_PATTERN: &str = r"([0-9a-f]{64})";
+const TXID_FORMAT: &str = "txid as 64 lowercase hex chars";
+
+// Output script in hex
+const SCRIPT_PATTERN: &str = r"((?:[0-9a-f]{2}){1,10000})";
+const SCRIPT_FORMAT: &str = "output script in lowercase hex";
+
+// Base58 or bech32 address, validated by bitcoind
+const ADDRESS_PATTERN: &str = r"([0-9A-Za-z]{14,90})";
+const ADDRESS_FORMAT: &str = "base58 or bech32 address of node network";
+
+// Maximum edit distance between path segments for suggestion, one typo allowed
+// for every 3 chars of segment
+const SUGGEST_DISTANCE_MAX: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteId {
+    Root,
+    Mempool,
+    MempoolFeerates,
+    MempoolHistogram,
+    MempoolProjectedBlock,
+    MempoolTx,
+    FeesConfirmationTimes,
+    FeeEstimate,
+    Block,
+    BlockHex,
+    BlockFilter,
+    TxBroadcast,
+    TxTest,
+    TxsPackage,
+    Tx,
+    Outpoint,
+    OutpointSpend,
+    AddressTransactions,
+    AdminBlacklist,
+    AdminAudit,
+    AdminBitcoind,
+    AdminMaintenance,
+    AdminPause,
+    AdminResume,
+    AdminBlocksKeep,
+    AdminChaos,
+    AdminJobs,
+    AdminBans,
+    AdminNodes,
+    AdminPeers,
+    Watch,
+    WatchExport,
+    WatchTimelocks,
+    WatchReactions,
+    Usage,
+    Schemas,
+    EventsProto,
+    Stats,
+    Metrics,
+    BlockSources,
+    Reorgs,
+    NetworkHalving,
+    NetworkDifficulty,
+    NodePeers,
+    Nodes,
+    Version,
+    Ws,
+}
+
+#[derive(Debug)]
+struct RouteSpec {
+    id: RouteId,
+    methods: &'static [Method],
+    // Path with `{param}` placeholders
+    path: &'static str,
+    description: &'static str,
+    params: &'static [(&'static str, &'static str, &'static str)],
+    // API versions with this route, unversioned route if empty
+    versions: &'static [&'static str],
+    admin: bool,
+}
+
+// Route table, used for dispatch and for suggestions on unknown paths
+const ROUTES: &[RouteSpec] = &[
+    RouteSpec {
+        id: RouteId::Root,
+        methods: &[Method::GET],
+        path: "/",
+        description: "Server info, enabled features and endpoints",
+        params: &[],
+        versions: &[],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Mempool,
+        methods: &[Method::GET],
+        path: "/mempool",
+        description: "Transactions in mempool",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::MempoolFeerates,
+        methods: &[Method::GET],
+        path: "/mempool/feerates",
+        description: "Percentiles of mempool fee rates in sat/vB",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::MempoolHistogram,
+        methods: &[Method::GET],
+        path: "/mempool/histogram",
+        description: "Mempool transactions, virtual size and fees by fee rate band",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::MempoolProjectedBlock,
+        methods: &[Method::GET],
+        path: "/mempool/projected-block",
+        description: "Next block assembled from mempool by package fee rate",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::MempoolTx,
+        methods: &[Method::GET],
+        path: "/mempool/tx/{txid}",
+        description: "Mempool transaction with fee, ancestors, descendants and time in mempool",
+        params: &[("txid", TXID_PATTERN, TXID_FORMAT)],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::FeesConfirmationTimes,
+        methods: &[Method::GET],
+        path: "/fees/confirmation-times",
+        description: "Median time from mempool entry to confirmation by fee rate band",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::FeeEstimate,
+        methods: &[Method::GET],
+        path: "/fee-estimate",
+        description: "Fee rate from `estimatesmartfee`, `?target=6&mode=conservative|economical`",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Block,
+        methods: &[Method::GET],
+        path: "/block/{id}",
+        description: "Block with transactions, `?page=&limit=` for one page of transactions",
+        params: &[("id", BLOCK_ID_PATTERN, BLOCK_ID_FORMAT)],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::BlockHex,
+        methods: &[Method::GET],
+        path: "/block/{id}/hex",
+        description: "Raw block in hex",
+        params: &[("id", BLOCK_ID_PATTERN, BLOCK_ID_FORMAT)],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::BlockFilter,
+        methods: &[Method::GET],
+        path: "/block/{id}/filter",
+        description: "Basic compact block filter (BIP158) for light clients",
+        params: &[("id", BLOCK_ID_PATTERN, BLOCK_ID_FORMAT)],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::TxBroadcast,
+        methods: &[Method::POST],
+        path: "/tx",
+        description: "Broadcast raw transaction (hex body) through bitcoind",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::TxTest,
+        methods: &[Method::POST],
+        path: "/tx/test",
+        description: "Test transactions (or package) against node policy without broadcast",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::TxsPackage,
+        methods: &[Method::POST],
+        path: "/txs/package",
+        description: "Submit transactions as package (parent with children)",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Tx,
+        methods: &[Method::GET],
+        path: "/tx/{txid}",
+        description: "Decoded transaction with confirmations from tracked blocks",
+        params: &[("txid", TXID_PATTERN, TXID_FORMAT)],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Outpoint,
+        methods: &[Method::GET],
+        path: "/outpoint/{outpoint}",
+        description: "Unspent output from bitcoind with spend in mempool",
+        params: &[("outpoint", OUTPOINT_PATTERN, OUTPOINT_FORMAT)],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::OutpointSpend,
+        methods: &[Method::GET],
+        path: "/outpoint/{outpoint}/spend",
+        description: "Spending transaction of output in tracked blocks or mempool",
+        params: &[("outpoint", OUTPOINT_PATTERN, OUTPOINT_FORMAT)],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::AddressTransactions,
+        methods: &[Method::GET],
+        path: "/address/{address}/transactions",
+        description: "Transactions with outputs to address in tracked blocks",
+        params: &[("address", ADDRESS_PATTERN, ADDRESS_FORMAT)],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::AdminBlacklist,
+        methods: &[Method::GET, Method::POST, Method::DELETE],
+        path: "/admin/blacklist",
+        description: "Blacklisted transactions and output scripts (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminAudit,
+        methods: &[Method::GET],
+        path: "/admin/audit",
+        description: "Audit log of mutating requests (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminBitcoind,
+        methods: &[Method::PUT],
+        path: "/admin/bitcoind",
+        description: "Switch to another bitcoind node without restart (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminMaintenance,
+        methods: &[Method::GET, Method::PUT],
+        path: "/admin/maintenance",
+        description: "Maintenance mode, API responds with 503 if enabled (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminPause,
+        methods: &[Method::POST],
+        path: "/admin/pause",
+        description: "Pause update loop, cached data served with `stale_since` (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminResume,
+        methods: &[Method::POST],
+        path: "/admin/resume",
+        description: "Resume paused update loop (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminBlocksKeep,
+        methods: &[Method::GET, Method::PUT],
+        path: "/admin/blocks-keep",
+        description: "Number of tracked blocks, changed without restart (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminChaos,
+        methods: &[Method::GET, Method::PUT],
+        path: "/admin/chaos",
+        description: "Fault injection in bitcoind client, `--features chaos` build (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminJobs,
+        methods: &[Method::GET],
+        path: "/admin/jobs",
+        description: "Periodic jobs with intervals and status of last run (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminBans,
+        methods: &[Method::GET, Method::POST, Method::DELETE],
+        path: "/admin/bans",
+        description: "Banned IP addresses and subnets of node (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminNodes,
+        methods: &[Method::POST, Method::DELETE],
+        path: "/admin/nodes",
+        description: "Add or remove node connection with `addnode` (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::AdminPeers,
+        methods: &[Method::DELETE],
+        path: "/admin/peers",
+        description: "Disconnect peer by address or node id (admin)",
+        params: &[],
+        versions: &["1"],
+        admin: true,
+    },
+    RouteSpec {
+        id: RouteId::Watch,
+        methods: &[Method::GET, Method::POST, Method::DELETE],
+        path: "/watch",
+        description: "Watched output scripts of tenant",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::WatchTimelocks,
+        methods: &[Method::GET, Method::POST, Method::DELETE],
+        path: "/watch/timelocks",
+        description: "Watched outputs of tenant with CLTV/CSV timelocks",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::WatchReactions,
+        methods: &[Method::GET, Method::POST, Method::DELETE],
+        path: "/watch/reactions",
+        description: "Pre-signed transactions of tenant broadcast when watched output is spent",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    // Keyed by script, not by an id: `/watch` entries are added, removed and
+    // listed by script, interned ids are internal and reused after release
+    RouteSpec {
+        id: RouteId::WatchExport,
+        methods: &[Method::GET],
+        path: "/watch/{script}/export",
+        description:
+            "Confirmed transactions of watched script as CSV, `?format=csv&from=&to=` (unix time)",
+        params: &[("script", SCRIPT_PATTERN, SCRIPT_FORMAT)],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Usage,
+        methods: &[Method::GET],
+        path: "/usage",
+        description: "API usage and quotas of tenant in current day and month",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Schemas,
+        methods: &[Method::GET],
+        path: "/schemas",
+        description: "JSON Schemas of requests, responses and events",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::EventsProto,
+        methods: &[Method::GET],
+        path: "/schemas/events.proto",
+        description: "Protobuf schema of events",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Stats,
+        methods: &[Method::GET],
+        path: "/stats",
+        description: "Server statistics",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Metrics,
+        methods: &[Method::GET],
+        path: "/metrics",
+        description: "Metrics in Prometheus text format",
+        params: &[],
+        versions: &[],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::BlockSources,
+        methods: &[Method::GET],
+        path: "/metrics/block-sources",
+        description: "Which block notification source announced latest blocks first",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Reorgs,
+        methods: &[Method::GET],
+        path: "/reorgs",
+        description: "Recent reorgs of tracked chain with depth, newest first",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::NetworkHalving,
+        methods: &[Method::GET],
+        path: "/network/halving",
+        description: "Current subsidy era and estimated time of next halving",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::NetworkDifficulty,
+        methods: &[Method::GET],
+        path: "/network/difficulty-adjustment",
+        description: "Estimated difficulty change and time of next retarget",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::NodePeers,
+        methods: &[Method::GET],
+        path: "/node/peers",
+        description: "Aggregated peers of node, list with `detailed=true` for admin",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Nodes,
+        methods: &[Method::GET],
+        path: "/nodes",
+        description: "Tips of `--bitcoind` nodes and chain splits with primary node",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Version,
+        methods: &[Method::GET],
+        path: "/version",
+        description: "Server version, git commit and supported API versions",
+        params: &[],
+        versions: &[],
+        admin: false,
+    },
+    RouteSpec {
+        id: RouteId::Ws,
+        methods: &[Method::GET],
+        path: "/ws",
+        description: "WebSocket with events",
+        params: &[],
+        versions: &["1"],
+        admin: false,
+    },
+];
+
+#[derive(Debug)]
+pub enum RouteMatch {
+    Found(Found),
+    MethodNotAllowed(Vec<Method>),
+    NotFound(NotFound),
+}
+
+#[derive(Debug)]
+pub struct Found {
+    pub id: RouteId,
+    // Captured params with names, in order of placeholders
+    pub params: Vec<(&'static str, String)>,
+    // Version from path prefix
+    pub version: Option<&'static str>,
+    // Path of same route with version prefix, for deprecated aliases
+    pub successor: Option<String>,
+    pub admin: bool,
+    // Route path without version prefix, with placeholders
+    pub path: &'static str,
+}
+
+impl Found {
+    // Captured param, route specs guarantee that it exists
+    pub fn param(&self, name: &str) -> &str {
+        self.params
+            .iter()
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_else(|| panic!("Route {:?} without param {}", self.id, name))
+    }
+}
+
+#[derive(Debug)]
+struct Route {
+    spec: &'static RouteSpec,
+    // Path with version prefix
+    path: String,
+    version: Option<&'static str>,
+    alias: bool,
+    re: Regex,
+}
+
+// Routes compiled once, admin routes included only if enabled
+#[derive(Debug)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new(admin: bool) -> Self {
+        let mut routes = vec![];
+        for spec in ROUTES.iter().filter(|spec| admin || !spec.admin) {
+            if spec.versions.is_empty() {
+                routes.push(Route::new(spec, spec.path.to_owned(), None, false));
+            }
+            for version in spec.versions {
+                let path = format!("/v{}{}", version, spec.path);
+                routes.push(Route::new(spec, path, Some(version), false));
+            }
+            if spec.versions.contains(&LEGACY_VERSION) {
+                let path = spec.path.to_owned();
+                routes.push(Route::new(spec, path, Some(LEGACY_VERSION), true));
+            }
+        }
+
+        Router { routes }
+    }
+
+    pub fn find(&self, method: &Method, path: &str) -> RouteMatch {
+        let mut allowed = vec![];
+        for route in self.routes.iter() {
+            let caps = match route.re.captures(path) {
+                Some(caps) => caps,
+                None => continue,
+            };
+            if !route.spec.methods.contains(method) {
+                allowed.extend_from_slice(route.spec.methods);
+                continue;
+            }
+
+            let params = route
+                .spec
+                .params
+                .iter()
+                .zip(caps.iter().skip(1).flatten())
+                .map(|((name, _, _), cap)| (*name, cap.as_str().to_owned()))
+                .collect();
+            let successor = if route.alias {
+                Some(format!("/v{}{}", LEGACY_VERSION, path))
+            } else {
+                None
+            };
+            return RouteMatch::Found(Found {
+                id: route.spec.id,
+                params,
+                version: route.version,
+                successor,
+                admin: route.spec.admin,
+                path: route.spec.path,
+            });
+        }
+
+        if allowed.is_empty() {
+            RouteMatch::NotFound(self.not_found(path))
+        } else {
+            RouteMatch::MethodNotAllowed(allowed)
+        }
+    }
+
+    // All routes with links relative to `base_url` (`scheme://host`)
+    pub fn endpoints(&self, base_url: &str) -> Vec<RouteHint> {
+        self.routes
+            .iter()
+            .filter(|route| !route.alias)
+            .map(|route| route.hint(Some(base_url)))
+            .collect()
+    }
+
+    // Routes with same path prefix or with typo in one of segments, paths
+    // compared with and without version prefix
+    fn not_found(&self, path: &str) -> NotFound {
+        let segments = split_path(path);
+        let suggestions = self
+            .routes
+            .iter()
+            .filter(|route| !route.alias)
+            .filter(|route| {
+                is_close(&split_path(&route.path), &segments)
+                    || is_close(&split_path(route.spec.path), &segments)
+            })
+            .map(|route| route.hint(None))
+            .collect();
+
+        NotFound {
+            error: "Not Found".to_owned(),
+            path: path.to_owned(),
+            suggestions,
+        }
+    }
+}
+
+impl Route {
+    fn new(
+        spec: &'static RouteSpec,
+        path: String,
+        version: Option<&'static str>,
+        alias: bool,
+    ) -> Self {
+        let mut pattern = regex::escape(&path);
+        for (name, param_pattern, _) in spec.params {
+            let placeholder = regex::escape(&format!("{{{}}}", name));
+            pattern = pattern.replace(&placeholder, param_pattern);
+        }
+        let re = Regex::new(&format!("^{}$", pattern)).unwrap();
+
+        Route {
+            spec,
+            path,
+            version,
+            alias,
+            re,
+        }
+    }
+
+    fn hint(&self, base_url: Option<&str>) -> RouteHint {
+        let spec = self.spec;
+        RouteHint {
+            methods: spec.methods.iter().map(|m| m.to_string()).collect(),
+            path: self.path.clone(),
+            description: spec.description.to_owned(),
+            params: spec
+                .params
+                .iter()
+                .map(|(name, _, format)| (name.to_string(), format.to_string()))
+                .collect(),
+            href: match base_url {
+                Some(base_url) if spec.params.is_empty() => {
+                    Some(format!("{}{}", base_url, self.path))
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+// Version from `Accept-Version` (`1` or `v1`), latest if not specified
+pub fn negotiate_version(accept: Option<&str>) -> Result<&'static str, String> {
+    let accept = match accept {
+        Some(accept) => accept.trim(),
+        None => return Ok(API_VERSIONS[API_VERSIONS.len() - 1]),
+    };
+    let version = accept.strip_prefix('v').unwrap_or(accept);
+    API_VERSIONS
+        .iter()
+        .find(|supported| **supported == version)
+        .copied()
+        .ok_or_else(|| accept.to_owned())
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+// Compare segments present in both paths, placeholders match any value
+fn is_close(route: &[&str], path: &[&str]) -> bool {
+    if route.is_empty() || path.is_empty() {
+        return false;
+    }
+
+    route.iter().zip(path.iter()).all(|(expected, actual)| {
+        let distance_max = std::cmp::min(expected.len() / 3, SUGGEST_DISTANCE_MAX);
+        expected.starts_with('{') || edit_distance(expected, actual) <= distance_max
+    })
+}
+
+// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(router: &Router, method: Method, path: &str) -> Found {
+        match router.find(&method, path) {
+            RouteMatch::Found(found) => found,
+            other => panic!("{} {}: {:?}", method, path, other),
+        }
+    }
+
+    #[test]
+    fn find_routes() {
+        let router = Router::new(false);
+        let hash = "ab".repeat(32);
+
+        let block = found(&router, Method::GET, &format!("/v1/block/{}/hex", hash));
+        assert_eq!(block.id, RouteId::BlockHex);
+        assert_eq!(block.param("id"), hash);
+        assert_eq!(block.path, "/block/{id}/hex");
+        assert!(!block.admin);
+
+        let outpoint = format!("{}:3", hash);
+        let spend = found(
+            &router,
+            Method::GET,
+            &format!("/v1/outpoint/{}/spend", outpoint),
+        );
+        assert_eq!(spend.id, RouteId::OutpointSpend);
+        assert_eq!(spend.param("outpoint"), outpoint);
+
+        assert_eq!(
+            found(&router, Method::POST, "/v1/tx/test").id,
+            RouteId::TxTest
+        );
+        assert_eq!(found(&router, Method::GET, "/metrics").id, RouteId::Metrics);
+
+        match router.find(&Method::PUT, "/v1/tx") {
+            RouteMatch::MethodNotAllowed(allowed) => assert_eq!(allowed, vec![Method::POST]),
+            other => panic!("{:?}", other),
+        }
+
+        // admin routes exist only if enabled
+        assert!(matches!(
+            router.find(&Method::GET, "/v1/admin/jobs"),
+            RouteMatch::NotFound(_)
+        ));
+        let jobs = found(&Router::new(true), Method::GET, "/v1/admin/jobs");
+        assert_eq!(jobs.id, RouteId::AdminJobs);
+        assert!(jobs.admin);
+    }
+
+    #[test]
+    fn address_route() {
+        let router = Router::new(false);
+        let address = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+        let path = format!("/v1/address/{}/transactions", address);
+        let found = found(&router, Method::GET, &path);
+        assert_eq!(found.id, RouteId::AddressTransactions);
+        assert_eq!(found.param("address"), address);
+
+        // Length and charset checked by pattern, network by bitcoind
+        for address in &["1short", &"a".repeat(91), "bcrt1q-w508d6qejxtdg4y5r3zar"] {
+            let path = format!("/v1/address/{}/transactions", address);
+            assert!(
+                matches!(router.find(&Method::GET, &path), RouteMatch::NotFound(_)),
+                "{}",
+                address
+            );
+        }
+    }
+
+    #[test]
+    fn params_by_name() {
+        // Names of params are placeholders of path, in same order
+        for spec in ROUTES.iter() {
+            let placeholders = spec
+                .path
+                .split('/')
+                .filter_map(|part| part.strip_prefix('{')?.strip_suffix('}'))
+                .collect::<Vec<_>>();
+            let names = spec.params.iter().map(|(name, _, _)| *name);
+            assert_eq!(placeholders, names.collect::<Vec<_>>(), "{}", spec.path);
+        }
+
+        let router = Router::new(false);
+        let txid = "cd".repeat(32);
+        let tx = found(&router, Method::GET, &format!("/v1/tx/{}", txid));
+        assert_eq!(tx.params, vec![("txid", txid.clone())]);
+        assert_eq!(tx.param("txid"), txid);
+    }
+
+    #[test]
+    #[should_panic(expected = "without param id")]
+    fn unknown_param() {
+        let router = Router::new(false);
+        found(&router, Method::GET, "/v1/mempool").param("id");
+    }
+
+    #[test]
+    fn not_found_suggestions() {
+        let router = Router::new(false);
+        let paths = |not_found: &NotFound| {
+            not_found
+                .suggestions
+                .iter()
+                .map(|hint| hint.path.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // truncated hash
+        let not_found = match router.find(&Method::GET, "/v1/block/00ab") {
+            RouteMatch::NotFound(not_found) => not_found,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(not_found.error, "Not Found");
+        assert_eq!(not_found.path, "/v1/block/00ab");
+        assert_eq!(
+            paths(&not_found),
+            vec![
+                "/v1/block/{id}",
+                "/v1/block/{id}/hex",
+                "/v1/block/{id}/filter"
+            ]
+        );
+        assert_eq!(not_found.suggestions[0].params.len(), 1);
+        assert_eq!(not_found.suggestions[0].params["id"], BLOCK_ID_FORMAT);
+
+        // typo, compared without version prefix too
+        let not_found = match router.find(&Method::GET, "/mempol/feerate") {
+            RouteMatch::NotFound(not_found) => not_found,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            paths(&not_found),
+            vec!["/v1/mempool", "/v1/mempool/feerates"]
+        );
+
+        let not_found = match router.find(&Method::GET, "/unknown") {
+            RouteMatch::NotFound(not_found) => not_found,
+            other => panic!("{:?}", other),
+        };
+        assert!(not_found.suggestions.is_empty());
+    }
+
+    #[test]
+    fn endpoints() {
+        let endpoints = Router::new(false).endpoints("http://localhost:8000");
+        let root = &endpoints[0];
+        assert_eq!(root.path, "/");
+        assert_eq!(root.methods, vec!["GET"]);
+        assert_eq!(root.href.as_deref(), Some("http://localhost:8000/"));
+
+        // links only for routes without params, aliases and admin routes skipped
+        let hint = |path: &str| endpoints.iter().find(|hint| hint.path == path);
+        let mempool = hint("/v1/mempool").unwrap();
+        assert_eq!(
+            mempool.href.as_deref(),
+            Some("http://localhost:8000/v1/mempool")
+        );
+        assert_eq!(hint("/v1/block/{id}").unwrap().href, None);
+        assert!(hint("/mempool").is_none());
+        assert!(hint("/v1/admin/jobs").is_none());
+
+        let admin = Router::new(true).endpoints("http://localhost:8000");
+        assert!(admin.iter().any(|hint| hint.path == "/v1/admin/jobs"));
+        assert_eq!(
+            admin.len(),
+            ROUTES
+                .iter()
+                .map(|spec| spec.versions.len().max(1))
+                .sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn versioned_routes() {
+        let router = Router::new(false);
+
+        let mempool = found(&router, Method::GET, "/v1/mempool");
+        assert_eq!(mempool.version, Some("1"));
+        assert_eq!(mempool.successor, None);
+
+        // unprefixed path is deprecated alias of v1
+        let hash = "ab".repeat(32);
+        let alias = found(&router, Method::GET, &format!("/tx/{}", hash));
+        assert_eq!(alias.id, RouteId::Tx);
+        assert_eq!(alias.param("txid"), hash);
+        assert_eq!(alias.version, Some("1"));
+        assert_eq!(alias.successor, Some(format!("/v1/tx/{}", hash)));
+
+        // unversioned routes
+        for path in &["/", "/version", "/metrics"] {
+            let route = found(&router, Method::GET, path);
+            assert_eq!((route.version, route.successor), (None, None), "{}", path);
+        }
+        for path in &["/v1/", "/v1/version", "/v2/mempool"] {
+            let route = router.find(&Method::GET, path);
+            assert!(matches!(route, RouteMatch::NotFound(_)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn version_negotiation() {
+        assert_eq!(negotiate_version(None), Ok("1"));
+        assert_eq!(negotiate_version(Some("1")), Ok("1"));
+        assert_eq!(negotiate_version(Some(" v1 ")), Ok("1"));
+        assert_eq!(negotiate_version(Some("v2")), Err("v2".to_owned()));
+        assert_eq!(negotiate_version(Some("")), Err("".to_owned()));
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("mempool", "mempool"), 0);
+        assert_eq!(edit_distance("mempool", "mempol"), 1);
+        assert_eq!(edit_distance("block", "blcok"), 2);
+        assert_eq!(edit_distance("", "tx"), 2);
+        assert!(is_close(&["block", "{id}"], &["blok", "00"]));
+        assert!(!is_close(&["tx"], &["ty"]));
+        assert!(!is_close(&[], &["tx"]));
+    }
+}
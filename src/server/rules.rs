@@ -0,0 +1,260 @@
+// Alert rules: every `*.rhai` file in rules directory is Rhai expression over
+// new mempool transaction fields, if result is `true` alert event is sent.
+// Variables: txid, vsize, fee (satoshi), fee_rate (sat/vB), value (BTC), outputs
+// Example: `value > 100.0 && fee_rate < 2.0`
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::{error, info, warn};
+use rhai::{Engine, Scope, AST};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+const RULES_MAX_OPERATIONS: u64 = 100_000;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum RulesError {
+        ReadDir(path: PathBuf, err: std::io::Error) {
+            display("Failed to read rules directory {}: {}", path.display(), err)
+        }
+    }
+}
+
+pub type RulesResult<T> = Result<T, RulesError>;
+
+// Transaction fields available in rules
+#[derive(Debug, Clone)]
+pub struct RuleInput {
+    pub txid: String,
+    pub vsize: u32,
+    pub fee: u64,
+    pub value: f64,
+    pub outputs: usize,
+}
+
+// Built-in alerts for absurd fees of mempool and new block transactions, work
+// without rules directory. Alert names: `high-fee`, `high-fee-rate`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FeeAlerts {
+    // Satoshis
+    pub fee: Option<u64>,
+    // sat/vB
+    pub fee_rate: Option<f64>,
+}
+
+impl FeeAlerts {
+    // Names of exceeded thresholds, logged at warn
+    pub fn check(&self, txid: &str, vsize: u32, fee: u64) -> Vec<String> {
+        let fee_rate = if vsize > 0 {
+            fee as f64 / vsize as f64
+        } else {
+            0.0
+        };
+
+        let mut matched = vec![];
+        if self.fee.is_some_and(|threshold| fee > threshold) {
+            matched.push("high-fee".to_owned());
+        }
+        if self.fee_rate.is_some_and(|threshold| fee_rate > threshold) {
+            matched.push("high-fee-rate".to_owned());
+        }
+        if !matched.is_empty() {
+            warn!(
+                "Tx {} pays fee {} sat ({:.1} sat/vB): {}",
+                txid,
+                fee,
+                fee_rate,
+                matched.join(", ")
+            );
+        }
+        matched
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RuleStats {
+    pub name: String,
+    pub matches: u64,
+    pub errors: u64,
+}
+
+#[derive(Debug)]
+struct Rule {
+    ast: AST,
+    stats: RuleStats,
+}
+
+#[derive(Debug)]
+pub struct Rules {
+    dir: PathBuf,
+    engine: Engine,
+    rules: Vec<Rule>,
+    // Modification time of files from last load, for hot reload
+    mtimes: HashMap<PathBuf, SystemTime>,
+}
+
+impl Rules {
+    pub fn load(dir: &Path) -> RulesResult<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(RULES_MAX_OPERATIONS);
+
+        let mut rules = Rules {
+            dir: dir.to_owned(),
+            engine,
+            rules: vec![],
+            mtimes: HashMap::new(),
+        };
+        rules.reload_if_changed()?;
+        Ok(rules)
+    }
+
+    // Recompile all rules if any file was added, removed or modified
+    pub fn reload_if_changed(&mut self) -> RulesResult<bool> {
+        let mtimes = self.scan_dir()?;
+        if mtimes == self.mtimes {
+            return Ok(false);
+        }
+
+        let mut paths = mtimes.keys().collect::<Vec<_>>();
+        paths.sort();
+
+        let mut rules = vec![];
+        for path in paths {
+            let name = path
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            // Rule with error skipped, other rules still work
+            match self.engine.compile_file(path.clone()) {
+                Ok(ast) => rules.push(Rule {
+                    ast,
+                    stats: RuleStats {
+                        name,
+                        ..Default::default()
+                    },
+                }),
+                Err(err) => error!("Failed to compile rule {}: {}", path.display(), err),
+            }
+        }
+
+        info!("Rules loaded: {} of {}", rules.len(), mtimes.len());
+        self.rules = rules;
+        self.mtimes = mtimes;
+        Ok(true)
+    }
+
+    fn scan_dir(&self) -> RulesResult<HashMap<PathBuf, SystemTime>> {
+        let map_err = |err| RulesError::ReadDir(self.dir.clone(), err);
+
+        let mut mtimes = HashMap::new();
+        for entry in fs::read_dir(&self.dir).map_err(map_err)? {
+            let path = entry.map_err(map_err)?.path();
+            if path.extension().map(|ext| ext == "rhai") != Some(true) {
+                continue;
+            }
+
+            let mtime = fs::metadata(&path).and_then(|meta| meta.modified());
+            mtimes.insert(path, mtime.map_err(map_err)?);
+        }
+        Ok(mtimes)
+    }
+
+    // Names of matched rules
+    pub fn check(&mut self, input: &RuleInput) -> Vec<String> {
+        let fee_rate = if input.vsize > 0 {
+            input.fee as f64 / input.vsize as f64
+        } else {
+            0.0
+        };
+
+        let mut scope = Scope::new();
+        scope.push_constant("txid", input.txid.clone());
+        scope.push_constant("vsize", input.vsize as i64);
+        scope.push_constant("fee", input.fee as i64);
+        scope.push_constant("fee_rate", fee_rate);
+        scope.push_constant("value", input.value);
+        scope.push_constant("outputs", input.outputs as i64);
+
+        let mut matched = vec![];
+        for rule in self.rules.iter_mut() {
+            // Own scope for every rule, so variables defined in rule do not leak
+            let mut scope = scope.clone();
+            match self
+                .engine
+                .eval_ast_with_scope::<bool>(&mut scope, &rule.ast)
+            {
+                Ok(true) => {
+                    rule.stats.matches += 1;
+                    matched.push(rule.stats.name.clone());
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    rule.stats.errors += 1;
+                    error!(
+                        "Rule {} error on tx {}: {}",
+                        rule.stats.name, input.txid, err
+                    );
+                }
+            }
+        }
+        matched
+    }
+
+    pub fn stats(&self) -> Vec<RuleStats> {
+        self.rules.iter().map(|rule| rule.stats.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(value: f64, vsize: u32, fee: u64) -> RuleInput {
+        RuleInput {
+            txid: "00".to_owned(),
+            vsize,
+            fee,
+            value,
+            outputs: 2,
+        }
+    }
+
+    #[test]
+    fn check_and_reload() {
+        let dir = std::env::temp_dir().join(format!("rules-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("whale.rhai"), "value > 100.0 && fee_rate < 2.0").unwrap();
+        fs::write(dir.join("spin.rhai"), "loop {}").unwrap();
+        fs::write(dir.join("broken.rhai"), "value >").unwrap();
+        fs::write(dir.join("notes.txt"), "true").unwrap();
+
+        // Rule with compile error skipped, runtime errors counted
+        let mut rules = Rules::load(&dir).unwrap();
+        assert_eq!(rules.check(&input(150.0, 200, 100)), vec!["whale"]);
+        assert!(rules.check(&input(150.0, 200, 1000)).is_empty());
+        let stats = rules
+            .stats()
+            .into_iter()
+            .map(|stats| (stats.name, stats.matches, stats.errors))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stats,
+            vec![("spin".to_owned(), 0, 2), ("whale".to_owned(), 1, 0)]
+        );
+
+        assert!(!rules.reload_if_changed().unwrap());
+        fs::remove_file(dir.join("whale.rhai")).unwrap();
+        assert!(rules.reload_if_changed().unwrap());
+        assert!(rules.check(&input(150.0, 200, 100)).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            rules.reload_if_changed(),
+            Err(RulesError::ReadDir(..))
+        ));
+    }
+}
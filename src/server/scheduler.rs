@@ -0,0 +1,264 @@
+// Periodic background jobs (mempool refresh, chain split and cross-checks,
+// clock skew, DNS resolution, stats export, rules reload) owned by one
+// scheduler.
+//
+// Every job ticks with own interval plus random jitter, so jobs with same
+// interval do not hit bitcoind at same moment. Run is awaited by task of job,
+// ticks passed during long run are skipped instead of started back to back.
+// Status of every job is served at `GET /admin/jobs`.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::BoxFuture;
+use log::{info, warn};
+use tokio::time::Instant;
+
+use super::json;
+use super::state::{unix_time, State};
+use crate::signals::ShutdownReceiver;
+
+// Interval is not allowed to be zero, job would spin
+const INTERVAL_MIN: Duration = Duration::from_millis(1);
+
+pub type JobResult = Result<(), String>;
+
+// Periodic work with own state between runs, errors are logged by scheduler
+pub trait Job<S = State>: Send {
+    fn run<'a>(&'a mut self, state: &'a S) -> BoxFuture<'a, JobResult>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Schedule {
+    pub interval: Duration,
+    // Random delay from zero to `jitter` added to every tick
+    pub jitter: Duration,
+    // First run on start instead of after first interval
+    pub immediate: bool,
+}
+
+impl Schedule {
+    // Jitter is tenth of interval
+    pub fn every(interval: Duration) -> Self {
+        let interval = interval.max(INTERVAL_MIN);
+        Schedule {
+            interval,
+            jitter: interval / 10,
+            immediate: false,
+        }
+    }
+
+    pub fn immediate(mut self) -> Self {
+        self.immediate = true;
+        self
+    }
+
+    // Delay from start of job to `tick`
+    fn at(&self, tick: u32) -> Duration {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        let jitter = self.jitter.mul_f64(f64::from(nanos) / 1e9);
+        self.interval * tick + jitter
+    }
+
+    // First tick not passed at `elapsed` from start
+    fn next_tick(&self, elapsed: Duration) -> u32 {
+        (elapsed.as_nanos() / self.interval.as_nanos()) as u32 + 1
+    }
+}
+
+// Statuses of started jobs, by registration order
+#[derive(Debug, Default)]
+pub struct Jobs {
+    statuses: Mutex<Vec<json::JobStatus>>,
+}
+
+impl Jobs {
+    pub fn list(&self) -> Vec<json::JobStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    fn register(&self, name: &str, schedule: &Schedule) -> usize {
+        let mut statuses = self.statuses.lock().unwrap();
+        statuses.push(json::JobStatus {
+            name: name.to_owned(),
+            interval_ms: schedule.interval.as_millis() as u64,
+            jitter_ms: schedule.jitter.as_millis() as u64,
+            running: false,
+            runs: 0,
+            failures: 0,
+            skipped: 0,
+            last_run: None,
+            last_duration_ms: None,
+            last_error: None,
+            next_run: None,
+        });
+        statuses.len() - 1
+    }
+
+    fn update<F: FnOnce(&mut json::JobStatus)>(&self, index: usize, f: F) {
+        f(&mut self.statuses.lock().unwrap()[index]);
+    }
+}
+
+pub struct Scheduler<S = State> {
+    jobs: Vec<(&'static str, Schedule, Box<dyn Job<S>>)>,
+}
+
+impl<S> Default for Scheduler<S> {
+    fn default() -> Self {
+        Scheduler { jobs: vec![] }
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler::default()
+    }
+}
+
+impl<S: Send + Sync + 'static> Scheduler<S> {
+    pub fn add(
+        &mut self,
+        name: &'static str,
+        schedule: Schedule,
+        job: Box<dyn Job<S>>,
+    ) -> &mut Self {
+        self.jobs.push((name, schedule, job));
+        self
+    }
+
+    // Every job in own task with status in `jobs`, until shutdown
+    pub fn spawn(self, state: Arc<S>, jobs: Arc<Jobs>, shutdown: ShutdownReceiver) {
+        for (name, schedule, job) in self.jobs {
+            info!("Run job {} every {:?}", name, schedule.interval);
+            let index = jobs.register(name, &schedule);
+            let (state, jobs, shutdown) = (state.clone(), jobs.clone(), shutdown.clone());
+            tokio::spawn(run_job(state, name, jobs, index, schedule, job, shutdown));
+        }
+    }
+}
+
+async fn run_job<S>(
+    state: Arc<S>,
+    name: &'static str,
+    jobs: Arc<Jobs>,
+    index: usize,
+    schedule: Schedule,
+    mut job: Box<dyn Job<S>>,
+    mut shutdown: ShutdownReceiver,
+) {
+    let start = Instant::now();
+    let mut tick = if schedule.immediate { 0 } else { 1 };
+    loop {
+        let at = start + schedule.at(tick);
+        let delay = at.saturating_duration_since(Instant::now());
+        jobs.update(index, |status| {
+            status.next_run = Some(unix_time() + delay.as_secs());
+        });
+        tokio::select! {
+            _ = tokio::time::delay_for(delay) => {},
+            _ = shutdown.recv() => break,
+        }
+
+        jobs.update(index, |status| status.running = true);
+        let started = Instant::now();
+        let last_run = unix_time();
+        let result = job.run(&state).await;
+        let elapsed = started.elapsed();
+        if let Err(err) = &result {
+            warn!("Job {} failed: {}", name, err);
+        }
+
+        // Ticks passed during run are skipped, not run one after another
+        let next = schedule.next_tick(start.elapsed()).max(tick + 1);
+        jobs.update(index, |status| {
+            status.running = false;
+            status.runs += 1;
+            status.failures += result.is_err() as u64;
+            status.skipped += u64::from(next - tick - 1);
+            status.last_run = Some(last_run);
+            status.last_duration_ms = Some(elapsed.as_millis() as u64);
+            status.last_error = result.err();
+        });
+        tick = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::FutureExt as _;
+
+    use super::*;
+
+    // Job slower than its interval, fails every second run
+    struct SlowJob {
+        delay: Duration,
+        runs: u64,
+    }
+
+    impl Job<()> for SlowJob {
+        fn run<'a>(&'a mut self, _state: &'a ()) -> BoxFuture<'a, JobResult> {
+            async move {
+                tokio::time::delay_for(self.delay).await;
+                self.runs += 1;
+                match self.runs % 2 {
+                    0 => Err(format!("run {} failed", self.runs)),
+                    _ => Ok(()),
+                }
+            }
+            .boxed()
+        }
+    }
+
+    // Paused clock jumps to next timer when runtime is idle, so every delay
+    // takes exactly its duration
+    async fn sleep(millis: u64) {
+        tokio::time::delay_for(Duration::from_millis(millis)).await;
+    }
+
+    #[tokio::test]
+    async fn skips_overlapping_ticks() {
+        tokio::time::pause();
+        let schedule = Schedule {
+            interval: Duration::from_millis(20),
+            jitter: Duration::from_millis(0),
+            immediate: true,
+        };
+        let job = SlowJob {
+            delay: Duration::from_millis(50),
+            runs: 0,
+        };
+        let jobs = Arc::new(Jobs::default());
+        let mut shutdown = ShutdownReceiver::new();
+        let mut scheduler = Scheduler::default();
+        scheduler.add("slow", schedule, Box::new(job));
+        scheduler.spawn(Arc::new(()), jobs.clone(), shutdown.clone());
+
+        // Runs at 0, 60 and 120 ms, every run covers 3 ticks of 20 ms
+        sleep(175).await;
+        let list = jobs.list();
+        assert_eq!(list.len(), 1);
+        let job = &list[0];
+        assert_eq!((job.name.as_str(), job.interval_ms), ("slow", 20));
+        assert_eq!((job.runs, job.skipped, job.failures), (3, 6, 1));
+        assert_eq!(job.last_duration_ms, Some(50));
+        assert_eq!(job.last_error, None);
+        assert!(!job.running);
+
+        // Fourth run started at 180 ms
+        sleep(10).await;
+        let job = &jobs.list()[0];
+        assert!(job.running);
+        sleep(50).await;
+        let job = &jobs.list()[0];
+        assert_eq!((job.runs, job.failures), (4, 2));
+        assert_eq!(job.last_error.as_deref(), Some("run 4 failed"));
+
+        shutdown.set();
+        sleep(100).await;
+        assert_eq!(jobs.list()[0].runs, 4);
+    }
+}
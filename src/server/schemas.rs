@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use schemars::{schema_for, JsonSchema};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use super::audit::AuditRecord;
+use super::events::Topic;
+use super::json;
+
+// Changed on every incompatible change of events format
+pub const EVENTS_SCHEMA_VERSION: u32 = 1;
+
+// Events sent as text messages, so every event type described with pattern,
+// groups of pattern are event fields
+const EVENTS: &[(&str, Topic, &str, &[&str])] = &[
+    (
+        "Added",
+        Topic::Mempool,
+        r"^Added tx: ([0-9a-f]{64})$",
+        &["txid"],
+    ),
+    (
+        "Confirmed",
+        Topic::Mempool,
+        r"^Confirmed tx: ([0-9a-f]{64})$",
+        &["txid"],
+    ),
+    (
+        "Removed",
+        Topic::Mempool,
+        r"^Removed tx: ([0-9a-f]{64})$",
+        &["txid"],
+    ),
+    (
+        "AddedBlock",
+        Topic::Blocks,
+        r"^Added block: ([0-9a-f]{64})$",
+        &["hash"],
+    ),
+    (
+        "RemovedBlock",
+        Topic::Blocks,
+        r"^Removed block: ([0-9a-f]{64})$",
+        &["hash"],
+    ),
+    (
+        "CaughtUp",
+        Topic::Blocks,
+        r"^CaughtUp block: ([0-9a-f]{64})$",
+        &["hash"],
+    ),
+    (
+        "Reorg",
+        Topic::Blocks,
+        r"^Reorg depth (\d+): old tip ([0-9a-f]{64}), new tip ([0-9a-f]{64})$",
+        &["depth", "old_tip", "new_tip"],
+    ),
+    (
+        "Watch",
+        Topic::Blocks,
+        r"^Watch tx: ([0-9a-f]{64})$",
+        &["txid"],
+    ),
+    (
+        "Timelock",
+        Topic::Blocks,
+        r"^Timelock expired: ([0-9a-f]{64}):(\d+)$",
+        &["txid", "vout"],
+    ),
+    (
+        "Plugin",
+        Topic::Plugins,
+        r"^Plugin (.+?): (.*)$",
+        &["topic", "data"],
+    ),
+    (
+        "Alert",
+        Topic::Alerts,
+        r"^Alert (.+) tx: ([0-9a-f]{64})$",
+        &["rule", "txid"],
+    ),
+    (
+        "Large",
+        Topic::Large,
+        r"^Large tx ([0-9a-f]{64}): (\d+) sat$",
+        &["txid", "value"],
+    ),
+    (
+        "Streak",
+        Topic::Blocks,
+        r"^Streak (empty|full): (\d+) blocks, (\d+)-(\d+)$",
+        &["kind", "length", "start_height", "end_height"],
+    ),
+    (
+        "Divergence",
+        Topic::Alerts,
+        r"^Divergence (.+): local ([0-9a-f]{64}), remote ([0-9a-f]{64})$",
+        &["source", "local", "remote"],
+    ),
+    (
+        "ChainSplit",
+        Topic::Alerts,
+        r"^ChainSplit (.+) at (\d+): primary ([0-9a-f]{64}), node ([0-9a-f]{64})$",
+        &["node", "height", "primary_hash", "node_hash"],
+    ),
+    (
+        "ProjectedBlock",
+        Topic::Mempool,
+        r"^ProjectedBlock (\d+): (\d+) txs, weight (\d+), fees (\d+) sat$",
+        &["height", "transactions", "weight", "fees"],
+    ),
+    (
+        "Maintenance",
+        Topic::Alerts,
+        r"^Maintenance (?:on: (.*)|off)$",
+        &["message"],
+    ),
+    (
+        "Lagged",
+        Topic::Alerts,
+        r"^Lagged: (\d+) events skipped$",
+        &["skipped"],
+    ),
+];
+
+// Text event matched with one of known event schemas
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParsedEvent {
+    pub event: &'static str,
+    pub topic: String,
+    #[serde(flatten)]
+    pub fields: BTreeMap<&'static str, String>,
+}
+
+// Compiled patterns of text events, for clients which validate and parse
+// received events
+#[derive(Debug)]
+pub struct EventSchemas {
+    events: Vec<(&'static str, Topic, Regex, &'static [&'static str])>,
+}
+
+impl EventSchemas {
+    pub fn new() -> Self {
+        let events = EVENTS
+            .iter()
+            .map(|(name, topic, pattern, fields)| {
+                (*name, *topic, Regex::new(pattern).unwrap(), *fields)
+            })
+            .collect();
+        EventSchemas { events }
+    }
+
+    // `None` for event unknown to this version
+    pub fn parse(&self, text: &str) -> Option<ParsedEvent> {
+        self.events.iter().find_map(|(name, topic, re, fields)| {
+            let captures = re.captures(text)?;
+            // Optional groups without match are not set
+            let fields = fields
+                .iter()
+                .zip(captures.iter().skip(1))
+                .filter_map(|(field, value)| Some((*field, value?.as_str().to_owned())))
+                .collect();
+            Some(ParsedEvent {
+                event: name,
+                topic: topic.to_string(),
+                fields,
+            })
+        })
+    }
+}
+
+impl Default for EventSchemas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn schema<T: JsonSchema>() -> Value {
+    serde_json::to_value(schema_for!(T)).unwrap()
+}
+
+// JSON Schemas of API requests, responses and events for this server version
+pub fn get_schemas() -> Value {
+    let hex = json!({ "type": "string", "pattern": "^[0-9a-f]*$" });
+    let responses = json!({
+        "GET /": schema::<json::Root>(),
+        "GET /v1/mempool": schema::<Vec<json::Transaction>>(),
+        "GET /v1/mempool/feerates": schema::<json::MempoolFeerates>(),
+        "GET /v1/mempool/histogram": schema::<json::MempoolHistogram>(),
+        "GET /v1/mempool/projected-block": schema::<json::ProjectedBlock>(),
+        "GET /v1/mempool/tx/{txid}": schema::<json::MempoolTransaction>(),
+        "GET /v1/fees/confirmation-times": schema::<json::ConfirmationTimes>(),
+        "GET /v1/fee-estimate": schema::<json::FeeEstimate>(),
+        "GET /v1/block/{id}": schema::<json::Block>(),
+        "GET /v1/block/{id}?page=&limit=": schema::<json::BlockPage>(),
+        "GET /v1/block/{id}/hex": hex,
+        "GET /v1/block/{id}/filter": schema::<json::BlockFilter>(),
+        "POST /v1/tx": schema::<json::TxBroadcast>(),
+        "POST /v1/tx/test": schema::<Vec<json::TxTestResult>>(),
+        "POST /v1/txs/package": schema::<json::PackageSubmit>(),
+        "GET /v1/tx/{txid}": schema::<json::TransactionInfo>(),
+        "GET /v1/outpoint/{outpoint}": schema::<json::OutpointInfo>(),
+        "GET /v1/outpoint/{outpoint}/spend": schema::<json::OutpointSpend>(),
+        "GET /v1/address/{address}/transactions": schema::<json::AddressTransactions>(),
+        "GET /v1/network/halving": schema::<json::Halving>(),
+        "GET /v1/network/difficulty-adjustment": schema::<json::DifficultyAdjustment>(),
+        "GET /v1/node/peers": schema::<json::Peers>(),
+        "GET /v1/stats": schema::<json::Stats>(),
+        "GET /v1/metrics/block-sources": schema::<json::BlockSources>(),
+        "GET /v1/reorgs": schema::<json::Reorgs>(),
+        "GET /v1/nodes": schema::<json::Nodes>(),
+        "GET /version": schema::<json::Version>(),
+        "GET /v1/watch": schema::<json::Watch>(),
+        "POST /v1/watch": schema::<json::Watch>(),
+        "DELETE /v1/watch": schema::<json::Watch>(),
+        "GET /v1/watch/timelocks": schema::<json::Timelocks>(),
+        "POST /v1/watch/timelocks": schema::<json::Timelocks>(),
+        "DELETE /v1/watch/timelocks": schema::<json::Timelocks>(),
+        "GET /v1/watch/reactions": schema::<json::Reactions>(),
+        "POST /v1/watch/reactions": schema::<json::Reactions>(),
+        "DELETE /v1/watch/reactions": schema::<json::Reactions>(),
+        "WS /v1/ws": schema::<json::WsReply>(),
+        "GET /v1/usage": schema::<json::Usage>(),
+        "GET /v1/admin/blacklist": schema::<json::Blacklist>(),
+        "POST /v1/admin/blacklist": schema::<json::Blacklist>(),
+        "DELETE /v1/admin/blacklist": schema::<json::Blacklist>(),
+        "GET /v1/admin/audit": schema::<Vec<AuditRecord>>(),
+        "PUT /v1/admin/bitcoind": schema::<json::BitcoindSwitch>(),
+        "GET /v1/admin/maintenance": schema::<json::Maintenance>(),
+        "PUT /v1/admin/maintenance": schema::<json::Maintenance>(),
+        "POST /v1/admin/pause": schema::<json::UpdatesPause>(),
+        "POST /v1/admin/resume": schema::<json::UpdatesPause>(),
+        "GET /v1/admin/blocks-keep": schema::<json::BlocksKeep>(),
+        "PUT /v1/admin/blocks-keep": schema::<json::BlocksKeep>(),
+        "GET /v1/admin/chaos": schema::<json::Chaos>(),
+        "PUT /v1/admin/chaos": schema::<json::Chaos>(),
+        "GET /v1/admin/jobs": schema::<Vec<json::JobStatus>>(),
+        "GET /v1/admin/bans": schema::<Vec<json::Ban>>(),
+        "POST /v1/admin/bans": schema::<Vec<json::Ban>>(),
+        "DELETE /v1/admin/bans": schema::<Vec<json::Ban>>(),
+        "POST /v1/admin/nodes": schema::<json::NodeEntry>(),
+        "DELETE /v1/admin/nodes": schema::<json::NodeEntry>(),
+        "DELETE /v1/admin/peers": schema::<json::PeerEntry>(),
+    });
+    let requests = json!({
+        "POST /v1/tx/test": schema::<json::TxTestRequest>(),
+        "POST /v1/txs/package": schema::<json::PackageSubmitRequest>(),
+        "POST /v1/watch": schema::<json::WatchEntry>(),
+        "DELETE /v1/watch": schema::<json::WatchEntry>(),
+        "POST /v1/watch/timelocks": schema::<json::TimelockEntry>(),
+        "DELETE /v1/watch/timelocks": schema::<json::TimelockEntry>(),
+        "POST /v1/watch/reactions": schema::<json::ReactionEntry>(),
+        "DELETE /v1/watch/reactions": schema::<json::ReactionEntry>(),
+        "WS /v1/ws": schema::<json::WsCommand>(),
+        "POST /v1/admin/blacklist": schema::<json::BlacklistEntry>(),
+        "DELETE /v1/admin/blacklist": schema::<json::BlacklistEntry>(),
+        "PUT /v1/admin/bitcoind": schema::<json::BitcoindEndpoint>(),
+        "PUT /v1/admin/maintenance": schema::<json::Maintenance>(),
+        "PUT /v1/admin/blocks-keep": schema::<json::BlocksKeep>(),
+        "PUT /v1/admin/chaos": schema::<json::Chaos>(),
+        "POST /v1/admin/bans": schema::<json::BanEntry>(),
+        "DELETE /v1/admin/bans": schema::<json::BanEntry>(),
+        "POST /v1/admin/nodes": schema::<json::NodeEntry>(),
+        "DELETE /v1/admin/nodes": schema::<json::NodeEntry>(),
+        "DELETE /v1/admin/peers": schema::<json::PeerEntry>(),
+    });
+
+    let errors = json!({
+        "400": schema::<json::ErrorResponse>(),
+        "401": schema::<json::ErrorResponse>(),
+        "404": schema::<json::NotFound>(),
+        "406": schema::<json::UnsupportedVersion>(),
+        "429": schema::<json::QuotaExceeded>(),
+        "500": schema::<json::ErrorResponse>(),
+        "502": schema::<json::ErrorResponse>(),
+        "503": schema::<json::Unavailable>(),
+        "504": schema::<json::ErrorResponse>(),
+    });
+
+    let mut events = Map::new();
+    for (name, topic, pattern, _) in EVENTS {
+        let schema = json!({
+            "topic": topic.to_string(),
+            "schema": { "type": "string", "pattern": pattern },
+        });
+        events.insert((*name).to_owned(), schema);
+    }
+
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "events_schema_version": EVENTS_SCHEMA_VERSION,
+        "responses": responses,
+        "requests": requests,
+        "errors": errors,
+        "events": events,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::events::{Event, TxEvent};
+    use super::*;
+
+    #[test]
+    fn event_patterns() {
+        // Every pattern group has field name, names are unique
+        let mut names = vec![];
+        for (name, _, pattern, fields) in EVENTS {
+            let re = Regex::new(pattern).unwrap();
+            assert_eq!(re.captures_len() - 1, fields.len(), "{}", name);
+            names.push(*name);
+        }
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), EVENTS.len());
+
+        let schemas = get_schemas();
+        assert_eq!(schemas["events_schema_version"], EVENTS_SCHEMA_VERSION);
+        assert_eq!(schemas["events"]["Reorg"]["topic"], "blocks");
+        assert!(schemas["responses"]["GET /v1/block/{id}"].is_object());
+        assert!(schemas["requests"]["POST /v1/watch"].is_object());
+    }
+
+    #[test]
+    fn parse_events() {
+        let schemas = EventSchemas::new();
+        let txid = "ab".repeat(32);
+        let parsed = schemas
+            .parse(&Event::Tx(TxEvent::Confirmed, txid.clone()).to_string())
+            .unwrap();
+        assert_eq!(
+            (parsed.event, parsed.topic.as_str()),
+            ("Confirmed", "mempool")
+        );
+        assert_eq!(parsed.fields["txid"], txid);
+
+        // Non-greedy topic, data can contain separator
+        let event = Event::Plugin("ordinals".to_owned(), "a: b".to_owned());
+        let parsed = schemas.parse(&event.to_string()).unwrap();
+        assert_eq!(
+            (
+                parsed.fields["topic"].as_str(),
+                parsed.fields["data"].as_str()
+            ),
+            ("ordinals", "a: b")
+        );
+
+        let parsed = schemas.parse(&Event::Lagged(7).to_string()).unwrap();
+        assert_eq!(parsed.fields["skipped"], "7");
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            json!({ "event": "Lagged", "topic": "alerts", "skipped": "7" })
+        );
+        assert_eq!(schemas.parse("Unknown event"), None);
+    }
+}
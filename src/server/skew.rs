@@ -0,0 +1,159 @@
+// Clock skew between server, bitcoind and miners. Time derived metrics mix
+// clocks: mempool entry time is node clock, block time is miner clock and
+// receive time is server clock, so skew of any of them shows up as delay.
+//
+// Node offset to its peers is `timeoffset` of `getnetworkinfo`. Server offset
+// to node is estimated from entry times of new mempool transactions: we see
+// transaction after node accepted it, so smallest difference is upper bound
+// of offset. Block delay is time between header time and receive of new tip.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt as _};
+use log::{info, warn};
+
+use super::json;
+use super::scheduler::{Job, JobResult};
+use super::state::{unix_time, State};
+
+// Node time offset checked with this interval
+pub const CLOCK_SKEW_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Latest mempool entries for server offset
+const ENTRIES_MAX: usize = 100;
+// Latest tip blocks for delay median
+const BLOCKS_MAX: usize = 11;
+// Blocks received later are from catch up, not new tips
+const BLOCK_DELAY_MAX: i64 = 2 * 60 * 60;
+
+#[derive(Debug, Default)]
+struct Samples {
+    node_offset: Option<i64>,
+    // Server time minus node entry time
+    entries: VecDeque<i64>,
+    // Server time minus block header time
+    blocks: VecDeque<i64>,
+    exceeded: bool,
+}
+
+#[derive(Debug)]
+pub struct ClockSkew {
+    threshold: u64,
+    samples: Mutex<Samples>,
+}
+
+impl ClockSkew {
+    pub fn new(threshold: Duration) -> Self {
+        ClockSkew {
+            threshold: threshold.as_secs(),
+            samples: Mutex::new(Samples::default()),
+        }
+    }
+
+    pub fn set_node_offset(&self, offset: i64) {
+        self.samples.lock().unwrap().node_offset = Some(offset);
+    }
+
+    // New mempool transaction, entry time not reported by old bitcoind
+    pub fn push_entry(&self, time: u64) {
+        if time > 0 {
+            let mut samples = self.samples.lock().unwrap();
+            push(&mut samples.entries, ENTRIES_MAX, diff(unix_time(), time));
+        }
+    }
+
+    // New tip block
+    pub fn push_block(&self, time: u32) {
+        let delay = diff(unix_time(), u64::from(time));
+        if delay <= BLOCK_DELAY_MAX {
+            let mut samples = self.samples.lock().unwrap();
+            push(&mut samples.blocks, BLOCKS_MAX, delay);
+        }
+    }
+
+    pub fn report(&self) -> json::ClockSkew {
+        let samples = self.samples.lock().unwrap();
+        let server_offset = samples.entries.iter().min().copied();
+        let mut blocks = samples.blocks.iter().copied().collect::<Vec<_>>();
+        blocks.sort_unstable();
+        let block_delay = blocks.get(blocks.len() / 2).copied();
+        // Server clock minus network time, which is node time plus offset
+        let skew = server_offset.unwrap_or(0) - samples.node_offset.unwrap_or(0);
+
+        json::ClockSkew {
+            node_offset: samples.node_offset,
+            server_offset,
+            block_delay,
+            block_delay_corrected: block_delay.map(|delay| delay - skew),
+            threshold: self.threshold,
+            exceeded: samples.exceeded,
+        }
+    }
+
+    // Seconds added to durations from node entry time to block time, so both
+    // are in network time
+    pub fn entry_correction(&self) -> Option<i64> {
+        self.samples
+            .lock()
+            .unwrap()
+            .node_offset
+            .map(|offset| -offset)
+    }
+
+    // Log offsets above threshold once, until they are back in range
+    pub fn check(&self) {
+        let report = self.report();
+        let threshold = self.threshold as i64;
+        let mut exceeded = vec![];
+        if let Some(offset) = report.node_offset.filter(|offset| offset.abs() > threshold) {
+            exceeded.push(format!("node offset to peers {}s", offset));
+        }
+        if let Some(offset) = report
+            .server_offset
+            .filter(|offset| offset.abs() > threshold)
+        {
+            exceeded.push(format!("server offset to node {}s", offset));
+        }
+        // Header time can be ahead of real time only a bit
+        let newest = self.samples.lock().unwrap().blocks.back().copied();
+        if let Some(delay) = newest.filter(|delay| *delay < -threshold) {
+            exceeded.push(format!("tip block time {}s ahead", -delay));
+        }
+
+        let mut samples = self.samples.lock().unwrap();
+        if !exceeded.is_empty() && !samples.exceeded {
+            warn!("Clock skew above {}s: {}", threshold, exceeded.join(", "));
+        } else if exceeded.is_empty() && samples.exceeded {
+            info!("Clock skew back below {}s", threshold);
+        }
+        samples.exceeded = !exceeded.is_empty();
+    }
+}
+
+// Skew checked with last known node offset if it is not updated
+pub struct ClockSkewCheck;
+
+impl Job for ClockSkewCheck {
+    fn run<'a>(&'a mut self, state: &'a State) -> BoxFuture<'a, JobResult> {
+        async move {
+            let result = state.update_node_time_offset().await;
+            state.get_clock_skew().check();
+            result
+                .map(|_| ())
+                .map_err(|err| format!("failed to get bitcoind time offset: {}", err))
+        }
+        .boxed()
+    }
+}
+
+fn push(samples: &mut VecDeque<i64>, max: usize, sample: i64) {
+    if samples.len() == max {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+fn diff(a: u64, b: u64) -> i64 {
+    a as i64 - b as i64
+}
@@ -0,0 +1,365 @@
+// Soak test (`server --soak hours`, hidden): server runs as usual against
+// regtest node, while churn of blocks and reorgs is generated with RPC of
+// same node. After every churn step server should follow node and pass
+// checks, first violation stops soak with error:
+//   - block events replay tip by tip, without skipped events
+//   - tracked window match node chain
+//   - index sizes match tracked blocks and mempool (no leaks)
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::info;
+use tokio::sync::broadcast::{self, TryRecvError};
+
+use super::bitcoind::Bitcoind;
+use super::error::{AppError, AppResult};
+use super::events::{BlockEvent, BusMessage, Event};
+use super::state::State;
+use crate::signals::ShutdownReceiver;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+const SYNC_POLL: Duration = Duration::from_millis(50);
+// Update loop polls node every 25ms, even deep reorg is resolved in seconds
+const SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+
+type Window = Vec<(u32, String)>;
+
+#[derive(Debug, Default)]
+struct Progress {
+    steps: u64,
+    mined: u64,
+    reorgs: u64,
+    events: u64,
+}
+
+// Churn until `duration` passed or shutdown signal received
+pub async fn run(
+    state: Arc<State>,
+    bitcoind: Bitcoind,
+    mut events: broadcast::Receiver<BusMessage>,
+    duration: Duration,
+    mut shutdown: ShutdownReceiver,
+) -> AppResult<()> {
+    let info = bitcoind
+        .getblockchaininfo()
+        .await
+        .map_err(AppError::Bitcoind)?;
+    if info.chain != "regtest" {
+        let reason = format!("regtest node required, got {}", info.chain);
+        return Err(AppError::SoakViolation(reason));
+    }
+
+    // Chain is initialized without events, replay starts from first sync
+    let started = Instant::now();
+    while state.get_blocks_tracked().await == 0 {
+        if started.elapsed() > SYNC_TIMEOUT {
+            return violation("chain is not initialized");
+        }
+        tokio::time::delay_for(SYNC_POLL).await;
+    }
+    let mut progress = Progress::default();
+    let mut replay = sync(&state, &bitcoind, &mut events, None, &mut progress).await?;
+
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    let mut rng = Rng(seed | 1);
+    info!("Soak for {:?}, seed {}", duration, seed);
+
+    let mut last_progress = Instant::now();
+    while started.elapsed() < duration && !shutdown.is_recv() {
+        churn(&state, &bitcoind, &mut rng, &mut progress).await?;
+        replay = sync(&state, &bitcoind, &mut events, Some(replay), &mut progress).await?;
+        check_accounting(&state).await?;
+        progress.steps += 1;
+
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            info!(
+                "Soak: {} steps, {} blocks mined, {} reorgs, {} block events, {:?} left",
+                progress.steps,
+                progress.mined,
+                progress.reorgs,
+                progress.events,
+                duration.checked_sub(started.elapsed()).unwrap_or_default(),
+            );
+            last_progress = Instant::now();
+        }
+    }
+
+    info!(
+        "Soak passed: {} steps, {} blocks mined, {} reorgs, {} block events",
+        progress.steps, progress.mined, progress.reorgs, progress.events
+    );
+    Ok(())
+}
+
+// One change of node chain: new blocks, reorg or nothing
+async fn churn(
+    state: &State,
+    bitcoind: &Bitcoind,
+    rng: &mut Rng,
+    progress: &mut Progress,
+) -> AppResult<()> {
+    // Coinbase to different anyone-can-spend script (OP_1..OP_16) on every
+    // step, so block of new branch is not same as invalidated block
+    let descriptor = format!("raw({:02x})", 0x51 + progress.steps % 16);
+
+    let info = bitcoind
+        .getblockchaininfo()
+        .await
+        .map_err(AppError::Bitcoind)?;
+    match rng.below(100) {
+        0..=64 => {
+            let blocks = 1 + rng.below(3) as u32;
+            generate(bitcoind, blocks, &descriptor, progress).await
+        }
+        65..=94 => {
+            // Deeper than window sometimes, for refetch of stale blocks
+            let max_depth = (state.get_blocks_keep() as u64 + 2).min(info.blocks as u64);
+            if max_depth == 0 {
+                return generate(bitcoind, 1, &descriptor, progress).await;
+            }
+            let depth = 1 + rng.below(max_depth) as u32;
+            let height = info.blocks + 1 - depth;
+            let hash = match bitcoind.getblockhash(height).await {
+                Ok(Some(hash)) => hash,
+                Ok(None) => return violation(&format!("node has no block {}", height)),
+                Err(error) => return Err(AppError::Bitcoind(error)),
+            };
+            bitcoind
+                .invalidateblock(&hash)
+                .await
+                .map_err(AppError::Bitcoind)?;
+            progress.reorgs += 1;
+            generate(bitcoind, depth + 1, &descriptor, progress).await
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn generate(
+    bitcoind: &Bitcoind,
+    blocks: u32,
+    descriptor: &str,
+    progress: &mut Progress,
+) -> AppResult<()> {
+    bitcoind
+        .generatetodescriptor(blocks, descriptor)
+        .await
+        .map_err(AppError::Bitcoind)?;
+    progress.mined += blocks as u64;
+    Ok(())
+}
+
+// Wait until server follow node tip, with block events applied to `replay`
+// window. Returns tracked window, which should match node chain.
+async fn sync(
+    state: &State,
+    bitcoind: &Bitcoind,
+    events: &mut broadcast::Receiver<BusMessage>,
+    mut replay: Option<Window>,
+    progress: &mut Progress,
+) -> AppResult<Window> {
+    let started = Instant::now();
+    loop {
+        loop {
+            let event = match events.try_recv() {
+                Ok(message) => message.event,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Lagged(skipped)) => {
+                    return violation(&format!("{} events skipped", skipped));
+                }
+                Err(TryRecvError::Closed) => return violation("events channel closed"),
+            };
+            if let (Event::Block(event, height, hash), Some(window)) = (event, replay.as_mut()) {
+                apply(window, event, height, hash)?;
+                progress.events += 1;
+            }
+        }
+
+        let info = bitcoind
+            .getblockchaininfo()
+            .await
+            .map_err(AppError::Bitcoind)?;
+        let tip = state.get_tip().await;
+        let replayed = match replay.as_ref().and_then(|window| window.last()) {
+            Some((_, hash)) => hash == &tip.hash,
+            None => true,
+        };
+        if tip.hash == info.bestblockhash && replayed {
+            break;
+        }
+
+        if started.elapsed() > SYNC_TIMEOUT {
+            let reason = format!(
+                "not synced in {:?}: node tip {}, our tip {}",
+                SYNC_TIMEOUT, info.bestblockhash, tip.hash
+            );
+            return violation(&reason);
+        }
+        tokio::time::delay_for(SYNC_POLL).await;
+    }
+
+    let window = state.get_window().await;
+    check_window(state, bitcoind, &window).await?;
+    if let Some(replay) = replay {
+        // Blocks below window are dropped without events
+        let replay = &replay[replay.len().saturating_sub(window.len())..];
+        if !window.ends_with(replay) {
+            let reason = format!(
+                "block events replay {:?} is not end of tracked window {:?}",
+                replay, window
+            );
+            return violation(&reason);
+        }
+    }
+    Ok(window)
+}
+
+// Every event should change tip, removed blocks below window are not tracked
+fn apply(window: &mut Window, event: BlockEvent, height: u32, hash: String) -> AppResult<()> {
+    match event {
+        BlockEvent::Added => {
+            if let Some((tip, _)) = window.last() {
+                if tip + 1 != height {
+                    let reason = format!("added block {} does not extend tip {}", height, tip);
+                    return violation(&reason);
+                }
+            }
+            window.push((height, hash));
+        }
+        BlockEvent::Removed => {
+            if let Some(tip) = window.pop() {
+                if tip != (height, hash.clone()) {
+                    let reason = format!("removed block {} {} is not tip", height, hash);
+                    return violation(&reason);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn check_window(
+    state: &State,
+    bitcoind: &Bitcoind,
+    window: &[(u32, String)],
+) -> AppResult<()> {
+    let tip = window.last().map(|(height, _)| *height).unwrap_or(0);
+    let expected = state.get_blocks_keep().min(tip as usize + 1);
+    if window.len() != expected {
+        let reason = format!("{} blocks tracked, expected {}", window.len(), expected);
+        return violation(&reason);
+    }
+
+    let heights = window.iter().map(|(height, _)| *height).collect::<Vec<_>>();
+    let hashes = bitcoind
+        .getblockhashes(&heights)
+        .await
+        .map_err(AppError::Bitcoind)?;
+    for ((height, hash), node) in window.iter().zip(hashes) {
+        if node.as_ref() != Some(hash) {
+            let reason = format!("tracked block {} {} is not in node chain", height, hash);
+            return violation(&reason);
+        }
+    }
+    Ok(())
+}
+
+async fn check_accounting(state: &State) -> AppResult<()> {
+    let accounting = state.get_accounting().await;
+    let checks = [
+        (
+            accounting.blocks <= accounting.blocks_keep,
+            "tracked blocks above limit",
+        ),
+        (
+            accounting.scripts == accounting.block_scripts,
+            "scripts index does not match outputs of tracked blocks",
+        ),
+        (
+            accounting.interned <= accounting.scripts,
+            "interned scripts without index entries",
+        ),
+        (
+            accounting.spends_blocks == accounting.block_spent,
+            "spends index does not match inputs of tracked blocks",
+        ),
+        (
+            accounting.spends_mempool_txs <= accounting.mempool,
+            "spends of transactions not in mempool",
+        ),
+        (
+            accounting.reorgs <= accounting.reorgs_max,
+            "reorgs history above limit",
+        ),
+    ];
+    for (ok, reason) in checks.iter() {
+        if !ok {
+            return violation(&format!("{}: {:?}", reason, accounting));
+        }
+    }
+    Ok(())
+}
+
+fn violation<T>(reason: &str) -> AppResult<T> {
+    Err(AppError::SoakViolation(reason.to_owned()))
+}
+
+// Xorshift, churn does not need good randomness
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(height: u32) -> (u32, String) {
+        (height, format!("{:064x}", height))
+    }
+
+    #[test]
+    fn replay_events() {
+        let mut window = vec![block(1), block(2)];
+        let (height, hash) = block(3);
+        apply(&mut window, BlockEvent::Added, height, hash).unwrap();
+        assert_eq!(window, vec![block(1), block(2), block(3)]);
+        let (height, hash) = block(3);
+        apply(&mut window, BlockEvent::Removed, height, hash).unwrap();
+        assert_eq!(window, vec![block(1), block(2)]);
+
+        // Skipped height and removal of not tip are violations
+        let (height, hash) = block(4);
+        assert!(apply(&mut window, BlockEvent::Added, height, hash).is_err());
+        let (height, _) = block(2);
+        let removed = apply(&mut window, BlockEvent::Removed, height, "ff".repeat(32));
+        assert!(matches!(removed, Err(AppError::SoakViolation(_))));
+
+        // Removed blocks below window are not tracked
+        let mut window = vec![];
+        let (height, hash) = block(7);
+        apply(&mut window, BlockEvent::Removed, height, hash).unwrap();
+        let (height, hash) = block(7);
+        apply(&mut window, BlockEvent::Added, height, hash).unwrap();
+        assert_eq!(window, vec![block(7)]);
+    }
+
+    #[test]
+    fn rng_range() {
+        let mut rng = Rng(1);
+        let values = (0..1000).map(|_| rng.below(100)).collect::<Vec<_>>();
+        assert!(values.iter().all(|value| *value < 100));
+        assert!(values.iter().any(|value| *value < 10));
+        assert!(values.iter().any(|value| *value >= 90));
+    }
+}
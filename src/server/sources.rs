@@ -0,0 +1,146 @@
+// Which source announced block first and by how much, so operators can tune
+// notification setup. Sources are polling of our bitcoind (`poll`) and
+// cross-check source, if enabled.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::json;
+
+pub const SOURCE_POLL: &str = "poll";
+
+// Latest blocks kept for comparison
+const BLOCKS_MAX: usize = 100;
+
+#[derive(Debug)]
+struct Announce {
+    height: u32,
+    hash: String,
+    first_source: String,
+    first_at: Instant,
+    // Unix time in milliseconds
+    first_time: u64,
+    // Delay of other sources after first
+    lags: BTreeMap<String, Duration>,
+}
+
+#[derive(Debug, Default)]
+pub struct BlockSources {
+    blocks: Mutex<VecDeque<Announce>>,
+}
+
+impl BlockSources {
+    // Record block seen by source, repeated announces are ignored
+    pub fn announce(&self, source: &str, height: u32, hash: &str) {
+        let mut blocks = self.blocks.lock().unwrap();
+        if let Some(block) = blocks.iter_mut().find(|block| block.hash == hash) {
+            if block.first_source != source && !block.lags.contains_key(source) {
+                let lag = block.first_at.elapsed();
+                block.lags.insert(source.to_owned(), lag);
+            }
+            return;
+        }
+
+        if blocks.len() == BLOCKS_MAX {
+            blocks.pop_front();
+        }
+        let first_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        blocks.push_back(Announce {
+            height,
+            hash: hash.to_owned(),
+            first_source: source.to_owned(),
+            first_at: Instant::now(),
+            first_time,
+            lags: BTreeMap::new(),
+        });
+    }
+
+    // Per block announces and per source summary, latest blocks first
+    pub fn report(&self) -> json::BlockSources {
+        let blocks = self.blocks.lock().unwrap();
+
+        let mut sources = BTreeMap::<String, json::BlockSourceStats>::new();
+        for block in blocks.iter() {
+            let first = sources.entry(block.first_source.clone()).or_default();
+            first.announced += 1;
+            first.first += 1;
+            for (source, lag) in block.lags.iter() {
+                let stats = sources.entry(source.clone()).or_default();
+                let lag = lag.as_millis() as u64;
+                stats.lag_avg_ms = (stats.lag_avg_ms * (stats.announced - stats.first) + lag)
+                    / (stats.announced - stats.first + 1);
+                stats.lag_max_ms = stats.lag_max_ms.max(lag);
+                stats.announced += 1;
+            }
+        }
+
+        json::BlockSources {
+            sources,
+            blocks: blocks
+                .iter()
+                .rev()
+                .map(|block| json::BlockAnnounce {
+                    height: block.height,
+                    hash: block.hash.clone(),
+                    first: block.first_source.clone(),
+                    time: block.first_time,
+                    lags_ms: block
+                        .lags
+                        .iter()
+                        .map(|(source, lag)| (source.clone(), lag.as_millis() as u64))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_source_and_lags() {
+        let sources = BlockSources::default();
+        sources.announce(SOURCE_POLL, 1, "a");
+        std::thread::sleep(Duration::from_millis(20));
+        sources.announce("esplora", 1, "a");
+        sources.announce("esplora", 1, "a");
+        sources.announce(SOURCE_POLL, 1, "a");
+        sources.announce("esplora", 2, "b");
+        sources.announce(SOURCE_POLL, 2, "b");
+
+        let report = sources.report();
+        let blocks = report
+            .blocks
+            .iter()
+            .map(|block| (block.height, block.first.as_str(), block.lags_ms.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, vec![(2, "esplora", 1), (1, SOURCE_POLL, 1)]);
+        assert!(report.blocks[1].lags_ms["esplora"] >= 20);
+
+        let poll = &report.sources[SOURCE_POLL];
+        assert_eq!((poll.announced, poll.first), (2, 1));
+        let esplora = &report.sources["esplora"];
+        assert_eq!((esplora.announced, esplora.first), (2, 1));
+        assert!(esplora.lag_max_ms >= 20);
+        assert_eq!(esplora.lag_avg_ms, report.blocks[1].lags_ms["esplora"]);
+    }
+
+    #[test]
+    fn latest_blocks() {
+        let sources = BlockSources::default();
+        for height in 0..BLOCKS_MAX as u32 + 5 {
+            sources.announce(SOURCE_POLL, height, &height.to_string());
+        }
+        let report = sources.report();
+        assert_eq!(report.blocks.len(), BLOCKS_MAX);
+        assert_eq!(report.blocks[0].height, BLOCKS_MAX as u32 + 4);
+        assert_eq!(report.blocks[BLOCKS_MAX - 1].height, 5);
+        assert_eq!(report.sources[SOURCE_POLL].announced, BLOCKS_MAX as u64);
+    }
+}
@@ -1,50 +1,262 @@
-use std::collections::{HashMap, LinkedList};
-use std::error::Error as StdError;
-use std::time::{Duration, SystemTime};
+use std::collections::{HashMap, HashSet, LinkedList, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock as StdRwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use log::info;
+use futures::future::{BoxFuture, FutureExt as _};
+use futures::stream::{self, StreamExt as _};
+use log::{error, info};
 use tokio::sync::{broadcast, RwLock};
-use tokio_tungstenite::tungstenite::protocol::Message;
 
-use super::bitcoind::json::{ResponseBlock, ResponseRawMempoolTransaction};
-use super::bitcoind::{Bitcoind, BitcoindError};
+use super::audit::{AuditLog, AuditRecord};
+use super::bitcoind::json::{
+    ResponseBlock, ResponseBlockTransaction, ResponseRawMempool, ResponseRawMempoolTransaction,
+};
+use super::bitcoind::{parse_block, wtxid, Bitcoind, BitcoindError, BitcoindResult};
+use super::confirmations::ConfirmationTimes;
+use super::egress::{EgressBudget, EgressBudgets};
 use super::error::{AppError, AppResult};
+use super::estimates::FeeEstimates;
+use super::events::{BlockEvent, BusMessage, Event, EventBus, TxEvent};
+use super::filters::{self, BlockFilters};
+use super::histogram::FeeHistogram;
+use super::interner::Interner;
 use super::json;
+use super::metrics::METRICS;
+use super::network;
+use super::peers;
+use super::pipeline::{Pipeline, PipelineError};
+use super::plugins::{PluginEvent, PluginHost, PluginStats};
+use super::projection::{self, Candidate, BLOCK_WEIGHT_MAX};
+use super::quotas::{Period, QuotaConfig, QuotaResult, Quotas};
+use super::redact_url;
+use super::rules::{FeeAlerts, RuleInput, Rules, RulesResult};
+use super::scheduler::{Job, JobResult, Jobs};
+use super::skew::ClockSkew;
+use super::sources::{BlockSources, SOURCE_POLL};
+use super::storage::{
+    self, Storage, StorageError, StorageResult, StoredBlock, StoredMempoolTx, META_MEMPOOL,
+    TREE_BLACKLIST, TREE_CHAIN, TREE_META,
+};
+use super::streaks::BlockStreaks;
+use super::tenants::{TenantConfig, TenantError, TenantResult, Tenants, TriggeredReaction};
+use super::timings::{BlockTimings, BlockTimingsStats};
 use crate::signals::ShutdownReceiver;
 
-const APP_BLOCKS_MINIMUM: usize = 6;
-const UPDATE_DELAY_MAX: Duration = Duration::from_millis(25);
-const UPDATE_DELAY_MIN: Duration = Duration::from_millis(5);
-const UPDATE_MEMPOOL_LOG_INTERVAL: Duration = Duration::from_secs(30);
+// Same limits as consensus rules: time should be greater than median of
+// previous 11 blocks and not more than 2 hours in future
+const BLOCK_TIME_FUTURE_MAX: u64 = 2 * 60 * 60;
+const BLOCK_TIME_MEDIAN_SPAN: usize = 11;
+const CATCH_UP_BLOCKS_MIN: u32 = 10;
+const CATCH_UP_FETCH_JOBS: usize = 8;
+// Limit of `rest/headers` in bitcoind (`MAX_REST_HEADERS_RESULTS`)
+const HEADERS_FIRST_BATCH_MAX: usize = 2000;
+const NEW_TXS_FETCH_JOBS: usize = 8;
+// Transactions in one batch request to bitcoind
+const NEW_TXS_BATCH_SIZE: usize = 100;
+const REORGS_HISTORY_MAX: usize = 100;
+// Resume from stored tip only if node is not too far ahead, otherwise start
+// from node tip as without stored chain
+const RESUME_BLOCKS_BEHIND_MAX: u32 = 2016;
+pub const RULES_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+// Check of resume in paused update loop
+const UPDATE_PAUSED_DELAY: Duration = Duration::from_millis(100);
+
+// Update loop timings: `poll` is interval between updates if chain is not
+// changed (update duration included, but not less than `poll_min` delay) and
+// interval of mempool refresh job
+#[derive(Debug, Clone, Copy)]
+pub struct UpdateIntervals {
+    pub poll: Duration,
+    pub poll_min: Duration,
+    pub mempool_log: Duration,
+}
+
+impl Default for UpdateIntervals {
+    fn default() -> Self {
+        UpdateIntervals {
+            poll: Duration::from_millis(25),
+            poll_min: Duration::from_millis(5),
+            mempool_log: Duration::from_secs(30),
+        }
+    }
+}
+
+// Recompile of alert rules, scheduled only with rules directory
+pub struct RulesReload;
+
+impl Job for RulesReload {
+    fn run<'a>(&'a mut self, state: &'a State) -> BoxFuture<'a, JobResult> {
+        async move { state.reload_rules().await.map_err(|err| err.to_string()) }.boxed()
+    }
+}
+
+// Refresh of mempool from node, skipped until chain is initialized and while
+// updates are paused
+pub struct MempoolRefresh;
+
+impl Job for MempoolRefresh {
+    fn run<'a>(&'a mut self, state: &'a State) -> BoxFuture<'a, JobResult> {
+        async move { state.refresh_mempool().await.map_err(|err| err.to_string()) }.boxed()
+    }
+}
 
 #[derive(Debug)]
 pub struct State {
-    bitcoind: Bitcoind,
+    // Replaced on endpoint switch, see `switch_bitcoind`
+    bitcoind: StdRwLock<Arc<Bitcoind>>,
+    // Network of node (main, test, regtest), set on start
+    chain: Mutex<Option<String>>,
+    // Height and time of first block of retarget window, fetched on demand
+    retarget_start: Mutex<Option<(u32, u32)>>,
+    // Number of tracked blocks, also maximum reorg depth handled without
+    // refetch of blocks below our chain; changed with `set_blocks_keep`
+    blocks_keep: AtomicUsize,
+    intervals: UpdateIntervals,
+    // Hashes of blocks below first known block taken from headers on
+    // initialization, so bodies can be fetched in parallel
+    headers_first: bool,
     blocks: RwLock<LinkedList<StateBlock>>,
+    // Reorg in progress: old tip and removed blocks, finished when we follow
+    // node chain again
+    reorg: Mutex<Option<(json::ReorgTip, u32)>>,
+    reorgs: Mutex<VecDeque<json::Reorg>>,
     mempool: RwLock<StateMempool>,
-    events: broadcast::Sender<Message>,
+    scripts: RwLock<StateScripts>,
+    spends: RwLock<StateSpends>,
+    blacklist: RwLock<StateBlacklist>,
+    // Computed on request, by block hash
+    filters: BlockFilters,
+    // API responds with 503 if enabled, update loop is not affected
+    maintenance: Mutex<json::Maintenance>,
+    // Unix time of update loop pause, tracked data is stale since
+    paused: Mutex<Option<u64>>,
+    // Held by update loop during update, so pause waits for it
+    // Held for read by chain update and mempool refresh, for write by pause
+    updating: RwLock<()>,
+    // Tip height and time of last check of node chain, for data age headers
+    last_update: Mutex<Option<(u32, Instant)>>,
+    tenants: RwLock<Tenants>,
+    quotas: Mutex<Quotas>,
+    egress: EgressBudgets,
+    audit: AuditLog,
+    plugins: Option<StatePlugins>,
+    rules: Option<RwLock<Rules>>,
+    fee_alerts: FeeAlerts,
+    // Satoshis, `large` events for transactions above
+    large_tx: Option<u64>,
+    block_streaks: BlockStreaks,
+    clock_skew: ClockSkew,
+    confirmation_times: ConfirmationTimes,
+    fee_estimates: FeeEstimates,
+    block_sources: BlockSources,
+    // Tips of `--bitcoind` nodes from last chain split check
+    node_tips: Mutex<Vec<json::NodeTip>>,
+    // Last projection of `projected-block` job
+    projected_block: Mutex<Option<Arc<json::ProjectedBlock>>>,
+    jobs: Arc<Jobs>,
+    block_timings: BlockTimingsStats,
+    events: Arc<EventBus>,
+    storage: Box<dyn Storage>,
 }
 
 impl State {
-    pub fn new(bitcoind: Bitcoind) -> Self {
-        State {
-            bitcoind,
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        bitcoind: Bitcoind,
+        events: EventBus,
+        storage: Box<dyn Storage>,
+        tenants: Vec<TenantConfig>,
+        quotas: Vec<QuotaConfig>,
+        egress_budgets: Vec<EgressBudget>,
+        plugins: Option<PluginHost>,
+        rules: Option<Rules>,
+        fee_alerts: FeeAlerts,
+        large_tx: Option<u64>,
+        block_streak: usize,
+        clock_skew_threshold: Duration,
+        blocks_keep: usize,
+        headers_first: bool,
+        intervals: UpdateIntervals,
+    ) -> AppResult<Self> {
+        let blacklist = StateBlacklist::load(storage.as_ref()).map_err(AppError::Storage)?;
+        let tenants = Tenants::load(tenants, storage.as_ref()).map_err(AppError::Storage)?;
+        let audit = AuditLog::load(storage.as_ref()).map_err(AppError::Storage)?;
+
+        Ok(State {
+            bitcoind: StdRwLock::new(Arc::new(bitcoind)),
+            chain: Mutex::new(None),
+            retarget_start: Mutex::new(None),
+            blocks_keep: AtomicUsize::new(blocks_keep),
+            intervals,
+            headers_first,
             blocks: RwLock::new(LinkedList::new()),
+            reorg: Mutex::new(None),
+            reorgs: Mutex::new(VecDeque::new()),
             mempool: RwLock::new(StateMempool {
                 transactions: HashMap::new(),
+                histogram: FeeHistogram::default(),
                 last_log: None,
                 added: 0,
                 removed: 0,
+                confirmed: None,
             }),
-            events: broadcast::channel(10_000).0,
-        }
+            scripts: RwLock::new(StateScripts::default()),
+            spends: RwLock::new(StateSpends::default()),
+            blacklist: RwLock::new(blacklist),
+            filters: BlockFilters::default(),
+            maintenance: Mutex::new(json::Maintenance::default()),
+            paused: Mutex::new(None),
+            updating: RwLock::new(()),
+            last_update: Mutex::new(None),
+            tenants: RwLock::new(tenants),
+            quotas: Mutex::new(Quotas::new(quotas)),
+            egress: EgressBudgets::new(egress_budgets),
+            audit,
+            plugins: plugins.map(StatePlugins::new),
+            rules: rules.map(RwLock::new),
+            fee_alerts,
+            large_tx,
+            block_streaks: BlockStreaks::new(block_streak),
+            clock_skew: ClockSkew::new(clock_skew_threshold),
+            confirmation_times: ConfirmationTimes::default(),
+            fee_estimates: FeeEstimates::default(),
+            block_sources: BlockSources::default(),
+            node_tips: Mutex::new(vec![]),
+            projected_block: Mutex::new(None),
+            jobs: Arc::new(Jobs::default()),
+            block_timings: BlockTimingsStats::default(),
+            events: Arc::new(events),
+            storage,
+        })
     }
 
     pub async fn run_update_loop(&self, mut shutdown: ShutdownReceiver) -> AppResult<()> {
-        {
-            let mut blocks = self.blocks.write().await;
-            self.init_blocks(&mut blocks, Some(&mut shutdown)).await?;
+        // Locked until chain initialized, so API requests wait for it instead
+        // of empty chain (WebSocket client resume after restart)
+        let mut blocks = self.blocks.write().await;
+
+        let chain = self.get_chain().await.map_err(AppError::Bitcoind)?;
+        *self.chain.lock().unwrap() = Some(chain);
+
+        // Continue from tip of previous run, so blocks mined meanwhile are
+        // processed as new. Mempool snapshot is useful only in this case.
+        let resume = self.find_resume_hash().await?;
+        let snapshot = self.take_mempool_snapshot().map_err(AppError::Storage)?;
+        if let (Some(hash), Some(snapshot)) = (&resume, snapshot) {
+            info!(
+                "Resume from block {}, mempool snapshot: {} transactions",
+                hash,
+                snapshot.len()
+            );
+            self.restore_mempool(snapshot).await;
         }
+        self.clear_stored_chain().map_err(AppError::Storage)?;
+
+        self.init_blocks(&mut blocks, Some(&mut shutdown), resume)
+            .await?;
+        self.set_last_update(&blocks);
+        drop(blocks);
 
         loop {
             // Should we stop loop check
@@ -52,23 +264,35 @@ impl State {
                 break;
             }
 
+            // Nothing fetched from bitcoind until resumed
+            let updating = self.updating.read().await;
+            if self.get_stale_since().is_some() {
+                drop(updating);
+                tokio::select! {
+                    _ = tokio::time::delay_for(UPDATE_PAUSED_DELAY) => continue,
+                    _ = shutdown.recv() => break,
+                }
+            }
+
             // Save current timestamp for timeout after check
             let ts = SystemTime::now();
 
-            // Update our chain
-            let blocks_modified = self.update_blocks().await?;
-            if blocks_modified == UpdateBlocksModified::Yes {
-                continue;
+            // Update our chain until synced, mempool is refreshed meanwhile by
+            // `MempoolRefresh` job
+            loop {
+                let blocks_modified = self.update_blocks().await?;
+                self.set_last_update(&*self.blocks.read().await);
+                if blocks_modified == UpdateBlocksModified::No {
+                    break;
+                }
             }
-
-            // Update mempool
-            self.update_mempool().await?;
+            drop(updating);
 
             // Some delay if blocks chain was not modified
             let elapsed = ts.elapsed().unwrap();
-            let sleep_duration = match UPDATE_DELAY_MAX.checked_sub(elapsed) {
-                Some(delay) => std::cmp::max(delay, UPDATE_DELAY_MIN),
-                None => UPDATE_DELAY_MIN,
+            let sleep_duration = match self.intervals.poll.checked_sub(elapsed) {
+                Some(delay) => std::cmp::max(delay, self.intervals.poll_min),
+                None => self.intervals.poll_min,
             };
 
             // Exit earlier if shutdown signal received
@@ -78,38 +302,376 @@ impl State {
             }
         }
 
+        self.save_mempool_snapshot()
+            .await
+            .map_err(AppError::Storage)
+    }
+
+    fn bitcoind(&self) -> Arc<Bitcoind> {
+        self.bitcoind.read().unwrap().clone()
+    }
+
+    pub fn get_blocks_keep(&self) -> usize {
+        self.blocks_keep.load(Ordering::Relaxed)
+    }
+
+    pub async fn get_blocks_tracked(&self) -> usize {
+        self.blocks.read().await.len()
+    }
+
+    // Height and hash of tracked blocks, from oldest
+    pub async fn get_window(&self) -> Vec<(u32, String)> {
+        let blocks = self.blocks.read().await;
+        blocks
+            .iter()
+            .map(|block| (block.height, block.hash.clone()))
+            .collect()
+    }
+
+    // Sizes of indexes with sizes expected from tracked blocks and mempool,
+    // index entries left after block or transaction removal are leaks
+    pub async fn get_accounting(&self) -> StateAccounting {
+        let blocks = self.blocks.read().await;
+        let mut accounting = StateAccounting {
+            blocks: blocks.len(),
+            blocks_keep: self.get_blocks_keep(),
+            reorgs: self.reorgs.lock().unwrap().len(),
+            reorgs_max: REORGS_HISTORY_MAX,
+            ..StateAccounting::default()
+        };
+        for block in blocks.iter() {
+            accounting.block_scripts += block.scripts.len();
+            accounting.block_spent += block.spent.len();
+        }
+
+        let scripts = self.scripts.read().await;
+        accounting.scripts = scripts.transactions.values().map(Vec::len).sum();
+        accounting.interned = scripts.interner.len();
+        drop(scripts);
+
+        let mempool = self.mempool.read().await;
+        let spends = self.spends.read().await;
+        accounting.spends_blocks = spends.blocks.len();
+        accounting.mempool = mempool.transactions.len();
+        accounting.spends_mempool_txs = spends.mempool_txs.len();
+        accounting
+    }
+
+    // Change number of tracked blocks without restart: extra blocks removed
+    // from front of our chain, missed blocks fetched before response
+    pub async fn set_blocks_keep(&self, keep: usize) -> AppResult<json::BlocksKeep> {
+        let mut blocks = self.blocks.write().await;
+        let previous = self.blocks_keep.swap(keep, Ordering::Relaxed);
+        info!("Change tracked blocks from {} to {}", previous, keep);
+
+        if blocks.len() > keep {
+            let mut scripts = self.scripts.write().await;
+            let mut spends = self.spends.write().await;
+            let side = BlocksListSide::Front;
+            self.remove_blocks(&mut blocks, &mut scripts, &mut spends, side, keep);
+        } else {
+            self.init_blocks(&mut blocks, None, None).await?;
+        }
+
+        Ok(json::BlocksKeep {
+            blocks: keep,
+            tracked: blocks.len(),
+        })
+    }
+
+    pub fn bitcoind_host(&self) -> Option<(String, u16)> {
+        self.bitcoind().host()
+    }
+
+    pub fn bitcoind_name(&self) -> String {
+        self.bitcoind().name()
+    }
+
+    // Drop pooled connections to bitcoind, replaced under lock so concurrent
+    // switch to another node is not reverted
+    pub fn recycle_bitcoind(&self) -> AppResult<()> {
+        let mut bitcoind = self.bitcoind.write().unwrap();
+        *bitcoind = Arc::new(bitcoind.recycle().map_err(AppError::Bitcoind)?);
         Ok(())
     }
 
+    // Tracked blocks after block last received by WebSocket client. Block
+    // removed while client was disconnected is replaced by blocks from same
+    // height, `None` if block is not tracked anymore
+    pub async fn get_blocks_since(&self, last: &json::WsResumeBlock) -> Option<Vec<(u32, String)>> {
+        let blocks = self.blocks.read().await;
+        let start = match blocks.iter().position(|block| block.hash == last.hash) {
+            Some(index) => blocks.iter().nth(index).unwrap().height + 1,
+            None => match (last.height, blocks.front()) {
+                (Some(height), Some(front)) if height >= front.height => height,
+                _ => return None,
+            },
+        };
+        let since = blocks.iter().filter(|block| block.height >= start);
+        Some(
+            since
+                .map(|block| (block.height, block.hash.clone()))
+                .collect(),
+        )
+    }
+
+    // Switch to another node without restart. Node should be on same chain
+    // and not behind our tip, blocks which hashes differ from new node are
+    // removed from our chain and fetched again by update loop.
+    pub async fn switch_bitcoind(&self, url: &str) -> AppResult<json::BitcoindSwitch> {
+        let chain = self.chain.lock().unwrap().clone();
+        let chain = chain.ok_or(AppError::NotReady)?;
+        let bitcoind = self.bitcoind().switch_to(url);
+        let bitcoind = bitcoind.map_err(AppError::Bitcoind)?;
+        bitcoind.validate().await.map_err(AppError::Bitcoind)?;
+        let info = bitcoind.getblockchaininfo().await;
+        let info = info.map_err(AppError::Bitcoind)?;
+
+        if info.chain != chain {
+            let reason = format!("on chain {} instead of {}", info.chain, chain);
+            return Err(AppError::BitcoindSwitch(reason));
+        }
+
+        // Blocks lock held until switch done, so update loop do not mix nodes
+        let mut blocks = self.blocks.write().await;
+        let tip_height = blocks.back().ok_or(AppError::NotReady)?.height;
+        if info.blocks < tip_height {
+            let reason = format!("behind our tip ({} < {})", info.blocks, tip_height);
+            return Err(AppError::BitcoindSwitch(reason));
+        }
+
+        // Blocks from first block with different hash up to tip
+        let heights = blocks.iter().map(|block| block.height).collect::<Vec<_>>();
+        let hashes = bitcoind.getblockhashes(&heights).await;
+        let hashes = hashes.map_err(AppError::Bitcoind)?;
+        let diverged = blocks
+            .iter()
+            .zip(hashes.iter())
+            .position(|(block, hash)| hash.as_ref() != Some(&block.hash));
+        let resynced = diverged.map(|index| blocks.len() - index).unwrap_or(0);
+
+        *self.bitcoind.write().unwrap() = Arc::new(bitcoind);
+        info!(
+            "Switch bitcoind to {}, resync {} blocks",
+            redact_url(url),
+            resynced
+        );
+
+        if resynced > 0 {
+            let mut scripts = self.scripts.write().await;
+            let mut spends = self.spends.write().await;
+            for _ in 0..resynced {
+                let block = blocks.pop_back().unwrap();
+                block.release(&mut scripts, &mut spends);
+                info!("Remove block {}: {}", block.height, &block.hash);
+                self.unstore_block(block.height);
+                self.update_tip_time(&blocks);
+                METRICS.blocks_removed.inc();
+                let event = Event::Block(BlockEvent::Removed, block.height, block.hash);
+                self.events.publish(event);
+            }
+            drop(spends);
+            drop(scripts);
+            self.init_blocks(&mut blocks, None, None).await?;
+        }
+
+        let tip = blocks.back().ok_or(AppError::NotReady)?;
+        Ok(json::BitcoindSwitch {
+            chain: info.chain,
+            tip: json::Tip {
+                height: tip.height,
+                hash: tip.hash.clone(),
+                transactions: tip.transactions.len(),
+            },
+            resynced,
+        })
+    }
+
+    // Fetch and decode block, with durations of both stages
+    async fn fetch_block(
+        &self,
+        hash: &str,
+    ) -> BitcoindResult<Option<(ResponseBlock, BlockTimings)>> {
+        let started = Instant::now();
+        let body = match self.bitcoind().getblockbodybyhash(hash).await? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        let fetched = Instant::now();
+        let block = parse_block(hash, &body)?;
+
+        let timings = BlockTimings {
+            fetch: Some(fetched - started),
+            decode: Some(fetched.elapsed()),
+            ..BlockTimings::default()
+        };
+        Ok(Some((block, timings)))
+    }
+
     // Add block to our chain
     async fn add_block(
         &self,
         blocks: &mut LinkedList<StateBlock>,
-        block: StateBlock,
+        block: ResponseBlock,
         side: BlocksListSide,
+        mut timings: BlockTimings,
     ) {
-        let block = match side {
+        let started = Instant::now();
+        let stored = StoredBlock::from(&block);
+        let block_time = block.time;
+
+        // Only new blocks are interesting for watchers
+        let (watched, reactions, plugins_input, new_txs, streak) = match side {
             BlocksListSide::Front => {
-                self.remove_blocks(blocks, BlocksListSide::Back);
-                blocks.push_front(block);
-                blocks.front().unwrap()
+                self.block_streaks.push_old(&block);
+                (vec![], vec![], None, vec![], None)
             }
             BlocksListSide::Back => {
-                self.remove_blocks(blocks, BlocksListSide::Front);
-                blocks.push_back(block);
-                blocks.back().unwrap()
+                self.block_sources
+                    .announce(SOURCE_POLL, block.height, &block.hash);
+                let streak = self.block_streaks.push_tip(&block);
+                self.clock_skew.push_block(block.time);
+                let mut tenants = self.tenants.write().await;
+                let watched = tenants
+                    .match_block(self.storage.as_ref(), &block)
+                    .unwrap_or_else(|err| {
+                        error!("Failed to save watch history: {}", err);
+                        vec![]
+                    });
+                let prev_time = Self::tip_median_time(blocks);
+                if let Err(err) =
+                    tenants.confirm_timelocks(self.storage.as_ref(), &block, prev_time)
+                {
+                    error!("Failed to save watched timelocks: {}", err);
+                }
+                // Spends which were not seen in mempool
+                let reactions = if tenants.has_reactions() {
+                    let spends = Self::tx_spends(block.transactions.iter());
+                    tenants
+                        .match_spends(self.storage.as_ref(), &spends)
+                        .unwrap_or_else(|err| {
+                            error!("Failed to save watch reactions: {}", err);
+                            vec![]
+                        })
+                } else {
+                    vec![]
+                };
+                drop(tenants);
+                let plugins_input = self
+                    .plugins
+                    .as_ref()
+                    .map(|_| serde_json::to_vec(&block).unwrap());
+                // Fee is reported only if bitcoind has undo data for block
+                let new_txs = block
+                    .transactions
+                    .iter()
+                    .skip(1)
+                    .map(|tx| {
+                        let vsize = tx.vsize.unwrap_or(tx.size);
+                        let fee = tx.fee.map(|fee| (fee * 1e8).round() as u64);
+                        let outputs = tx.vout.iter().map(|output| output.value).sum::<f64>();
+                        let outputs = (outputs * 1e8).round() as u64;
+                        (tx.txid.clone(), vsize, fee, outputs)
+                    })
+                    .collect::<Vec<_>>();
+                (watched, reactions, plugins_input, new_txs, streak)
+            }
+        };
+
+        let block = {
+            // Room for new block
+            let keep = self.get_blocks_keep();
+            let mut scripts = self.scripts.write().await;
+            let mut spends = self.spends.write().await;
+            let block = StateBlock::new(block, &mut scripts, &mut spends);
+            match side {
+                BlocksListSide::Front => {
+                    let side = BlocksListSide::Back;
+                    self.remove_blocks(blocks, &mut scripts, &mut spends, side, keep - 1);
+                    blocks.push_front(block);
+                    blocks.front().unwrap()
+                }
+                BlocksListSide::Back => {
+                    let side = BlocksListSide::Front;
+                    self.remove_blocks(blocks, &mut scripts, &mut spends, side, keep - 1);
+                    blocks.push_back(block);
+                    blocks.back().unwrap()
+                }
             }
         };
+        self.store_block(&stored);
+        self.update_tip_time(blocks);
+        let expired = match side {
+            BlocksListSide::Front => vec![],
+            BlocksListSide::Back => self.check_timelocks(blocks).await,
+        };
+        let indexed = Instant::now();
+        timings.index = indexed - started;
 
         let mut mempool = self.mempool.write().await;
+        let mut spends = self.spends.write().await;
+        // Mempool transactions were checked when added
+        let mut tx_events = vec![];
+        for (txid, vsize, fee, outputs) in new_txs {
+            if mempool.transactions.contains_key(&txid) {
+                continue;
+            }
+            if let Some(fee) = fee {
+                for alert in self.fee_alerts.check(&txid, vsize, fee) {
+                    tx_events.push(Event::Alert(alert, txid.clone()));
+                }
+            }
+            // Without fee outputs total is lower bound of inputs total
+            let value = outputs + fee.unwrap_or(0);
+            tx_events.extend(self.check_large_tx(&txid, value, Some(block.height)));
+        }
+        if let Some(txids) = mempool.confirmed.as_mut() {
+            txids.extend(block.transactions.iter().cloned());
+        }
         let mut confirmed: usize = 0;
         for hash in block.transactions.iter() {
-            if mempool.transactions.contains_key(hash) {
+            if let Some(tx) = mempool.remove(hash) {
                 confirmed += 1;
-                mempool.transactions.remove(hash);
-                self.send_tx_event(EventsMempoolTx::Confirmed, &hash);
+                if tx.time > 0 {
+                    let block_time = u64::from(block_time);
+                    self.confirmation_times
+                        .record(tx.size, tx.fee, tx.time, block_time);
+                }
+                spends.remove_mempool(hash);
+                self.send_tx_event(TxEvent::Confirmed, hash);
             }
         }
+        let reconciled = Instant::now();
+        timings.mempool = reconciled - indexed;
+
+        if side == BlocksListSide::Back {
+            METRICS.blocks_added.inc();
+            let event = Event::Block(BlockEvent::Added, block.height, block.hash.clone());
+            self.events.publish(event);
+        }
+        if let Some(streak) = streak {
+            info!(
+                "Streak of {} {} blocks: {}-{}",
+                streak.length, streak.kind, streak.start_height, streak.end_height
+            );
+            self.events.publish(Event::Streak(streak));
+        }
+        for (tenant, txid) in watched {
+            self.events.publish(Event::Watch(tenant, txid));
+        }
+        for (tenant, txid, vout) in expired {
+            self.events.publish(Event::Timelock(tenant, txid, vout));
+        }
+        for event in tx_events {
+            self.events.publish(event);
+        }
+        if let (Some(plugins), Some(input)) = (&self.plugins, plugins_input) {
+            let events = plugins.run(move |host| host.on_block(&input)).await;
+            self.publish_plugin_events(events);
+        }
+        timings.events = reconciled.elapsed();
+
+        drop(spends);
 
         info!(
             "Add block {}: {} (mempool size: {}, confirmed: {})",
@@ -119,54 +681,295 @@ impl State {
             confirmed,
         );
 
+        info!("Block {} timings: {}", block.height, timings);
+        self.block_timings.record(&timings);
+
         mempool.last_log = Some(SystemTime::now());
         mempool.added = 0;
         mempool.removed = 0;
+        drop(mempool);
+
+        self.broadcast_reactions(reactions).await;
+    }
+
+    // Spent outputs with spending txid
+    fn tx_spends<'a>(
+        txs: impl Iterator<Item = &'a ResponseBlockTransaction>,
+    ) -> Vec<(Outpoint, String)> {
+        txs.flat_map(|tx| {
+            StateSpends::spent_outputs(tx)
+                .into_iter()
+                .map(move |(_, outpoint)| (outpoint, tx.txid.clone()))
+        })
+        .collect()
+    }
+
+    async fn react_spends(&self, spends: Vec<(Outpoint, String)>) {
+        let mut tenants = self.tenants.write().await;
+        if !tenants.has_reactions() {
+            return;
+        }
+        let reactions = tenants
+            .match_spends(self.storage.as_ref(), &spends)
+            .unwrap_or_else(|err| {
+                error!("Failed to save watch reactions: {}", err);
+                vec![]
+            });
+        drop(tenants);
+        self.broadcast_reactions(reactions).await;
+    }
+
+    // Pre-signed transactions of triggered reactions relayed to bitcoind, in
+    // dry run only logged. Result is written to audit log in both cases.
+    async fn broadcast_reactions(&self, reactions: Vec<TriggeredReaction>) {
+        for reaction in reactions {
+            let (txid, vout) = reaction.outpoint;
+            let actor = format!("tenant:{}@watchtower", reaction.tenant);
+            let target = format!("outpoint:{}:{}", txid, vout);
+            if reaction.dry_run {
+                let tx = reaction.tx;
+                info!("Reaction to spend of {}:{} (dry run): {}", txid, vout, tx);
+                self.audit(&actor, "watch.reaction.dry-run", &target, "ok");
+                continue;
+            }
+
+            match self.bitcoind().sendrawtransaction(&reaction.tx).await {
+                Ok(reaction_txid) => {
+                    info!("Reaction to spend of {}:{}: {}", txid, vout, reaction_txid);
+                    let result = format!("ok: {}", reaction_txid);
+                    self.audit(&actor, "watch.reaction.broadcast", &target, &result);
+                }
+                Err(err) => {
+                    error!("Reaction to spend of {}:{} failed: {}", txid, vout, err);
+                    let result = format!("error: {}", err);
+                    self.audit(&actor, "watch.reaction.broadcast", &target, &result);
+                }
+            }
+        }
+    }
+
+    // Timelocks which became spendable on top of our tip
+    async fn check_timelocks(&self, blocks: &LinkedList<StateBlock>) -> Vec<(String, String, u32)> {
+        let height = match blocks.back() {
+            Some(block) => block.height,
+            None => return vec![],
+        };
+        let time = Self::tip_median_time(blocks);
+        let mut tenants = self.tenants.write().await;
+        tenants
+            .check_timelocks(self.storage.as_ref(), height, time)
+            .unwrap_or_else(|err| {
+                error!("Failed to save watched timelocks: {}", err);
+                vec![]
+            })
     }
 
-    fn remove_blocks(&self, blocks: &mut LinkedList<StateBlock>, side: BlocksListSide) {
-        while blocks.len() >= APP_BLOCKS_MINIMUM {
+    // Remove blocks from `side` until `keep` blocks left
+    fn remove_blocks(
+        &self,
+        blocks: &mut LinkedList<StateBlock>,
+        scripts: &mut StateScripts,
+        spends: &mut StateSpends,
+        side: BlocksListSide,
+        keep: usize,
+    ) {
+        while blocks.len() > keep {
             let block = match side {
                 BlocksListSide::Front => blocks.pop_front().unwrap(),
                 BlocksListSide::Back => blocks.pop_back().unwrap(),
             };
+            block.release(scripts, spends);
+            self.unstore_block(block.height);
             info!("Remove block {}: {}", block.height, &block.hash);
         }
+        if side == BlocksListSide::Back {
+            self.update_tip_time(blocks);
+        }
+    }
+
+    // Chain time of events follows our tip
+    fn update_tip_time(&self, blocks: &LinkedList<StateBlock>) {
+        let time = blocks
+            .back()
+            .map(|block| (block.time, Self::tip_median_time(blocks)));
+        self.events.set_tip_time(time);
     }
 
     // Pop best block from our chain
     async fn remove_best_block(&self, blocks: &mut LinkedList<StateBlock>) -> AppResult<()> {
-        blocks.pop_back();
-        self.init_blocks(blocks, None).await
+        if let Some(block) = blocks.pop_back() {
+            let mut scripts = self.scripts.write().await;
+            block.release(&mut scripts, &mut *self.spends.write().await);
+            self.unstore_block(block.height);
+            self.update_tip_time(blocks);
+            self.block_streaks.pop_tip(block.height);
+            let mut tenants = self.tenants.write().await;
+            if let Err(err) = tenants.disconnect_block(self.storage.as_ref(), block.height) {
+                error!("Failed to remove watch history: {}", err);
+            }
+            drop(tenants);
+            for (tenant, txid, vout) in self.check_timelocks(blocks).await {
+                self.events.publish(Event::Timelock(tenant, txid, vout));
+            }
+            METRICS.blocks_removed.inc();
+            {
+                let mut reorg = self.reorg.lock().unwrap();
+                let (_, depth) = reorg.get_or_insert_with(|| {
+                    let tip = json::ReorgTip {
+                        height: block.height,
+                        hash: block.hash.clone(),
+                    };
+                    (tip, 0)
+                });
+                *depth += 1;
+            }
+            let event = Event::Block(BlockEvent::Removed, block.height, block.hash);
+            self.events.publish(event);
+        }
+        self.init_blocks(blocks, None, None).await
+    }
+
+    // Publish reorg in progress, if any, with node tip as new tip
+    fn finish_reorg(&self, height: u32, hash: &str) {
+        let (old_tip, depth) = match self.reorg.lock().unwrap().take() {
+            Some(reorg) => reorg,
+            None => return,
+        };
+        let reorg = json::Reorg {
+            depth,
+            old_tip,
+            new_tip: json::ReorgTip {
+                height,
+                hash: hash.to_owned(),
+            },
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+        };
+        info!(
+            "Reorg depth {}: {} -> {}",
+            depth, &reorg.old_tip.hash, &reorg.new_tip.hash
+        );
+
+        let mut reorgs = self.reorgs.lock().unwrap();
+        if reorgs.len() == REORGS_HISTORY_MAX {
+            reorgs.pop_back();
+        }
+        reorgs.push_front(reorg.clone());
+        drop(reorgs);
+        self.events.publish(Event::Reorg(reorg));
+    }
+
+    pub fn get_reorgs(&self) -> json::Reorgs {
+        let reorgs = self.reorgs.lock().unwrap();
+        json::Reorgs {
+            reorgs: reorgs.iter().cloned().collect(),
+        }
+    }
+
+    // Last stored block which is still in node chain, only blocks of our
+    // window are stored, so deeper reorg while we were down is not resumed
+    async fn find_resume_hash(&self) -> AppResult<Option<String>> {
+        let stored = self.storage.scan(TREE_CHAIN).map_err(AppError::Storage)?;
+        if stored.is_empty() {
+            return Ok(None);
+        }
+
+        let mut blocks = Vec::with_capacity(stored.len());
+        for (_, value) in stored.iter().rev() {
+            let block = serde_json::from_slice::<StoredBlock>(value);
+            blocks.push(block.map_err(|err| AppError::Storage(StorageError::Serde(err)))?);
+        }
+
+        let info = self.bitcoind().getblockchaininfo().await;
+        let info = info.map_err(AppError::Bitcoind)?;
+        if info.blocks > blocks[0].height + RESUME_BLOCKS_BEHIND_MAX {
+            info!(
+                "Stored tip {} is too far behind node tip {}, not resumed",
+                blocks[0].height, info.blocks
+            );
+            return Ok(None);
+        }
+
+        let heights = blocks.iter().map(|block| block.height).collect::<Vec<_>>();
+        let hashes = self.bitcoind().getblockhashes(&heights).await;
+        let hashes = hashes.map_err(AppError::Bitcoind)?;
+        for (block, hash) in blocks.into_iter().zip(hashes) {
+            if hash.as_ref() == Some(&block.hash) {
+                return Ok(Some(block.hash));
+            }
+        }
+
+        info!("Stored blocks are not in node chain, not resumed");
+        Ok(None)
+    }
+
+    // Storage errors do not stop update loop, chain only mirrored for resume
+    fn store_block(&self, block: &StoredBlock) {
+        let key = block.height.to_be_bytes();
+        if let Err(err) = storage::insert_json(self.storage.as_ref(), TREE_CHAIN, &key, block) {
+            error!("Failed to store block {}: {}", block.height, err);
+        }
+    }
+
+    fn unstore_block(&self, height: u32) {
+        if let Err(err) = self.storage.remove(TREE_CHAIN, &height.to_be_bytes()) {
+            error!("Failed to remove stored block {}: {}", height, err);
+        }
+    }
+
+    fn clear_stored_chain(&self) -> StorageResult<()> {
+        for (key, _) in self.storage.scan(TREE_CHAIN)? {
+            self.storage.remove(TREE_CHAIN, &key)?;
+        }
+        Ok(())
     }
 
-    // Initialize our chain
+    // Initialize our chain, from `tip` or node tip if chain is empty
     async fn init_blocks(
         &self,
         blocks: &mut LinkedList<StateBlock>,
         mut shutdown: Option<&mut ShutdownReceiver>,
+        mut tip: Option<String>,
     ) -> AppResult<()> {
-        // Keep at least 6 blocks in chain
-        while blocks.len() < APP_BLOCKS_MINIMUM {
+        // Blocks below first known block in height order, from headers
+        let mut prefetched = vec![];
+
+        // Keep at least `--blocks-keep` blocks in chain
+        while blocks.len() < self.get_blocks_keep() {
             // Out from loop if we received shutdown signal
             if shutdown.is_some() && shutdown.as_mut().unwrap().is_recv() {
                 break;
             }
 
-            // Get prevhash from first known block or just get tip
+            // Get prevhash from first known block or just get tip, node tip
+            // used if requested tip is not found
             let hash = if let Some(block) = blocks.front() {
                 match block.prevhash {
                     None => return Err(AppError::NotEnoughBlocks),
                     Some(ref hash) => hash.clone(),
                 }
+            } else if let Some(hash) = tip.take() {
+                hash
             } else {
-                let info = self.bitcoind.getblockchaininfo().await;
+                let info = self.bitcoind().getblockchaininfo().await;
                 info.map_err(AppError::Bitcoind)?.bestblockhash
             };
 
-            // Try fetch block
-            let block_fut = self.bitcoind.getblockbyhash(&hash);
-            let block = block_fut.await.map_err(AppError::Bitcoind)?;
+            if self.headers_first && prefetched.is_empty() {
+                if let Some(front) = blocks.front() {
+                    let count = self.get_blocks_keep() - blocks.len();
+                    let fetched = self.fetch_blocks_before(front, count).await;
+                    prefetched = fetched.map_err(AppError::Bitcoind)?;
+                }
+            }
+
+            // Try fetch block, prefetched block is checked as any other
+            let block = match prefetched.pop() {
+                Some(block) => Some(block),
+                None => self.fetch_block(&hash).await.map_err(AppError::Bitcoind)?,
+            };
 
             // If block not found, try again if there is no blocks, otherwise blockchain corrupted
             if block.is_none() {
@@ -178,7 +981,7 @@ impl State {
             };
 
             // Check that chain is valid
-            let block = StateBlock::from(block.unwrap());
+            let (block, timings) = block.unwrap();
             if let Some(front) = blocks.front() {
                 if block.height + 1 != front.height {
                     return Err(AppError::InvalidBlockchain);
@@ -188,11 +991,68 @@ impl State {
                 }
             }
 
+            // Median time can be checked only for later blocks, see `validate_blocks`
+            Self::check_block_time_future(&block.hash, block.time)?;
+
             // Add block
-            self.add_block(blocks, block, BlocksListSide::Front).await;
+            self.add_block(blocks, block, BlocksListSide::Front, timings)
+                .await;
         }
 
-        Ok(())
+        Self::validate_blocks(blocks)
+    }
+
+    // Up to `count` blocks below `front` in height order: hashes from one
+    // headers request, bodies fetched in parallel. Empty if chain changed, so
+    // blocks are fetched one by one
+    async fn fetch_blocks_before(
+        &self,
+        front: &StateBlock,
+        count: usize,
+    ) -> BitcoindResult<Vec<(ResponseBlock, BlockTimings)>> {
+        let count = count
+            .min(HEADERS_FIRST_BATCH_MAX)
+            .min(front.height as usize);
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        let bitcoind = self.bitcoind();
+        let start = front.height - count as u32;
+        let hash = match bitcoind.getblockhash(start).await? {
+            Some(hash) => hash,
+            None => return Ok(vec![]),
+        };
+        let headers = match bitcoind.getheaders(count, &hash).await? {
+            Some(headers) => headers,
+            None => return Ok(vec![]),
+        };
+
+        // Headers should be linked with each other and with our first block
+        let linked = headers.len() == count
+            && headers[0].0 == hash
+            && headers.windows(2).all(|pair| pair[1].1 == pair[0].0)
+            && front.prevhash.as_ref() == headers.last().map(|(hash, _)| hash);
+        if !linked {
+            info!("Headers below block {} changed", front.height);
+            return Ok(vec![]);
+        }
+
+        info!("Fetch blocks {}..{} by headers", start, front.height - 1);
+        let hashes = headers.into_iter().map(|(hash, _)| hash);
+        let fetched = stream::iter(hashes)
+            .map(|hash| async move { self.fetch_block(&hash).await })
+            .buffered(CATCH_UP_FETCH_JOBS)
+            .collect::<Vec<_>>()
+            .await;
+        let mut blocks = Vec::with_capacity(fetched.len());
+        for block in fetched {
+            match block? {
+                Some(block) => blocks.push(block),
+                None => return Ok(vec![]),
+            }
+        }
+        Ok(blocks)
     }
 
     // Update our chain, return `true` if need call update again
@@ -201,14 +1061,22 @@ impl State {
         let mut last = self.blocks.read().await.back().unwrap().to_owned();
 
         // Get bitcoind info
-        let info_fut = self.bitcoind.getblockchaininfo();
-        let info = info_fut.await.map_err(AppError::Bitcoind)?;
+        let info = self.bitcoind().getblockchaininfo().await;
+        let info = info.map_err(AppError::Bitcoind)?;
 
         // Best hash did not changed, return
         if info.bestblockhash == last.hash {
+            self.finish_reorg(info.blocks, &info.bestblockhash);
             return Ok(UpdateBlocksModified::No);
         }
 
+        // We are far behind the node, fetch missed blocks in bulk
+        if info.blocks > last.height + CATCH_UP_BLOCKS_MIN {
+            self.finish_reorg(info.blocks, &info.bestblockhash);
+            self.catch_up(last.height + 1, info.blocks).await?;
+            return Ok(UpdateBlocksModified::Yes);
+        }
+
         // Remove blocks in our chain on reorg
         while last.height >= info.blocks {
             let mut blocks = self.blocks.write().await;
@@ -217,20 +1085,29 @@ impl State {
         }
 
         // Add maximum 1 block
-        let block_fut = self.bitcoind.getblockbyheight(last.height + 1);
-        if let Some(block) = block_fut.await.map_err(AppError::Bitcoind)? {
-            let block = StateBlock::from(block);
+        let block = match self.bitcoind().getblockhash(last.height + 1).await {
+            Ok(Some(hash)) => self.fetch_block(&hash).await,
+            Ok(None) => Ok(None),
+            Err(error) => Err(error),
+        };
+        if let Some((block, timings)) = block.map_err(AppError::Bitcoind)? {
+            // Chain changed between requests
+            if block.height != last.height + 1 {
+                return Err(AppError::Bitcoind(BitcoindError::ResultMismatch));
+            }
 
             // If next block do not have previous blockhash, something wrong with blockchain
-            if block.prevhash.is_none() {
+            if block.previousblockhash.is_none() {
                 return Err(AppError::InvalidBlockchain);
             }
 
             // If previoush hash match to our best hash in new block, add it
             // Otherwise remove our best block
             let mut blocks = self.blocks.write().await;
-            if block.prevhash.as_ref().unwrap() == &last.hash {
-                self.add_block(&mut blocks, block, BlocksListSide::Back)
+            if block.previousblockhash.as_ref().unwrap() == &last.hash {
+                Self::check_block_time(&blocks, &block.hash, block.time)?;
+                self.finish_reorg(info.blocks, &info.bestblockhash);
+                self.add_block(&mut blocks, block, BlocksListSide::Back, timings)
                     .await;
             } else {
                 self.remove_best_block(&mut blocks).await?;
@@ -241,135 +1118,1621 @@ impl State {
         Ok(UpdateBlocksModified::Yes)
     }
 
-    async fn update_mempool(&self) -> AppResult<()> {
-        let mempool_new_fut = self.bitcoind.getrawmempool();
-        let mempool_new = mempool_new_fut.await.map_err(AppError::Bitcoind)?;
+    // Add blocks in range with parallel fetching, regular update continue if chain changed
+    async fn catch_up(&self, from: u32, to: u32) -> AppResult<()> {
+        info!("Catch up blocks {}..{}", from, to);
 
-        let mut mempool = self.mempool.write().await;
-        let hashes: Vec<String> = mempool
-            .transactions
-            .iter()
-            .filter(|x| !mempool_new.contains_key(x.0))
-            .map(|x| x.0.clone())
-            .collect();
-        mempool.removed += hashes.len();
-        for hash in hashes {
-            mempool.transactions.remove(&hash);
-            self.send_tx_event(EventsMempoolTx::Removed, &hash);
-        }
-
-        mempool.added += mempool_new.len() - mempool.transactions.len();
-        for (hash, data) in mempool_new.into_iter() {
-            mempool.transactions.entry(hash.clone()).or_insert_with(|| {
-                self.send_tx_event(EventsMempoolTx::Added, &hash);
-                data.into()
-            });
-        }
+        let bitcoind = self.bitcoind();
+        let pipeline = Pipeline::new(CATCH_UP_FETCH_JOBS, Pipeline::default_analyze_jobs());
+        let mut stream = pipeline.blocks(&bitcoind, from..=to, |block| block);
+        while let Some(block) = stream.next().await {
+            let block = match block {
+                Ok(block) => block,
+                Err(PipelineError::BlockNotFound(_))
+                | Err(PipelineError::Bitcoind(BitcoindError::ResultMismatch)) => {
+                    info!("Catch up interrupted: chain changed");
+                    return Ok(());
+                }
+                Err(error) => return Err(AppError::Pipeline(error)),
+            };
 
-        if mempool.last_log.is_none()
-            || mempool.last_log.as_ref().unwrap().elapsed().unwrap() > UPDATE_MEMPOOL_LOG_INTERVAL
-        {
-            info!(
-                "Mempool update, size: {}, added: {}, removed: {}",
-                mempool.transactions.len(),
-                mempool.added,
-                mempool.removed,
-            );
-            mempool.last_log = Some(SystemTime::now());
-            mempool.added = 0;
-            mempool.removed = 0;
+            // Our best block is not in node chain anymore, remove it like
+            // regular update does, otherwise next catch up stops on same block
+            let mut blocks = self.blocks.write().await;
+            if block.previousblockhash.as_ref() != Some(&blocks.back().unwrap().hash) {
+                info!(
+                    "Catch up interrupted at block {}: chain changed",
+                    block.height
+                );
+                return self.remove_best_block(&mut blocks).await;
+            }
+            Self::check_block_time(&blocks, &block.hash, block.time)?;
+            let timings = BlockTimings::default();
+            self.add_block(&mut blocks, block, BlocksListSide::Back, timings)
+                .await;
         }
 
+        // Final validation of our chain before report that we are live again
+        let blocks = self.blocks.read().await;
+        Self::validate_blocks(&blocks)?;
+        let tip = blocks.back().unwrap();
+        info!("Caught up at block {}: {}", tip.height, &tip.hash);
+        self.events.publish(Event::CaughtUp(tip.hash.clone()));
+
         Ok(())
     }
 
-    fn send_tx_event(&self, event: EventsMempoolTx, hash: &str) {
-        if self.events.receiver_count() > 0 {
-            let msg = format!("{:?} tx: {}", event, hash);
-            let _ = self.events.send(Message::text(msg));
+    fn validate_blocks(blocks: &LinkedList<StateBlock>) -> AppResult<()> {
+        for (prev, block) in blocks.iter().zip(blocks.iter().skip(1)) {
+            if prev.height + 1 != block.height || block.prevhash.as_ref() != Some(&prev.hash) {
+                return Err(AppError::InvalidBlockchain);
+            }
         }
-    }
 
-    pub async fn get_block_tip(&self) -> Result<Option<json::Block>, Box<dyn StdError>> {
-        let hash = self.blocks.read().await.back().unwrap().hash.clone();
-        self.get_block_by_hash(&hash).await
+        let first_height = blocks.front().map_or(0, |block| block.height);
+        let times = blocks.iter().map(|block| block.time).collect::<Vec<_>>();
+        for (index, block) in blocks.iter().enumerate() {
+            let start = index.saturating_sub(BLOCK_TIME_MEDIAN_SPAN);
+            let height = first_height + start as u32;
+            match Self::median_time_past(&times[start..index], height) {
+                Some(median) if block.time <= median => {
+                    return Err(AppError::InvalidBlockTime(
+                        block.hash.clone(),
+                        "not greater than median time of previous blocks",
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
     }
 
-    pub async fn get_block_by_hash(
-        &self,
-        hash: &str,
-    ) -> Result<Option<json::Block>, Box<dyn StdError>> {
-        let block = self.bitcoind.getblockbyhash(hash).await?;
-        Ok(block.map(|blk| blk.into()))
+    // Check time of block which will be added on top of our chain
+    fn check_block_time(blocks: &LinkedList<StateBlock>, hash: &str, time: u32) -> AppResult<()> {
+        let times = blocks
+            .iter()
+            .rev()
+            .take(BLOCK_TIME_MEDIAN_SPAN)
+            .map(|block| block.time)
+            .collect::<Vec<_>>();
+        let height = blocks.back().map_or(0, |block| {
+            (block.height + 1).saturating_sub(times.len() as u32)
+        });
+        match Self::median_time_past(&times, height) {
+            Some(median) if time <= median => Err(AppError::InvalidBlockTime(
+                hash.to_owned(),
+                "not greater than median time of previous blocks",
+            )),
+            _ => Self::check_block_time_future(hash, time),
+        }
     }
 
-    pub async fn get_block_by_height(
-        &self,
-        height: u32,
-    ) -> Result<Option<json::Block>, Box<dyn StdError>> {
-        loop {
-            match self.bitcoind.getblockbyheight(height).await {
-                Ok(block) => return Ok(block.map(|blk| blk.into())),
-                Err(BitcoindError::ResultMismatch) => {}
-                Err(e) => return Err(e.into()),
-            }
+    fn check_block_time_future(hash: &str, time: u32) -> AppResult<()> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        if time as u64 > now + BLOCK_TIME_FUTURE_MAX {
+            return Err(AppError::InvalidBlockTime(
+                hash.to_owned(),
+                "too far in the future",
+            ));
         }
+        Ok(())
     }
 
-    pub async fn get_mempool(&self) -> Result<Vec<json::Transaction>, Box<dyn StdError>> {
-        let mempool = &self.mempool.read().await.transactions;
-        Ok(mempool
+    // Median time past of our tip, exact only with at least
+    // `BLOCK_TIME_MEDIAN_SPAN` tracked blocks
+    fn tip_median_time(blocks: &LinkedList<StateBlock>) -> u32 {
+        let times = blocks
             .iter()
-            .map(|(hash, tx)| json::Transaction {
-                hash: hash.to_owned(),
-                size: tx.size,
-            })
-            .collect())
+            .rev()
+            .take(BLOCK_TIME_MEDIAN_SPAN)
+            .map(|block| block.time)
+            .collect::<Vec<_>>();
+        if times.is_empty() {
+            0
+        } else {
+            Self::median_time(&times)
+        }
     }
 
-    pub fn get_events_receiver(&self) -> broadcast::Receiver<Message> {
-        self.events.subscribe()
+    // Median time past from times of previous blocks, first of them at
+    // `height`. Without all `BLOCK_TIME_MEDIAN_SPAN` previous blocks median is
+    // not known, except near genesis where bitcoind uses all of them
+    fn median_time_past(times: &[u32], height: u32) -> Option<u32> {
+        if times.is_empty() || (times.len() < BLOCK_TIME_MEDIAN_SPAN && height != 0) {
+            return None;
+        }
+        Some(Self::median_time(times))
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct StateBlock {
-    pub height: u32,
-    pub hash: String,
-    pub prevhash: Option<String>,
-    pub transactions: Vec<String>,
-}
+    // Median of last `BLOCK_TIME_MEDIAN_SPAN` times, `times` should not be empty
+    fn median_time(times: &[u32]) -> u32 {
+        let skip = times.len().saturating_sub(BLOCK_TIME_MEDIAN_SPAN);
+        let mut times = times[skip..].to_vec();
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
 
-impl From<ResponseBlock> for StateBlock {
-    fn from(block: ResponseBlock) -> Self {
-        StateBlock {
-            height: block.height,
+    pub async fn refresh_mempool(&self) -> AppResult<()> {
+        let _updating = self.updating.read().await;
+        if self.get_data_age().is_none() || self.get_stale_since().is_some() {
+            return Ok(());
+        }
+        self.update_mempool().await
+    }
+
+    async fn update_mempool(&self) -> AppResult<()> {
+        self.mempool.write().await.confirmed = Some(HashSet::new());
+        let mempool_new = self.bitcoind().getrawmempool().await;
+        match mempool_new {
+            Ok(mempool_new) => self.apply_mempool(mempool_new).await,
+            Err(error) => {
+                self.mempool.write().await.confirmed = None;
+                Err(AppError::Bitcoind(error))
+            }
+        }
+    }
+
+    // Node mempool can be fetched before blocks added meanwhile, so their
+    // transactions are not added back as new
+    async fn apply_mempool(&self, mut mempool_new: ResponseRawMempool) -> AppResult<()> {
+        let mut mempool = self.mempool.write().await;
+        if let Some(confirmed) = mempool.confirmed.take() {
+            mempool_new.retain(|hash, _| !confirmed.contains(hash));
+        }
+        // Children added and parents confirmed change packages of known
+        // transactions
+        for (hash, data) in mempool_new.iter() {
+            if let Some(tx) = mempool.transactions.get_mut(hash) {
+                tx.ancestors = data.ancestorcount;
+                tx.descendants = data.descendantcount;
+            }
+        }
+        let (removed, added) = diff_mempool(&mempool.transactions, mempool_new);
+        mempool.removed += removed.len();
+        let mut spends = self.spends.write().await;
+        for hash in removed {
+            mempool.remove(&hash);
+            spends.remove_mempool(&hash);
+            self.send_tx_event(TxEvent::Removed, &hash);
+        }
+
+        drop(spends);
+
+        mempool.added += added.len();
+        // Transactions of first update entered node mempool before our start
+        let entries_sampled = mempool.last_log.is_some();
+        let mut new_txs = Vec::with_capacity(added.len());
+        for (hash, data) in added {
+            if entries_sampled {
+                self.clock_skew.push_entry(data.time);
+            }
+            self.send_tx_event(TxEvent::Added, &hash);
+            for alert in self.fee_alerts.check(&hash, data.size, data.fee_sat()) {
+                self.events.publish(Event::Alert(alert, hash.clone()));
+            }
+            new_txs.push((hash.clone(), data.size, data.fee_sat()));
+            mempool.insert(hash, data.into());
+        }
+
+        if mempool.last_log.is_none()
+            || mempool.last_log.as_ref().unwrap().elapsed().unwrap() > self.intervals.mempool_log
+        {
+            info!(
+                "Mempool update, size: {}, added: {}, removed: {}",
+                mempool.transactions.len(),
+                mempool.added,
+                mempool.removed,
+            );
+            mempool.last_log = Some(SystemTime::now());
+            mempool.added = 0;
+            mempool.removed = 0;
+        }
+        drop(mempool);
+
+        if !new_txs.is_empty() {
+            self.process_new_txs(new_txs).await;
+        }
+
+        Ok(())
+    }
+
+    // Transactions of previous run, so they are not announced again and
+    // confirmed or removed while we were down are announced
+    async fn restore_mempool(&self, snapshot: Vec<StoredMempoolTx>) {
+        let mut mempool = self.mempool.write().await;
+        let mut spends = self.spends.write().await;
+        for tx in snapshot {
+            let spent = tx
+                .spent
+                .into_iter()
+                .map(|(vin, txid, vout)| (vin, (txid, vout)))
+                .collect();
+            spends.insert_mempool(&tx.txid, spent);
+            // Package counts are updated with first mempool update
+            let transaction = StateTransaction {
+                size: tx.size,
+                fee: tx.fee,
+                time: tx.time,
+                first_seen: if tx.first_seen > 0 {
+                    tx.first_seen
+                } else {
+                    tx.time
+                },
+                ancestors: 1,
+                descendants: 1,
+            };
+            mempool.insert(tx.txid, transaction);
+        }
+    }
+
+    // Snapshot removed after load, so stale snapshot is not used after crash
+    fn take_mempool_snapshot(&self) -> StorageResult<Option<Vec<StoredMempoolTx>>> {
+        let key = META_MEMPOOL.as_bytes();
+        let snapshot = storage::get_json(self.storage.as_ref(), TREE_META, key)?;
+        self.storage.remove(TREE_META, key)?;
+        Ok(snapshot)
+    }
+
+    async fn save_mempool_snapshot(&self) -> StorageResult<()> {
+        let mempool = self.mempool.read().await;
+        let spends = self.spends.read().await;
+        let snapshot = mempool
+            .transactions
+            .iter()
+            .map(|(txid, tx)| StoredMempoolTx {
+                txid: txid.clone(),
+                size: tx.size,
+                fee: tx.fee,
+                time: tx.time,
+                first_seen: tx.first_seen,
+                spent: spends
+                    .mempool_spent(txid)
+                    .into_iter()
+                    .map(|(vin, (txid, vout))| (vin, txid, vout))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+
+        let key = META_MEMPOOL.as_bytes();
+        storage::insert_json(self.storage.as_ref(), TREE_META, key, &snapshot)?;
+        self.storage.flush()?;
+        info!("Mempool snapshot saved: {} transactions", snapshot.len());
+        Ok(())
+    }
+
+    // Fetch new mempool transactions for spent outputs index, plugins and
+    // alert rules
+    async fn process_new_txs(&self, added: Vec<(String, u32, u64)>) {
+        // Owned batches, borrowed slices make future not `Send`
+        let chunks = added
+            .chunks(NEW_TXS_BATCH_SIZE)
+            .map(|chunk| chunk.to_vec())
+            .collect::<Vec<_>>();
+        let batches = stream::iter(chunks)
+            .map(|chunk| async move {
+                let txids = chunk
+                    .iter()
+                    .map(|(txid, ..)| txid.clone())
+                    .collect::<Vec<_>>();
+                let txs = self.bitcoind().getmempooltransactions(&txids).await;
+                (chunk, txs)
+            })
+            .buffered(NEW_TXS_FETCH_JOBS)
+            .collect::<Vec<_>>()
+            .await;
+        let mut txs = vec![];
+        for (chunk, result) in batches {
+            match result {
+                // Transaction can be already removed from mempool
+                Ok(fetched) => {
+                    for ((_, vsize, fee), tx) in chunk.iter().zip(fetched) {
+                        txs.extend(tx.map(|tx| (*vsize, *fee, tx)));
+                    }
+                }
+                Err(err) => error!("Failed to fetch {} transactions: {}", chunk.len(), err),
+            }
+        }
+
+        let mempool = self.mempool.read().await;
+        let mut spends = self.spends.write().await;
+        for (_, _, tx) in txs.iter() {
+            // Transaction can be removed or confirmed while fetched
+            if mempool.transactions.contains_key(&tx.txid) {
+                spends.add_mempool(tx);
+            }
+        }
+        drop(spends);
+        drop(mempool);
+        self.react_spends(Self::tx_spends(txs.iter().map(|(_, _, tx)| tx)))
+            .await;
+
+        if let Some(rules) = &self.rules {
+            let mut rules = rules.write().await;
+            for (vsize, fee, tx) in txs.iter() {
+                let input = RuleInput {
+                    txid: tx.txid.clone(),
+                    vsize: *vsize,
+                    fee: *fee,
+                    value: tx.vout.iter().map(|output| output.value).sum(),
+                    outputs: tx.vout.len(),
+                };
+                for rule in rules.check(&input) {
+                    self.events.publish(Event::Alert(rule, input.txid.clone()));
+                }
+            }
+        }
+
+        for (_, fee, tx) in txs.iter() {
+            let outputs = tx.vout.iter().map(|output| output.value).sum::<f64>();
+            let value = (outputs * 1e8).round() as u64 + fee;
+            if let Some(event) = self.check_large_tx(&tx.txid, value, None) {
+                self.events.publish(event);
+            }
+        }
+
+        if let Some(plugins) = &self.plugins {
+            let inputs = txs
+                .iter()
+                .map(|(_, _, tx)| serde_json::to_vec(tx).unwrap())
+                .collect::<Vec<_>>();
+            let events = plugins
+                .run(move |host| {
+                    inputs
+                        .iter()
+                        .flat_map(|input| host.classify_tx(input))
+                        .collect()
+                })
+                .await;
+            self.publish_plugin_events(events);
+        }
+    }
+
+    // Inputs total is outputs total with fee, so prevouts resolved by bitcoind
+    // for fee are used instead of fetch of spent transactions
+    fn check_large_tx(&self, txid: &str, value: u64, height: Option<u32>) -> Option<Event> {
+        match self.large_tx {
+            Some(threshold) if value > threshold => Some(Event::Large(json::LargeTx {
+                txid: txid.to_owned(),
+                value,
+                height,
+            })),
+            _ => None,
+        }
+    }
+
+    // Recompile rules on changes in rules directory
+    pub async fn reload_rules(&self) -> RulesResult<()> {
+        if let Some(rules) = &self.rules {
+            rules.write().await.reload_if_changed()?;
+        }
+        Ok(())
+    }
+
+    fn publish_plugin_events(&self, events: Vec<PluginEvent>) {
+        for event in events {
+            self.events.publish(Event::Plugin(event.topic, event.data));
+        }
+    }
+
+    fn send_tx_event(&self, event: TxEvent, hash: &str) {
+        if self.events.has_receivers() {
+            self.events.publish(Event::Tx(event, hash.to_owned()));
+        }
+    }
+
+    pub async fn get_block_tip(&self) -> BitcoindResult<Option<json::Block>> {
+        let hash = self.blocks.read().await.back().unwrap().hash.clone();
+        self.get_block_by_hash(&hash).await
+    }
+
+    pub async fn get_block_by_hash(&self, hash: &str) -> BitcoindResult<Option<json::Block>> {
+        let block = self.bitcoind().getblockbyhash(hash).await?;
+        Ok(block.map(|blk| self.to_json_block(blk)))
+    }
+
+    fn to_json_block(&self, block: ResponseBlock) -> json::Block {
+        let interval = network::halving_interval(&self.chain_name());
+        let subsidy = network::block_subsidy(block.height, interval);
+        json::Block::new(block, subsidy)
+    }
+
+    // Chain of node, empty before update loop started
+    fn chain_name(&self) -> String {
+        self.chain.lock().unwrap().clone().unwrap_or_default()
+    }
+
+    pub async fn get_halving(&self) -> json::Halving {
+        let blocks = self.blocks.read().await;
+        let times = blocks.iter().map(|block| block.time).collect::<Vec<_>>();
+        let tip = blocks.back().unwrap();
+        let interval = network::block_interval(&times);
+        network::halving(&self.chain_name(), tip.height, tip.time, interval)
+    }
+
+    pub async fn get_difficulty_adjustment(&self) -> BitcoindResult<json::DifficultyAdjustment> {
+        let (height, time) = {
+            let blocks = self.blocks.read().await;
+            let tip = blocks.back().unwrap();
+            (tip.height, tip.time)
+        };
+
+        let start = network::retarget_start(height);
+        let cached = *self.retarget_start.lock().unwrap();
+        let start_time = match cached {
+            Some((cached_height, time)) if cached_height == start => time,
+            _ => {
+                let header = self.bitcoind().getblockheaderbyheight(start).await?;
+                let time = header.ok_or(BitcoindError::ResultNotFound)?.time;
+                *self.retarget_start.lock().unwrap() = Some((start, time));
+                time
+            }
+        };
+
+        let chain = self.chain_name();
+        Ok(network::difficulty_adjustment(
+            &chain, height, time, start_time,
+        ))
+    }
+
+    pub async fn get_peers(&self, detailed: bool) -> BitcoindResult<json::Peers> {
+        let peers = self.bitcoind().getpeerinfo().await?;
+        Ok(peers::summarize(peers, detailed))
+    }
+
+    pub async fn get_bans(&self) -> BitcoindResult<Vec<json::Ban>> {
+        let bans = self.bitcoind().listbanned().await?;
+        Ok(bans
+            .into_iter()
+            .map(|ban| json::Ban {
+                address: ban.address,
+                ban_created: ban.ban_created,
+                banned_until: ban.banned_until,
+            })
+            .collect())
+    }
+
+    pub async fn set_ban(&self, entry: &json::BanEntry, add: bool) -> BitcoindResult<()> {
+        let bitcoind = self.bitcoind();
+        bitcoind
+            .setban(&entry.subnet, add, entry.bantime, entry.absolute)
+            .await
+    }
+
+    pub async fn update_node(&self, entry: &json::NodeEntry, add: bool) -> BitcoindResult<()> {
+        let command = match (add, entry.onetry) {
+            (true, true) => "onetry",
+            (true, false) => "add",
+            (false, _) => "remove",
+        };
+        self.bitcoind().addnode(&entry.addr, command).await
+    }
+
+    pub async fn disconnect_peer(&self, entry: &json::PeerEntry) -> BitcoindResult<()> {
+        let bitcoind = self.bitcoind();
+        match entry {
+            json::PeerEntry::Address(address) => bitcoind.disconnectnode(address, None).await,
+            json::PeerEntry::Nodeid(nodeid) => bitcoind.disconnectnode("", Some(*nodeid)).await,
+        }
+    }
+
+    pub async fn get_chain(&self) -> BitcoindResult<String> {
+        let info = self.bitcoind().getblockchaininfo().await?;
+        Ok(info.chain)
+    }
+
+    // Height and hash of node tip, not of tracked blocks
+    pub async fn get_node_tip(&self) -> BitcoindResult<(u32, String)> {
+        let info = self.bitcoind().getblockchaininfo().await?;
+        Ok((info.blocks, info.bestblockhash))
+    }
+
+    pub async fn get_tip(&self) -> json::Tip {
+        let blocks = self.blocks.read().await;
+        let block = blocks.back().unwrap();
+        json::Tip {
+            height: block.height,
+            hash: block.hash.clone(),
+            transactions: block.transactions.len(),
+        }
+    }
+
+    // Subsystems enabled in state, API settings filled by API server
+    pub async fn get_features(&self) -> json::Features {
+        json::Features {
+            tenants: !self.tenants.read().await.is_empty(),
+            hooks: self.events.hooks_stats().len(),
+            plugins: match &self.plugins {
+                Some(plugins) => plugins.stats().len(),
+                None => 0,
+            },
+            rules: match &self.rules {
+                Some(rules) => rules.read().await.stats().len(),
+                None => 0,
+            },
+            protobuf: true,
+            chaos: cfg!(feature = "chaos"),
+            ..Default::default()
+        }
+    }
+
+    pub async fn get_block_tip_hash(&self) -> String {
+        self.blocks.read().await.back().unwrap().hash.clone()
+    }
+
+    pub async fn get_block_hash_by_height(&self, height: u32) -> BitcoindResult<Option<String>> {
+        self.bitcoind().getblockhash(height).await
+    }
+
+    // Raw block, if block do not have blacklisted transactions or output scripts
+    pub async fn get_block_hex(&self, hash: &str) -> BitcoindResult<Option<RawPayload>> {
+        let block = match self.bitcoind().getblockbyhash(hash).await? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        if self.blacklist.read().await.contains_block(&block) {
+            return Ok(Some(RawPayload::Blacklisted));
+        }
+
+        let hex = self.bitcoind().getblockhex(hash).await?;
+        Ok(hex.map(RawPayload::Hex))
+    }
+
+    // Basic filter (BIP158) from binary block and spent outputs
+    pub async fn get_block_filter(&self, hash: &str) -> BitcoindResult<Option<json::BlockFilter>> {
+        if let Some(filter) = self.filters.get(hash) {
+            return Ok(Some(filter));
+        }
+
+        let bitcoind = self.bitcoind();
+        let block = match bitcoind.getblockraw(hash).await? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        let spent = bitcoind.getspentscripts(&block).await?;
+        let filter = filters::basic_filter(&block, &spent);
+        self.filters.insert(filter.clone());
+        Ok(Some(filter))
+    }
+
+    // Check transactions against node policy without broadcast
+    pub async fn test_txs(
+        &self,
+        txs: &[String],
+        maxfeerate: Option<f64>,
+    ) -> BitcoindResult<Vec<json::TxTestResult>> {
+        let results = self.bitcoind().testmempoolaccept(txs, maxfeerate).await?;
+        Ok(results.into_iter().map(|result| result.into()).collect())
+    }
+
+    // Relay raw transaction to bitcoind, txid on success
+    pub async fn broadcast_tx(&self, tx: &str) -> BitcoindResult<json::TxBroadcast> {
+        let txid = self.bitcoind().sendrawtransaction(tx).await?;
+        Ok(json::TxBroadcast { txid })
+    }
+
+    // Submit transactions as package. Nodes without `submitpackage` receive
+    // transactions one by one in request order, rest are skipped after first
+    // rejected transaction, because children can not be accepted without it.
+    pub async fn submit_package(&self, txs: &[String]) -> BitcoindResult<json::PackageSubmit> {
+        let bitcoind = self.bitcoind();
+        let wtxids = txs
+            .iter()
+            .map(|tx| hex::decode(tx).map(|raw| wtxid(&raw)).unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        if bitcoind.supports_submitpackage().await? {
+            let mut package = bitcoind.submitpackage(txs).await?;
+            let txs = wtxids
+                .into_iter()
+                .map(|wtxid| match package.tx_results.remove(&wtxid) {
+                    Some(result) => json::PackageTxResult {
+                        txid: Some(result.txid),
+                        wtxid,
+                        accepted: result.error.is_none(),
+                        vsize: result.vsize,
+                        fee: result.fees.map(|fees| (fees.base * 1e8).round() as u64),
+                        error: result.error,
+                    },
+                    None => json::PackageTxResult {
+                        txid: None,
+                        wtxid,
+                        accepted: false,
+                        vsize: None,
+                        fee: None,
+                        error: Some("not validated".to_owned()),
+                    },
+                })
+                .collect();
+            return Ok(json::PackageSubmit {
+                method: "submitpackage".to_owned(),
+                message: package.package_msg,
+                txs,
+            });
+        }
+
+        let mut results = Vec::with_capacity(txs.len());
+        let mut rejected = false;
+        for (tx, wtxid) in txs.iter().zip(wtxids) {
+            let mut result = json::PackageTxResult {
+                txid: None,
+                wtxid,
+                accepted: false,
+                vsize: None,
+                fee: None,
+                error: None,
+            };
+            if rejected {
+                result.error = Some("not submitted, previous transaction rejected".to_owned());
+            } else {
+                match bitcoind.sendrawtransaction(tx).await {
+                    Ok(txid) => {
+                        result.txid = Some(txid);
+                        result.accepted = true;
+                    }
+                    Err(BitcoindError::ResultRPC(err)) => {
+                        result.error = Some(err.message);
+                        rejected = true;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            results.push(result);
+        }
+        Ok(json::PackageSubmit {
+            method: "sendrawtransaction".to_owned(),
+            message: None,
+            txs: results,
+        })
+    }
+
+    // Unspent output from bitcoind with mempool spend from own index, which
+    // is useful if mempool is not included
+    pub async fn get_outpoint(
+        &self,
+        txid: &str,
+        vout: u32,
+        include_mempool: bool,
+    ) -> BitcoindResult<json::OutpointInfo> {
+        let txout = self
+            .bitcoind()
+            .gettxout(txid, vout, include_mempool)
+            .await?;
+        let spend = self.spends.read().await.get(txid, vout);
+        Ok(json::OutpointInfo {
+            txid: txid.to_owned(),
+            vout,
+            include_mempool,
+            utxo: txout.map(|txout| json::Utxo {
+                value: (txout.value * 1e8).round() as u64,
+                script: txout.script_pubkey.hex,
+                confirmations: txout.confirmations,
+                coinbase: txout.coinbase,
+            }),
+            mempool_spend: spend.filter(|spend| spend.height.is_none()),
+        })
+    }
+
+    // Spending transaction of output, only tracked blocks and mempool checked
+    pub async fn get_outpoint_spend(&self, txid: &str, vout: u32) -> json::OutpointSpend {
+        json::OutpointSpend {
+            txid: txid.to_owned(),
+            vout,
+            spend: self.spends.read().await.get(txid, vout),
+        }
+    }
+
+    // Transaction from tracked blocks, mempool, or older blocks if bitcoind
+    // has `-txindex`. Confirmations counted from our tip.
+    pub async fn get_transaction(
+        &self,
+        txid: &str,
+    ) -> BitcoindResult<Option<json::TransactionInfo>> {
+        let (tracked, tip) = {
+            let blocks = self.blocks.read().await;
+            let tracked = blocks
+                .iter()
+                .find(|block| block.transactions.iter().any(|hash| hash == txid))
+                .map(|block| (block.height, block.hash.clone()));
+            (tracked, blocks.back().unwrap().height)
+        };
+
+        let tx = match self
+            .bitcoind()
+            .gettransaction(txid, tracked.as_ref().map(|(_, hash)| hash.as_str()))
+            .await?
+        {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let block = match tx.blockhash {
+            Some(hash)
+                if tracked
+                    .as_ref()
+                    .is_some_and(|(_, tracked)| tracked == &hash) =>
+            {
+                let (height, _) = tracked.unwrap();
+                Some(json::TransactionBlock { height, hash })
+            }
+            Some(hash) => {
+                let header = self.bitcoind().getblockheader(&hash).await?;
+                Some(json::TransactionBlock {
+                    height: header.height,
+                    hash,
+                })
+            }
+            None => None,
+        };
+        Ok(Some(json::TransactionInfo::new(tx.transaction, block, tip)))
+    }
+
+    // `None` if address is not valid for network of node
+    pub async fn get_address_transactions(
+        &self,
+        address: &str,
+    ) -> BitcoindResult<Option<json::AddressTransactions>> {
+        let validated = self.bitcoind().validateaddress(address).await?;
+        if !validated.isvalid {
+            return Ok(None);
+        }
+
+        let scripts = self.scripts.read().await;
+        let mut transactions = scripts
+            .interner
+            .get(&validated.script_pubkey)
+            .and_then(|id| scripts.transactions.get(&id))
+            .into_iter()
+            .flatten()
+            .map(|(txid, height)| json::AddressTransaction {
+                txid: txid.clone(),
+                height: *height,
+            })
+            .collect::<Vec<_>>();
+        // Transaction with several outputs to same script listed once
+        transactions.sort_by(|a, b| b.height.cmp(&a.height).then_with(|| a.txid.cmp(&b.txid)));
+        transactions.dedup_by(|a, b| a.txid == b.txid);
+
+        Ok(Some(json::AddressTransactions {
+            address: address.to_owned(),
+            script: validated.script_pubkey,
+            transactions,
+        }))
+    }
+
+    pub async fn get_blacklist(&self) -> json::Blacklist {
+        let blacklist = self.blacklist.read().await;
+        json::Blacklist {
+            txids: blacklist.txids.iter().cloned().collect(),
+            scripts: blacklist.scripts.iter().map(hex::encode).collect(),
+        }
+    }
+
+    pub async fn add_to_blacklist(&self, entry: json::BlacklistEntry) -> StorageResult<()> {
+        let mut blacklist = self.blacklist.write().await;
+        self.storage
+            .insert(TREE_BLACKLIST, &StateBlacklist::key(&entry), &[])?;
+        match entry {
+            json::BlacklistEntry::Txid(txid) => blacklist.txids.insert(txid),
+            json::BlacklistEntry::Script(script) => blacklist.scripts.insert(script),
+        };
+        Ok(())
+    }
+
+    pub async fn remove_from_blacklist(&self, entry: json::BlacklistEntry) -> StorageResult<()> {
+        let mut blacklist = self.blacklist.write().await;
+        self.storage
+            .remove(TREE_BLACKLIST, &StateBlacklist::key(&entry))?;
+        match entry {
+            json::BlacklistEntry::Txid(txid) => blacklist.txids.remove(&txid),
+            json::BlacklistEntry::Script(script) => blacklist.scripts.remove(&script),
+        };
+        Ok(())
+    }
+
+    pub fn get_maintenance(&self) -> json::Maintenance {
+        self.maintenance.lock().unwrap().clone()
+    }
+
+    // Subscribers notified only on changes, so repeated requests are not visible
+    pub fn set_maintenance(&self, maintenance: json::Maintenance) {
+        let mut current = self.maintenance.lock().unwrap();
+        let changed = current.enabled != maintenance.enabled
+            || (maintenance.enabled && current.message != maintenance.message);
+        if changed {
+            if maintenance.enabled {
+                info!("Maintenance mode on: {}", maintenance.message);
+            } else {
+                info!("Maintenance mode off");
+            }
+            let event = Event::Maintenance(maintenance.enabled, maintenance.message.clone());
+            self.events.publish(event);
+        }
+        *current = maintenance;
+    }
+
+    fn set_last_update(&self, blocks: &LinkedList<StateBlock>) {
+        if let Some(block) = blocks.back() {
+            *self.last_update.lock().unwrap() = Some((block.height, Instant::now()));
+        }
+    }
+
+    // Tip height and time since last check of node chain, `None` before chain
+    // initialized
+    pub fn get_data_age(&self) -> Option<(u32, Duration)> {
+        self.last_update
+            .lock()
+            .unwrap()
+            .map(|(height, time)| (height, time.elapsed()))
+    }
+
+    // Unix time of pause if update loop is paused
+    pub fn get_stale_since(&self) -> Option<u64> {
+        *self.paused.lock().unwrap()
+    }
+
+    // Returned after update in progress, pause time kept on repeated pause
+    pub async fn set_paused(&self, paused: bool) -> json::UpdatesPause {
+        let stale_since = self.set_stale_since(paused);
+        if paused {
+            drop(self.updating.write().await);
+        }
+        json::UpdatesPause {
+            paused,
+            stale_since,
+        }
+    }
+
+    fn set_stale_since(&self, paused: bool) -> Option<u64> {
+        let mut stale_since = self.paused.lock().unwrap();
+        match (paused, *stale_since) {
+            (true, None) => {
+                info!("Update loop paused");
+                *stale_since = Some(unix_time());
+            }
+            (false, Some(_)) => {
+                info!("Update loop resumed");
+                *stale_since = None;
+            }
+            _ => {}
+        }
+        *stale_since
+    }
+
+    // Record mutating action, failed write only logged because action already done
+    pub fn audit(&self, actor: &str, action: &str, target: &str, result: &str) {
+        let storage = self.storage.as_ref();
+        if let Err(err) = self.audit.append(storage, actor, action, target, result) {
+            error!(
+                "Audit record ({} {} {}) write error: {}",
+                actor, action, target, err
+            );
+        }
+    }
+
+    pub fn get_audit(&self, limit: usize) -> StorageResult<Vec<AuditRecord>> {
+        self.audit.recent(self.storage.as_ref(), limit)
+    }
+
+    pub async fn authenticate_tenant(&self, api_key: &str) -> Option<String> {
+        let tenants = self.tenants.read().await;
+        tenants.authenticate(api_key).map(|id| id.to_owned())
+    }
+
+    pub fn consume_quota(&self, tenant: &str, route: &str) -> StorageResult<QuotaResult> {
+        let mut quotas = self.quotas.lock().unwrap();
+        quotas.consume(self.storage.as_ref(), tenant, route)
+    }
+
+    // Bytes sent to API clients, counted against egress budgets
+    pub fn record_egress(&self, bytes: usize) {
+        self.egress.record(bytes);
+    }
+
+    // Period of spent egress budget and seconds until its reset
+    pub fn get_egress_exceeded(&self) -> Option<(Period, u64)> {
+        self.egress.exceeded()
+    }
+
+    pub fn get_usage(&self, tenant: &str) -> StorageResult<json::Usage> {
+        let mut quotas = self.quotas.lock().unwrap();
+        quotas.report(self.storage.as_ref(), tenant)
+    }
+
+    pub async fn get_watch(&self, tenant: &str) -> json::Watch {
+        json::Watch {
+            scripts: self.tenants.read().await.get_watch(tenant),
+        }
+    }
+
+    pub async fn add_watch(&self, tenant: &str, script: Vec<u8>) -> TenantResult<()> {
+        let mut tenants = self.tenants.write().await;
+        tenants.add_watch(self.storage.as_ref(), tenant, script)
+    }
+
+    pub async fn remove_watch(&self, tenant: &str, script: &[u8]) -> TenantResult<()> {
+        let mut tenants = self.tenants.write().await;
+        tenants.remove_watch(self.storage.as_ref(), tenant, script)
+    }
+
+    pub async fn get_timelocks(&self, tenant: &str) -> json::Timelocks {
+        json::Timelocks {
+            timelocks: self.tenants.read().await.get_timelocks(tenant),
+        }
+    }
+
+    // Confirmation of output looked up in tracked blocks, then in mempool and
+    // bitcoind, relative timelock is counted from it
+    pub async fn add_timelock(
+        &self,
+        tenant: &str,
+        entry: &json::TimelockEntry,
+    ) -> TenantResult<()> {
+        let blocks = self.blocks.read().await;
+        let tip = match blocks.back() {
+            Some(block) => (block.height, Self::tip_median_time(&blocks)),
+            None => (0, 0),
+        };
+        let position = blocks
+            .iter()
+            .position(|block| block.transactions.contains(&entry.txid));
+        let confirmed = match position {
+            Some(position) if position > 0 => {
+                let times = blocks
+                    .iter()
+                    .take(position)
+                    .map(|block| block.time)
+                    .collect::<Vec<_>>();
+                let height = blocks.iter().nth(position).unwrap().height;
+                Some((height, Self::median_time(&times)))
+            }
+            _ if self
+                .mempool
+                .read()
+                .await
+                .transactions
+                .contains_key(&entry.txid) =>
+            {
+                None
+            }
+            _ => {
+                let txout = self
+                    .bitcoind()
+                    .gettxout(&entry.txid, entry.vout, true)
+                    .await?
+                    .ok_or(TenantError::InvalidTimelock("output not found or spent"))?;
+                if txout.confirmations == 0 {
+                    None
+                } else {
+                    let height = (tip.0 + 1).saturating_sub(txout.confirmations);
+                    let prev = height.saturating_sub(1);
+                    let header = self.bitcoind().getblockheaderbyheight(prev).await?;
+                    Some((height, header.map_or(0, |header| header.mediantime)))
+                }
+            }
+        };
+        drop(blocks);
+
+        let mut tenants = self.tenants.write().await;
+        tenants.add_timelock(self.storage.as_ref(), tenant, entry, confirmed, tip)
+    }
+
+    pub async fn remove_timelock(&self, tenant: &str, txid: &str, vout: u32) -> TenantResult<()> {
+        let mut tenants = self.tenants.write().await;
+        tenants.remove_timelock(self.storage.as_ref(), tenant, txid, vout)
+    }
+
+    pub async fn get_reactions(&self, tenant: &str) -> json::Reactions {
+        json::Reactions {
+            reactions: self.tenants.read().await.get_reactions(tenant),
+        }
+    }
+
+    // Reaction is triggered at once if output is already spent in tracked
+    // blocks or mempool
+    pub async fn add_reaction(
+        &self,
+        tenant: &str,
+        entry: &json::ReactionEntry,
+    ) -> TenantResult<()> {
+        let mut tenants = self.tenants.write().await;
+        tenants.add_reaction(self.storage.as_ref(), tenant, entry)?;
+        drop(tenants);
+
+        let spend = self.spends.read().await.get(&entry.txid, entry.vout);
+        if let Some(spend) = spend {
+            let outpoint = (entry.txid.clone(), entry.vout);
+            self.react_spends(vec![(outpoint, spend.txid)]).await;
+        }
+        Ok(())
+    }
+
+    pub async fn remove_reaction(&self, tenant: &str, txid: &str, vout: u32) -> TenantResult<()> {
+        let mut tenants = self.tenants.write().await;
+        tenants.remove_reaction(self.storage.as_ref(), tenant, txid, vout)
+    }
+
+    // Watch history as CSV, confirmations counted from our tip
+    pub async fn export_watch(
+        &self,
+        tenant: &str,
+        script: &[u8],
+        from: Option<u32>,
+        to: Option<u32>,
+    ) -> String {
+        let tip = self
+            .blocks
+            .read()
+            .await
+            .back()
+            .map_or(0, |block| block.height);
+        let tenants = self.tenants.read().await;
+        tenants.export_history(tenant, script, tip, from, to)
+    }
+
+    pub async fn get_block_by_height(&self, height: u32) -> BitcoindResult<Option<json::Block>> {
+        loop {
+            match self.bitcoind().getblockbyheight(height).await {
+                Ok(block) => return Ok(block.map(|blk| self.to_json_block(blk))),
+                Err(BitcoindError::ResultMismatch) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub async fn get_mempool(&self) -> Vec<json::Transaction> {
+        let mempool = &self.mempool.read().await.transactions;
+        mempool
+            .iter()
+            .map(|(hash, tx)| json::Transaction {
+                hash: hash.to_owned(),
+                size: tx.size,
+            })
+            .collect()
+    }
+
+    pub async fn get_mempool_transaction(&self, txid: &str) -> Option<json::MempoolTransaction> {
+        let mempool = self.mempool.read().await;
+        let tx = mempool.transactions.get(txid)?;
+        Some(json::MempoolTransaction {
+            txid: txid.to_owned(),
+            size: tx.size,
+            fee: tx.fee,
+            fee_rate: tx.fee as f64 / f64::from(tx.size.max(1)),
+            ancestors: tx.ancestors,
+            descendants: tx.descendants,
+            time: tx.time,
+            first_seen: tx.first_seen,
+            time_in_mempool: unix_time().saturating_sub(tx.first_seen),
+        })
+    }
+
+    // Nearest-rank percentiles of fee rates in sat/vB
+    pub async fn get_mempool_feerates(&self) -> json::MempoolFeerates {
+        let mut rates = {
+            let mempool = self.mempool.read().await;
+            mempool
+                .transactions
+                .values()
+                .map(|tx| tx.fee as f64 / f64::from(tx.size.max(1)))
+                .collect::<Vec<_>>()
+        };
+        rates.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let percentile = |p: usize| match rates.len() {
+            0 => 0.0,
+            len => rates[(p * len).div_ceil(100).max(1) - 1],
+        };
+        json::MempoolFeerates {
+            transactions: rates.len(),
+            min: percentile(0),
+            p10: percentile(10),
+            p50: percentile(50),
+            p90: percentile(90),
+            max: percentile(100),
+        }
+    }
+
+    // Projection of periodic job, assembled now if job is disabled
+    pub async fn get_projected_block(&self) -> Arc<json::ProjectedBlock> {
+        let projected = self.projected_block.lock().unwrap().clone();
+        match projected {
+            Some(block) => block,
+            None => Arc::new(self.project_block().await),
+        }
+    }
+
+    // Assemble new projection and announce it
+    pub async fn update_projected_block(&self) {
+        let block = Arc::new(self.project_block().await);
+        *self.projected_block.lock().unwrap() = Some(block.clone());
+        self.events.publish(Event::ProjectedBlock(block));
+    }
+
+    async fn project_block(&self) -> json::ProjectedBlock {
+        let height = match self.blocks.read().await.back() {
+            Some(block) => block.height + 1,
+            None => 0,
+        };
+        let candidates = {
+            let mempool = self.mempool.read().await;
+            let spends = self.spends.read().await;
+            mempool
+                .transactions
+                .iter()
+                .map(|(txid, tx)| Candidate {
+                    txid: txid.clone(),
+                    vsize: tx.size,
+                    fee: tx.fee,
+                    parents: spends.mempool_parents(txid),
+                })
+                .collect::<Vec<_>>()
+        };
+        projection::assemble(height, &candidates, BLOCK_WEIGHT_MAX)
+    }
+
+    pub async fn get_mempool_histogram(&self) -> json::MempoolHistogram {
+        self.mempool.read().await.histogram.report()
+    }
+
+    pub fn get_confirmation_times(&self) -> json::ConfirmationTimes {
+        let mut report = self.confirmation_times.report();
+        report.skew_correction = self.clock_skew.entry_correction();
+        report
+    }
+
+    // Result of `estimatesmartfee`, bitcoind requested once per target and
+    // mode for every tip
+    pub async fn get_fee_estimate(
+        &self,
+        target: u16,
+        mode: json::FeeEstimateMode,
+    ) -> BitcoindResult<json::FeeEstimate> {
+        let (height, tip) = match self.blocks.read().await.back() {
+            Some(block) => (block.height, block.hash.clone()),
+            None => (0, String::new()),
+        };
+        let estimate = match self.fee_estimates.get(&tip, target, mode) {
+            Some(estimate) => estimate,
+            None => {
+                let bitcoind = self.bitcoind();
+                let estimate = bitcoind.estimatesmartfee(target, mode.as_str()).await?;
+                self.fee_estimates
+                    .insert(&tip, target, mode, estimate.clone());
+                estimate
+            }
+        };
+        Ok(json::FeeEstimate {
+            target,
+            mode,
+            height,
+            blocks: estimate.blocks,
+            feerate: estimate.feerate,
+            sat_per_vbyte: estimate.feerate.map(|feerate| feerate * 1e5),
+            errors: estimate.errors,
+        })
+    }
+
+    pub async fn get_stats(&self) -> json::Stats {
+        json::Stats {
+            blocks: self.blocks.read().await.len(),
+            mempool: self.mempool.read().await.transactions.len(),
+            scripts: self.scripts.read().await.interner.stats(),
+            hooks: self.events.hooks_stats(),
+            plugins: match &self.plugins {
+                Some(plugins) => plugins.stats(),
+                None => vec![],
+            },
+            rules: match &self.rules {
+                Some(rules) => rules.read().await.stats(),
+                None => vec![],
+            },
+            block_timings: self.block_timings.stats(),
+            block_streaks: self.block_streaks.stats(),
+            stale_since: self.get_stale_since(),
+            clock_skew: self.clock_skew.report(),
+            traffic: METRICS.traffic(),
+            egress_budgets: self.egress.report(),
+        }
+    }
+
+    // Block seen by notification source other than our polling
+    pub fn announce_block(&self, source: &str, height: u32, hash: &str) {
+        self.block_sources.announce(source, height, hash);
+    }
+
+    pub fn get_jobs(&self) -> Arc<Jobs> {
+        self.jobs.clone()
+    }
+
+    pub fn get_clock_skew(&self) -> &ClockSkew {
+        &self.clock_skew
+    }
+
+    pub async fn update_node_time_offset(&self) -> BitcoindResult<i64> {
+        let info = self.bitcoind().getnetworkinfo().await?;
+        self.clock_skew.set_node_offset(info.timeoffset);
+        Ok(info.timeoffset)
+    }
+
+    pub fn get_block_sources(&self) -> json::BlockSources {
+        self.block_sources.report()
+    }
+
+    pub fn set_node_tips(&self, tips: Vec<json::NodeTip>) {
+        *self.node_tips.lock().unwrap() = tips;
+    }
+
+    // Empty without second node in `--bitcoind` list
+    pub fn get_nodes(&self) -> json::Nodes {
+        json::Nodes {
+            nodes: self.node_tips.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn get_events(&self) -> Arc<EventBus> {
+        self.events.clone()
+    }
+
+    pub fn get_events_receiver(&self, raw: bool) -> broadcast::Receiver<BusMessage> {
+        self.events.subscribe(raw)
+    }
+}
+
+// Plugins executed out of runtime thread, stats copied after every run
+#[derive(Debug)]
+pub struct StatePlugins {
+    host: Arc<Mutex<PluginHost>>,
+    stats: Mutex<Vec<PluginStats>>,
+}
+
+impl StatePlugins {
+    fn new(host: PluginHost) -> Self {
+        let stats = host.stats();
+        StatePlugins {
+            host: Arc::new(Mutex::new(host)),
+            stats: Mutex::new(stats),
+        }
+    }
+
+    async fn run<F>(&self, f: F) -> Vec<PluginEvent>
+    where
+        F: FnOnce(&mut PluginHost) -> Vec<PluginEvent> + Send + 'static,
+    {
+        let host = self.host.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut host = host.lock().unwrap();
+            let events = f(&mut host);
+            (events, host.stats())
+        })
+        .await;
+
+        match result {
+            Ok((events, stats)) => {
+                *self.stats.lock().unwrap() = stats;
+                events
+            }
+            Err(err) => {
+                error!("Plugins run failed: {}", err);
+                vec![]
+            }
+        }
+    }
+
+    fn stats(&self) -> Vec<PluginStats> {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StateBlock {
+    pub height: u32,
+    pub hash: String,
+    pub prevhash: Option<String>,
+    pub time: u32,
+    pub transactions: Vec<String>,
+    // Interned output scripts with transaction index
+    pub scripts: Vec<(u32, usize)>,
+    // Outputs spent by transactions of block
+    pub spent: Vec<Outpoint>,
+}
+
+impl StateBlock {
+    fn new(block: ResponseBlock, scripts: &mut StateScripts, spends: &mut StateSpends) -> Self {
+        let mut transactions = Vec::with_capacity(block.transactions.len());
+        let mut block_scripts = vec![];
+        let mut spent = vec![];
+        for (index, tx) in block.transactions.into_iter().enumerate() {
+            for output in tx.vout.iter() {
+                let id = scripts.add(&tx.hash, block.height, &output.script_pubkey.hex);
+                block_scripts.push((id, index));
+            }
+            spent.extend(spends.add_block(&tx, block.height));
+            transactions.push(tx.hash);
+        }
+
+        StateBlock {
+            height: block.height,
             hash: block.hash,
             prevhash: block.previousblockhash,
-            transactions: block.transactions.into_iter().map(|t| t.hash).collect(),
+            time: block.time,
+            transactions,
+            scripts: block_scripts,
+            spent,
         }
     }
+
+    fn release(&self, scripts: &mut StateScripts, spends: &mut StateSpends) {
+        for (id, index) in self.scripts.iter() {
+            scripts.remove(*id, &self.transactions[*index]);
+        }
+        for outpoint in self.spent.iter() {
+            spends.blocks.remove(outpoint);
+        }
+    }
+}
+
+// Index of transactions in tracked blocks by output script, with block height
+#[derive(Debug, Default)]
+pub struct StateScripts {
+    pub interner: Interner,
+    pub transactions: HashMap<u32, Vec<(String, u32)>>,
+}
+
+impl StateScripts {
+    fn add(&mut self, txid: &str, height: u32, script: &[u8]) -> u32 {
+        let id = self.interner.intern(script);
+        let txids = self.transactions.entry(id).or_default();
+        txids.push((txid.to_owned(), height));
+        id
+    }
+
+    fn remove(&mut self, id: u32, txid: &str) {
+        if let Some(txids) = self.transactions.get_mut(&id) {
+            if let Some(pos) = txids.iter().position(|(x, _)| x == txid) {
+                txids.swap_remove(pos);
+            }
+            if txids.is_empty() {
+                self.transactions.remove(&id);
+            }
+        }
+        self.interner.release(id);
+    }
+}
+
+// Spent output, transaction id with output index
+pub type Outpoint = (String, u32);
+
+// Index of spending transactions by spent outputs in tracked blocks and
+// mempool, output can be spent in both after reorg
+#[derive(Debug, Default)]
+pub struct StateSpends {
+    blocks: HashMap<Outpoint, json::Spend>,
+    mempool: HashMap<Outpoint, json::Spend>,
+    // Spent outputs by mempool transaction
+    mempool_txs: HashMap<String, Vec<Outpoint>>,
+}
+
+impl StateSpends {
+    fn add_block(&mut self, tx: &ResponseBlockTransaction, height: u32) -> Vec<Outpoint> {
+        let spent = Self::spent_outputs(tx);
+        for (vin, outpoint) in spent.iter() {
+            let spend = json::Spend {
+                txid: tx.txid.clone(),
+                vin: *vin,
+                height: Some(height),
+            };
+            self.blocks.insert(outpoint.clone(), spend);
+        }
+        spent.into_iter().map(|(_, outpoint)| outpoint).collect()
+    }
+
+    fn add_mempool(&mut self, tx: &ResponseBlockTransaction) {
+        self.insert_mempool(&tx.txid, Self::spent_outputs(tx));
+    }
+
+    fn insert_mempool(&mut self, txid: &str, spent: Vec<(u32, Outpoint)>) {
+        for (vin, outpoint) in spent.iter() {
+            let spend = json::Spend {
+                txid: txid.to_owned(),
+                vin: *vin,
+                height: None,
+            };
+            self.mempool.insert(outpoint.clone(), spend);
+        }
+        let outpoints = spent.into_iter().map(|(_, outpoint)| outpoint).collect();
+        self.mempool_txs.insert(txid.to_owned(), outpoints);
+    }
+
+    // Transactions with outputs spent by mempool transaction, can be confirmed
+    fn mempool_parents(&self, txid: &str) -> Vec<String> {
+        let outpoints = self.mempool_txs.get(txid).into_iter().flatten();
+        outpoints.map(|(txid, _)| txid.clone()).collect()
+    }
+
+    // Outputs spent by mempool transaction with input index
+    fn mempool_spent(&self, txid: &str) -> Vec<(u32, Outpoint)> {
+        let outpoints = self.mempool_txs.get(txid).into_iter().flatten();
+        outpoints
+            .filter_map(|outpoint| match self.mempool.get(outpoint) {
+                Some(spend) if spend.txid == txid => Some((spend.vin, outpoint.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn remove_mempool(&mut self, txid: &str) {
+        for outpoint in self.mempool_txs.remove(txid).unwrap_or_default() {
+            // Output can be spent by replacement already
+            if self.mempool.get(&outpoint).map(|spend| spend.txid.as_str()) == Some(txid) {
+                self.mempool.remove(&outpoint);
+            }
+        }
+    }
+
+    // Confirmed spend preferred over mempool
+    fn get(&self, txid: &str, vout: u32) -> Option<json::Spend> {
+        let outpoint = (txid.to_owned(), vout);
+        self.blocks
+            .get(&outpoint)
+            .or_else(|| self.mempool.get(&outpoint))
+            .cloned()
+    }
+
+    // Outputs spent by transaction with input index, coinbase spend nothing
+    fn spent_outputs(tx: &ResponseBlockTransaction) -> Vec<(u32, Outpoint)> {
+        tx.vin
+            .iter()
+            .enumerate()
+            .filter_map(|(vin, input)| match (&input.txid, input.vout) {
+                (Some(txid), Some(vout)) => Some((vin as u32, (txid.clone(), vout))),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// Transactions and output scripts which raw data should not be served
+#[derive(Debug, Default)]
+pub struct StateBlacklist {
+    pub txids: HashSet<String>,
+    pub scripts: HashSet<Vec<u8>>,
+}
+
+impl StateBlacklist {
+    fn load(storage: &dyn Storage) -> StorageResult<Self> {
+        let mut blacklist = StateBlacklist::default();
+        for (key, _) in storage.scan(TREE_BLACKLIST)? {
+            let key = String::from_utf8_lossy(&key);
+            if let Some(txid) = key.strip_prefix("txid:") {
+                blacklist.txids.insert(txid.to_owned());
+            } else if let Some(script) = key.strip_prefix("script:") {
+                let script =
+                    hex::decode(script).map_err(|_| StorageError::InvalidKey(key.to_string()))?;
+                blacklist.scripts.insert(script);
+            } else {
+                return Err(StorageError::InvalidKey(key.to_string()));
+            }
+        }
+        Ok(blacklist)
+    }
+
+    fn key(entry: &json::BlacklistEntry) -> Vec<u8> {
+        entry.to_string().into_bytes()
+    }
+
+    fn contains_block(&self, block: &ResponseBlock) -> bool {
+        block.transactions.iter().any(|tx| {
+            self.txids.contains(&tx.txid)
+                || tx
+                    .vout
+                    .iter()
+                    .any(|output| self.scripts.contains(&output.script_pubkey.hex))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum RawPayload {
+    Hex(String),
+    Blacklisted,
+}
+
+#[derive(Debug, Default)]
+pub struct StateAccounting {
+    pub blocks: usize,
+    pub blocks_keep: usize,
+    // Outputs and spent outputs of tracked blocks
+    pub block_scripts: usize,
+    pub block_spent: usize,
+    // Entries of scripts index and distinct interned scripts
+    pub scripts: usize,
+    pub interned: usize,
+    pub spends_blocks: usize,
+    pub mempool: usize,
+    pub spends_mempool_txs: usize,
+    pub reorgs: usize,
+    pub reorgs_max: usize,
 }
 
 #[derive(Debug)]
 pub struct StateMempool {
+    // Changed with `insert` and `remove`, so histogram follows it
     pub transactions: HashMap<String, StateTransaction>,
+    pub histogram: FeeHistogram,
     pub last_log: Option<SystemTime>,
     pub added: usize,
     pub removed: usize,
+    // Transactions of blocks added while node mempool is fetched, `None` if
+    // mempool is not fetched
+    pub confirmed: Option<HashSet<String>>,
+}
+
+impl StateMempool {
+    fn insert(&mut self, hash: String, tx: StateTransaction) {
+        self.histogram.insert(tx.size, tx.fee);
+        if let Some(prev) = self.transactions.insert(hash, tx) {
+            self.histogram.remove(prev.size, prev.fee);
+        }
+    }
+
+    fn remove(&mut self, hash: &str) -> Option<StateTransaction> {
+        let tx = self.transactions.remove(hash)?;
+        self.histogram.remove(tx.size, tx.fee);
+        Some(tx)
+    }
+}
+
+// Transactions which are not in node mempool anymore and new transactions
+pub(super) fn diff_mempool<T>(
+    current: &HashMap<String, T>,
+    mut new: ResponseRawMempool,
+) -> (Vec<String>, Vec<(String, ResponseRawMempoolTransaction)>) {
+    let removed = current
+        .keys()
+        .filter(|hash| !new.contains_key(*hash))
+        .cloned()
+        .collect();
+    new.retain(|hash, _| !current.contains_key(hash));
+    (removed, new.into_iter().collect())
 }
 
 #[derive(Debug)]
 pub struct StateTransaction {
     pub size: u32,
+    // Satoshis
+    pub fee: u64,
+    // Unix time of mempool entry, 0 if unknown
+    pub time: u64,
+    // Unix time when transaction was seen by us first time
+    pub first_seen: u64,
+    // Package counts with transaction itself, updated with every mempool update
+    pub ancestors: u32,
+    pub descendants: u32,
 }
 
 impl From<ResponseRawMempoolTransaction> for StateTransaction {
     fn from(tx: ResponseRawMempoolTransaction) -> Self {
-        StateTransaction { size: tx.size }
+        StateTransaction {
+            size: tx.size,
+            fee: tx.fee_sat(),
+            time: tx.time,
+            first_seen: unix_time(),
+            ancestors: tx.ancestorcount,
+            descendants: tx.descendantcount,
+        }
     }
 }
 
+pub(super) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
 #[derive(Debug, PartialEq)]
 enum BlocksListSide {
     Front,
@@ -382,9 +2745,5 @@ enum UpdateBlocksModified {
     No,
 }
 
-#[derive(Debug, PartialEq)]
-enum EventsMempoolTx {
-    Added,
-    Confirmed,
-    Removed,
-}
+#[cfg(test)]
+mod tests;
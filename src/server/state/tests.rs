@@ -0,0 +1,2501 @@
+// Reorg scenarios against mock bitcoind: chain window, events order and
+// mempool reconciliation. Update steps are called directly instead of update
+// loop, so results do not depend on timings.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use proptest::prelude::*;
+use tokio::sync::broadcast;
+
+use super::{MempoolRefresh, State, UpdateBlocksModified, UpdateIntervals};
+use crate::server::api::watched_height_reply;
+use crate::server::bitcoind::mock::{funding, funding_script, txid, MockBitcoind};
+use crate::server::bitcoind::{wtxid, Bitcoind, BitcoindError, RestFormat, RetryPolicy};
+use crate::server::crosscheck::{CrossCheck, CrossCheckConfig};
+use crate::server::egress::{EgressBudget, EgressBudgets};
+use crate::server::error::AppError;
+use crate::server::events::{BlockEvent, BusMessage, ChainTimeSource, Event, EventBus, TxEvent};
+use crate::server::filters;
+use crate::server::json::{
+    BanEntry, BlockPage, ChainSplit, FeeEstimateMode, Maintenance, MempoolHistogram, NodeEntry,
+    PeerEntry, ReactionEntry, StreakKind, TimelockEntry, WsResumeBlock,
+};
+use crate::server::pool::{BitcoindPool, ChainSplitConfig};
+use crate::server::quotas::Period;
+use crate::server::rules::FeeAlerts;
+use crate::server::scheduler::Job;
+use crate::server::statsd::{Statsd, StatsdConfig};
+use crate::server::storage;
+use crate::server::tenants::{TenantConfig, Tenants};
+use crate::server::timings::StageStats;
+use crate::server::EventSchemas;
+use crate::signals::ShutdownReceiver;
+
+// Default of `--blocks-keep`
+const BLOCKS_KEEP: usize = 6;
+// Updates before chain considered as stuck
+const SYNC_STEPS_MAX: usize = 100;
+// Node chain is not shorter, so window can be always filled after disconnect
+const NODE_BLOCKS_MIN: usize = 20;
+
+type Window = Vec<(u32, String)>;
+type BlockEvents = Vec<(BlockEvent, u32, String)>;
+type TxEvents = Vec<(TxEvent, String)>;
+
+struct Harness {
+    node: MockBitcoind,
+    state: State,
+    events: broadcast::Receiver<BusMessage>,
+}
+
+impl Harness {
+    // Node with `blocks` blocks and server with initialized chain
+    async fn start(blocks: usize) -> Self {
+        let harness = Self::connect(MockBitcoind::start(blocks));
+        harness.init().await;
+        harness
+    }
+
+    fn connect(node: MockBitcoind) -> Self {
+        Self::connect_with(node, storage::open("memory").unwrap())
+    }
+
+    // Storage of previous server, as after restart
+    fn connect_with(node: MockBitcoind, storage: Box<dyn storage::Storage>) -> Self {
+        // Blocks of mock are only in JSON, except blocks of `mine_raw`, so
+        // every fetch is one request
+        let bitcoind = Bitcoind::new(&node.url).unwrap();
+        let bitcoind = bitcoind.with_rest_formats(&[RestFormat::Json]);
+        let events = EventBus::new(HashMap::new());
+        let state = State::new(
+            bitcoind,
+            events,
+            storage,
+            vec![],
+            vec![],
+            vec![],
+            None,
+            None,
+            FeeAlerts::default(),
+            None,
+            3,
+            Duration::from_secs(30),
+            BLOCKS_KEEP,
+            false,
+            UpdateIntervals::default(),
+        );
+        let state = state.unwrap();
+        let events = state.get_events_receiver(true);
+        Harness {
+            node,
+            state,
+            events,
+        }
+    }
+
+    async fn init(&self) {
+        let mut blocks = self.state.blocks.write().await;
+        self.state
+            .init_blocks(&mut blocks, None, None)
+            .await
+            .unwrap();
+    }
+
+    // Update chain until tip is not changed, then mempool
+    async fn sync(&self) {
+        for _ in 0..SYNC_STEPS_MAX {
+            if self.state.update_blocks().await.unwrap() == UpdateBlocksModified::No {
+                self.state.update_mempool().await.unwrap();
+                return;
+            }
+        }
+        panic!("chain not synced in {} updates", SYNC_STEPS_MAX);
+    }
+
+    async fn window(&self) -> Window {
+        let blocks = self.state.blocks.read().await;
+        blocks
+            .iter()
+            .map(|block| (block.height, block.hash.clone()))
+            .collect()
+    }
+
+    fn node_chain(&self) -> Window {
+        self.node.with(|node| {
+            node.chain()
+                .iter()
+                .map(|block| (block.height, block.hash.clone()))
+                .collect()
+        })
+    }
+
+    // Last blocks of node chain, as should be tracked by server
+    fn node_window(&self) -> Window {
+        let chain = self.node_chain();
+        chain[chain.len() - self.state.get_blocks_keep()..].to_vec()
+    }
+
+    fn node_hash(&self, height: u32) -> String {
+        self.node
+            .with(|node| node.chain()[height as usize].hash.clone())
+    }
+
+    async fn mempool(&self) -> Vec<String> {
+        let mempool = self.state.mempool.read().await;
+        let mut txids = mempool.transactions.keys().cloned().collect::<Vec<_>>();
+        txids.sort();
+        txids
+    }
+
+    // Height of spending transaction, `Some(None)` for mempool
+    async fn spend_height(&self, txid: &str) -> Option<Option<u32>> {
+        let outpoint = self::txid(&funding(txid));
+        let spend = self.state.get_outpoint_spend(&outpoint, 0).await.spend;
+        spend.map(|spend| {
+            assert_eq!(spend.txid, txid);
+            spend.height
+        })
+    }
+
+    // Transactions of tip block page, checked against total in block
+    async fn tip_page(&self, page: usize, limit: usize) -> Vec<String> {
+        let block = self.state.get_block_tip().await.unwrap().unwrap();
+        let total = block.transactions.len();
+        let page = BlockPage::new(block, page, limit);
+        assert_eq!(page.total, total);
+        page.transactions.into_iter().map(|tx| tx.hash).collect()
+    }
+
+    // Wait for update loop to follow node chain
+    async fn wait_synced(&self) -> bool {
+        for _ in 0..SYNC_STEPS_MAX {
+            if self.window().await == self.node_window() {
+                return true;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    async fn assert_synced(&self) {
+        assert_eq!(self.window().await, self.node_window());
+        let mempool = self.node.with(|node| node.mempool());
+        assert_eq!(self.mempool().await, mempool);
+
+        // Incremental histogram same as computed from whole mempool
+        let histogram = self.state.get_mempool_histogram().await;
+        let txs = self.state.mempool.read().await;
+        let vsize = txs.transactions.values().map(|tx| u64::from(tx.size));
+        let fee = txs.transactions.values().map(|tx| tx.fee);
+        assert_eq!(
+            (histogram.transactions, histogram.vsize, histogram.fee),
+            (txs.transactions.len(), vsize.sum(), fee.sum())
+        );
+    }
+
+    // Block and transaction events published since previous call
+    fn events(&mut self) -> (BlockEvents, TxEvents) {
+        let mut blocks = vec![];
+        let mut txs = vec![];
+        while let Ok(message) = self.events.try_recv() {
+            match message.event {
+                Event::Block(event, height, hash) => blocks.push((event, height, hash)),
+                Event::Tx(event, txid) => txs.push((event, txid)),
+                _ => {}
+            }
+        }
+        (blocks, txs)
+    }
+
+    fn block_events(&mut self) -> BlockEvents {
+        self.events().0
+    }
+
+    // Alert events since previous call, sorted
+    fn alerts(&mut self) -> Vec<(String, String)> {
+        let mut alerts = vec![];
+        while let Ok(message) = self.events.try_recv() {
+            if let Event::Alert(alert, txid) = message.event {
+                alerts.push((alert, txid));
+            }
+        }
+        alerts.sort();
+        alerts
+    }
+
+    // Outputs with expired timelock since previous call
+    fn timelocks(&mut self) -> Vec<(String, String, u32)> {
+        let mut timelocks = vec![];
+        while let Ok(message) = self.events.try_recv() {
+            if let Event::Timelock(tenant, txid, vout) = message.event {
+                timelocks.push((tenant, txid, vout));
+            }
+        }
+        timelocks
+    }
+
+    // Large transactions events since previous call: txid, value and height
+    fn large_txs(&mut self) -> Vec<(String, u64, Option<u32>)> {
+        let mut txs = vec![];
+        while let Ok(message) = self.events.try_recv() {
+            if let Event::Large(tx) = message.event {
+                txs.push((tx.txid, tx.value, tx.height));
+            }
+        }
+        txs
+    }
+
+    // Streak events since previous call: kind, length and end height
+    fn streaks(&mut self) -> Vec<(StreakKind, usize, u32)> {
+        let mut streaks = vec![];
+        while let Ok(message) = self.events.try_recv() {
+            if let Event::Streak(streak) = message.event {
+                streaks.push((streak.kind, streak.length, streak.end_height));
+            }
+        }
+        streaks
+    }
+
+    // Local and remote hashes of divergence alerts since previous call
+    fn divergences(&mut self) -> Vec<(String, String)> {
+        let mut divergences = vec![];
+        while let Ok(message) = self.events.try_recv() {
+            if let Event::Divergence(_, local, remote) = message.event {
+                divergences.push((local, remote));
+            }
+        }
+        divergences
+    }
+
+    // Maintenance mode changes since previous call
+    fn maintenance_events(&mut self) -> Vec<(bool, String)> {
+        let mut events = vec![];
+        while let Ok(message) = self.events.try_recv() {
+            if let Event::Maintenance(enabled, message) = message.event {
+                events.push((enabled, message));
+            }
+        }
+        events
+    }
+
+    fn chain_splits(&mut self) -> Vec<ChainSplit> {
+        let mut splits = vec![];
+        while let Ok(message) = self.events.try_recv() {
+            if let Event::ChainSplit(split) = message.event {
+                splits.push(split);
+            }
+        }
+        splits
+    }
+}
+
+// Apply block events to window, every event should change tip
+fn replay(mut window: Window, events: &[(BlockEvent, u32, String)]) -> Window {
+    for (event, height, hash) in events {
+        match event {
+            BlockEvent::Added => {
+                if let Some((tip, _)) = window.last() {
+                    assert_eq!(tip + 1, *height, "added block does not extend tip");
+                }
+                window.push((*height, hash.clone()));
+            }
+            BlockEvent::Removed => {
+                let tip = window.pop();
+                assert_eq!(
+                    tip,
+                    Some((*height, hash.clone())),
+                    "removed block is not tip"
+                );
+            }
+        }
+    }
+    window
+}
+
+fn removed(window: &[(u32, String)]) -> BlockEvents {
+    window
+        .iter()
+        .rev()
+        .map(|(height, hash)| (BlockEvent::Removed, *height, hash.clone()))
+        .collect()
+}
+
+async fn check_reorg(depth: usize) {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+    let old = harness.window().await;
+
+    harness.node.with(|node| node.reorg(depth));
+    harness.sync().await;
+    harness.assert_synced().await;
+
+    let events = harness.block_events();
+    let mut expected = removed(&old[old.len() - depth..]);
+    for height in 20 - depth as u32..=20 {
+        expected.push((BlockEvent::Added, height, harness.node_hash(height)));
+    }
+    assert_eq!(events, expected);
+
+    // Whole reorg reported once, from our old tip to node tip
+    let reorgs = harness.state.get_reorgs().reorgs;
+    assert_eq!(reorgs.len(), 1);
+    assert_eq!(reorgs[0].depth, depth as u32);
+    let (height, hash) = old.last().unwrap().clone();
+    assert_eq!(
+        (reorgs[0].old_tip.height, reorgs[0].old_tip.hash.clone()),
+        (height, hash)
+    );
+    let new_tip = (reorgs[0].new_tip.height, reorgs[0].new_tip.hash.clone());
+    assert_eq!(new_tip, (20, harness.node_hash(20)));
+}
+
+#[tokio::test]
+async fn reorg_1_deep() {
+    check_reorg(1).await;
+}
+
+#[tokio::test]
+async fn reorg_2_deep() {
+    check_reorg(2).await;
+}
+
+#[tokio::test]
+async fn reorg_6_deep() {
+    // Whole window replaced
+    check_reorg(BLOCKS_KEEP).await;
+}
+
+#[tokio::test]
+async fn blocks_keep_change() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    let old = harness.node_chain();
+
+    let keep = harness.state.set_blocks_keep(2).await.unwrap();
+    assert_eq!((keep.blocks, keep.tracked), (2, 2));
+    harness.assert_synced().await;
+    harness.events();
+
+    // Reorg deeper than window, stale blocks below window fetched by hash
+    harness.node.with(|node| node.reorg(4));
+    harness.sync().await;
+    harness.assert_synced().await;
+    assert_eq!(replay(old, &harness.block_events()), harness.node_chain());
+
+    let keep = harness.state.set_blocks_keep(10).await.unwrap();
+    assert_eq!((keep.blocks, keep.tracked), (10, 10));
+    harness.assert_synced().await;
+    harness.assert_window().await;
+}
+
+#[tokio::test]
+async fn reorg_during_init() {
+    let node = MockBitcoind::start(20);
+    let old = node.with(|node| {
+        // Tip and its parent fetched before reorg, rest of window from stale blocks
+        node.on_fetch(2, |node| node.reorg(3));
+        node.chain()[14..]
+            .iter()
+            .map(|block| (block.height, block.hash.clone()))
+            .collect::<Window>()
+    });
+    let mut harness = Harness::connect(node);
+    harness.init().await;
+    assert_eq!(harness.window().await, old);
+
+    harness.sync().await;
+    harness.assert_synced().await;
+    let mut expected = removed(&old[3..]);
+    for height in 17..=20 {
+        expected.push((BlockEvent::Added, height, harness.node_hash(height)));
+    }
+    assert_eq!(harness.block_events(), expected);
+}
+
+#[tokio::test]
+async fn reorg_during_catch_up() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+    let old = harness.window().await;
+
+    harness.node.with(|node| {
+        for _ in 0..20 {
+            node.mine();
+        }
+        // Catch up fetches blocks in parallel, so only final state is exact
+        node.on_fetch(4, |node| node.reorg(18));
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+
+    let events = harness.block_events();
+    assert!(replay(old, &events).ends_with(&harness.node_window()));
+}
+
+#[tokio::test]
+async fn block_not_found_on_update() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+    let old = harness.window().await;
+
+    let hash = harness.node.with(|node| {
+        let hash = node.mine().hash;
+        node.hide(&hash, 2);
+        hash
+    });
+    for _ in 0..2 {
+        let modified = harness.state.update_blocks().await.unwrap();
+        assert_eq!(modified, UpdateBlocksModified::Yes);
+        assert_eq!(harness.window().await, old);
+    }
+    assert!(harness.block_events().is_empty());
+
+    harness.sync().await;
+    harness.assert_synced().await;
+    assert_eq!(harness.block_events(), vec![(BlockEvent::Added, 20, hash)]);
+}
+
+#[tokio::test]
+async fn block_not_found_during_catch_up() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+
+    harness.node.with(|node| {
+        for _ in 0..20 {
+            node.mine();
+        }
+        let hash = node.chain()[27].hash.clone();
+        node.hide(&hash, 1);
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+
+    let expected = (20..40)
+        .map(|height| (BlockEvent::Added, height, harness.node_hash(height)))
+        .collect::<Vec<_>>();
+    assert_eq!(harness.block_events(), expected);
+}
+
+#[tokio::test]
+async fn block_error_during_catch_up() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+
+    harness.node.with(|node| {
+        for _ in 0..20 {
+            node.mine();
+        }
+        let hash = node.chain()[27].hash.clone();
+        node.fail(&hash);
+    });
+    // Blocks before failed one added, even if fetched after it
+    assert!(harness.state.update_blocks().await.is_err());
+    let expected = (20..27)
+        .map(|height| (BlockEvent::Added, height, harness.node_hash(height)))
+        .collect::<Vec<_>>();
+    assert_eq!(harness.block_events(), expected);
+    let window = harness.window().await;
+    assert_eq!(window.last(), Some(&(26, harness.node_hash(26))));
+}
+
+#[tokio::test]
+async fn tip_not_found_on_init() {
+    let node = MockBitcoind::start(20);
+    node.with(|node| {
+        let hash = node.tip().hash.clone();
+        node.hide(&hash, 1);
+    });
+    let harness = Harness::connect(node);
+    harness.init().await;
+    harness.assert_synced().await;
+}
+
+#[tokio::test]
+async fn node_height_regression() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+    let old = harness.window().await;
+
+    // Like `invalidateblock`: tip goes back without new blocks
+    harness.node.with(|node| node.disconnect(3));
+    harness.sync().await;
+    harness.assert_synced().await;
+
+    // Block at node height is removed too and added back, because same height
+    // reorg can be found only this way
+    let mut expected = removed(&old[2..]);
+    expected.push((BlockEvent::Added, 16, old[2].1.clone()));
+    assert_eq!(harness.block_events(), expected);
+
+    harness.node.with(|node| {
+        for _ in 0..4 {
+            node.mine();
+        }
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+    let expected = (17..=20)
+        .map(|height| (BlockEvent::Added, height, harness.node_hash(height)))
+        .collect::<Vec<_>>();
+    assert_eq!(harness.block_events(), expected);
+}
+
+#[tokio::test]
+async fn block_time_median() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.state.set_blocks_keep(12).await.unwrap();
+
+    // Earlier than tip, but later than median of previous 11 blocks (14)
+    harness.node.with(|node| {
+        node.set_block_time(20, node.block_time(14) + 1);
+        node.mine();
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+
+    let hash = harness.node.with(|node| {
+        node.set_block_time(21, node.block_time(20));
+        node.mine().hash
+    });
+    match harness.state.update_blocks().await {
+        Err(AppError::InvalidBlockTime(invalid, _)) => assert_eq!(invalid, hash),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn block_time_short_window() {
+    // Median of previous blocks is not known, so backwards time is accepted
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.node.with(|node| {
+        node.set_block_time(20, node.block_time(14) + 1);
+        node.mine();
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+
+    // Near genesis all previous blocks are known, as in bitcoind median of
+    // even count is upper one
+    let node = MockBitcoind::start(1);
+    node.with(|node| {
+        let start = node.block_time(0);
+        for (height, offset) in [(1, 1000), (2, 2000), (3, 3000), (4, 4000), (5, 3500)].iter() {
+            node.set_block_time(*height, start + offset);
+            node.mine();
+        }
+    });
+    let harness = Harness::connect(node);
+    harness.init().await;
+    assert_eq!(harness.window().await, harness.node_chain());
+
+    let hash = harness.node.with(|node| {
+        node.set_block_time(6, node.block_time(0) + 3000);
+        node.mine().hash
+    });
+    match harness.state.update_blocks().await {
+        Err(AppError::InvalidBlockTime(invalid, _)) => assert_eq!(invalid, hash),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn confirmation_times() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    let (a, b, c) = (txid("a"), txid("b"), txid("c"));
+
+    // Mock entry time is tip time, 600s for every block
+    harness.node.with(|node| {
+        node.add_mempool_with_fee(&a, 200, 600);
+        node.add_mempool_with_fee(&b, 200, 700);
+        node.add_mempool_with_fee(&c, 200, 6_000);
+    });
+    harness.sync().await;
+    harness.node.with(|node| node.mine_with(&[c]));
+    harness.sync().await;
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+
+    let bands = harness.state.get_confirmation_times().bands;
+    let medians = bands
+        .iter()
+        .map(|band| (band.min, band.samples, band.median))
+        .filter(|(_, samples, _)| *samples > 0)
+        .collect::<Vec<_>>();
+    assert_eq!(medians, vec![(2.0, 2, Some(1200)), (20.0, 1, Some(600))]);
+    assert_eq!(bands.last().unwrap().max, None);
+}
+
+#[tokio::test]
+async fn mempool_feerates() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    let feerates = harness.state.get_mempool_feerates().await;
+    assert_eq!(feerates.transactions, 0);
+    assert_eq!(feerates.max, 0.0);
+
+    // 1..=10 sat/vB
+    harness.node.with(|node| {
+        for rate in 1..=10 {
+            node.add_mempool_with_fee(&txid(&rate.to_string()), 200, rate * 200);
+        }
+    });
+    harness.sync().await;
+    let feerates = harness.state.get_mempool_feerates().await;
+    assert_eq!(feerates.transactions, 10);
+    let percentiles = [
+        feerates.min,
+        feerates.p10,
+        feerates.p50,
+        feerates.p90,
+        feerates.max,
+    ];
+    assert_eq!(percentiles, [1.0, 1.0, 5.0, 9.0, 10.0]);
+}
+
+#[tokio::test]
+async fn fee_alerts() {
+    let mut harness = Harness::start(20).await;
+    harness.state.fee_alerts = FeeAlerts {
+        fee: Some(100_000),
+        fee_rate: Some(100.0),
+    };
+    harness.sync().await;
+    harness.alerts();
+    let (a, b, c, d) = (txid("a"), txid("b"), txid("c"), txid("d"));
+
+    harness.node.with(|node| {
+        node.add_mempool_with_fee(&a, 200, 1_000);
+        node.add_mempool_with_fee(&b, 200, 200_000);
+        node.add_mempool_with_fee(&c, 100, 20_000);
+    });
+    harness.sync().await;
+    let mut expected = vec![
+        ("high-fee".to_owned(), b.clone()),
+        ("high-fee-rate".to_owned(), b),
+        ("high-fee-rate".to_owned(), c),
+    ];
+    expected.sort();
+    assert_eq!(harness.alerts(), expected);
+
+    // Not seen in mempool, checked with fee from block
+    harness.node.with(|node| {
+        node.add_mempool_with_fee(&d, 100, 500_000);
+        node.mine();
+    });
+    harness.sync().await;
+    let expected = vec![
+        ("high-fee".to_owned(), d.clone()),
+        ("high-fee-rate".to_owned(), d),
+    ];
+    assert_eq!(harness.alerts(), expected);
+}
+
+#[tokio::test]
+async fn large_txs() {
+    let mut harness = Harness::start(20).await;
+    // Mock transactions have one output of 1 BTC
+    harness.state.large_tx = Some(100_050_000);
+    harness.sync().await;
+    harness.large_txs();
+    let (a, b, c) = (txid("a"), txid("b"), txid("c"));
+
+    harness.node.with(|node| {
+        node.add_mempool_with_fee(&a, 200, 1_000);
+        node.add_mempool_with_fee(&b, 200, 100_000);
+    });
+    harness.sync().await;
+    assert_eq!(harness.large_txs(), vec![(b, 100_100_000, None)]);
+
+    // Only transaction not seen in mempool reported with block
+    let block = harness.node.with(|node| {
+        node.add_mempool_with_fee(&c, 200, 200_000);
+        node.mine()
+    });
+    harness.sync().await;
+    let expected = vec![(c, 100_200_000, Some(block.height))];
+    assert_eq!(harness.large_txs(), expected);
+}
+
+#[tokio::test]
+async fn block_streaks() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.streaks();
+    let stats = harness.state.get_stats().await.block_streaks;
+    assert_eq!(
+        stats.current.map(|streak| streak.kind),
+        Some(StreakKind::Empty)
+    );
+
+    harness.node.with(|node| {
+        node.add_mempool(&txid("a"));
+        node.mine()
+    });
+    harness.sync().await;
+    assert!(harness
+        .state
+        .get_stats()
+        .await
+        .block_streaks
+        .current
+        .is_none());
+
+    // Event once streak reaches threshold
+    let mut tip = 0;
+    for _ in 0..4 {
+        tip = harness.node.with(|node| node.mine()).height;
+        harness.sync().await;
+    }
+    assert_eq!(harness.streaks(), vec![(StreakKind::Empty, 3, tip - 1)]);
+
+    // Transaction alone above block weight limit
+    harness.node.with(|node| {
+        node.add_mempool_with_fee(&txid("b"), 1_000_000, 1_000);
+        node.mine()
+    });
+    harness.sync().await;
+    let stats = harness.state.get_stats().await.block_streaks;
+    let current = stats.current.unwrap();
+    assert_eq!((current.kind, current.length), (StreakKind::Full, 1));
+    assert_eq!(stats.full, 1);
+    assert!(stats.longest_empty >= 4);
+}
+
+#[tokio::test]
+async fn event_schemas() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.node.with(|node| {
+        node.add_mempool(&txid("a"));
+        node.reorg(2);
+    });
+    harness.sync().await;
+
+    // Every published event is known for client, fields taken from text
+    let schemas = EventSchemas::new();
+    let mut names = vec![];
+    while let Ok(message) = harness.events.try_recv() {
+        let parsed = schemas.parse(&message.event.to_string()).unwrap();
+        assert_eq!(parsed.topic, message.event.topic().to_string());
+        if let Event::Block(_, _, hash) = &message.event {
+            assert_eq!(parsed.fields["hash"], *hash);
+        }
+        names.push(parsed.event);
+    }
+    for name in &["AddedBlock", "RemovedBlock", "Reorg", "Added"] {
+        assert!(names.contains(name), "no {} event", name);
+    }
+
+    let parsed = schemas.parse(&Event::Maintenance(false, String::new()).to_string());
+    assert!(parsed.unwrap().fields.is_empty());
+    let parsed = schemas.parse(&Event::Maintenance(true, "a, b".to_owned()).to_string());
+    assert_eq!(parsed.unwrap().fields["message"], "a, b");
+    assert_eq!(schemas.parse("Added tx: abc"), None);
+}
+
+#[tokio::test]
+async fn event_sequence() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.node.with(|node| {
+        node.add_mempool(&txid("a"));
+        node.reorg(2);
+    });
+    harness.sync().await;
+
+    // Sequence without gaps across topics, time never goes back
+    let mut messages = vec![];
+    while let Ok(message) = harness.events.try_recv() {
+        messages.push(message);
+    }
+    let topics = messages.iter().map(|message| message.event.topic());
+    assert!(topics.collect::<std::collections::HashSet<_>>().len() > 1);
+    for (index, message) in messages.iter().enumerate() {
+        assert_eq!(message.seq, index as u64 + 1);
+        assert!(message.time.chain.is_some());
+    }
+    for pair in messages.windows(2) {
+        assert!(pair[0].time.received <= pair[1].time.received);
+    }
+
+    // Chain time of tip block event is median time past of new tip
+    let message = messages
+        .iter()
+        .rev()
+        .find(|message| matches!(message.event, Event::Block(BlockEvent::Added, ..)))
+        .unwrap();
+    let blocks = harness.state.blocks.read().await;
+    assert_eq!(message.time.chain, Some(State::tip_median_time(&blocks)));
+}
+
+#[test]
+fn event_sequence_concurrent() {
+    let mut bus = EventBus::new(HashMap::new());
+    bus.set_chain_time_source(ChainTimeSource::Tip);
+    let bus = Arc::new(bus);
+    let mut raw = bus.subscribe(true);
+    let mut coalesced = bus.subscribe(false);
+    bus.set_tip_time(Some((1_000, 900)));
+
+    let threads = (0..4)
+        .map(|thread| {
+            let bus = Arc::clone(&bus);
+            std::thread::spawn(move || {
+                for index in 0..100u32 {
+                    let hash = format!("{}:{}", thread, index);
+                    if index.is_multiple_of(2) {
+                        bus.publish(Event::Tx(TxEvent::Added, hash));
+                    } else {
+                        bus.publish(Event::Block(BlockEvent::Added, index, hash));
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // Order in channel is order of sequence, every channel counted separately
+    for receiver in [&mut raw, &mut coalesced].iter_mut() {
+        let mut seq = 0;
+        while let Ok(message) = receiver.try_recv() {
+            assert_eq!(message.seq, seq + 1);
+            assert_eq!(message.time.chain, Some(1_000));
+            seq = message.seq;
+        }
+        assert_eq!(seq, 400);
+    }
+}
+
+#[tokio::test]
+async fn update_pause() {
+    let harness = Harness::connect(MockBitcoind::start(20));
+    let mut shutdown = ShutdownReceiver::new();
+    let update_loop = harness.state.run_update_loop(shutdown.clone());
+    let check = async {
+        assert!(harness.wait_synced().await);
+        let pause = harness.state.set_paused(true).await;
+        assert!(pause.paused);
+        let stale_since = pause.stale_since.unwrap();
+        let window = harness.window().await;
+
+        // Nothing fetched while paused, repeated pause keeps time
+        harness.node.with(|node| node.mine());
+        tokio::time::delay_for(Duration::from_millis(300)).await;
+        assert_eq!(harness.window().await, window);
+        let (height, age) = harness.state.get_data_age().unwrap();
+        assert_eq!(height, window.last().unwrap().0);
+        assert!(age >= Duration::from_millis(300));
+        let stats = harness.state.get_stats().await;
+        assert_eq!(stats.stale_since, Some(stale_since));
+        assert_eq!(
+            harness.state.set_paused(true).await.stale_since,
+            Some(stale_since)
+        );
+
+        let pause = harness.state.set_paused(false).await;
+        assert_eq!((pause.paused, pause.stale_since), (false, None));
+        assert!(harness.wait_synced().await);
+        shutdown.set();
+    };
+    let (result, ()) = tokio::join!(update_loop, check);
+    result.unwrap();
+}
+
+#[tokio::test]
+async fn mempool_refresh_job() {
+    let harness = Harness::connect(MockBitcoind::start(20));
+    harness.node.with(|node| node.add_mempool(&txid("a")));
+
+    // Nothing before chain initialized by update loop
+    let mut job = MempoolRefresh;
+    job.run(&harness.state).await.unwrap();
+    assert!(harness.mempool().await.is_empty());
+
+    let mut shutdown = ShutdownReceiver::new();
+    let update_loop = harness.state.run_update_loop(shutdown.clone());
+    let check = async {
+        assert!(harness.wait_synced().await);
+        job.run(&harness.state).await.unwrap();
+        assert_eq!(harness.mempool().await, vec![txid("a")]);
+
+        // Paused with update loop
+        harness.state.set_paused(true).await;
+        harness.node.with(|node| node.add_mempool(&txid("b")));
+        job.run(&harness.state).await.unwrap();
+        assert_eq!(harness.mempool().await, vec![txid("a")]);
+        harness.state.set_paused(false).await;
+        job.run(&harness.state).await.unwrap();
+        harness.assert_synced().await;
+        shutdown.set();
+    };
+    let (result, ()) = tokio::join!(update_loop, check);
+    result.unwrap();
+}
+
+#[tokio::test]
+async fn block_pages() {
+    let harness = Harness::start(20).await;
+    harness.node.with(|node| {
+        for name in &["a", "b", "c", "d", "e"] {
+            node.add_mempool(&txid(name));
+        }
+        node.mine()
+    });
+    harness.sync().await;
+
+    let block = harness.state.get_block_tip().await.unwrap().unwrap();
+    let hashes = block
+        .transactions
+        .iter()
+        .map(|tx| tx.hash.clone())
+        .collect::<Vec<_>>();
+    assert!(hashes.len() >= 5);
+
+    assert_eq!(harness.tip_page(1, 2).await, hashes[..2]);
+    assert_eq!(harness.tip_page(2, 2).await, hashes[2..4]);
+    let last = hashes.len().div_ceil(2);
+    assert_eq!(harness.tip_page(last, 2).await, hashes[(last - 1) * 2..]);
+    assert!(harness.tip_page(last + 1, 2).await.is_empty());
+    assert!(harness.tip_page(usize::MAX, usize::MAX).await.is_empty());
+}
+
+#[tokio::test]
+async fn mempool_transaction() {
+    let harness = Harness::start(20).await;
+    let (a, b) = (txid("a"), txid("b"));
+    harness.node.with(|node| {
+        node.add_mempool_with_fee(&a, 200, 1_000);
+        node.set_package(&a, 1, 1);
+    });
+    harness.sync().await;
+    let tx = harness.state.get_mempool_transaction(&a).await.unwrap();
+    assert_eq!((tx.fee, tx.fee_rate), (1_000, 5.0));
+    assert_eq!((tx.ancestors, tx.descendants), (1, 1));
+    assert!(tx.first_seen > 0 && tx.time_in_mempool < 60);
+
+    // Counts of known transaction updated when child enters mempool
+    harness.node.with(|node| {
+        node.add_mempool(&b);
+        node.set_package(&a, 1, 2);
+        node.set_package(&b, 2, 1);
+    });
+    harness.sync().await;
+    let tx = harness.state.get_mempool_transaction(&a).await.unwrap();
+    assert_eq!((tx.ancestors, tx.descendants), (1, 2));
+    let tx = harness.state.get_mempool_transaction(&b).await.unwrap();
+    assert_eq!((tx.ancestors, tx.descendants), (2, 1));
+
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    assert!(harness.state.get_mempool_transaction(&a).await.is_none());
+}
+
+#[tokio::test]
+async fn chain_split() {
+    let mut harness = Harness::start(20).await;
+    let other = MockBitcoind::start(20);
+    let config = ChainSplitConfig {
+        nodes: vec![other.url.parse().unwrap()],
+        interval: Duration::from_secs(30),
+        depth: 1,
+    };
+    let mut pool = BitcoindPool::new(&config).unwrap();
+    let split = |nodes: &[crate::server::json::NodeTip]| nodes[1].split;
+
+    pool.check(&harness.state).await;
+    let nodes = harness.state.get_nodes().nodes;
+    assert!(nodes[0].primary && !nodes[1].primary);
+    assert_eq!(nodes[0].hash, nodes[1].hash);
+
+    // Different blocks at tip are race, not split
+    harness.node.with(|node| node.mine());
+    other.with(|node| node.reorg(0));
+    pool.check(&harness.state).await;
+    let nodes = harness.state.get_nodes().nodes;
+    assert_ne!(nodes[0].hash, nodes[1].hash);
+    assert!(!split(&nodes));
+    assert!(harness.chain_splits().is_empty());
+
+    // Alert sent once while chains differ below tip
+    harness.node.with(|node| node.mine());
+    other.with(|node| node.mine());
+    pool.check(&harness.state).await;
+    pool.check(&harness.state).await;
+    assert!(split(&harness.state.get_nodes().nodes));
+    let splits = harness.chain_splits();
+    assert_eq!(splits.len(), 1);
+    assert_eq!(splits[0].height, 20);
+    assert_ne!(splits[0].primary_hash, splits[0].node_hash);
+    let text = Event::ChainSplit(splits[0].clone()).to_string();
+    assert_eq!(
+        EventSchemas::new().parse(&text).unwrap().fields["height"],
+        "20"
+    );
+
+    // Node back to common chain
+    other.with(|node| node.disconnect(2));
+    pool.check(&harness.state).await;
+    assert!(!split(&harness.state.get_nodes().nodes));
+}
+
+#[tokio::test]
+async fn clock_skew() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    let skew = harness.state.get_clock_skew();
+    assert_eq!(skew.report().server_offset, None);
+
+    // Entry time of new transaction is behind our clock by poll delay only
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+    harness.node.with(|node| {
+        node.add_mempool(&txid("a"));
+        node.set_entry_time(&txid("a"), now - 100);
+    });
+    harness.sync().await;
+    let offset = skew.report().server_offset.unwrap();
+    assert!((100..110).contains(&offset));
+    skew.check();
+    assert!(skew.report().exceeded);
+
+    harness.node.with(|node| node.set_timeoffset(-40));
+    assert_eq!(harness.state.update_node_time_offset().await.unwrap(), -40);
+    let report = skew.report();
+    assert_eq!(report.node_offset, Some(-40));
+    let correction = harness.state.get_confirmation_times().skew_correction;
+    assert_eq!(correction, Some(40));
+}
+
+#[tokio::test]
+async fn bandwidth_accounting() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+
+    // Metrics are global, other tests use other mock nodes
+    let stats = harness.state.get_stats().await;
+    let name = harness.state.bitcoind_name();
+    let rpc = stats.traffic.bitcoind[&format!("{} rpc", name)];
+    assert!(rpc.sent > 0 && rpc.received > 0);
+    let rest = stats.traffic.bitcoind[&format!("{} rest", name)];
+    assert!(rest.sent == 0 && rest.received > 0);
+
+    let budgets = EgressBudgets::new(vec![EgressBudget {
+        period: Period::Day,
+        limit: 100,
+    }]);
+    budgets.record(60);
+    assert_eq!(budgets.exceeded(), None);
+    budgets.record(40);
+    let (period, reset_after) = budgets.exceeded().unwrap();
+    assert_eq!(period, Period::Day);
+    assert!(reset_after > 0 && reset_after <= 24 * 60 * 60);
+    assert_eq!(budgets.report()[0].used, 100);
+}
+
+#[tokio::test]
+async fn bitcoind_retries() {
+    let harness = Harness::start(20).await;
+    let retry = RetryPolicy {
+        retries: 2,
+        delay: Duration::from_millis(1),
+    };
+    let bitcoind = Bitcoind::new(&harness.node.url).unwrap().with_retry(retry);
+    *harness.state.bitcoind.write().unwrap() = Arc::new(bitcoind);
+    harness.sync().await;
+
+    harness.node.with(|node| {
+        node.mine();
+        node.drop_requests(2);
+    });
+    harness.sync().await;
+    assert_eq!(harness.window().await, harness.node_window());
+
+    // Error after retries, state is not changed
+    harness.node.with(|node| {
+        node.mine();
+        node.drop_requests(3);
+    });
+    assert!(harness.state.update_blocks().await.is_err());
+    harness.sync().await;
+    assert_eq!(harness.window().await, harness.node_window());
+}
+
+#[tokio::test]
+async fn bitcoind_switch_not_ready() {
+    let harness = Harness::connect(MockBitcoind::start(20));
+    let switch = harness.state.switch_bitcoind(&harness.node.url).await;
+    assert!(matches!(switch, Err(AppError::NotReady)));
+
+    // Chain known, but window is not loaded yet
+    *harness.state.chain.lock().unwrap() = Some("regtest".to_owned());
+    let switch = harness.state.switch_bitcoind(&harness.node.url).await;
+    assert!(matches!(switch, Err(AppError::NotReady)));
+
+    harness.init().await;
+    let switch = harness.state.switch_bitcoind(&harness.node.url).await;
+    let switch = switch.unwrap();
+    assert_eq!((switch.tip.height, switch.resynced), (19, 0));
+}
+
+#[tokio::test]
+async fn ws_resume() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    let window = harness.window().await;
+    let (height, hash) = window[window.len() - 3].clone();
+    let last = |hash: &str, height| WsResumeBlock {
+        hash: hash.to_owned(),
+        height,
+    };
+
+    let blocks = harness.state.get_blocks_since(&last(&hash, None)).await;
+    assert_eq!(blocks, Some(window[window.len() - 2..].to_vec()));
+
+    // Removed block replaced by blocks from same height
+    harness.node.with(|node| node.reorg(3));
+    harness.sync().await;
+    let window = harness.window().await;
+    assert!(window.iter().all(|block| block.1 != hash));
+    let blocks = harness
+        .state
+        .get_blocks_since(&last(&hash, Some(height)))
+        .await;
+    let expected = window.iter().filter(|block| block.0 >= height).cloned();
+    assert_eq!(blocks, Some(expected.collect()));
+
+    // Unknown block without height or below tracked blocks
+    assert_eq!(
+        harness.state.get_blocks_since(&last(&hash, None)).await,
+        None
+    );
+    let blocks = harness.state.get_blocks_since(&last(&hash, Some(0))).await;
+    assert_eq!(blocks, None);
+}
+
+#[tokio::test]
+async fn rest_formats() {
+    let harness = Harness::start(20).await;
+    let formats = [RestFormat::Bin, RestFormat::Json];
+    let bitcoind = Bitcoind::new(&harness.node.url)
+        .unwrap()
+        .with_rest_formats(&formats);
+    *harness.state.bitcoind.write().unwrap() = Arc::new(bitcoind);
+    harness.sync().await;
+
+    // Blocks without binary form fetched as JSON
+    let block = harness.node.with(|node| {
+        node.mine();
+        node.mine_raw("raw")
+    });
+    harness.sync().await;
+    assert_eq!(harness.window().await, harness.node_window());
+    let blocks = harness.state.blocks.read().await;
+    assert_eq!(blocks.back().unwrap().transactions.len(), 2);
+    drop(blocks);
+
+    // Decoded block is same as JSON from bitcoind
+    let get_block = |formats: &[RestFormat]| {
+        let bitcoind = Bitcoind::new(&harness.node.url).unwrap();
+        let bitcoind = bitcoind.with_rest_formats(formats);
+        let hash = block.hash.clone();
+        async move {
+            let block = bitcoind.getblockbyhash(&hash).await.unwrap();
+            serde_json::to_value(block).unwrap()
+        }
+    };
+    let json = get_block(&[RestFormat::Json]).await;
+    assert_eq!(json["height"], block.height);
+    // Fee of decoded block from spent outputs
+    assert_eq!(json["tx"][1]["fee"], 0.00001);
+    assert_eq!(get_block(&[RestFormat::Hex]).await, json);
+    assert_eq!(get_block(&[RestFormat::Bin]).await, json);
+
+    // Not found in last format
+    let hash = harness.node_hash(block.height - 1);
+    let bitcoind = Bitcoind::new(&harness.node.url).unwrap();
+    let bitcoind = bitcoind.with_rest_formats(&[RestFormat::Bin]);
+    assert!(bitcoind.getblockbyhash(&hash).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn headers_first() {
+    let mut harness = Harness::connect(MockBitcoind::start(20));
+    harness.state.headers_first = true;
+    harness.init().await;
+    assert_eq!(harness.window().await, harness.node_window());
+
+    // Block not found by hash from headers, blocks fetched one by one
+    let hash = harness.node_hash(10);
+    harness.node.with(|node| node.hide(&hash, 1));
+    harness.state.set_blocks_keep(12).await.unwrap();
+    assert_eq!(harness.window().await, harness.node_window());
+
+    // Window larger than chain
+    let result = harness.state.set_blocks_keep(30).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn block_filters() {
+    // Testnet genesis block, vector from BIP158
+    let script = hex::decode(
+        "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35\
+         504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac",
+    )
+    .unwrap();
+    let hash = "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943";
+    let (elements, filter) = filters::build_filter(hash, std::iter::once(script.as_slice()));
+    assert_eq!((elements, hex::encode(filter)), (1, "019dfca8".to_owned()));
+
+    let harness = Harness::start(20).await;
+    let block = harness.node.with(|node| node.mine_raw("raw"));
+    let filter = harness.state.get_block_filter(&block.hash).await.unwrap();
+    let filter = filter.unwrap();
+    assert_eq!(filter.height, block.height);
+
+    // Outputs except witness commitment and spent output
+    let raw = harness.state.bitcoind().getblockraw(&block.hash).await;
+    let raw = raw.unwrap().unwrap();
+    let mut scripts = raw
+        .transactions
+        .iter()
+        .map(|tx| tx.vout[0].script_pubkey.hex.clone())
+        .collect::<Vec<_>>();
+    scripts.push(funding_script("raw"));
+    let (elements, expected) =
+        filters::build_filter(&block.hash, scripts.iter().map(|script| script.as_slice()));
+    assert_eq!(filter.elements, 3);
+    assert_eq!(
+        (filter.elements, filter.filter.clone()),
+        (elements, hex::encode(expected))
+    );
+
+    // Cached, so served while node does not have block
+    harness.node.with(|node| node.hide(&block.hash, 1));
+    let cached = harness.state.get_block_filter(&block.hash).await.unwrap();
+    assert_eq!(cached.unwrap().filter, filter.filter);
+
+    // Block without binary form
+    let block = harness.node.with(|node| node.mine());
+    let filter = harness.state.get_block_filter(&block.hash).await.unwrap();
+    assert!(filter.is_none());
+}
+
+#[tokio::test]
+async fn watch_export() {
+    let harness = Harness::start(20).await;
+    let tenant = TenantConfig {
+        id: "acme".to_owned(),
+        api_key: "key".to_owned(),
+        max_watch: 10,
+    };
+    let storage = harness.state.storage.as_ref();
+    *harness.state.tenants.write().await = Tenants::load(vec![tenant], storage).unwrap();
+    harness.sync().await;
+
+    // Mock transaction spends first output of `txid(funding(id))`
+    let b = txid("b");
+    let a = txid(&funding(&b));
+    let script = hex::decode(format!("0014{}", &a[..40])).unwrap();
+    harness
+        .state
+        .add_watch("acme", script.clone())
+        .await
+        .unwrap();
+    let export = |from| harness.state.export_watch("acme", &script, from, None);
+
+    harness.node.with(|node| {
+        node.add_mempool(&a);
+        node.mine();
+        node.add_mempool(&b);
+        node.mine();
+    });
+    harness.sync().await;
+    let expected = format!(
+        "timestamp,txid,direction,amount,confirmations\n\
+         2020-09-13T15:46:40Z,{},in,1.00000000,2\n\
+         2020-09-13T15:56:40Z,{},out,1.00000000,1\n",
+        a, b
+    );
+    assert_eq!(export(None).await, expected);
+    assert_eq!(export(Some(1_600_012_600)).await.lines().count(), 2);
+
+    // Spend is recorded again after reorg
+    harness.node.with(|node| node.reorg(1));
+    harness.sync().await;
+    assert_eq!(export(None).await.lines().count(), 2);
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    let csv = export(None).await;
+    let last = csv.lines().last().unwrap();
+    assert_eq!(last, format!("2020-09-13T16:16:40Z,{},out,1.00000000,1", b));
+}
+
+#[tokio::test]
+async fn watch_timelocks() {
+    let mut harness = Harness::start(20).await;
+    let tenant = TenantConfig {
+        id: "acme".to_owned(),
+        api_key: "key".to_owned(),
+        max_watch: 10,
+    };
+    let storage = harness.state.storage.as_ref();
+    *harness.state.tenants.write().await = Tenants::load(vec![tenant], storage).unwrap();
+    let a = txid("a");
+    harness.node.with(|node| node.add_mempool(&a));
+    harness.sync().await;
+
+    let (tip, _) = harness.window().await.pop().unwrap();
+    let mut entry = TimelockEntry {
+        txid: a.clone(),
+        vout: 0,
+        locktime: Some(tip + 3),
+        sequence: Some(1 << 31),
+    };
+    let add = harness.state.add_timelock("acme", &entry).await;
+    assert!(add.is_err(), "disabled sequence accepted");
+    entry.sequence = Some(2);
+    harness.state.add_timelock("acme", &entry).await.unwrap();
+
+    // Relative lock counted from confirmation, then absolute lock expires
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    let timelocks = harness.state.get_timelocks("acme").await.timelocks;
+    assert_eq!(timelocks[0].height, Some(tip + 1));
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    assert_eq!(harness.timelocks(), vec![]);
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    let expired = vec![("acme".to_owned(), a.clone(), 0)];
+    assert_eq!(harness.timelocks(), expired);
+    assert!(harness.state.get_timelocks("acme").await.timelocks[0].spendable);
+
+    // Reported again when reached after reorg
+    harness.node.with(|node| node.reorg(1));
+    harness.sync().await;
+    assert_eq!(harness.timelocks(), expired);
+    harness.state.remove_timelock("acme", &a, 0).await.unwrap();
+    assert!(harness
+        .state
+        .get_timelocks("acme")
+        .await
+        .timelocks
+        .is_empty());
+}
+
+#[tokio::test]
+async fn watch_reactions() {
+    let harness = Harness::start(20).await;
+    let tenant = TenantConfig {
+        id: "acme".to_owned(),
+        api_key: "key".to_owned(),
+        max_watch: 10,
+    };
+    let storage = harness.state.storage.as_ref();
+    *harness.state.tenants.write().await = Tenants::load(vec![tenant], storage).unwrap();
+    harness.sync().await;
+
+    // Mock transaction spends first output of `txid(funding(id))`
+    let (b, c) = (txid("b"), txid("c"));
+    for (spend, tx, dry_run) in [(&b, "0200", false), (&c, "0201", true)].iter() {
+        let entry = ReactionEntry {
+            txid: txid(&funding(spend)),
+            vout: 0,
+            tx: tx.to_string(),
+            dry_run: *dry_run,
+        };
+        harness.state.add_reaction("acme", &entry).await.unwrap();
+    }
+    let audit = |action: &str| {
+        let records = harness.state.get_audit(100).unwrap();
+        records
+            .into_iter()
+            .filter(|record| record.action == action)
+            .map(|record| record.result)
+            .collect::<Vec<_>>()
+    };
+
+    // Spend seen in mempool, reaction is relayed to node
+    harness.node.with(|node| node.add_mempool(&b));
+    harness.sync().await;
+    let broadcast = audit("watch.reaction.broadcast");
+    assert_eq!(broadcast.len(), 1);
+    let reaction = broadcast[0].strip_prefix("ok: ").unwrap().to_owned();
+    let mut mempool = harness.node.with(|node| node.mempool());
+    mempool.sort();
+    let mut expected = vec![b.clone(), reaction];
+    expected.sort();
+    assert_eq!(mempool, expected);
+
+    // Spend seen only in block, dry run is not relayed and not triggered again
+    harness
+        .node
+        .with(|node| node.mine_with(std::slice::from_ref(&c)));
+    harness.sync().await;
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    assert_eq!(audit("watch.reaction.dry-run"), vec!["ok".to_owned()]);
+    assert_eq!(audit("watch.reaction.broadcast").len(), 1);
+    let reactions = harness.state.get_reactions("acme").await.reactions;
+    let spent_by = reactions
+        .into_iter()
+        .map(|reaction| reaction.spent_by.unwrap())
+        .collect::<Vec<_>>();
+    let mut expected = vec![b, c];
+    expected.sort_by_key(|spend| txid(&funding(spend)));
+    assert_eq!(spent_by, expected);
+}
+
+#[tokio::test]
+async fn fee_estimate_cache() {
+    let harness = Harness::start(20).await;
+    let estimate = |target| {
+        let mode = FeeEstimateMode::Economical;
+        harness.state.get_fee_estimate(target, mode)
+    };
+    let estimate_first = estimate(6).await.unwrap();
+    assert_eq!(estimate_first.feerate, None);
+    assert_eq!(estimate_first.errors.len(), 1);
+
+    // Same result until new block
+    harness.node.with(|node| node.set_feerate(Some(0.0001)));
+    assert_eq!(estimate(6).await.unwrap().feerate, None);
+    assert_eq!(estimate(2).await.unwrap().sat_per_vbyte, Some(10.0));
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    let estimate_next = estimate(6).await.unwrap();
+    assert_eq!(estimate_next.feerate, Some(0.0001));
+    assert_eq!(estimate_next.height, estimate_first.height + 1);
+}
+
+#[tokio::test]
+async fn broadcast_tx() {
+    let harness = Harness::start(20).await;
+    let result = harness.state.broadcast_tx("0200").await.unwrap();
+    assert_eq!(harness.node.with(|node| node.mempool()), vec![result.txid]);
+
+    match harness.state.broadcast_tx("").await {
+        Err(BitcoindError::ResultRPC(err)) => assert_eq!(err.code, -22),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn transaction_lookup() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    let a = txid("a");
+    assert!(harness.state.get_transaction(&a).await.unwrap().is_none());
+
+    harness.node.with(|node| node.add_mempool(&a));
+    harness.sync().await;
+    let tx = harness.state.get_transaction(&a).await.unwrap().unwrap();
+    assert_eq!(tx.txid, a);
+    assert_eq!(tx.inputs[0].txid, Some(txid(&funding(&a))));
+    assert_eq!(tx.outputs[0].value, 100_000_000);
+    assert!(tx.block.is_none());
+    assert_eq!(tx.confirmations, 0);
+
+    // Confirmed in tracked block, found without `-txindex`
+    let block = harness.node.with(|node| {
+        let block = node.mine();
+        node.mine_with(&[]);
+        block
+    });
+    harness.sync().await;
+    let tx = harness.state.get_transaction(&a).await.unwrap().unwrap();
+    let tx_block = tx.block.unwrap();
+    assert_eq!((tx_block.height, tx_block.hash), (block.height, block.hash));
+    assert_eq!(tx.confirmations, 2);
+}
+
+#[tokio::test]
+async fn mempool_reconciliation() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+    let (a, b) = (txid("a"), txid("b"));
+
+    harness.node.with(|node| {
+        node.add_mempool(&a);
+        node.add_mempool(&b);
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+    let (_, mut txs) = harness.events();
+    txs.sort_by(|x, y| x.1.cmp(&y.1));
+    let mut expected = vec![(TxEvent::Added, a.clone()), (TxEvent::Added, b.clone())];
+    expected.sort_by(|x, y| x.1.cmp(&y.1));
+    assert_eq!(txs, expected);
+    assert_eq!(harness.spend_height(&a).await, Some(None));
+
+    // Confirmed in block
+    harness
+        .node
+        .with(|node| node.mine_with(std::slice::from_ref(&a)));
+    harness.sync().await;
+    harness.assert_synced().await;
+    let (_, txs) = harness.events();
+    assert_eq!(txs, vec![(TxEvent::Confirmed, a.clone())]);
+    assert_eq!(harness.spend_height(&a).await, Some(Some(20)));
+
+    // Block with transaction reorged out, transaction back in mempool
+    harness.node.with(|node| node.reorg(1));
+    harness.sync().await;
+    harness.assert_synced().await;
+    let (blocks, txs) = harness.events();
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(txs, vec![(TxEvent::Added, a.clone())]);
+
+    // Evicted from mempool
+    harness.node.with(|node| node.remove_mempool(&b));
+    harness.sync().await;
+    harness.assert_synced().await;
+    let (_, txs) = harness.events();
+    assert_eq!(txs, vec![(TxEvent::Removed, b.clone())]);
+    assert_eq!(harness.spend_height(&b).await, None);
+
+    // Confirmed again in new chain
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    harness.assert_synced().await;
+    let (_, txs) = harness.events();
+    assert_eq!(txs, vec![(TxEvent::Confirmed, a)]);
+}
+
+#[tokio::test]
+async fn mempool_histogram() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    let (a, b, c) = (txid("a"), txid("b"), txid("c"));
+
+    // Bands of 1.5, 50 and 0.5 sat/vB
+    harness.node.with(|node| {
+        node.add_mempool_with_fee(&a, 100, 150);
+        node.add_mempool_with_fee(&b, 200, 10_000);
+        node.add_mempool_with_fee(&c, 100, 50);
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+    let bands = |histogram: MempoolHistogram| {
+        let bands = histogram
+            .bands
+            .into_iter()
+            .filter(|band| band.transactions > 0);
+        bands
+            .map(|band| (band.min, band.transactions, band.vsize, band.weight))
+            .collect::<Vec<_>>()
+    };
+    let histogram = harness.state.get_mempool_histogram().await;
+    assert_eq!(histogram.fee, 10_200);
+    assert_eq!(
+        bands(histogram),
+        vec![(0.0, 1, 100, 400), (1.0, 1, 100, 400), (50.0, 1, 200, 800)]
+    );
+
+    // Confirmed and evicted transactions leave their bands
+    harness.node.with(|node| {
+        node.mine_with(std::slice::from_ref(&a));
+        node.remove_mempool(&c);
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+    let histogram = harness.state.get_mempool_histogram().await;
+    assert_eq!(bands(histogram), vec![(50.0, 1, 200, 800)]);
+}
+
+#[tokio::test]
+async fn projected_block_event() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+    let child = txid("child");
+    let parent = txid(&funding(&child));
+
+    harness.node.with(|node| {
+        node.add_mempool_with_fee(&parent, 100, 100);
+        node.add_mempool_with_fee(&child, 100, 5_000);
+        node.add_mempool_with_fee(&txid("other"), 100, 2_000);
+    });
+    harness.sync().await;
+    harness.state.update_projected_block().await;
+
+    let block = harness.state.get_projected_block().await;
+    assert_eq!(block.height, 20);
+    assert_eq!(block.txids, vec![parent, child, txid("other")]);
+    let events = std::iter::from_fn(|| harness.events.try_recv().ok());
+    let text = events
+        .filter(|message| matches!(message.event, Event::ProjectedBlock(_)))
+        .map(|message| message.event.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        text,
+        vec!["ProjectedBlock 20: 3 txs, weight 1200, fees 7100 sat"]
+    );
+    let parsed = EventSchemas::new().parse(&text[0]).unwrap();
+    assert_eq!(parsed.fields["fees"], "7100");
+}
+
+#[tokio::test]
+async fn mempool_fetched_before_block() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+    let a = txid("a");
+
+    // Node mempool fetched, then block with transaction added before
+    // mempool applied
+    harness.node.with(|node| node.add_mempool(&a));
+    harness.state.mempool.write().await.confirmed = Some(Default::default());
+    let bitcoind = Bitcoind::new(&harness.node.url).unwrap();
+    let mempool = bitcoind.getrawmempool().await.unwrap();
+    harness
+        .node
+        .with(|node| node.mine_with(std::slice::from_ref(&a)));
+    let modified = harness.state.update_blocks().await.unwrap();
+    assert_eq!(modified, UpdateBlocksModified::Yes);
+    harness.state.apply_mempool(mempool).await.unwrap();
+
+    // Not added back from stale mempool
+    assert_eq!(harness.mempool().await, Vec::<String>::new());
+    let (blocks, txs) = harness.events();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(txs, vec![]);
+    harness.sync().await;
+    harness.assert_synced().await;
+}
+
+// Change of node chain, followed by sync of server
+#[derive(Debug, Clone)]
+enum ChainOp {
+    // More than `CATCH_UP_BLOCKS_MIN` blocks go through catch up
+    Mine(usize),
+    Reorg(usize),
+    Disconnect(usize),
+}
+
+fn chain_op() -> impl Strategy<Value = ChainOp> {
+    prop_oneof![
+        (1..16usize).prop_map(ChainOp::Mine),
+        (1..10usize).prop_map(ChainOp::Reorg),
+        (1..4usize).prop_map(ChainOp::Disconnect),
+    ]
+}
+
+impl Harness {
+    fn apply(&self, op: &ChainOp) {
+        self.node.with(|node| match *op {
+            ChainOp::Mine(count) => {
+                for _ in 0..count {
+                    node.mine();
+                }
+            }
+            ChainOp::Reorg(depth) => node.reorg(depth),
+            ChainOp::Disconnect(count) => {
+                let count = count.min(node.chain().len() - NODE_BLOCKS_MIN);
+                node.disconnect(count);
+            }
+        });
+    }
+
+    // Window is full, linked, same as node window and scripts index has only
+    // outputs of window transactions
+    async fn assert_window(&self) {
+        let blocks = self.state.blocks.read().await;
+        assert_eq!(blocks.len(), self.state.get_blocks_keep());
+        for (prev, block) in blocks.iter().zip(blocks.iter().skip(1)) {
+            assert_eq!(prev.height + 1, block.height);
+            assert_eq!(block.prevhash.as_ref(), Some(&prev.hash));
+        }
+
+        // Every mock transaction has one output
+        let transactions = blocks
+            .iter()
+            .map(|block| block.transactions.len())
+            .sum::<usize>();
+        let scripts = self.state.scripts.read().await;
+        let indexed = scripts.transactions.values().map(Vec::len).sum::<usize>();
+        assert_eq!(indexed, transactions);
+        drop(scripts);
+        drop(blocks);
+
+        assert_eq!(self.window().await, self.node_window());
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn window_invariants(ops in prop::collection::vec(chain_op(), 1..8)) {
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut harness = Harness::start(NODE_BLOCKS_MIN + 10).await;
+            harness.sync().await;
+            harness.events();
+
+            for op in ops.iter() {
+                // Reorg deeper than window removes blocks which were added to
+                // front of window without events
+                let old = harness.node_chain();
+                harness.apply(op);
+                harness.sync().await;
+                harness.assert_window().await;
+
+                let events = harness.block_events();
+                assert!(replay(old, &events).ends_with(&harness.node_window()));
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn server_info() {
+    let harness = Harness::start(20).await;
+    assert_eq!(harness.state.get_chain().await.unwrap(), "regtest");
+
+    let tip = harness.state.get_tip().await;
+    assert_eq!(
+        (tip.height, tip.hash.clone()),
+        harness.node_window()[BLOCKS_KEEP - 1]
+    );
+    // Coinbase and transactions of mock block
+    let txids = harness
+        .node
+        .with(|node| node.chain().last().unwrap().txids.len());
+    assert_eq!(tip.transactions, txids + 1);
+
+    // API settings are not known to state
+    let features = harness.state.get_features().await;
+    assert!(!features.admin && !features.tls && !features.tenants);
+    assert_eq!(
+        (features.hooks, features.plugins, features.rules),
+        (0, 0, 0)
+    );
+    assert!(features.protobuf);
+}
+
+#[tokio::test]
+async fn cross_check() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+    let other = MockBitcoind::start(20);
+    let config = CrossCheckConfig {
+        url: other.url.clone(),
+        interval: Duration::from_secs(1),
+        divergence: Duration::from_secs(0),
+    };
+    let mut job = CrossCheck::new(&config).unwrap();
+
+    job.run(&harness.state).await.unwrap();
+    assert!(harness.divergences().is_empty());
+
+    // Compared at local tip height, alert sent once
+    other.with(|node| node.reorg(1));
+    job.run(&harness.state).await.unwrap();
+    let local = harness.node_hash(19);
+    let remote = other.with(|node| node.chain()[19].hash.clone());
+    assert_eq!(harness.divergences(), vec![(local, remote)]);
+    job.run(&harness.state).await.unwrap();
+    assert!(harness.divergences().is_empty());
+
+    // Same reorg on our node
+    harness.node.with(|node| node.reorg(1));
+    harness.sync().await;
+    job.run(&harness.state).await.unwrap();
+    assert!(harness.divergences().is_empty());
+    let remote_tip = other.with(|node| node.tip().hash.clone());
+    assert_eq!(harness.node_window().last().unwrap().1, remote_tip);
+
+    // Unavailable source is error, not divergence
+    other.with(|node| node.drop_requests(10));
+    assert!(job.run(&harness.state).await.is_err());
+}
+
+#[tokio::test]
+async fn maintenance_mode() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+    let maintenance = |enabled, message: &str| Maintenance {
+        enabled,
+        message: message.to_owned(),
+        retry_after: 60,
+    };
+
+    // Subscribers notified only on changes
+    harness.state.set_maintenance(maintenance(true, "upgrade"));
+    harness.state.set_maintenance(maintenance(true, "upgrade"));
+    harness.state.set_maintenance(maintenance(true, "reindex"));
+    harness.state.set_maintenance(maintenance(false, ""));
+    harness.state.set_maintenance(maintenance(false, "done"));
+    assert_eq!(
+        harness.maintenance_events(),
+        vec![
+            (true, "upgrade".to_owned()),
+            (true, "reindex".to_owned()),
+            (false, "".to_owned())
+        ]
+    );
+
+    // Update loop is not affected
+    harness.state.set_maintenance(maintenance(true, "upgrade"));
+    assert!(harness.state.get_maintenance().enabled);
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    harness.assert_synced().await;
+}
+
+#[tokio::test]
+async fn mempool_accept() {
+    let harness = Harness::start(20).await;
+    let (a, b) = (txid("a"), txid("b"));
+    harness.node.with(|node| {
+        let result = serde_json::json!([
+            { "txid": a, "wtxid": a, "allowed": true, "vsize": 141, "fees": { "base": 0.0000141 } },
+            { "txid": b, "allowed": false, "reject-reason": "min relay fee not met" },
+        ]);
+        node.set_response("testmempoolaccept", serde_json::json!({ "result": result }));
+    });
+
+    let txs = vec!["0200".to_owned(), "0201".to_owned()];
+    let results = harness.state.test_txs(&txs, Some(0.1)).await.unwrap();
+    let results = results
+        .into_iter()
+        .map(|result| {
+            (
+                result.txid,
+                result.allowed,
+                result.fee,
+                result.reject_reason,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        vec![
+            (a, true, Some(1410), None),
+            (b, false, None, Some("min relay fee not met".to_owned()))
+        ]
+    );
+    let calls = harness.node.with(|node| node.calls().to_vec());
+    assert_eq!(
+        calls,
+        vec![(
+            "testmempoolaccept".to_owned(),
+            vec![serde_json::json!(txs), serde_json::json!(0.1)]
+        )]
+    );
+
+    harness.node.with(|node| {
+        let error = serde_json::json!({ "code": -22, "message": "TX decode failed" });
+        node.set_response("testmempoolaccept", serde_json::json!({ "error": error }));
+    });
+    match harness.state.test_txs(&txs, None).await {
+        Err(BitcoindError::ResultRPC(err)) => assert_eq!(err.code, -22),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn package_submit() {
+    let harness = Harness::start(20).await;
+    let txs = vec!["0200".to_owned(), "".to_owned(), "0201".to_owned()];
+    let (parent, child) = (
+        wtxid(&hex::decode("0200").unwrap()),
+        wtxid(&hex::decode("0201").unwrap()),
+    );
+    harness.node.with(|node| {
+        let result = serde_json::json!({
+            "package_msg": "transaction failed",
+            "tx-results": {
+                parent.clone(): { "txid": parent, "vsize": 141, "fees": { "base": 0.0000141 } },
+                child.clone(): { "txid": child, "error": "bad-txns-inputs-missingorspent" },
+            },
+        });
+        node.set_response("submitpackage", serde_json::json!({ "result": result }));
+    });
+
+    // Results in request order, transaction not reported by node is not
+    // validated
+    let package = harness.state.submit_package(&txs).await.unwrap();
+    assert_eq!(package.method, "submitpackage");
+    assert_eq!(package.message.as_deref(), Some("transaction failed"));
+    let results = package
+        .txs
+        .into_iter()
+        .map(|tx| (tx.wtxid, tx.accepted, tx.vsize, tx.fee, tx.error))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        vec![
+            (parent.clone(), true, Some(141), Some(1410), None),
+            (
+                wtxid(&[]),
+                false,
+                None,
+                None,
+                Some("not validated".to_owned())
+            ),
+            (
+                child.clone(),
+                false,
+                None,
+                None,
+                Some("bad-txns-inputs-missingorspent".to_owned())
+            ),
+        ]
+    );
+
+    // Before 26.0 transactions are sent one by one until first rejection
+    harness.node.with(|node| {
+        let info = serde_json::json!({
+            "version": 250000,
+            "subversion": "/Satoshi:25.0.0/",
+            "timeoffset": 0,
+        });
+        node.set_response("getnetworkinfo", serde_json::json!({ "result": info }));
+    });
+    let package = harness.state.submit_package(&txs).await.unwrap();
+    assert_eq!(package.method, "sendrawtransaction");
+    assert_eq!(package.message, None);
+    let results = package
+        .txs
+        .into_iter()
+        .map(|tx| (tx.txid, tx.accepted, tx.error))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        vec![
+            (Some(parent.clone()), true, None),
+            (None, false, Some("TX decode failed".to_owned())),
+            (
+                None,
+                false,
+                Some("not submitted, previous transaction rejected".to_owned())
+            ),
+        ]
+    );
+    let mempool = harness.node.with(|node| node.mempool());
+    assert!(mempool.contains(&parent));
+    assert!(!mempool.contains(&child));
+}
+
+#[tokio::test]
+async fn outpoint_spend() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    let a = txid("a");
+    harness.node.with(|node| node.add_mempool(&a));
+    harness.sync().await;
+    let spend = harness
+        .state
+        .get_outpoint_spend(&txid(&funding(&a)), 0)
+        .await;
+    assert_eq!(spend.spend.map(|spend| spend.vin), Some(0));
+    assert_eq!(harness.spend_height(&a).await, Some(None));
+    let other = harness
+        .state
+        .get_outpoint_spend(&txid(&funding(&a)), 1)
+        .await;
+    assert!(other.spend.is_none());
+
+    // Spend moves between block and mempool on reorg
+    harness
+        .node
+        .with(|node| node.mine_with(std::slice::from_ref(&a)));
+    harness.sync().await;
+    assert_eq!(harness.spend_height(&a).await, Some(Some(20)));
+    harness.node.with(|node| node.reorg(1));
+    harness.sync().await;
+    harness.assert_synced().await;
+    assert_eq!(harness.spend_height(&a).await, Some(None));
+
+    // Spend in block is forgotten with block removed from window
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    assert_eq!(harness.spend_height(&a).await, Some(Some(22)));
+    for _ in 0..BLOCKS_KEEP {
+        harness.node.with(|node| node.mine());
+    }
+    harness.sync().await;
+    harness.assert_synced().await;
+    assert_eq!(harness.spend_height(&a).await, None);
+}
+
+#[tokio::test]
+async fn outpoint_info() {
+    let harness = Harness::start(20).await;
+    let a = txid("a");
+    let outpoint = txid(&funding(&a));
+    harness.node.with(|node| {
+        node.add_mempool(&a);
+        let txout = serde_json::json!({
+            "confirmations": 3,
+            "value": 0.5,
+            "scriptPubKey": { "hex": "0014" },
+            "coinbase": false,
+        });
+        node.set_response("gettxout", serde_json::json!({ "result": txout }));
+    });
+    harness.sync().await;
+
+    // Output spent in mempool is still unspent without mempool
+    let info = harness
+        .state
+        .get_outpoint(&outpoint, 0, false)
+        .await
+        .unwrap();
+    let utxo = info.utxo.unwrap();
+    assert_eq!((utxo.value, utxo.confirmations), (50_000_000, 3));
+    assert_eq!(utxo.script, hex::decode("0014").unwrap());
+    assert_eq!(info.mempool_spend.map(|spend| spend.txid), Some(a.clone()));
+    let calls = harness.node.with(|node| node.calls().to_vec());
+    assert_eq!(
+        calls,
+        vec![(
+            "gettxout".to_owned(),
+            vec![outpoint.clone().into(), 0.into(), false.into()]
+        )]
+    );
+
+    // Not unspent output is `null`, confirmed spend is not reported
+    harness.node.with(|node| {
+        node.mine();
+        node.set_response("gettxout", serde_json::json!({ "result": null }));
+    });
+    harness.sync().await;
+    let info = harness
+        .state
+        .get_outpoint(&outpoint, 0, true)
+        .await
+        .unwrap();
+    assert!(info.utxo.is_none() && info.mempool_spend.is_none());
+}
+
+#[tokio::test]
+async fn halving() {
+    let harness = Harness::start(20).await;
+    let tip = harness.state.get_block_tip().await.unwrap().unwrap();
+    assert_eq!(tip.subsidy, 5_000_000_000);
+
+    // Regtest interval, cadence of mock blocks
+    *harness.state.chain.lock().unwrap() = Some("regtest".to_owned());
+    let halving = harness.state.get_halving().await;
+    assert_eq!((halving.height, halving.era), (19, 0));
+    assert_eq!((halving.next_height, halving.blocks_left), (150, 131));
+    assert_eq!(halving.next_subsidy, 2_500_000_000);
+    assert_eq!(halving.block_interval, 600);
+    let time = harness.node.with(|node| node.block_time(19));
+    assert_eq!(halving.estimated_time, time as u64 + 131 * 600);
+}
+
+#[tokio::test]
+async fn difficulty_adjustment() {
+    let harness = Harness::start(20).await;
+    let genesis = harness.node.with(|node| node.chain()[0].hash.clone());
+    let time = harness.node.with(|node| {
+        // Window started by genesis, blocks twice faster than target
+        let time = node.block_time(19);
+        let start = time - 19 * 300;
+        let header = serde_json::json!({
+            "hash": genesis,
+            "height": 0,
+            "time": start,
+            "mediantime": start,
+        });
+        node.set_response("getblockheader", serde_json::json!({ "result": header }));
+        time
+    });
+
+    let adjustment = harness.state.get_difficulty_adjustment().await.unwrap();
+    assert_eq!((adjustment.height, adjustment.window_start), (19, 0));
+    assert_eq!(
+        (adjustment.blocks_elapsed, adjustment.blocks_left),
+        (19, 1997)
+    );
+    assert_eq!(adjustment.block_interval, 300);
+    assert_eq!(adjustment.estimated_change, 100.0);
+    assert_eq!(adjustment.estimated_time, time as u64 + 1997 * 300);
+
+    // Time of window start is cached
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    *harness.state.chain.lock().unwrap() = Some("regtest".to_owned());
+    let adjustment = harness.state.get_difficulty_adjustment().await.unwrap();
+    assert_eq!(adjustment.blocks_elapsed, 20);
+    assert!(!adjustment.retargeting);
+    let calls = harness.node.with(|node| node.calls().to_vec());
+    assert_eq!(
+        calls,
+        vec![("getblockheader".to_owned(), vec![genesis.into()])]
+    );
+}
+
+#[tokio::test]
+async fn node_commands() {
+    let harness = Harness::start(20).await;
+    harness.node.with(|node| {
+        let bans = serde_json::json!([
+            { "address": "1.2.3.0/24", "ban_created": 1700000000, "banned_until": 1700086400 },
+        ]);
+        node.set_response("listbanned", serde_json::json!({ "result": bans }));
+        for method in &["setban", "addnode", "disconnectnode"] {
+            node.set_response(method, serde_json::json!({ "result": null }));
+        }
+    });
+
+    let bans = harness.state.get_bans().await.unwrap();
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0].address, "1.2.3.0/24");
+    assert_eq!(bans[0].banned_until, 1_700_086_400);
+
+    let ban: BanEntry = serde_json::from_str(r#"{"subnet": "1.2.3.0/24"}"#).unwrap();
+    harness.state.set_ban(&ban, true).await.unwrap();
+    harness.state.set_ban(&ban, false).await.unwrap();
+    let node: NodeEntry = serde_json::from_str(r#"{"addr": "node.local:8333"}"#).unwrap();
+    harness.state.update_node(&node, true).await.unwrap();
+    harness.state.update_node(&node, false).await.unwrap();
+    let onetry = NodeEntry {
+        onetry: true,
+        ..node
+    };
+    harness.state.update_node(&onetry, true).await.unwrap();
+    for body in &[r#"{"address": "1.2.3.4:8333"}"#, r#"{"nodeid": 7}"#] {
+        let peer: PeerEntry = serde_json::from_str(body).unwrap();
+        harness.state.disconnect_peer(&peer).await.unwrap();
+    }
+
+    let calls = harness.node.with(|node| node.calls().to_vec());
+    let calls = calls
+        .into_iter()
+        .map(|(method, params)| (method, serde_json::Value::from(params)))
+        .collect::<Vec<_>>();
+    let call = |method: &str, params| (method.to_owned(), params);
+    assert_eq!(
+        calls,
+        vec![
+            call("listbanned", serde_json::json!([])),
+            call("setban", serde_json::json!(["1.2.3.0/24", "add", 0, false])),
+            call("setban", serde_json::json!(["1.2.3.0/24", "remove"])),
+            call("addnode", serde_json::json!(["node.local:8333", "add"])),
+            call("addnode", serde_json::json!(["node.local:8333", "remove"])),
+            call("addnode", serde_json::json!(["node.local:8333", "onetry"])),
+            call("disconnectnode", serde_json::json!(["1.2.3.4:8333"])),
+            call("disconnectnode", serde_json::json!(["", 7])),
+        ]
+    );
+
+    // Rejected command is RPC error
+    harness.node.with(|node| {
+        let error = serde_json::json!({ "code": -23, "message": "Error: Node already added" });
+        node.set_response("addnode", serde_json::json!({ "error": error }));
+    });
+    match harness.state.update_node(&onetry, true).await {
+        Err(BitcoindError::ResultRPC(err)) => assert_eq!(err.code, -23),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn statsd_export() {
+    let harness = Harness::start(20).await;
+    let mut socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let config = StatsdConfig {
+        addr: socket.local_addr().unwrap(),
+        prefix: "test".to_owned(),
+        tags: vec!["env:ci".to_owned()],
+        interval: Duration::from_secs(10),
+    };
+    let mut statsd = Statsd::new(&config);
+    statsd.run(&harness.state).await.unwrap();
+
+    let mut buf = vec![0; 2048];
+    let size = socket.recv(&mut buf).await.unwrap();
+    let datagram = String::from_utf8(buf[..size].to_vec()).unwrap();
+    let lines = datagram.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], format!("test.blocks:{}|g|#env:ci", BLOCKS_KEEP));
+    assert_eq!(lines[1], "test.mempool:0|g|#env:ci");
+}
+
+#[tokio::test]
+async fn block_timings() {
+    let harness = Harness::start(20).await;
+    let initial = harness.state.get_stats().await.block_timings;
+    let count = |stats: &BTreeMap<String, StageStats>, stage: &str| {
+        stats.get(stage).map(|stats| stats.count).unwrap_or(0)
+    };
+
+    // Every stage recorded for new tip
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    let stats = harness.state.get_stats().await.block_timings;
+    for stage in &["fetch", "decode", "index", "mempool", "events"] {
+        assert_eq!(
+            count(&stats, stage),
+            count(&initial, stage) + 1,
+            "{}",
+            stage
+        );
+    }
+}
+
+#[tokio::test]
+async fn resume_after_restart() {
+    let harness = Harness::start(20).await;
+    let (a, b, c) = (txid("a"), txid("b"), txid("c"));
+    harness.node.with(|node| {
+        node.add_mempool(&a);
+        node.add_mempool(&b);
+    });
+    harness.sync().await;
+    harness.state.save_mempool_snapshot().await.unwrap();
+    let stored = harness.state.storage.scan(storage::TREE_CHAIN).unwrap();
+    assert_eq!(stored.len(), BLOCKS_KEEP);
+
+    // Mined and changed mempool while server was down
+    let Harness { node, state, .. } = harness;
+    node.with(|node| {
+        node.mine_with(std::slice::from_ref(&a));
+        node.remove_mempool(&b);
+        node.add_mempool(&c);
+        node.mine_with(&[]);
+    });
+    let mut harness = Harness::connect_with(node, state.storage);
+
+    let mut shutdown = ShutdownReceiver::new();
+    let update_loop = harness.state.run_update_loop(shutdown.clone());
+    let check = async {
+        assert!(harness.wait_synced().await);
+        MempoolRefresh.run(&harness.state).await.unwrap();
+        assert_eq!(harness.mempool().await, vec![c.clone()]);
+        shutdown.set();
+    };
+    let (result, ()) = tokio::join!(update_loop, check);
+    result.unwrap();
+
+    // Blocks after stored tip are new, snapshot transactions are not
+    // announced again
+    let (blocks, mut txs) = harness.events();
+    let added = blocks
+        .into_iter()
+        .filter(|(event, ..)| *event == BlockEvent::Added)
+        .map(|(_, height, _)| height)
+        .collect::<Vec<_>>();
+    assert_eq!(added, vec![20, 21]);
+    txs.sort_by(|x, y| x.1.cmp(&y.1));
+    let mut expected = vec![
+        (TxEvent::Confirmed, a),
+        (TxEvent::Removed, b),
+        (TxEvent::Added, c.clone()),
+    ];
+    expected.sort_by(|x, y| x.1.cmp(&y.1));
+    assert_eq!(txs, expected);
+
+    // Snapshot of shutdown replaces loaded one
+    let snapshot = harness.state.take_mempool_snapshot().unwrap().unwrap();
+    let txids = snapshot.into_iter().map(|tx| tx.txid).collect::<Vec<_>>();
+    assert_eq!(txids, vec![c]);
+    assert!(harness.state.take_mempool_snapshot().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn resume_hash() {
+    let harness = Harness::start(20).await;
+    let Harness { node, state, .. } = harness;
+    let tip = node.with(|node| node.tip().hash.clone());
+    let harness = Harness::connect_with(node, state.storage);
+    assert_eq!(harness.state.find_resume_hash().await.unwrap(), Some(tip));
+
+    // Node too far ahead of stored tip
+    harness.node.with(|node| {
+        for _ in 0..2017 {
+            node.mine_with(&[]);
+        }
+    });
+    assert_eq!(harness.state.find_resume_hash().await.unwrap(), None);
+
+    // Whole stored window replaced by reorg
+    let harness = Harness::start(20).await;
+    let Harness { node, state, .. } = harness;
+    node.with(|node| node.reorg(BLOCKS_KEEP));
+    let harness = Harness::connect_with(node, state.storage);
+    assert_eq!(harness.state.find_resume_hash().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn address_transactions() {
+    let harness = Harness::start(20).await;
+    let a = txid("a");
+    harness
+        .node
+        .with(|node| node.mine_with(std::slice::from_ref(&a)));
+    harness.sync().await;
+    let validated = |script: &str| {
+        let result = serde_json::json!({ "isvalid": true, "scriptPubKey": script });
+        harness.node.with(|node| {
+            node.set_response("validateaddress", serde_json::json!({ "result": result }))
+        });
+    };
+
+    // Output script of mock transaction
+    let script = format!("0014{}", &a[..40]);
+    validated(&script);
+    let txs = harness
+        .state
+        .get_address_transactions("bcrt1qa")
+        .await
+        .unwrap();
+    let txs = txs.unwrap();
+    assert_eq!(
+        (txs.address.as_str(), hex::encode(&txs.script)),
+        ("bcrt1qa", script.clone())
+    );
+    let found = txs
+        .transactions
+        .into_iter()
+        .map(|tx| (tx.txid, tx.height))
+        .collect::<Vec<_>>();
+    assert_eq!(found, vec![(a, 20)]);
+    let calls = harness.node.with(|node| node.calls().to_vec());
+    assert_eq!(
+        calls,
+        vec![("validateaddress".to_owned(), vec!["bcrt1qa".into()])]
+    );
+
+    validated("0014ff");
+    let txs = harness
+        .state
+        .get_address_transactions("bcrt1qb")
+        .await
+        .unwrap();
+    assert!(txs.unwrap().transactions.is_empty());
+
+    // Address of another network
+    harness.node.with(|node| {
+        let result = serde_json::json!({ "isvalid": false });
+        node.set_response("validateaddress", serde_json::json!({ "result": result }));
+    });
+    let txs = harness
+        .state
+        .get_address_transactions("bc1qa")
+        .await
+        .unwrap();
+    assert!(txs.is_none());
+
+    // Only blocks of window indexed
+    validated(&script);
+    for _ in 0..BLOCKS_KEEP {
+        harness.node.with(|node| node.mine());
+    }
+    harness.sync().await;
+    let txs = harness
+        .state
+        .get_address_transactions("bcrt1qa")
+        .await
+        .unwrap();
+    assert!(txs.unwrap().transactions.is_empty());
+}
+
+#[tokio::test]
+async fn watch_height() {
+    let mut harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.events();
+
+    // Replies of connection watching height 22
+    let heights = std::iter::once(22).collect::<BTreeSet<_>>();
+    let replies = |events: BlockEvents| {
+        events
+            .into_iter()
+            .filter_map(|(kind, height, hash)| {
+                watched_height_reply(&Event::Block(kind, height, hash), &heights)
+            })
+            .map(|reply| serde_json::to_value(reply).unwrap())
+            .collect::<Vec<_>>()
+    };
+    harness.node.with(|node| {
+        node.mine();
+        node.mine();
+    });
+
+    harness.sync().await;
+    assert!(replies(harness.block_events()).is_empty());
+    let hash = harness.node.with(|node| node.mine().hash);
+    harness.sync().await;
+    let reached = serde_json::json!({ "height_reached": 22, "hash": hash });
+    assert_eq!(replies(harness.block_events()), vec![reached]);
+
+    // Reorg above height does not trigger it again
+    harness.node.with(|node| {
+        node.mine();
+        node.reorg(1);
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+    assert!(replies(harness.block_events()).is_empty());
+
+    // Reached again by block of new branch
+    harness.node.with(|node| node.reorg(3));
+    harness.sync().await;
+    harness.assert_synced().await;
+    let new_hash = harness.node_hash(22);
+    let expected = vec![
+        serde_json::json!({ "height_reverted": 22, "hash": hash }),
+        serde_json::json!({ "height_reached": 22, "hash": new_hash }),
+    ];
+    assert_eq!(replies(harness.block_events()), expected);
+}
+
+// Same checks as soak mode after every churn step
+#[tokio::test]
+async fn accounting_after_reorg() {
+    let harness = Harness::start(20).await;
+    harness.sync().await;
+    harness.node.with(|node| {
+        for name in &["a", "b", "c"] {
+            node.add_mempool(&txid(name));
+        }
+    });
+    harness.sync().await;
+    harness.node.with(|node| node.mine());
+    harness.sync().await;
+    harness.node.with(|node| {
+        node.reorg(1);
+        node.mine_with(&[txid("a"), txid("b")]);
+    });
+    harness.sync().await;
+    harness.assert_synced().await;
+
+    let accounting = harness.state.get_accounting().await;
+    assert_eq!(accounting.blocks, BLOCKS_KEEP);
+    assert!(accounting.block_scripts > 0);
+    assert_eq!(accounting.scripts, accounting.block_scripts);
+    assert!(accounting.interned <= accounting.scripts);
+    assert!(accounting.block_spent > 0);
+    assert_eq!(accounting.spends_blocks, accounting.block_spent);
+    assert_eq!(accounting.mempool, harness.mempool().await.len());
+    assert!(accounting.spends_mempool_txs <= accounting.mempool);
+    assert_eq!(accounting.reorgs, 1);
+}
@@ -0,0 +1,257 @@
+// Export of `/stats` counters to statsd over UDP, for setups which collect
+// metrics with statsd or Datadog agent. Gauges sent as is, counters as
+// increments since previous flush. Tags use DogStatsD form (`|#key:value`).
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt as _};
+use log::info;
+use tokio::net::UdpSocket;
+
+use super::json;
+use super::scheduler::{Job, JobResult, Schedule};
+use super::state::State;
+
+// Payload of one datagram, small enough to avoid fragmentation
+const DATAGRAM_SIZE_MAX: usize = 1432;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum StatsdError {
+        Socket(err: std::io::Error) {
+            display("statsd socket error: {}", err)
+        }
+    }
+}
+
+pub type StatsdResult<T> = Result<T, StatsdError>;
+
+#[derive(Debug, Clone)]
+pub struct StatsdConfig {
+    pub addr: SocketAddr,
+    pub prefix: String,
+    // Tags in form `key:value`, added to every metric
+    pub tags: Vec<String>,
+    pub interval: Duration,
+}
+
+#[derive(Debug)]
+pub struct Statsd {
+    addr: SocketAddr,
+    prefix: String,
+    tags: Vec<String>,
+    interval: Duration,
+    // Bound on first export
+    socket: Option<UdpSocket>,
+    // Last values of counters, by metric name and tag
+    counters: HashMap<String, u64>,
+}
+
+impl Statsd {
+    pub fn new(config: &StatsdConfig) -> Self {
+        Statsd {
+            addr: config.addr,
+            prefix: config.prefix.clone(),
+            tags: config.tags.clone(),
+            interval: config.interval,
+            socket: None,
+            counters: HashMap::new(),
+        }
+    }
+
+    pub fn schedule(&self) -> Schedule {
+        Schedule::every(self.interval)
+    }
+
+    async fn export(&mut self, state: &State) -> StatsdResult<()> {
+        if self.socket.is_none() {
+            let bind = if self.addr.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(bind).await.map_err(StatsdError::Socket)?;
+            info!("Send stats to statsd {}", self.addr);
+            self.socket = Some(socket);
+        }
+
+        let stats = state.get_stats().await;
+        let addr = self.addr;
+        for datagram in self.datagrams(&stats) {
+            // Stats are not important enough to retry
+            let socket = self.socket.as_mut().unwrap();
+            socket
+                .send_to(datagram.as_bytes(), &addr)
+                .await
+                .map_err(StatsdError::Socket)?;
+        }
+        Ok(())
+    }
+
+    fn datagrams(&mut self, stats: &json::Stats) -> Vec<String> {
+        pack(self.lines(stats))
+    }
+
+    fn lines(&mut self, stats: &json::Stats) -> Vec<String> {
+        let mut lines = vec![
+            self.gauge("blocks", None, stats.blocks as u64),
+            self.gauge("mempool", None, stats.mempool as u64),
+            self.gauge("scripts.entries", None, stats.scripts.entries as u64),
+            self.gauge("scripts.bytes", None, stats.scripts.bytes as u64),
+        ];
+        lines.extend(self.counter("scripts.hits", None, stats.scripts.hits));
+        lines.extend(self.counter("scripts.misses", None, stats.scripts.misses));
+
+        for hook in stats.hooks.iter() {
+            let tag = Some(("hook", hook.name.as_str()));
+            lines.extend(self.counter("hooks.calls", tag, hook.calls));
+            lines.extend(self.counter("hooks.errors", tag, hook.errors));
+            lines.extend(self.counter("hooks.panics", tag, hook.panics));
+            lines.extend(self.counter("hooks.lagged", tag, hook.lagged));
+            lines.extend(self.counter("hooks.time_us", tag, hook.time_total_us));
+            lines.push(self.gauge("hooks.time_max_us", tag, hook.time_max_us));
+        }
+        for plugin in stats.plugins.iter() {
+            let tag = Some(("plugin", plugin.name.as_str()));
+            lines.extend(self.counter("plugins.calls", tag, plugin.calls));
+            lines.extend(self.counter("plugins.errors", tag, plugin.errors));
+            lines.extend(self.counter("plugins.events", tag, plugin.events));
+        }
+        for (stage, timings) in stats.block_timings.iter() {
+            let tag = Some(("stage", stage.as_str()));
+            lines.extend(self.counter("block_timings.count", tag, timings.count));
+            lines.extend(self.counter("block_timings.time_us", tag, timings.time_total_us));
+            lines.push(self.gauge("block_timings.time_max_us", tag, timings.time_max_us));
+        }
+        for rule in stats.rules.iter() {
+            let tag = Some(("rule", rule.name.as_str()));
+            lines.extend(self.counter("rules.matches", tag, rule.matches));
+            lines.extend(self.counter("rules.errors", tag, rule.errors));
+        }
+
+        lines
+    }
+
+    fn gauge(&self, name: &str, tag: Option<(&str, &str)>, value: u64) -> String {
+        self.line(name, tag, value, "g")
+    }
+
+    // Increment since previous flush, nothing if counter not changed. Counter
+    // less than previous value (rules reloaded) sent as is.
+    fn counter(&mut self, name: &str, tag: Option<(&str, &str)>, value: u64) -> Option<String> {
+        let key = match tag {
+            Some((tag, value)) => format!("{}#{}:{}", name, tag, value),
+            None => name.to_owned(),
+        };
+        let prev = self.counters.insert(key, value).unwrap_or(0);
+        let delta = if value >= prev { value - prev } else { value };
+        if delta == 0 {
+            None
+        } else {
+            Some(self.line(name, tag, delta, "c"))
+        }
+    }
+
+    fn line(&self, name: &str, tag: Option<(&str, &str)>, value: u64, kind: &str) -> String {
+        let mut line = format!("{}.{}:{}|{}", self.prefix, name, value, kind);
+        let tags = self
+            .tags
+            .iter()
+            .cloned()
+            .chain(tag.map(|(key, value)| format!("{}:{}", key, value)))
+            .collect::<Vec<_>>();
+        if !tags.is_empty() {
+            line.push_str("|#");
+            line.push_str(&tags.join(","));
+        }
+        line
+    }
+}
+
+// Metric lines packed into datagrams, line longer than datagram sent alone
+fn pack(lines: Vec<String>) -> Vec<String> {
+    let mut datagrams = vec![];
+    let mut datagram = String::new();
+    for line in lines {
+        if !datagram.is_empty() && datagram.len() + 1 + line.len() > DATAGRAM_SIZE_MAX {
+            datagrams.push(std::mem::take(&mut datagram));
+        }
+        if !datagram.is_empty() {
+            datagram.push('\n');
+        }
+        datagram.push_str(&line);
+    }
+    if !datagram.is_empty() {
+        datagrams.push(datagram);
+    }
+    datagrams
+}
+
+impl Job for Statsd {
+    fn run<'a>(&'a mut self, state: &'a State) -> BoxFuture<'a, JobResult> {
+        async move { self.export(state).await.map_err(|err| err.to_string()) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exporter(tags: &[&str]) -> Statsd {
+        Statsd::new(&StatsdConfig {
+            addr: "127.0.0.1:8125".parse().unwrap(),
+            prefix: "app".to_owned(),
+            tags: tags.iter().map(|tag| (*tag).to_owned()).collect(),
+            interval: Duration::from_secs(10),
+        })
+    }
+
+    #[test]
+    fn metric_lines() {
+        let mut statsd = exporter(&[]);
+        assert_eq!(statsd.gauge("blocks", None, 6), "app.blocks:6|g");
+
+        // Increments since previous value, counters with different tags are
+        // independent
+        let tag = Some(("hook", "a"));
+        assert_eq!(
+            statsd.counter("hooks.calls", tag, 5),
+            Some("app.hooks.calls:5|c|#hook:a".to_owned())
+        );
+        assert_eq!(statsd.counter("hooks.calls", tag, 5), None);
+        assert_eq!(
+            statsd.counter("hooks.calls", tag, 8),
+            Some("app.hooks.calls:3|c|#hook:a".to_owned())
+        );
+        assert_eq!(
+            statsd.counter("hooks.calls", Some(("hook", "b")), 1),
+            Some("app.hooks.calls:1|c|#hook:b".to_owned())
+        );
+        // Reset counter sent as is
+        assert_eq!(
+            statsd.counter("hooks.calls", tag, 2),
+            Some("app.hooks.calls:2|c|#hook:a".to_owned())
+        );
+
+        let statsd = exporter(&["env:prod", "dc:eu"]);
+        assert_eq!(
+            statsd.gauge("hooks.time_max_us", Some(("hook", "a")), 7),
+            "app.hooks.time_max_us:7|g|#env:prod,dc:eu,hook:a"
+        );
+    }
+
+    #[test]
+    fn datagrams() {
+        assert!(pack(vec![]).is_empty());
+        let lines = vec!["a:1|g".to_owned(), "b:2|c".to_owned()];
+        assert_eq!(pack(lines), vec!["a:1|g\nb:2|c"]);
+
+        // Two lines fill datagram with separator
+        let line = "x".repeat((DATAGRAM_SIZE_MAX - 1) / 2);
+        let long = "y".repeat(DATAGRAM_SIZE_MAX + 1);
+        let datagrams = pack(vec![line.clone(), line.clone(), long.clone(), line.clone()]);
+        assert_eq!(datagrams, vec![format!("{}\n{}", line, line), long, line]);
+    }
+}
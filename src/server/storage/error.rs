@@ -0,0 +1,22 @@
+use serde_json::Error as SerdeError;
+use sled::Error as SledError;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum StorageError {
+        UnknownBackend(spec: String) {
+            display(r#"Unknown storage backend "{}", expected "sled:<path>" or "memory""#, spec)
+        }
+        Sled(err: SledError) {
+            display("sled: {}", err)
+        }
+        InvalidKey(key: String) {
+            display("Invalid stored key: {}", key)
+        }
+        Serde(err: SerdeError) {
+            display("Invalid stored value ({})", err)
+        }
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
@@ -0,0 +1,52 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use super::{Storage, StorageResult};
+
+type Tree = BTreeMap<Vec<u8>, Vec<u8>>;
+
+// Non-persistent storage, everything lost on exit
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    trees: Mutex<HashMap<String, Tree>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, tree: &str, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        let trees = self.trees.lock().unwrap();
+        Ok(trees.get(tree).and_then(|tree| tree.get(key).cloned()))
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> StorageResult<()> {
+        let mut trees = self.trees.lock().unwrap();
+        let tree = trees.entry(tree.to_owned()).or_default();
+        tree.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> StorageResult<()> {
+        let mut trees = self.trees.lock().unwrap();
+        if let Some(tree) = trees.get_mut(tree) {
+            tree.remove(key);
+        }
+        Ok(())
+    }
+
+    fn scan(&self, tree: &str) -> StorageResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let trees = self.trees.lock().unwrap();
+        Ok(match trees.get(tree) {
+            Some(tree) => tree.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            None => vec![],
+        })
+    }
+
+    fn flush(&self) -> StorageResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub use self::error::{StorageError, StorageResult};
+use self::memory::MemoryStorage;
+use self::sled::SledStorage;
+use super::bitcoind::json::ResponseBlock;
+
+mod error;
+mod memory;
+mod sled;
+
+// Tree with audit records by sequence number (big-endian u64 keys)
+pub const TREE_AUDIT: &str = "audit";
+// Tree with blocks by height (big-endian u32 keys)
+pub const TREE_BLOCKS: &str = "blocks";
+// Tree with blocks tracked by server by height (big-endian u32 keys), mirror
+// of server chain for resume after restart
+pub const TREE_CHAIN: &str = "chain";
+// Tree with service values, like backfill checkpoints
+pub const TREE_META: &str = "meta";
+// Key in `TREE_META` with server mempool snapshot, saved on shutdown
+pub const META_MEMPOOL: &str = "server_mempool";
+// Tree with blacklisted txids and output scripts, values are empty
+pub const TREE_BLACKLIST: &str = "blacklist";
+// Tree with output scripts watched by tenants (`<tenant>:<script hex>` keys), values are empty
+pub const TREE_WATCH: &str = "watch";
+// Tree with confirmed transactions of watched scripts
+// (`<tenant>:<script hex>:<height hex>:<txid>:<direction>` keys)
+pub const TREE_WATCH_HISTORY: &str = "watch_history";
+// Tree with timelocked outputs watched by tenants (`<tenant>:<txid>:<vout>` keys)
+pub const TREE_WATCH_TIMELOCKS: &str = "watch_timelocks";
+// Tree with pre-signed reactions to spends of outputs watched by tenants
+// (`<tenant>:<txid>:<vout>` keys)
+pub const TREE_WATCH_REACTIONS: &str = "watch_reactions";
+// Tree with API usage of tenants (`<tenant>:<day or month>` keys)
+pub const TREE_USAGE: &str = "usage";
+// Tree with interned output scripts by id (big-endian u32 keys)
+pub const TREE_SCRIPTS: &str = "scripts";
+// Tree with ids of interned output scripts by script, reverse of `TREE_SCRIPTS`
+pub const TREE_SCRIPT_IDS: &str = "script_ids";
+// Key in `TREE_META` with next id of `TREE_SCRIPTS`
+pub const META_SCRIPTS_NEXT: &str = "scripts_next";
+// Tree with txids by script id, height and position in block (3x big-endian u32 keys)
+pub const TREE_SCRIPT_TXS: &str = "script_txs";
+
+// Key-value storage with named trees, keys are sorted bytewise
+pub trait Storage: fmt::Debug + Send + Sync {
+    fn get(&self, tree: &str, key: &[u8]) -> StorageResult<Option<Vec<u8>>>;
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> StorageResult<()>;
+    fn remove(&self, tree: &str, key: &[u8]) -> StorageResult<()>;
+    fn scan(&self, tree: &str) -> StorageResult<Vec<(Vec<u8>, Vec<u8>)>>;
+    fn flush(&self) -> StorageResult<()>;
+}
+
+// Open storage from spec: "sled:<path>" or "memory"
+// Backend spec check without opening, running instance can hold lock on
+// sled database
+pub fn check_spec(spec: &str) -> StorageResult<()> {
+    match spec.strip_prefix("sled:") {
+        _ if spec == "memory" => Ok(()),
+        Some(path) if !path.is_empty() => Ok(()),
+        _ => Err(StorageError::UnknownBackend(spec.to_owned())),
+    }
+}
+
+pub fn open(spec: &str) -> StorageResult<Box<dyn Storage>> {
+    if spec == "memory" {
+        return Ok(Box::new(MemoryStorage::new()));
+    }
+
+    match spec.strip_prefix("sled:") {
+        Some(path) if !path.is_empty() => Ok(Box::new(SledStorage::open(path)?)),
+        _ => Err(StorageError::UnknownBackend(spec.to_owned())),
+    }
+}
+
+pub fn get_json<T: DeserializeOwned>(
+    storage: &dyn Storage,
+    tree: &str,
+    key: &[u8],
+) -> StorageResult<Option<T>> {
+    match storage.get(tree, key)? {
+        Some(value) => serde_json::from_slice(&value)
+            .map(Some)
+            .map_err(StorageError::Serde),
+        None => Ok(None),
+    }
+}
+
+pub fn insert_json<T: Serialize>(
+    storage: &dyn Storage,
+    tree: &str,
+    key: &[u8],
+    value: &T,
+) -> StorageResult<()> {
+    let value = serde_json::to_vec(value).map_err(StorageError::Serde)?;
+    storage.insert(tree, key, &value)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredBlock {
+    pub height: u32,
+    pub hash: String,
+    pub prevhash: Option<String>,
+    pub size: u32,
+    pub transactions: Vec<String>,
+}
+
+impl From<&ResponseBlock> for StoredBlock {
+    fn from(block: &ResponseBlock) -> Self {
+        StoredBlock {
+            height: block.height,
+            hash: block.hash.clone(),
+            prevhash: block.previousblockhash.clone(),
+            size: block.size,
+            transactions: block.transactions.iter().map(|t| t.hash.clone()).collect(),
+        }
+    }
+}
+
+// Transaction of server mempool snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMempoolTx {
+    pub txid: String,
+    pub size: u32,
+    // Satoshis, missed in snapshots of older versions
+    #[serde(default)]
+    pub fee: u64,
+    // Unix time of mempool entry, missed in snapshots of older versions
+    #[serde(default)]
+    pub time: u64,
+    // Unix time when seen by us, missed in snapshots of older versions
+    #[serde(default)]
+    pub first_seen: u64,
+    // Outputs spent by transaction: input index, txid, output index
+    pub spent: Vec<(u32, String, u32)>,
+}
+
+// Confirmed transaction with outputs to watched script (`in`) or spending them
+// (`out`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredWatchRecord {
+    pub height: u32,
+    pub hash: String,
+    // Block time
+    pub time: u32,
+    pub txid: String,
+    pub incoming: bool,
+    // Received or spent outputs: txid, output index, satoshis
+    pub outputs: Vec<(String, u32, u64)>,
+}
+
+// Timelock of watched output with state of last check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredTimelock {
+    pub locktime: Option<u32>,
+    pub sequence: Option<u32>,
+    // Confirmation height of output and median time past of previous block
+    pub height: Option<u32>,
+    pub start_time: Option<u32>,
+    pub spendable: bool,
+}
+
+// Raw transaction broadcast when watched output is spent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredReaction {
+    pub tx: String,
+    pub dry_run: bool,
+    // Txid of first seen spend, reaction is not triggered again
+    pub spent_by: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends() {
+        let path = std::env::temp_dir().join(format!("storage-backends-{}", std::process::id()));
+        let spec = format!("sled:{}", path.display());
+        for spec in &["memory", &spec] {
+            let storage = open(spec).unwrap();
+            storage.insert(TREE_META, b"b", b"2").unwrap();
+            storage.insert(TREE_META, b"a", b"1").unwrap();
+            storage.insert(TREE_BLACKLIST, b"a", b"").unwrap();
+            assert_eq!(storage.get(TREE_META, b"a").unwrap(), Some(b"1".to_vec()));
+            assert_eq!(storage.get(TREE_META, b"c").unwrap(), None);
+            assert_eq!(storage.scan(TREE_WATCH).unwrap(), vec![]);
+
+            // Keys sorted bytewise, trees are independent
+            storage.remove(TREE_BLACKLIST, b"a").unwrap();
+            storage.remove(TREE_BLACKLIST, b"missed").unwrap();
+            assert_eq!(storage.scan(TREE_BLACKLIST).unwrap(), vec![]);
+            assert_eq!(
+                storage.scan(TREE_META).unwrap(),
+                vec![
+                    (b"a".to_vec(), b"1".to_vec()),
+                    (b"b".to_vec(), b"2".to_vec())
+                ]
+            );
+            storage.flush().unwrap();
+        }
+        std::fs::remove_dir_all(path).unwrap();
+
+        for spec in &["sled:", "rocksdb:/tmp/db", ""] {
+            let error = open(spec).unwrap_err();
+            assert!(matches!(error, StorageError::UnknownBackend(_)), "{}", spec);
+            assert!(check_spec(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn spec_check() {
+        // Database is not created by check
+        let path = std::env::temp_dir().join(format!("storage-check-{}", std::process::id()));
+        check_spec(&format!("sled:{}", path.display())).unwrap();
+        assert!(!path.exists());
+        check_spec("memory").unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+
+use super::{Storage, StorageError, StorageResult};
+
+// Persistent storage on top of sled, every tree is separate sled Tree
+pub struct SledStorage {
+    db: ::sled::Db,
+}
+
+impl fmt::Debug for SledStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SledStorage").finish()
+    }
+}
+
+impl SledStorage {
+    pub fn open(path: &str) -> StorageResult<Self> {
+        let db = ::sled::open(path).map_err(StorageError::Sled)?;
+        Ok(SledStorage { db })
+    }
+
+    fn tree(&self, name: &str) -> StorageResult<::sled::Tree> {
+        self.db.open_tree(name).map_err(StorageError::Sled)
+    }
+}
+
+impl Storage for SledStorage {
+    fn get(&self, tree: &str, key: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        let value = self.tree(tree)?.get(key).map_err(StorageError::Sled)?;
+        Ok(value.map(|v| v.to_vec()))
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> StorageResult<()> {
+        self.tree(tree)?
+            .insert(key, value)
+            .map_err(StorageError::Sled)?;
+        Ok(())
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> StorageResult<()> {
+        self.tree(tree)?.remove(key).map_err(StorageError::Sled)?;
+        Ok(())
+    }
+
+    fn scan(&self, tree: &str) -> StorageResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.tree(tree)?
+            .iter()
+            .map(|item| {
+                let (key, value) = item.map_err(StorageError::Sled)?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    fn flush(&self) -> StorageResult<()> {
+        self.db.flush().map_err(StorageError::Sled)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+// Streaks of empty (coinbase only) or near-full blocks at the tip. Long
+// streaks of empty blocks usually mean mining pool issues (SPV mining), while
+// full blocks mean fee pressure, so both are reported in `/stats` and as
+// `streak` events once streak reaches threshold.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::bitcoind::json::ResponseBlock;
+use super::json;
+
+// Latest blocks kept, one day
+const BLOCKS_MAX: usize = 144;
+// Consensus limit, BIP 141
+const MAX_BLOCK_WEIGHT: u32 = 4_000_000;
+// Block is near-full above 99% of weight limit
+const FULL_WEIGHT: u32 = MAX_BLOCK_WEIGHT / 100 * 99;
+
+#[derive(Debug)]
+struct StreakBlock {
+    height: u32,
+    transactions: usize,
+    kind: Option<json::StreakKind>,
+}
+
+impl StreakBlock {
+    fn new(block: &ResponseBlock) -> Self {
+        let weight = block.weight.unwrap_or(block.size * 4);
+        let kind = if block.transactions.len() <= 1 {
+            Some(json::StreakKind::Empty)
+        } else if weight >= FULL_WEIGHT {
+            Some(json::StreakKind::Full)
+        } else {
+            None
+        };
+        StreakBlock {
+            height: block.height,
+            transactions: block.transactions.len(),
+            kind,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockStreaks {
+    threshold: usize,
+    // Consecutive blocks, by height
+    blocks: Mutex<VecDeque<StreakBlock>>,
+}
+
+impl BlockStreaks {
+    pub fn new(threshold: usize) -> Self {
+        BlockStreaks {
+            threshold,
+            blocks: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // New tip, returns streak if it just reached threshold
+    pub fn push_tip(&self, block: &ResponseBlock) -> Option<json::BlockStreak> {
+        let mut blocks = self.blocks.lock().unwrap();
+        if let Some(last) = blocks.back() {
+            if last.height + 1 != block.height {
+                blocks.clear();
+            }
+        }
+        if blocks.len() == BLOCKS_MAX {
+            blocks.pop_front();
+        }
+        blocks.push_back(StreakBlock::new(block));
+
+        match current(&blocks) {
+            Some(streak) if streak.length == self.threshold => Some(streak),
+            _ => None,
+        }
+    }
+
+    // Older block loaded on start or after reorg
+    pub fn push_old(&self, block: &ResponseBlock) {
+        let mut blocks = self.blocks.lock().unwrap();
+        match blocks.front() {
+            Some(first) if first.height != block.height + 1 => {}
+            _ if blocks.len() == BLOCKS_MAX => {}
+            _ => blocks.push_front(StreakBlock::new(block)),
+        }
+    }
+
+    // Tip disconnected
+    pub fn pop_tip(&self, height: u32) {
+        let mut blocks = self.blocks.lock().unwrap();
+        if blocks.back().map(|block| block.height) == Some(height) {
+            blocks.pop_back();
+        }
+    }
+
+    pub fn stats(&self) -> json::BlockStreaks {
+        let blocks = self.blocks.lock().unwrap();
+
+        let mut stats = json::BlockStreaks {
+            threshold: self.threshold,
+            blocks: blocks.len(),
+            transactions_avg: 0,
+            empty: 0,
+            full: 0,
+            current: current(&blocks),
+            longest_empty: 0,
+            longest_full: 0,
+        };
+        let mut length = 0;
+        for (i, block) in blocks.iter().enumerate() {
+            stats.transactions_avg += block.transactions;
+            match block.kind {
+                Some(json::StreakKind::Empty) => stats.empty += 1,
+                Some(json::StreakKind::Full) => stats.full += 1,
+                None => {}
+            }
+            let same = i > 0 && blocks[i - 1].kind == block.kind;
+            length = if same { length + 1 } else { 1 };
+            match block.kind {
+                Some(json::StreakKind::Empty) => {
+                    stats.longest_empty = stats.longest_empty.max(length)
+                }
+                Some(json::StreakKind::Full) => stats.longest_full = stats.longest_full.max(length),
+                None => {}
+            }
+        }
+        if !blocks.is_empty() {
+            stats.transactions_avg /= blocks.len();
+        }
+        stats
+    }
+}
+
+// Streak ending at tip
+fn current(blocks: &VecDeque<StreakBlock>) -> Option<json::BlockStreak> {
+    let last = blocks.back()?;
+    let kind = last.kind?;
+    let length = blocks
+        .iter()
+        .rev()
+        .take_while(|block| block.kind == Some(kind))
+        .count();
+    Some(json::BlockStreak {
+        kind,
+        length,
+        start_height: last.height + 1 - length as u32,
+        end_height: last.height,
+    })
+}
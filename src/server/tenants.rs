@@ -0,0 +1,757 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::bitcoind::json::ResponseBlock;
+use super::bitcoind::BitcoindError;
+use super::interner::Interner;
+use super::json;
+use super::quotas::civil_from_days;
+use super::storage::{
+    self, Storage, StorageError, StorageResult, StoredReaction, StoredTimelock, StoredWatchRecord,
+    TREE_WATCH, TREE_WATCH_HISTORY, TREE_WATCH_REACTIONS, TREE_WATCH_TIMELOCKS,
+};
+
+const TENANT_MAX_WATCH_DEFAULT: usize = 1_000;
+
+// Locktime below is height, otherwise unix time
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+// BIP 68 relative lock: disabled, time in units of 512 seconds, value
+const SEQUENCE_DISABLE_FLAG: u32 = 1 << 31;
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_MASK: u32 = 0xffff;
+const SEQUENCE_GRANULARITY: u32 = 9;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum TenantError {
+        Storage(err: StorageError) {
+            display("storage: {}", err)
+            from()
+        }
+        Bitcoind(err: BitcoindError) {
+            display("bitcoind: {}", err)
+            from()
+        }
+        QuotaExceeded(limit: usize) {
+            display("Watch quota exceeded (max {} entries)", limit)
+        }
+        InvalidTimelock(reason: &'static str) {
+            display("Invalid timelock: {}", reason)
+        }
+    }
+}
+
+pub type TenantResult<T> = Result<T, TenantError>;
+
+#[derive(Debug, Clone)]
+pub struct TenantConfig {
+    pub id: String,
+    pub api_key: String,
+    pub max_watch: usize,
+}
+
+// Parse tenant in form `id:api-key[:max-watch]`
+pub fn parse_tenant(value: &str) -> Option<TenantConfig> {
+    let mut parts = value.splitn(3, ':');
+    let id = parts.next().filter(|id| !id.is_empty())?;
+    let api_key = parts.next().filter(|key| !key.is_empty())?;
+    let max_watch = match parts.next() {
+        Some(max_watch) => max_watch.parse::<usize>().ok()?,
+        None => TENANT_MAX_WATCH_DEFAULT,
+    };
+
+    Some(TenantConfig {
+        id: id.to_owned(),
+        api_key: api_key.to_owned(),
+        max_watch,
+    })
+}
+
+// Reaction of tenant to spend of watched output
+#[derive(Debug)]
+pub struct TriggeredReaction {
+    pub tenant: String,
+    pub outpoint: (String, u32),
+    pub tx: String,
+    pub dry_run: bool,
+}
+
+// Watched output scripts scoped by tenant, every tenant see only own entries.
+// Scripts are interned, so script watched by many tenants is kept once
+#[derive(Debug, Default)]
+pub struct Tenants {
+    tenants: HashMap<String, Tenant>,
+    scripts: Interner,
+}
+
+#[derive(Debug)]
+struct Tenant {
+    api_key: String,
+    max_watch: usize,
+    // Interned ids of watched scripts
+    watch: HashSet<u32>,
+    // Confirmed transactions of watched scripts by storage key
+    history: BTreeMap<String, StoredWatchRecord>,
+    // Received outputs not spent yet: script and satoshis
+    unspent: HashMap<(String, u32), (Vec<u8>, u64)>,
+    // Outputs with timelock, counted in watch quota
+    timelocks: BTreeMap<(String, u32), StoredTimelock>,
+    // Outputs with reaction to spend, counted in watch quota
+    reactions: BTreeMap<(String, u32), StoredReaction>,
+}
+
+impl Tenant {
+    // Entries counted in watch quota
+    fn entries(&self) -> usize {
+        self.watch.len() + self.timelocks.len() + self.reactions.len()
+    }
+}
+
+impl Tenants {
+    // Watch entries stored as `<tenant>:<script hex>`, entries of removed tenants ignored
+    pub fn load(configs: Vec<TenantConfig>, storage: &dyn Storage) -> StorageResult<Self> {
+        let mut tenants = configs
+            .into_iter()
+            .map(|config| {
+                let tenant = Tenant {
+                    api_key: config.api_key,
+                    max_watch: config.max_watch,
+                    watch: HashSet::new(),
+                    history: BTreeMap::new(),
+                    unspent: HashMap::new(),
+                    timelocks: BTreeMap::new(),
+                    reactions: BTreeMap::new(),
+                };
+                (config.id, tenant)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut scripts = Interner::new();
+        for (key, _) in storage.scan(TREE_WATCH)? {
+            let key = String::from_utf8_lossy(&key);
+            let mut parts = key.splitn(2, ':');
+            let (id, script) = match (parts.next(), parts.next()) {
+                (Some(id), Some(script)) => (id, script),
+                _ => return Err(StorageError::InvalidKey(key.to_string())),
+            };
+            let script =
+                hex::decode(script).map_err(|_| StorageError::InvalidKey(key.to_string()))?;
+            if let Some(tenant) = tenants.get_mut(id) {
+                tenant.watch.insert(scripts.intern(&script));
+            }
+        }
+
+        for (key, value) in storage.scan(TREE_WATCH_HISTORY)? {
+            let key = String::from_utf8_lossy(&key).into_owned();
+            let id = key.split(':').next().unwrap_or_default();
+            if let Some(tenant) = tenants.get_mut(id) {
+                let record = serde_json::from_slice(&value).map_err(StorageError::Serde)?;
+                tenant.history.insert(key, record);
+            }
+        }
+        for (key, value) in storage.scan(TREE_WATCH_TIMELOCKS)? {
+            let (id, outpoint) = Self::parse_outpoint_key(&key)?;
+            if let Some(tenant) = tenants.get_mut(&id) {
+                let timelock = serde_json::from_slice(&value).map_err(StorageError::Serde)?;
+                tenant.timelocks.insert(outpoint, timelock);
+            }
+        }
+        for (key, value) in storage.scan(TREE_WATCH_REACTIONS)? {
+            let (id, outpoint) = Self::parse_outpoint_key(&key)?;
+            if let Some(tenant) = tenants.get_mut(&id) {
+                let reaction = serde_json::from_slice(&value).map_err(StorageError::Serde)?;
+                tenant.reactions.insert(outpoint, reaction);
+            }
+        }
+
+        // Outputs received and spent in same block are not unspent
+        for tenant in tenants.values_mut() {
+            let mut records = tenant.history.iter().collect::<Vec<_>>();
+            records.sort_by_key(|(_, record)| (record.height, !record.incoming));
+            for (key, record) in records {
+                let script = Self::key_script(key)?;
+                for (txid, vout, value) in record.outputs.iter() {
+                    let outpoint = (txid.clone(), *vout);
+                    if record.incoming {
+                        tenant.unspent.insert(outpoint, (script.clone(), *value));
+                    } else {
+                        tenant.unspent.remove(&outpoint);
+                    }
+                }
+            }
+        }
+
+        Ok(Tenants { tenants, scripts })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+
+    pub fn authenticate(&self, api_key: &str) -> Option<&str> {
+        self.tenants
+            .iter()
+            .find(|(_, tenant)| tenant.api_key == api_key)
+            .map(|(id, _)| id.as_str())
+    }
+
+    pub fn get_watch(&self, id: &str) -> Vec<String> {
+        let mut scripts = self.tenants[id]
+            .watch
+            .iter()
+            .map(|id| hex::encode(self.scripts.value(*id)))
+            .collect::<Vec<_>>();
+        scripts.sort();
+        scripts
+    }
+
+    pub fn add_watch(
+        &mut self,
+        storage: &dyn Storage,
+        id: &str,
+        script: Vec<u8>,
+    ) -> TenantResult<()> {
+        let tenant = self.tenants.get_mut(id).unwrap();
+        if let Some(script_id) = self.scripts.get(&script) {
+            if tenant.watch.contains(&script_id) {
+                return Ok(());
+            }
+        }
+        if tenant.entries() >= tenant.max_watch {
+            return Err(TenantError::QuotaExceeded(tenant.max_watch));
+        }
+
+        storage.insert(TREE_WATCH, &Self::key(id, &script), &[])?;
+        tenant.watch.insert(self.scripts.intern(&script));
+        Ok(())
+    }
+
+    pub fn remove_watch(
+        &mut self,
+        storage: &dyn Storage,
+        id: &str,
+        script: &[u8],
+    ) -> TenantResult<()> {
+        let tenant = self.tenants.get_mut(id).unwrap();
+        storage.remove(TREE_WATCH, &Self::key(id, script))?;
+        if let Some(script_id) = self.scripts.get(script) {
+            if tenant.watch.remove(&script_id) {
+                self.scripts.release(script_id);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_timelocks(&self, id: &str) -> Vec<json::Timelock> {
+        self.tenants[id]
+            .timelocks
+            .iter()
+            .map(|((txid, vout), timelock)| json::Timelock {
+                txid: txid.clone(),
+                vout: *vout,
+                locktime: timelock.locktime,
+                sequence: timelock.sequence,
+                height: timelock.height,
+                spendable: timelock.spendable,
+            })
+            .collect()
+    }
+
+    // Added or replaced timelock, `confirmed` is confirmation height of output
+    // with median time past of previous block, `tip` is height and median
+    // time past of our tip
+    pub fn add_timelock(
+        &mut self,
+        storage: &dyn Storage,
+        id: &str,
+        entry: &json::TimelockEntry,
+        confirmed: Option<(u32, u32)>,
+        tip: (u32, u32),
+    ) -> TenantResult<()> {
+        if entry.locktime.is_none() && entry.sequence.is_none() {
+            return Err(TenantError::InvalidTimelock(
+                "locktime or sequence required",
+            ));
+        }
+        if entry.locktime == Some(0) {
+            return Err(TenantError::InvalidTimelock("locktime is disabled"));
+        }
+        if entry
+            .sequence
+            .is_some_and(|sequence| sequence & SEQUENCE_DISABLE_FLAG != 0)
+        {
+            return Err(TenantError::InvalidTimelock("sequence is disabled"));
+        }
+
+        let tenant = self.tenants.get_mut(id).unwrap();
+        let outpoint = (entry.txid.clone(), entry.vout);
+        if !tenant.timelocks.contains_key(&outpoint) && tenant.entries() >= tenant.max_watch {
+            return Err(TenantError::QuotaExceeded(tenant.max_watch));
+        }
+
+        let mut timelock = StoredTimelock {
+            locktime: entry.locktime,
+            sequence: entry.sequence,
+            height: confirmed.map(|(height, _)| height),
+            start_time: confirmed.map(|(_, time)| time),
+            spendable: false,
+        };
+        timelock.spendable = is_spendable(&timelock, tip.0, tip.1);
+        let key = Self::outpoint_key(id, &outpoint);
+        storage::insert_json(storage, TREE_WATCH_TIMELOCKS, &key, &timelock)?;
+        tenant.timelocks.insert(outpoint, timelock);
+        Ok(())
+    }
+
+    pub fn remove_timelock(
+        &mut self,
+        storage: &dyn Storage,
+        id: &str,
+        txid: &str,
+        vout: u32,
+    ) -> TenantResult<()> {
+        let tenant = self.tenants.get_mut(id).unwrap();
+        let outpoint = (txid.to_owned(), vout);
+        storage.remove(TREE_WATCH_TIMELOCKS, &Self::outpoint_key(id, &outpoint))?;
+        tenant.timelocks.remove(&outpoint);
+        Ok(())
+    }
+
+    pub fn get_reactions(&self, id: &str) -> Vec<json::Reaction> {
+        self.tenants[id]
+            .reactions
+            .iter()
+            .map(|((txid, vout), reaction)| json::Reaction {
+                txid: txid.clone(),
+                vout: *vout,
+                tx: reaction.tx.clone(),
+                dry_run: reaction.dry_run,
+                spent_by: reaction.spent_by.clone(),
+            })
+            .collect()
+    }
+
+    // Added or replaced reaction, replaced reaction can be triggered again
+    pub fn add_reaction(
+        &mut self,
+        storage: &dyn Storage,
+        id: &str,
+        entry: &json::ReactionEntry,
+    ) -> TenantResult<()> {
+        let tenant = self.tenants.get_mut(id).unwrap();
+        let outpoint = (entry.txid.clone(), entry.vout);
+        if !tenant.reactions.contains_key(&outpoint) && tenant.entries() >= tenant.max_watch {
+            return Err(TenantError::QuotaExceeded(tenant.max_watch));
+        }
+
+        let reaction = StoredReaction {
+            tx: entry.tx.clone(),
+            dry_run: entry.dry_run,
+            spent_by: None,
+        };
+        let key = Self::outpoint_key(id, &outpoint);
+        storage::insert_json(storage, TREE_WATCH_REACTIONS, &key, &reaction)?;
+        tenant.reactions.insert(outpoint, reaction);
+        Ok(())
+    }
+
+    pub fn remove_reaction(
+        &mut self,
+        storage: &dyn Storage,
+        id: &str,
+        txid: &str,
+        vout: u32,
+    ) -> TenantResult<()> {
+        let tenant = self.tenants.get_mut(id).unwrap();
+        let outpoint = (txid.to_owned(), vout);
+        storage.remove(TREE_WATCH_REACTIONS, &Self::outpoint_key(id, &outpoint))?;
+        tenant.reactions.remove(&outpoint);
+        Ok(())
+    }
+
+    pub fn has_reactions(&self) -> bool {
+        self.tenants
+            .values()
+            .any(|tenant| !tenant.reactions.is_empty())
+    }
+
+    // Reactions triggered by spends (spent outpoint with spending txid)
+    pub fn match_spends(
+        &mut self,
+        storage: &dyn Storage,
+        spends: &[((String, u32), String)],
+    ) -> StorageResult<Vec<TriggeredReaction>> {
+        let mut triggered = vec![];
+        for (id, tenant) in self.tenants.iter_mut() {
+            for (outpoint, spent_by) in spends.iter() {
+                let reaction = match tenant.reactions.get_mut(outpoint) {
+                    Some(reaction) if reaction.spent_by.is_none() => reaction,
+                    _ => continue,
+                };
+                reaction.spent_by = Some(spent_by.clone());
+                let key = Self::outpoint_key(id, outpoint);
+                storage::insert_json(storage, TREE_WATCH_REACTIONS, &key, reaction)?;
+                triggered.push(TriggeredReaction {
+                    tenant: id.clone(),
+                    outpoint: outpoint.clone(),
+                    tx: reaction.tx.clone(),
+                    dry_run: reaction.dry_run,
+                });
+            }
+        }
+        Ok(triggered)
+    }
+
+    // Confirmation of timelocked outputs in new tip, `time` is median time
+    // past of previous block
+    pub fn confirm_timelocks(
+        &mut self,
+        storage: &dyn Storage,
+        block: &ResponseBlock,
+        time: u32,
+    ) -> StorageResult<()> {
+        for (id, tenant) in self.tenants.iter_mut() {
+            if tenant.timelocks.is_empty() {
+                continue;
+            }
+            for tx in block.transactions.iter() {
+                let range = (tx.txid.clone(), 0)..=(tx.txid.clone(), u32::MAX);
+                for (outpoint, timelock) in tenant.timelocks.range_mut(range) {
+                    timelock.height = Some(block.height);
+                    timelock.start_time = Some(time);
+                    let key = Self::outpoint_key(id, outpoint);
+                    storage::insert_json(storage, TREE_WATCH_TIMELOCKS, &key, timelock)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Tenant and outpoint for every timelock which became spendable on top of
+    // tip with `height` and median time past `time`, timelocks which are not
+    // spendable anymore after reorg are reset and reported again later
+    pub fn check_timelocks(
+        &mut self,
+        storage: &dyn Storage,
+        height: u32,
+        time: u32,
+    ) -> StorageResult<Vec<(String, String, u32)>> {
+        let mut expired = vec![];
+        for (id, tenant) in self.tenants.iter_mut() {
+            for (outpoint, timelock) in tenant.timelocks.iter_mut() {
+                let spendable = is_spendable(timelock, height, time);
+                if spendable == timelock.spendable {
+                    continue;
+                }
+                timelock.spendable = spendable;
+                let key = Self::outpoint_key(id, outpoint);
+                storage::insert_json(storage, TREE_WATCH_TIMELOCKS, &key, timelock)?;
+                if spendable {
+                    expired.push((id.clone(), outpoint.0.clone(), outpoint.1));
+                }
+            }
+        }
+        Ok(expired)
+    }
+
+    // Tenant and txid for every transaction with output to watched script,
+    // receives and spends of watched outputs saved to history
+    pub fn match_block(
+        &mut self,
+        storage: &dyn Storage,
+        block: &ResponseBlock,
+    ) -> StorageResult<Vec<(String, String)>> {
+        let mut matched = vec![];
+        let scripts = &self.scripts;
+        for (id, tenant) in self.tenants.iter_mut() {
+            if tenant.watch.is_empty() && tenant.unspent.is_empty() {
+                continue;
+            }
+
+            for tx in block.transactions.iter() {
+                let mut spent = BTreeMap::<Vec<u8>, Vec<_>>::new();
+                for input in tx.vin.iter() {
+                    if let (Some(txid), Some(vout)) = (&input.txid, input.vout) {
+                        let outpoint = (txid.clone(), vout);
+                        if let Some((script, value)) = tenant.unspent.remove(&outpoint) {
+                            spent
+                                .entry(script)
+                                .or_default()
+                                .push((txid.clone(), vout, value));
+                        }
+                    }
+                }
+
+                let mut received = BTreeMap::<Vec<u8>, Vec<_>>::new();
+                for (vout, output) in tx.vout.iter().enumerate() {
+                    let script = &output.script_pubkey.hex;
+                    let watched = scripts.get(script);
+                    if watched.is_some_and(|script_id| tenant.watch.contains(&script_id)) {
+                        let value = (output.value * 1e8).round() as u64;
+                        let outpoint = (tx.txid.clone(), vout as u32);
+                        tenant.unspent.insert(outpoint, (script.clone(), value));
+                        let entry = received.entry(script.clone()).or_default();
+                        entry.push((tx.txid.clone(), vout as u32, value));
+                    }
+                }
+                if !received.is_empty() {
+                    matched.push((id.clone(), tx.txid.clone()));
+                }
+
+                let records = spent
+                    .into_iter()
+                    .map(|(script, outputs)| (script, outputs, false))
+                    .chain(
+                        received
+                            .into_iter()
+                            .map(|(script, outputs)| (script, outputs, true)),
+                    );
+                for (script, outputs, incoming) in records {
+                    let record = StoredWatchRecord {
+                        height: block.height,
+                        hash: block.hash.clone(),
+                        time: block.time,
+                        txid: tx.txid.clone(),
+                        incoming,
+                        outputs,
+                    };
+                    let key = Self::history_key(id, &script, &record);
+                    storage::insert_json(storage, TREE_WATCH_HISTORY, key.as_bytes(), &record)?;
+                    tenant.history.insert(key, record);
+                }
+            }
+        }
+        Ok(matched)
+    }
+
+    // Records of disconnected block removed, spent outputs are unspent again
+    pub fn disconnect_block(&mut self, storage: &dyn Storage, height: u32) -> StorageResult<()> {
+        for (id, tenant) in self.tenants.iter_mut() {
+            let keys = tenant
+                .history
+                .iter()
+                .filter(|(_, record)| record.height >= height)
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+            for key in keys {
+                storage.remove(TREE_WATCH_HISTORY, key.as_bytes())?;
+                let record = tenant.history.remove(&key).unwrap();
+                let script = Self::key_script(&key)?;
+                for (txid, vout, value) in record.outputs {
+                    if record.incoming {
+                        tenant.unspent.remove(&(txid, vout));
+                    } else {
+                        tenant.unspent.insert((txid, vout), (script.clone(), value));
+                    }
+                }
+            }
+
+            for (outpoint, timelock) in tenant.timelocks.iter_mut() {
+                if timelock.height.is_some_and(|confirmed| confirmed >= height) {
+                    timelock.height = None;
+                    timelock.start_time = None;
+                    let key = Self::outpoint_key(id, outpoint);
+                    storage::insert_json(storage, TREE_WATCH_TIMELOCKS, &key, timelock)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // CSV with history of watched script, oldest first, `from` and `to` are
+    // inclusive bounds of block time
+    pub fn export_history(
+        &self,
+        id: &str,
+        script: &[u8],
+        tip: u32,
+        from: Option<u32>,
+        to: Option<u32>,
+    ) -> String {
+        let prefix = format!("{}:{}:", id, hex::encode(script));
+        let mut csv = "timestamp,txid,direction,amount,confirmations\n".to_owned();
+        let records = self.tenants[id]
+            .history
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, record)| record)
+            .filter(|record| from.is_none_or(|from| record.time >= from))
+            .filter(|record| to.is_none_or(|to| record.time <= to));
+        for record in records {
+            let amount = record
+                .outputs
+                .iter()
+                .map(|(_, _, value)| value)
+                .sum::<u64>();
+            csv.push_str(&format!(
+                "{},{},{},{}.{:08},{}\n",
+                format_time(record.time),
+                record.txid,
+                if record.incoming { "in" } else { "out" },
+                amount / 100_000_000,
+                amount % 100_000_000,
+                tip.saturating_sub(record.height) + 1,
+            ));
+        }
+        csv
+    }
+
+    fn key(id: &str, script: &[u8]) -> Vec<u8> {
+        format!("{}:{}", id, hex::encode(script)).into_bytes()
+    }
+
+    fn outpoint_key(id: &str, outpoint: &(String, u32)) -> Vec<u8> {
+        format!("{}:{}:{}", id, outpoint.0, outpoint.1).into_bytes()
+    }
+
+    fn parse_outpoint_key(key: &[u8]) -> StorageResult<(String, (String, u32))> {
+        let key = String::from_utf8_lossy(key);
+        let mut parts = key.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(id), Some(txid), Some(vout)) => match vout.parse::<u32>() {
+                Ok(vout) => Ok((id.to_owned(), (txid.to_owned(), vout))),
+                Err(_) => Err(StorageError::InvalidKey(key.to_string())),
+            },
+            _ => Err(StorageError::InvalidKey(key.to_string())),
+        }
+    }
+
+    // Height in hex of fixed width, so records of script sorted by height
+    fn history_key(id: &str, script: &[u8], record: &StoredWatchRecord) -> String {
+        let direction = if record.incoming { "in" } else { "out" };
+        format!(
+            "{}:{}:{:08x}:{}:{}",
+            id,
+            hex::encode(script),
+            record.height,
+            record.txid,
+            direction
+        )
+    }
+
+    fn key_script(key: &str) -> StorageResult<Vec<u8>> {
+        key.split(':')
+            .nth(1)
+            .and_then(|script| hex::decode(script).ok())
+            .ok_or_else(|| StorageError::InvalidKey(key.to_owned()))
+    }
+}
+
+// Spending transaction can be included in block on top of tip with `height`
+// and median time past `time`, same rules as consensus
+fn is_spendable(timelock: &StoredTimelock, height: u32, time: u32) -> bool {
+    let absolute = timelock.locktime.is_none_or(|locktime| {
+        if locktime < LOCKTIME_THRESHOLD {
+            locktime <= height
+        } else {
+            locktime < time
+        }
+    });
+    let relative = timelock.sequence.is_none_or(|sequence| {
+        let value = sequence & SEQUENCE_MASK;
+        if sequence & SEQUENCE_TYPE_FLAG == 0 {
+            timelock
+                .height
+                .is_some_and(|confirmed| confirmed + value <= height + 1)
+        } else {
+            timelock
+                .start_time
+                .is_some_and(|start| start + (value << SEQUENCE_GRANULARITY) <= time)
+        }
+    });
+    absolute && relative
+}
+
+// ISO 8601 in UTC
+fn format_time(time: u32) -> String {
+    let time = i64::from(time);
+    let (year, month, day) = civil_from_days(time.div_euclid(86_400));
+    let secs = time.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::storage;
+
+    fn tenant(id: &str) -> TenantConfig {
+        TenantConfig {
+            id: id.to_owned(),
+            api_key: format!("key-{}", id),
+            max_watch: 2,
+        }
+    }
+
+    #[test]
+    fn watch_shared_script() {
+        let storage = storage::open("memory").unwrap();
+        let storage = storage.as_ref();
+        let configs = vec![tenant("a"), tenant("b")];
+        let mut tenants = Tenants::load(configs.clone(), storage).unwrap();
+
+        for id in &["a", "b"] {
+            tenants.add_watch(storage, id, vec![0x51]).unwrap();
+        }
+        tenants.add_watch(storage, "a", vec![0x51]).unwrap();
+        tenants.add_watch(storage, "a", vec![0x52]).unwrap();
+        assert_eq!(tenants.scripts.len(), 2);
+        match tenants.add_watch(storage, "a", vec![0x53]) {
+            Err(TenantError::QuotaExceeded(2)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // Script released only by last tenant
+        tenants.remove_watch(storage, "a", &[0x51]).unwrap();
+        tenants.remove_watch(storage, "a", &[0x51]).unwrap();
+        assert_eq!(tenants.get_watch("a"), vec!["52"]);
+        assert_eq!(tenants.get_watch("b"), vec!["51"]);
+        tenants.remove_watch(storage, "b", &[0x51]).unwrap();
+        assert_eq!(tenants.scripts.len(), 1);
+
+        let tenants = Tenants::load(configs, storage).unwrap();
+        assert_eq!(tenants.get_watch("a"), vec!["52"]);
+        assert!(tenants.get_watch("b").is_empty());
+        assert_eq!(tenants.authenticate("key-b"), Some("b"));
+    }
+
+    #[test]
+    fn tenant_config() {
+        let config = parse_tenant("alice:secret").unwrap();
+        assert_eq!(
+            (config.id.as_str(), config.api_key.as_str()),
+            ("alice", "secret")
+        );
+        assert_eq!(config.max_watch, TENANT_MAX_WATCH_DEFAULT);
+        assert_eq!(parse_tenant("alice:secret:5").unwrap().max_watch, 5);
+        for value in &["alice", ":secret", "alice:", "alice:secret:many"] {
+            assert!(parse_tenant(value).is_none(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn scoped_watch() {
+        let storage = storage::open("memory").unwrap();
+        let storage = storage.as_ref();
+        // Entries of removed tenant ignored
+        storage.insert(TREE_WATCH, b"c:51", b"").unwrap();
+        storage.insert(TREE_WATCH, b"a:52", b"").unwrap();
+        let tenants = Tenants::load(vec![tenant("a"), tenant("b")], storage).unwrap();
+
+        assert_eq!(tenants.authenticate("key-a"), Some("a"));
+        assert_eq!(tenants.authenticate("key-c"), None);
+        assert_eq!(tenants.get_watch("a"), vec!["52"]);
+        assert!(tenants.get_watch("b").is_empty());
+
+        storage.insert(TREE_WATCH, b"a-52", b"").unwrap();
+        let error = Tenants::load(vec![tenant("a")], storage).unwrap_err();
+        assert!(matches!(error, StorageError::InvalidKey(_)));
+    }
+}
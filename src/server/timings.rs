@@ -0,0 +1,177 @@
+// Durations of block processing stages, so regression in one stage is visible
+// in logs and `/stats` instead of only as slower tip updates.
+//
+// Stages: `fetch` (block body from bitcoind), `decode` (parse and header /
+// merkle root verification), `index` (scripts and spends indexes),
+// `mempool` (confirmed transactions removed from mempool) and `events`
+// (events publishing and plugins `on_block`). Blocks of catch up are fetched
+// and decoded in parallel, so they have only last three stages.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Upper bounds of histogram buckets in milliseconds, last bucket unbounded
+const BUCKETS_MS: [u64; 9] = [1, 5, 10, 50, 100, 500, 1000, 5000, 10000];
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BlockTimings {
+    pub fetch: Option<Duration>,
+    pub decode: Option<Duration>,
+    pub index: Duration,
+    pub mempool: Duration,
+    pub events: Duration,
+}
+
+impl BlockTimings {
+    fn stages(&self) -> Vec<(&'static str, Duration)> {
+        let mut stages = vec![];
+        if let Some(fetch) = self.fetch {
+            stages.push(("fetch", fetch));
+        }
+        if let Some(decode) = self.decode {
+            stages.push(("decode", decode));
+        }
+        stages.push(("index", self.index));
+        stages.push(("mempool", self.mempool));
+        stages.push(("events", self.events));
+        stages
+    }
+}
+
+// `fetch=12.345ms decode=1.234ms ... total=15.000ms`
+impl fmt::Display for BlockTimings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut total = Duration::default();
+        for (name, duration) in self.stages() {
+            write!(f, "{}={:.3}ms ", name, duration.as_secs_f64() * 1000.0)?;
+            total += duration;
+        }
+        write!(f, "total={:.3}ms", total.as_secs_f64() * 1000.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StageStats {
+    pub count: u64,
+    pub time_total_us: u64,
+    pub time_max_us: u64,
+    // Blocks per bucket, not cumulative
+    pub buckets: Vec<StageBucket>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StageBucket {
+    // Upper bound in milliseconds, `None` for last bucket
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+impl Default for StageStats {
+    fn default() -> Self {
+        let buckets = BUCKETS_MS
+            .iter()
+            .map(|le_ms| Some(*le_ms))
+            .chain(std::iter::once(None))
+            .map(|le_ms| StageBucket { le_ms, count: 0 })
+            .collect();
+        StageStats {
+            count: 0,
+            time_total_us: 0,
+            time_max_us: 0,
+            buckets,
+        }
+    }
+}
+
+impl StageStats {
+    fn record(&mut self, duration: Duration) {
+        let us = duration.as_micros() as u64;
+        self.count += 1;
+        self.time_total_us += us;
+        self.time_max_us = self.time_max_us.max(us);
+        let bucket = self
+            .buckets
+            .iter_mut()
+            .find(|bucket| bucket.le_ms.is_none_or(|le_ms| us <= le_ms * 1000))
+            .unwrap();
+        bucket.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BlockTimingsStats {
+    stages: Mutex<BTreeMap<&'static str, StageStats>>,
+}
+
+impl BlockTimingsStats {
+    pub fn record(&self, timings: &BlockTimings) {
+        let mut stages = self.stages.lock().unwrap();
+        for (name, duration) in timings.stages() {
+            stages.entry(name).or_default().record(duration);
+        }
+    }
+
+    pub fn stats(&self) -> BTreeMap<String, StageStats> {
+        let stages = self.stages.lock().unwrap();
+        stages
+            .iter()
+            .map(|(name, stats)| ((*name).to_owned(), stats.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let timings = BlockTimings {
+            fetch: Some(Duration::from_micros(12_345)),
+            decode: None,
+            index: Duration::from_millis(1),
+            mempool: Duration::from_micros(500),
+            events: Duration::default(),
+        };
+        assert_eq!(
+            timings.to_string(),
+            "fetch=12.345ms index=1.000ms mempool=0.500ms events=0.000ms total=13.845ms"
+        );
+    }
+
+    #[test]
+    fn stage_buckets() {
+        let stats = BlockTimingsStats::default();
+        for ms in &[1, 3, 20_000] {
+            stats.record(&BlockTimings {
+                fetch: None,
+                decode: Some(Duration::from_millis(*ms)),
+                index: Duration::from_micros(1_001),
+                ..BlockTimings::default()
+            });
+        }
+
+        let stats = stats.stats();
+        let names = stats.keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(names, vec!["decode", "events", "index", "mempool"]);
+        let decode = &stats["decode"];
+        assert_eq!(decode.count, 3);
+        assert_eq!(decode.time_total_us, 20_004_000);
+        assert_eq!(decode.time_max_us, 20_000_000);
+        // Bound is inclusive, last bucket is unbounded
+        let counts = decode
+            .buckets
+            .iter()
+            .map(|bucket| (bucket.le_ms, bucket.count))
+            .filter(|(_, count)| *count > 0)
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![(Some(1), 1), (Some(5), 1), (None, 1)]);
+        assert_eq!(stats["index"].buckets[1].count, 3);
+        assert_eq!(stats["events"].buckets[0].count, 3);
+    }
+}
@@ -6,6 +6,7 @@ use log::{error, info};
 use tokio::signal::unix;
 use tokio::sync::broadcast;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Signal {
     SIGINT,
@@ -19,6 +20,12 @@ pub struct Signals {
     streams: Vec<(unix::Signal, Signal)>,
 }
 
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Signals {
     pub fn new() -> Signals {
         let sig_map = [
@@ -114,6 +121,12 @@ impl ShutdownReceiver {
     }
 }
 
+impl Default for ShutdownReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Clone for ShutdownReceiver {
     fn clone(&self) -> Self {
         ShutdownReceiver {
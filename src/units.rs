@@ -0,0 +1,108 @@
+// Human-friendly values for CLI options: durations (`250ms`, `10m`) and sizes
+// (`64KiB`, `16MiB`), number without unit is in default unit of option
+
+use std::time::Duration;
+
+const DURATION_UNITS: &[(&str, u64)] = &[
+    ("ms", 1),
+    ("s", 1_000),
+    ("m", 60 * 1_000),
+    ("h", 60 * 60 * 1_000),
+    ("d", 24 * 60 * 60 * 1_000),
+];
+
+const SIZE_UNITS: &[(&str, u64)] = &[
+    ("B", 1),
+    ("KB", 1_000),
+    ("MB", 1_000_000),
+    ("GB", 1_000_000_000),
+    ("KiB", 1 << 10),
+    ("MiB", 1 << 20),
+    ("GiB", 1 << 30),
+];
+
+pub fn parse_duration(value: &str, default_unit: &str) -> Result<Duration, String> {
+    let ms = parse_with_units(value, default_unit, DURATION_UNITS, "duration")?;
+    Ok(Duration::from_millis(ms))
+}
+
+pub fn parse_size(value: &str, default_unit: &str) -> Result<u64, String> {
+    parse_with_units(value, default_unit, SIZE_UNITS, "size")
+}
+
+fn parse_with_units(
+    value: &str,
+    default_unit: &str,
+    units: &[(&str, u64)],
+    kind: &str,
+) -> Result<u64, String> {
+    let pos = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(pos);
+    let unit = if unit.is_empty() { default_unit } else { unit };
+
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!(r#"invalid {} "{}", expected number with unit"#, kind, value))?;
+    let multiplier = match units.iter().find(|(name, _)| *name == unit) {
+        Some((_, multiplier)) => multiplier,
+        None => {
+            let names = units.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            return Err(format!(
+                r#"unknown {} unit "{}", expected one of: {}"#,
+                kind,
+                unit,
+                names.join(", ")
+            ));
+        }
+    };
+    number
+        .checked_mul(*multiplier)
+        .ok_or_else(|| format!(r#"{} "{}" is too big"#, kind, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        let ms = |value, unit| parse_duration(value, unit).map(|d| d.as_millis());
+        assert_eq!(ms("250ms", "s"), Ok(250));
+        assert_eq!(ms("10m", "s"), Ok(600_000));
+        assert_eq!(ms("2d", "s"), Ok(172_800_000));
+        assert_eq!(ms("30", "s"), Ok(30_000));
+        assert_eq!(ms("30", "ms"), Ok(30));
+
+        assert_eq!(
+            ms("", "s"),
+            Err(r#"invalid duration "", expected number with unit"#.to_owned())
+        );
+        assert_eq!(
+            ms("s", "s"),
+            Err(r#"invalid duration "s", expected number with unit"#.to_owned())
+        );
+        assert_eq!(
+            ms("5 s", "s"),
+            Err(r#"unknown duration unit " s", expected one of: ms, s, m, h, d"#.to_owned())
+        );
+        assert_eq!(
+            ms("18446744073709551615s", "s"),
+            Err(r#"duration "18446744073709551615s" is too big"#.to_owned())
+        );
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("64KiB", "B"), Ok(65_536));
+        assert_eq!(parse_size("16MiB", "B"), Ok(16 << 20));
+        assert_eq!(parse_size("2GB", "B"), Ok(2_000_000_000));
+        assert_eq!(parse_size("2", "KiB"), Ok(2048));
+
+        // Units are case sensitive
+        let err = parse_size("1kb", "B").unwrap_err();
+        assert!(err.starts_with(r#"unknown size unit "kb""#), "{}", err);
+        assert!(parse_size("-1", "B").is_err());
+    }
+}